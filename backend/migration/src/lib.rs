@@ -10,6 +10,22 @@ mod m20241014_000007_create_unread_counts_table;
 mod m20241014_000008_create_online_status_table;
 mod m20241014_000009_alter_users_table;
 mod m20251015_000001_alter_messages_add_extended_columns;
+mod m20251020_000001_create_ws_outbox_table;
+mod m20251020_000002_reconcile_unread_counts;
+mod m20251020_000003_alter_online_status_add_last_seen;
+mod m20251020_000004_alter_sessions_add_priority;
+mod m20251020_000005_create_shop_routing_settings;
+mod m20251020_000006_create_message_edits;
+mod m20251020_000007_create_messages_fts;
+mod m20251020_000008_create_canned_responses;
+mod m20251020_000009_create_customer_tags_attributes;
+mod m20251020_000010_alter_customers_add_block_columns;
+mod m20251020_000011_alter_shop_staffs_add_permissions;
+mod m20251020_000012_create_auth_sessions;
+mod m20251020_000013_create_password_reset_tokens;
+mod m20251020_000014_create_two_factor;
+mod m20251020_000015_create_login_events;
+mod m20251020_000016_create_presence_connections;
 
 pub struct Migrator;
 
@@ -28,6 +44,38 @@ impl MigratorTrait for Migrator {
             Box::new(m20241014_000009_alter_users_table::Migration),
             // 2025-10-15 M1 扩展 messages 列 (阅读状态 / 软删除 / 富文本 / 引用 / 更新时间)
             Box::new(m20251015_000001_alter_messages_add_extended_columns::Migration),
+            // 多节点 WebSocket 扇出 outbox
+            Box::new(m20251020_000001_create_ws_outbox_table::Migration),
+            // 未读计数表结构对齐 + 客服个人已读游标 + 按 messages.is_read 回填
+            Box::new(m20251020_000002_reconcile_unread_counts::Migration),
+            // 在线状态记录最近在线时间
+            Box::new(m20251020_000003_alter_online_status_add_last_seen::Migration),
+            // 会话优先级
+            Box::new(m20251020_000004_alter_sessions_add_priority::Migration),
+            // 会话自动分配：店铺分配策略 + 客服最大并发会话数
+            Box::new(m20251020_000005_create_shop_routing_settings::Migration),
+            // 消息编辑历史
+            Box::new(m20251020_000006_create_message_edits::Migration),
+            // 消息全文搜索（FTS5 + 同步触发器）
+            Box::new(m20251020_000007_create_messages_fts::Migration),
+            // 快捷回复模板（店铺公共 + 客服个人）
+            Box::new(m20251020_000008_create_canned_responses::Migration),
+            // 客户标签与自定义属性
+            Box::new(m20251020_000009_create_customer_tags_attributes::Migration),
+            // 客户拉黑原因与到期时间
+            Box::new(m20251020_000010_alter_customers_add_block_columns::Migration),
+            // 员工按人能力覆盖
+            Box::new(m20251020_000011_alter_shop_staffs_add_permissions::Migration),
+            // 登录会话：刷新令牌轮换与 access token 吊销
+            Box::new(m20251020_000012_create_auth_sessions::Migration),
+            // 找回密码一次性重置令牌
+            Box::new(m20251020_000013_create_password_reset_tokens::Migration),
+            // TOTP 双因素认证、恢复码与店铺强制要求
+            Box::new(m20251020_000014_create_two_factor::Migration),
+            // 登录审计与失败次数统计
+            Box::new(m20251020_000015_create_login_events::Migration),
+            // 在线状态的跨节点连接明细
+            Box::new(m20251020_000016_create_presence_connections::Migration),
         ]
     }
 }
@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 多节点 WebSocket 扇出使用的共享 outbox 表
+// 各节点写入本地产生的投递事件，其他节点按自增 id 轮询并投递给自己持有的连接。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WsOutbox::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(WsOutbox::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(WsOutbox::OriginNode).string().not_null())
+                    .col(ColumnDef::new(WsOutbox::TargetKind).string().not_null())
+                    .col(ColumnDef::new(WsOutbox::ShopId).integer())
+                    .col(ColumnDef::new(WsOutbox::CustomerCode).string())
+                    .col(ColumnDef::new(WsOutbox::UserId).integer())
+                    .col(ColumnDef::new(WsOutbox::Payload).text().not_null())
+                    .col(ColumnDef::new(WsOutbox::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WsOutbox::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum WsOutbox { Table, Id, OriginNode, TargetKind, ShopId, CustomerCode, UserId, Payload, CreatedAt }
@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 对齐 unread_counts 与 entities::unread_counts（unread_count / updated_at NOT NULL），
+//          新增客服个人已读游标表 staff_read_cursors，并按 messages.is_read 回填未读数。
+// SQLite: 不支持修改列约束，采用新建表 + 拷贝 + 重命名。
+// Down: 仅删除 staff_read_cursors，unread_counts 保持新结构。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let statements = [
+            r#"CREATE TABLE IF NOT EXISTS staff_read_cursors (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                shop_id INTEGER NOT NULL,
+                customer_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                last_read_message_id INTEGER NOT NULL DEFAULT 0,
+                updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE(shop_id, customer_id, user_id)
+            )"#,
+            r#"CREATE TABLE unread_counts_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                shop_id INTEGER NOT NULL,
+                customer_id INTEGER NOT NULL,
+                unread_count INTEGER NOT NULL DEFAULT 0,
+                last_read_message_id INTEGER,
+                updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                UNIQUE(shop_id, customer_id)
+            )"#,
+            r#"INSERT OR IGNORE INTO unread_counts_new (id, shop_id, customer_id, unread_count, last_read_message_id, updated_at)
+               SELECT id, shop_id, customer_id, 0, last_read_message_id, COALESCE(updated_at, CURRENT_TIMESTAMP)
+               FROM unread_counts"#,
+            "DROP TABLE unread_counts",
+            "ALTER TABLE unread_counts_new RENAME TO unread_counts",
+            "CREATE INDEX IF NOT EXISTS idx_unread_counts_shop_customer ON unread_counts(shop_id, customer_id)",
+            r#"INSERT INTO unread_counts (shop_id, customer_id, unread_count, updated_at)
+               SELECT s.shop_id, s.customer_id, COUNT(m.id), CURRENT_TIMESTAMP
+               FROM messages m
+               JOIN sessions s ON s.id = m.session_id
+               WHERE m.sender_type = 'customer' AND m.is_read = 0 AND m.is_deleted = 0
+               GROUP BY s.shop_id, s.customer_id
+               ON CONFLICT(shop_id, customer_id) DO UPDATE SET
+                   unread_count = excluded.unread_count,
+                   updated_at = CURRENT_TIMESTAMP"#,
+        ];
+        for sql in statements {
+            db.execute_unprepared(sql).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .get_connection()
+            .execute_unprepared("DROP TABLE IF EXISTS staff_read_cursors")
+            .await?;
+        Ok(())
+    }
+}
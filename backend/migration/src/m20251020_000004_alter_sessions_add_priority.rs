@@ -0,0 +1,26 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 为 sessions 表添加 priority 列（0-10，默认 0）
+// SQLite: 若列已存在则忽略错误继续。
+// Down: SQLite 不支持 drop column，保持 no-op。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let alter = Table::alter()
+            .table(Alias::new("sessions"))
+            .add_column(ColumnDef::new(Alias::new("priority")).integer().not_null().default(0))
+            .to_owned();
+        if let Err(e) = manager.alter_table(alter).await {
+            if !e.to_string().contains("duplicate column name") { return Err(e); }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 会话自动分配配置
+// - shop_routing_settings：每店一行，分配策略 / 默认最大并发 / 轮询位置
+// - shop_staffs.max_concurrent_chats：单个客服的最大并发会话数（为空时使用店铺默认值）
+// Down: 删除配置表；SQLite 不支持 drop column，shop_staffs 保持不变。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ShopRoutingSettings::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(ShopRoutingSettings::ShopId).integer().not_null().primary_key())
+                    .col(ColumnDef::new(ShopRoutingSettings::Strategy).string().not_null().default("least_active"))
+                    .col(ColumnDef::new(ShopRoutingSettings::DefaultMaxConcurrent).integer().not_null().default(5))
+                    .col(ColumnDef::new(ShopRoutingSettings::LastAssignedUserId).integer())
+                    .col(ColumnDef::new(ShopRoutingSettings::UpdatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        let alter = Table::alter()
+            .table(Alias::new("shop_staffs"))
+            .add_column(ColumnDef::new(Alias::new("max_concurrent_chats")).integer())
+            .to_owned();
+        if let Err(e) = manager.alter_table(alter).await {
+            if !e.to_string().contains("duplicate column name") { return Err(e); }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ShopRoutingSettings::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum ShopRoutingSettings { Table, ShopId, Strategy, DefaultMaxConcurrent, LastAssignedUserId, UpdatedAt }
@@ -0,0 +1,49 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 消息编辑历史
+// - message_edits：每次编辑保存一行，记录编辑前的内容与编辑人
+// Down: 删除历史表。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MessageEdits::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(MessageEdits::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(MessageEdits::MessageId).integer().not_null())
+                    .col(ColumnDef::new(MessageEdits::SessionId).integer().not_null())
+                    .col(ColumnDef::new(MessageEdits::EditorType).string_len(10).not_null())
+                    .col(ColumnDef::new(MessageEdits::EditorId).integer().not_null())
+                    .col(ColumnDef::new(MessageEdits::PreviousContent).text().not_null())
+                    .col(ColumnDef::new(MessageEdits::EditedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_message_edits_message_id")
+                    .table(MessageEdits::Table)
+                    .col(MessageEdits::MessageId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MessageEdits::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum MessageEdits { Table, Id, MessageId, SessionId, EditorType, EditorId, PreviousContent, EditedAt }
@@ -0,0 +1,65 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 消息全文搜索
+// - messages_fts：FTS5 虚拟表（trigram 分词，支持中文子串），索引消息内容与客户名，rowid = messages.id
+// - 触发器：消息新增 / 编辑 / 软删除 / 删除、客户改名时同步索引
+// - 首次创建时回填未删除的历史消息
+// Down: 删除触发器与虚拟表。
+
+const TRIGGERS: [&str; 4] = ["messages_fts_ai", "messages_fts_au", "messages_fts_ad", "customers_fts_au"];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let existed = manager.has_table("messages_fts").await?;
+        let statements = [
+            "CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(content, customer_name, tokenize = 'trigram')",
+            r#"CREATE TRIGGER IF NOT EXISTS messages_fts_ai AFTER INSERT ON messages WHEN NEW.is_deleted = 0 BEGIN
+                INSERT INTO messages_fts(rowid, content, customer_name)
+                VALUES (NEW.id, NEW.content, (SELECT c.customer_name FROM sessions s JOIN customers c ON c.id = s.customer_id WHERE s.id = NEW.session_id));
+            END"#,
+            r#"CREATE TRIGGER IF NOT EXISTS messages_fts_au AFTER UPDATE OF content, is_deleted ON messages BEGIN
+                DELETE FROM messages_fts WHERE rowid = OLD.id;
+                INSERT INTO messages_fts(rowid, content, customer_name)
+                SELECT NEW.id, NEW.content, (SELECT c.customer_name FROM sessions s JOIN customers c ON c.id = s.customer_id WHERE s.id = NEW.session_id)
+                WHERE NEW.is_deleted = 0;
+            END"#,
+            r#"CREATE TRIGGER IF NOT EXISTS messages_fts_ad AFTER DELETE ON messages BEGIN
+                DELETE FROM messages_fts WHERE rowid = OLD.id;
+            END"#,
+            r#"CREATE TRIGGER IF NOT EXISTS customers_fts_au AFTER UPDATE OF customer_name ON customers
+                WHEN OLD.customer_name IS NOT NEW.customer_name BEGIN
+                UPDATE messages_fts SET customer_name = NEW.customer_name
+                WHERE rowid IN (SELECT m.id FROM messages m JOIN sessions s ON s.id = m.session_id WHERE s.customer_id = NEW.id);
+            END"#,
+        ];
+        for sql in statements {
+            db.execute_unprepared(sql).await?;
+        }
+        if !existed {
+            db.execute_unprepared(
+                r#"INSERT INTO messages_fts(rowid, content, customer_name)
+                   SELECT m.id, m.content, c.customer_name
+                   FROM messages m
+                   JOIN sessions s ON s.id = m.session_id
+                   LEFT JOIN customers c ON c.id = s.customer_id
+                   WHERE m.is_deleted = 0"#,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        for trigger in TRIGGERS {
+            db.execute_unprepared(&format!("DROP TRIGGER IF EXISTS {}", trigger)).await?;
+        }
+        db.execute_unprepared("DROP TABLE IF EXISTS messages_fts").await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 快捷回复模板
+// - canned_responses：owner_user_id 为空表示店铺公共模板，否则为该客服的个人模板
+// Down: 删除模板表。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(CannedResponses::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(CannedResponses::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(CannedResponses::ShopId).integer().not_null())
+                    .col(ColumnDef::new(CannedResponses::OwnerUserId).integer())
+                    .col(ColumnDef::new(CannedResponses::Title).string_len(100).not_null())
+                    .col(ColumnDef::new(CannedResponses::Shortcut).string_len(32))
+                    .col(ColumnDef::new(CannedResponses::Content).text().not_null())
+                    .col(ColumnDef::new(CannedResponses::UsageCount).integer().not_null().default(0))
+                    .col(ColumnDef::new(CannedResponses::CreatedBy).integer().not_null())
+                    .col(ColumnDef::new(CannedResponses::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(CannedResponses::UpdatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_canned_responses_shop_owner")
+                    .table(CannedResponses::Table)
+                    .col(CannedResponses::ShopId)
+                    .col(CannedResponses::OwnerUserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CannedResponses::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum CannedResponses { Table, Id, ShopId, OwnerUserId, Title, Shortcut, Content, UsageCount, CreatedBy, CreatedAt, UpdatedAt }
@@ -0,0 +1,108 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 客户标签与自定义属性
+// - customer_tags：店铺内的客户标签（如 VIP、待退款），同一客户同名标签唯一
+// - customer_attributes：客户自定义键值属性（如订单号、会员等级），同一客户同名键唯一
+// Down: 删除两张表。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(CustomerTags::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(CustomerTags::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(CustomerTags::ShopId).integer().not_null())
+                    .col(ColumnDef::new(CustomerTags::CustomerId).integer().not_null())
+                    .col(ColumnDef::new(CustomerTags::Tag).string_len(32).not_null())
+                    .col(ColumnDef::new(CustomerTags::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_customer_tags_customer_tag")
+                    .table(CustomerTags::Table)
+                    .col(CustomerTags::CustomerId)
+                    .col(CustomerTags::Tag)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_customer_tags_shop_tag")
+                    .table(CustomerTags::Table)
+                    .col(CustomerTags::ShopId)
+                    .col(CustomerTags::Tag)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(CustomerAttributes::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(CustomerAttributes::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(CustomerAttributes::ShopId).integer().not_null())
+                    .col(ColumnDef::new(CustomerAttributes::CustomerId).integer().not_null())
+                    .col(ColumnDef::new(CustomerAttributes::AttrKey).string_len(64).not_null())
+                    .col(ColumnDef::new(CustomerAttributes::AttrValue).text().not_null())
+                    .col(ColumnDef::new(CustomerAttributes::UpdatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_customer_attributes_customer_key")
+                    .table(CustomerAttributes::Table)
+                    .col(CustomerAttributes::CustomerId)
+                    .col(CustomerAttributes::AttrKey)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_customer_attributes_shop_key")
+                    .table(CustomerAttributes::Table)
+                    .col(CustomerAttributes::ShopId)
+                    .col(CustomerAttributes::AttrKey)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CustomerAttributes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(CustomerTags::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum CustomerTags { Table, Id, ShopId, CustomerId, Tag, CreatedAt }
+
+#[derive(Iden)]
+enum CustomerAttributes { Table, Id, ShopId, CustomerId, AttrKey, AttrValue, UpdatedAt }
@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 为 customers 表添加拉黑信息列（原因 / 到期时间 / 拉黑时间 / 操作人）
+// - status = 0 表示已拉黑；blocked_until 为空表示永久，到期后读取时自动解除
+// SQLite: 若列已存在则忽略错误继续。
+// Down: SQLite 不支持 drop column，保持 no-op。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let columns = [
+            ColumnDef::new(Alias::new("blocked_reason")).text().to_owned(),
+            ColumnDef::new(Alias::new("blocked_until")).timestamp().to_owned(),
+            ColumnDef::new(Alias::new("blocked_at")).timestamp().to_owned(),
+            ColumnDef::new(Alias::new("blocked_by")).integer().to_owned(),
+        ];
+        for mut column in columns {
+            let alter = Table::alter()
+                .table(Alias::new("customers"))
+                .add_column(&mut column)
+                .to_owned();
+            if let Err(e) = manager.alter_table(alter).await {
+                if !e.to_string().contains("duplicate column name") { return Err(e); }
+            }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
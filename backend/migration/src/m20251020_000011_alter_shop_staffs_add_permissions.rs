@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 为 shop_staffs 表添加 permissions 列（按人能力覆盖）
+// - JSON 对象，如 {"export": true, "view_all_sessions": false}；为空表示完全使用角色默认能力
+// - role 取值扩展为 admin / agent / viewer，历史数据中的 staff 按 agent 处理
+// SQLite: 若列已存在则忽略错误继续。
+// Down: SQLite 不支持 drop column，保持 no-op。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let alter = Table::alter()
+            .table(Alias::new("shop_staffs"))
+            .add_column(ColumnDef::new(Alias::new("permissions")).text())
+            .to_owned();
+        if let Err(e) = manager.alter_table(alter).await {
+            if !e.to_string().contains("duplicate column name") { return Err(e); }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 登录会话与刷新令牌
+// - auth_sessions：每次登录一行，jti 写入该会话签发的所有 access token
+// - refresh_token_hash 为当前刷新令牌的 SHA-256，刷新时轮换；previous_token_hash 用于识别旧令牌被重放
+// - revoked_at 非空表示已注销 / 被吊销，对应的 access token 立即失效
+// Down: 删除会话表。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuthSessions::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(AuthSessions::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(AuthSessions::Jti).string_len(64).not_null().unique_key())
+                    .col(ColumnDef::new(AuthSessions::UserId).integer().not_null())
+                    .col(ColumnDef::new(AuthSessions::RefreshTokenHash).string_len(64).not_null().unique_key())
+                    .col(ColumnDef::new(AuthSessions::PreviousTokenHash).string_len(64))
+                    .col(ColumnDef::new(AuthSessions::UserAgent).text())
+                    .col(ColumnDef::new(AuthSessions::IpAddress).string_len(64))
+                    .col(ColumnDef::new(AuthSessions::ExpiresAt).timestamp().not_null())
+                    .col(ColumnDef::new(AuthSessions::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .col(ColumnDef::new(AuthSessions::RefreshedAt).timestamp())
+                    .col(ColumnDef::new(AuthSessions::RevokedAt).timestamp())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_auth_sessions_user_id")
+                    .table(AuthSessions::Table)
+                    .col(AuthSessions::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AuthSessions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum AuthSessions {
+    Table,
+    Id,
+    Jti,
+    UserId,
+    RefreshTokenHash,
+    PreviousTokenHash,
+    UserAgent,
+    IpAddress,
+    ExpiresAt,
+    CreatedAt,
+    RefreshedAt,
+    RevokedAt,
+}
@@ -0,0 +1,59 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 找回密码的一次性重置令牌
+// - token_hash 为邮件中令牌的 SHA-256，明文令牌不落库
+// - expires_at 到期或 used_at 非空后令牌失效；同一用户再次申请时旧令牌一并作废
+// Down: 删除重置令牌表。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PasswordResetTokens::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(PasswordResetTokens::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(PasswordResetTokens::UserId).integer().not_null())
+                    .col(ColumnDef::new(PasswordResetTokens::TokenHash).string_len(64).not_null().unique_key())
+                    .col(ColumnDef::new(PasswordResetTokens::ExpiresAt).timestamp().not_null())
+                    .col(ColumnDef::new(PasswordResetTokens::UsedAt).timestamp())
+                    .col(ColumnDef::new(PasswordResetTokens::IpAddress).string_len(64))
+                    .col(ColumnDef::new(PasswordResetTokens::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_password_reset_tokens_user_id")
+                    .table(PasswordResetTokens::Table)
+                    .col(PasswordResetTokens::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PasswordResetTokens::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum PasswordResetTokens {
+    Table,
+    Id,
+    UserId,
+    TokenHash,
+    ExpiresAt,
+    UsedAt,
+    IpAddress,
+    CreatedAt,
+}
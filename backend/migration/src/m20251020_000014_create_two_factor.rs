@@ -0,0 +1,93 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: TOTP 双因素认证
+// - user_totp：每个用户一行，enabled_at 为空表示已生成密钥但尚未验证启用；last_used_step 防止同一验证码重复使用
+// - user_recovery_codes：一次性恢复码，只保存 SHA-256
+// - shops.require_2fa：店主要求全部员工启用双因素认证
+// SQLite: 若列已存在则忽略错误继续。
+// Down: 删除两张表；SQLite 不支持 drop column，require_2fa 保留。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserTotp::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(UserTotp::UserId).integer().not_null().primary_key())
+                    .col(ColumnDef::new(UserTotp::Secret).string_len(64).not_null())
+                    .col(ColumnDef::new(UserTotp::EnabledAt).timestamp())
+                    .col(ColumnDef::new(UserTotp::LastUsedStep).big_integer().not_null().default(0))
+                    .col(ColumnDef::new(UserTotp::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserRecoveryCodes::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(UserRecoveryCodes::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(UserRecoveryCodes::UserId).integer().not_null())
+                    .col(ColumnDef::new(UserRecoveryCodes::CodeHash).string_len(64).not_null())
+                    .col(ColumnDef::new(UserRecoveryCodes::UsedAt).timestamp())
+                    .col(ColumnDef::new(UserRecoveryCodes::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_user_recovery_codes_user_id")
+                    .table(UserRecoveryCodes::Table)
+                    .col(UserRecoveryCodes::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        let alter = Table::alter()
+            .table(Alias::new("shops"))
+            .add_column(ColumnDef::new(Alias::new("require_2fa")).boolean().not_null().default(false))
+            .to_owned();
+        if let Err(e) = manager.alter_table(alter).await {
+            if !e.to_string().contains("duplicate column name") { return Err(e); }
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserRecoveryCodes::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(UserTotp::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum UserTotp {
+    Table,
+    UserId,
+    Secret,
+    EnabledAt,
+    LastUsedStep,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+enum UserRecoveryCodes {
+    Table,
+    Id,
+    UserId,
+    CodeHash,
+    UsedAt,
+    CreatedAt,
+}
@@ -0,0 +1,71 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 登录审计
+// - login_events：每次登录尝试一行（成功、密码错误、账号停用、二次验证失败、被限制）
+// - user_id 为空表示用户名不存在；按 username / ip_address 统计近期失败次数用于限流与临时锁定
+// Down: 删除表。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(LoginEvents::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(LoginEvents::Id).integer().not_null().auto_increment().primary_key())
+                    .col(ColumnDef::new(LoginEvents::UserId).integer())
+                    .col(ColumnDef::new(LoginEvents::Username).string_len(100).not_null())
+                    .col(ColumnDef::new(LoginEvents::IpAddress).string_len(64))
+                    .col(ColumnDef::new(LoginEvents::UserAgent).string_len(512))
+                    .col(ColumnDef::new(LoginEvents::Result).string_len(32).not_null())
+                    .col(ColumnDef::new(LoginEvents::Success).boolean().not_null().default(false))
+                    .col(ColumnDef::new(LoginEvents::TwoFactor).boolean().not_null().default(false))
+                    .col(ColumnDef::new(LoginEvents::CreatedAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        for (name, col) in [
+            ("idx_login_events_user_id", LoginEvents::UserId),
+            ("idx_login_events_username", LoginEvents::Username),
+            ("idx_login_events_ip_address", LoginEvents::IpAddress),
+        ] {
+            manager
+                .create_index(
+                    Index::create()
+                        .if_not_exists()
+                        .name(name)
+                        .table(LoginEvents::Table)
+                        .col(col)
+                        .col(LoginEvents::CreatedAt)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(LoginEvents::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum LoginEvents {
+    Table,
+    Id,
+    UserId,
+    Username,
+    IpAddress,
+    UserAgent,
+    Result,
+    Success,
+    TwoFactor,
+    CreatedAt,
+}
@@ -0,0 +1,60 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+// Purpose: 在线状态的连接明细
+// - online_status 每个 (用户, 店铺) 只有一行，无法表达同一用户同时连在多个节点上
+// - presence_connections：每条 WebSocket 连接一行，记录所在节点；各节点定期刷新自己连接的 last_ping_at，
+//   节点崩溃遗留的行因长时间未刷新被清理
+// Down: 删除表。
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PresenceConnections::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(PresenceConnections::ConnectionId).string_len(64).not_null().primary_key())
+                    .col(ColumnDef::new(PresenceConnections::NodeId).string_len(64).not_null())
+                    .col(ColumnDef::new(PresenceConnections::UserType).string_len(16).not_null())
+                    .col(ColumnDef::new(PresenceConnections::UserId).integer().not_null())
+                    .col(ColumnDef::new(PresenceConnections::ShopId).integer().not_null())
+                    .col(ColumnDef::new(PresenceConnections::LastPingAt).timestamp().not_null().default(Expr::current_timestamp()))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .if_not_exists()
+                    .name("idx_presence_connections_user")
+                    .table(PresenceConnections::Table)
+                    .col(PresenceConnections::ShopId)
+                    .col(PresenceConnections::UserType)
+                    .col(PresenceConnections::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PresenceConnections::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+enum PresenceConnections {
+    Table,
+    ConnectionId,
+    NodeId,
+    UserType,
+    UserId,
+    ShopId,
+    LastPingAt,
+}
@@ -1,23 +1,150 @@
-use std::env;
+use std::{env, sync::OnceLock};
 
 use axum::{
     async_trait,
     extract::FromRequestParts,
-    http::{header, request::Parts, StatusCode},
+    http::{header, request::Parts, HeaderMap, StatusCode},
 };
 
-use crate::jwt::{decode_token, Claims, JwtError};
+use crate::database::Database;
+use crate::jwt::{decode_token, Claims, JwtError, JwtKey, Keyring, DEFAULT_KID};
+use crate::AppState;
 
-/// 从环境变量读取 JWT 密钥，默认回退到开发密钥
-pub fn jwt_secret_from_env() -> Vec<u8> {
-    env::var("JWT_SECRET")
-        .unwrap_or_else(|_| "your-secret-key".to_string())
-        .into_bytes()
+/// 仅开发模式可用的回退密钥
+const DEV_FALLBACK_SECRET: &str = "your-secret-key";
+/// HS256 密钥的最短长度（字节）
+const MIN_SECRET_LEN: usize = 32;
+/// 示例配置中的占位密钥特征
+const PLACEHOLDER_MARKERS: &[&str] = &["change-this", "please-change", "change-in-production"];
+
+static JWT_KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+/// 启动时加载 JWT 密钥环
+///
+/// - JWT_KEYS=`kid:secret[@退役时间RFC3339],...`：多密钥，JWT_ACTIVE_KID 指定签名密钥（默认第一个）
+/// - 未配置 JWT_KEYS 时使用 JWT_SECRET（kid = default）
+/// - 非开发模式下缺少密钥、使用默认/示例密钥或密钥短于 32 字节时拒绝启动
+pub fn init_jwt_keyring(is_dev_mode: bool) -> anyhow::Result<()> {
+    let keyring = load_keyring(is_dev_mode)?;
+    let kids: Vec<&str> = keyring.keys().iter().map(|k| k.kid.as_str()).collect();
+    tracing::info!("🔑 JWT 密钥环: active={}, keys={:?}", keyring.active().kid, kids);
+    let _ = JWT_KEYRING.set(keyring);
+    Ok(())
+}
+
+/// 当前进程的 JWT 密钥环（未调用 init_jwt_keyring 时按生产模式加载）
+pub fn jwt_keyring() -> &'static Keyring {
+    JWT_KEYRING.get_or_init(|| load_keyring(false).unwrap_or_else(|e| panic!("JWT 密钥配置无效: {e}")))
+}
+
+fn load_keyring(allow_insecure: bool) -> anyhow::Result<Keyring> {
+    let check = |kid: &str, secret: &str| -> anyhow::Result<()> {
+        let problem = if secret == DEV_FALLBACK_SECRET
+            || PLACEHOLDER_MARKERS.iter().any(|m| secret.to_lowercase().contains(m))
+        {
+            Some("默认或示例密钥")
+        } else if secret.len() < MIN_SECRET_LEN {
+            Some("密钥长度不足 32 字节")
+        } else {
+            None
+        };
+        match problem {
+            Some(problem) if allow_insecure => {
+                tracing::warn!("⚠️ JWT 密钥 {} 不安全（{}），仅限开发环境使用", kid, problem);
+                Ok(())
+            }
+            Some(problem) => anyhow::bail!("JWT 密钥 {} 不安全：{}", kid, problem),
+            None => Ok(()),
+        }
+    };
+
+    let raw_keys = env::var("JWT_KEYS").ok().filter(|v| !v.trim().is_empty());
+    if let Some(raw_keys) = raw_keys {
+        let mut keys: Vec<JwtKey> = Vec::new();
+        for entry in raw_keys.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (kid, rest) = entry
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("JWT_KEYS 格式错误，应为 kid:secret[@退役时间]"))?;
+            let (secret, retire_at) = match rest.rsplit_once('@') {
+                Some((secret, at)) => match chrono::DateTime::parse_from_rfc3339(at.trim()) {
+                    Ok(at) => (secret, Some(at.with_timezone(&chrono::Utc))),
+                    Err(_) => (rest, None),
+                },
+                None => (rest, None),
+            };
+            let kid = kid.trim();
+            if kid.is_empty() || secret.is_empty() {
+                anyhow::bail!("JWT_KEYS 中存在空的 kid 或密钥");
+            }
+            if keys.iter().any(|k| k.kid == kid) {
+                anyhow::bail!("JWT_KEYS 中 kid 重复: {}", kid);
+            }
+            check(kid, secret)?;
+            keys.push(JwtKey { kid: kid.to_string(), secret: secret.as_bytes().to_vec(), retire_at });
+        }
+        let active_kid = env::var("JWT_ACTIVE_KID")
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .or_else(|| keys.first().map(|k| k.kid.clone()))
+            .ok_or_else(|| anyhow::anyhow!("JWT_KEYS 为空"))?;
+        return Keyring::new(keys, active_kid.trim())
+            .map_err(|_| anyhow::anyhow!("JWT_ACTIVE_KID {} 不存在或已退役", active_kid));
+    }
+
+    match env::var("JWT_SECRET").ok().filter(|v| !v.is_empty()) {
+        Some(secret) => {
+            check(DEFAULT_KID, &secret)?;
+            Ok(Keyring::single(secret.into_bytes()))
+        }
+        None if allow_insecure => {
+            tracing::warn!("⚠️ 未配置 JWT_SECRET，使用开发回退密钥");
+            Ok(Keyring::single(DEV_FALLBACK_SECRET.as_bytes().to_vec()))
+        }
+        None => anyhow::bail!("未配置 JWT_SECRET 或 JWT_KEYS"),
+    }
 }
 
 pub fn verify_token(token: &str) -> Result<Claims, JwtError> {
-    let secret = jwt_secret_from_env();
-    decode_token(token, &secret)
+    decode_token(token, jwt_keyring())
+}
+
+/// 校验 token 签名、有效期以及所属登录会话（jti）未被注销
+pub async fn authenticate_token(db: &Database, token: &str) -> Result<AuthSession, StatusCode> {
+    let claims = verify_token(token).map_err(|e| {
+        tracing::error!("❌ token验证失败: {:?}", e);
+        StatusCode::UNAUTHORIZED
+    })?;
+
+    let user_id = claims.sub.parse::<i64>().map_err(|e| {
+        tracing::error!("❌ user_id解析失败: {:?}", e);
+        StatusCode::UNAUTHORIZED
+    })?;
+    let Some(jti) = claims.jti else {
+        tracing::warn!("❌ token 缺少 jti（旧版 token），需要重新登录");
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+
+    match crate::services::auth_session::is_active(db, user_id, &jti).await {
+        Ok(true) => Ok(AuthSession { user_id, jti }),
+        Ok(false) => {
+            tracing::warn!("❌ token 所属会话已注销: user_id={}", user_id);
+            Err(StatusCode::UNAUTHORIZED)
+        }
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// WebSocket 子协议中携带 token 时使用的协议名：`Sec-WebSocket-Protocol: bearer, <token>`
+pub const WS_BEARER_PROTOCOL: &str = "bearer";
+
+/// 从 `Sec-WebSocket-Protocol` 头中提取 token（浏览器 WebSocket 无法设置 Authorization 头）
+pub fn token_from_ws_protocol(headers: &HeaderMap) -> Option<String> {
+    let raw = headers.get(header::SEC_WEBSOCKET_PROTOCOL)?.to_str().ok()?;
+    let mut parts = raw.split(',').map(|p| p.trim()).filter(|p| !p.is_empty());
+    match parts.next()? {
+        WS_BEARER_PROTOCOL => parts.next().map(|t| t.to_string()),
+        _ => None,
+    }
 }
 
 /// 认证后的用户提取器
@@ -26,14 +153,28 @@ pub struct AuthUser {
     pub user_id: i64,
 }
 
+/// 认证后的登录会话提取器（注销等需要知道当前会话的接口使用）
+#[derive(Debug, Clone)]
+pub struct AuthSession {
+    pub user_id: i64,
+    pub jti: String,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let AuthSession { user_id, .. } = AuthSession::from_request_parts(parts, state).await?;
+        Ok(AuthUser { user_id })
+    }
+}
+
 #[async_trait]
-impl<S> FromRequestParts<S> for AuthUser
-where
-    S: Send + Sync,
-{
+impl FromRequestParts<AppState> for AuthSession {
     type Rejection = StatusCode;
 
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
         tracing::debug!("🔍 AuthUser: 开始认证检查");
         
         let auth = parts
@@ -62,17 +203,9 @@ where
                 StatusCode::UNAUTHORIZED
             })?;
 
-        let claims = verify_token(token).map_err(|e| {
-            tracing::error!("❌ AuthUser: token验证失败: {:?}", e);
-            StatusCode::UNAUTHORIZED
-        })?;
+        let session = authenticate_token(&state.db, token).await?;
         
-        let user_id: i64 = claims.sub.parse().map_err(|e| {
-            tracing::error!("❌ AuthUser: user_id解析失败: {:?}", e);
-            StatusCode::UNAUTHORIZED
-        })?;
-        
-        tracing::debug!("✅ AuthUser: 认证成功，user_id: {}", user_id);
-        Ok(AuthUser { user_id })
+        tracing::debug!("✅ AuthUser: 认证成功，user_id: {}", session.user_id);
+        Ok(session)
     }
 }
@@ -2,10 +2,23 @@
 
 pub mod ws_events {
     pub const AUTH_SUCCESS: &str = "auth_success";
+    pub const AUTH_FAILED: &str = "auth_failed";
     pub const NEW_MESSAGE: &str = "new_message";
     pub const TYPING: &str = "typing";
     pub const SYSTEM: &str = "system";
     pub const PONG: &str = "pong";
+    pub const MESSAGE_READ: &str = "message_read";
+    pub const MESSAGE_DELIVERED: &str = "message_delivered";
+    pub const PRESENCE_CHANGED: &str = "presence_changed";
+    pub const SESSION_UPDATED: &str = "session_updated";
+    pub const QUEUE_POSITION: &str = "queue_position";
+    pub const QUEUE_UPDATED: &str = "queue_updated";
+    pub const MESSAGE_EDITED: &str = "message_edited";
+    pub const MESSAGE_RECALLED: &str = "message_recalled";
+    /// 客户被拉黑：metadata.reason / metadata.blockedUntil，随后服务器关闭连接
+    pub const BLOCKED: &str = "blocked";
+    /// 发送过快，该帧被丢弃：metadata.retryAfter（秒）/ metadata.messageType
+    pub const RATE_LIMITED: &str = "rate_limited";
 }
 
 /// WebSocket 入站事件（客户端 -> 服务器）常量
@@ -14,6 +27,143 @@ pub mod ws_incoming {
     pub const SEND_MESSAGE: &str = "send_message";
     pub const TYPING: &str = "typing";
     pub const PING: &str = "ping";
+    pub const READ: &str = "read";
+    pub const DELIVERED: &str = "delivered";
+    /// metadata.messageId + content
+    pub const EDIT_MESSAGE: &str = "edit_message";
+    /// metadata.messageId
+    pub const RECALL_MESSAGE: &str = "recall_message";
+    /// 客户点击按钮 / 快捷回复：metadata.messageId + metadata.optionId
+    pub const QUICK_REPLY: &str = "quick_reply";
+    /// 客服内部备注：session_id + content（可选 reply_to），仅推送给店铺客服
+    pub const ADD_NOTE: &str = "add_note";
+    /// 客服发送快捷回复模板：session_id + metadata.templateId，内容由服务器展开变量
+    pub const SEND_TEMPLATE: &str = "send_template";
+}
+
+/// 断线重连补发策略
+pub mod replay_policy {
+    /// 单次 auth 最多补发的消息条数，超出部分由客户端拉取历史
+    pub const MAX_MESSAGES: u64 = 200;
+}
+
+/// 消息编辑与撤回：发送者只能在发送后的时限内操作自己的消息
+pub mod message_policy {
+    /// 可通过环境变量 MESSAGE_EDIT_WINDOW_SECS 覆盖
+    pub const DEFAULT_EDIT_WINDOW_SECS: i64 = 15 * 60;
+    /// 可通过环境变量 MESSAGE_RECALL_WINDOW_SECS 覆盖
+    pub const DEFAULT_RECALL_WINDOW_SECS: i64 = 2 * 60;
+    pub const MAX_CONTENT_BYTES: usize = 10000;
+    /// 内部备注的 sender_type：只对客服可见，不推送、不补发给客户
+    pub const NOTE_SENDER_TYPE: &str = "note";
+
+    pub fn edit_window_secs() -> i64 {
+        window_from_env("MESSAGE_EDIT_WINDOW_SECS", DEFAULT_EDIT_WINDOW_SECS)
+    }
+
+    pub fn recall_window_secs() -> i64 {
+        window_from_env("MESSAGE_RECALL_WINDOW_SECS", DEFAULT_RECALL_WINDOW_SECS)
+    }
+
+    fn window_from_env(key: &str, default: i64) -> i64 {
+        std::env::var(key)
+            .ok()
+            .and_then(|v| v.trim().parse::<i64>().ok())
+            .filter(|secs| *secs >= 0)
+            .unwrap_or(default)
+    }
+}
+
+/// 限流默认值：(突发容量, 每分钟补充令牌数)
+/// 可通过环境变量 RATE_LIMIT_<NAME>=<突发容量>:<每分钟补充数> 覆盖，容量为 0 表示不限
+pub mod rate_limit_policy {
+    /// 登录，按客户端 IP
+    pub const LOGIN: (u32, u32) = (10, 10);
+    /// 登录 / 找回密码，按账号（用户名或邮箱），防止分散 IP 针对同一账号
+    pub const LOGIN_ACCOUNT: (u32, u32) = (10, 10);
+    /// 访客上传，按客户端 IP 与访客分别计数
+    pub const UPLOAD: (u32, u32) = (10, 30);
+    /// 单个 WebSocket 连接的入站帧（含心跳、输入中）
+    pub const WS_FRAME: (u32, u32) = (30, 240);
+    /// 访客发消息，按 店铺 + 访客编码（多连接共享）
+    pub const CUSTOMER_MESSAGE: (u32, u32) = (10, 60);
+    /// 访客发消息，按客户端 IP（同一 IP 下的所有访客共享）
+    pub const IP_MESSAGE: (u32, u32) = (30, 300);
+    /// 客服发消息 / 备注，按用户 ID
+    pub const STAFF_MESSAGE: (u32, u32) = (30, 300);
+}
+
+/// 登录令牌：短期 access token + 可轮换的 refresh token
+pub mod auth_policy {
+    /// 可通过环境变量 ACCESS_TOKEN_TTL_MINUTES 覆盖
+    pub const DEFAULT_ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+    /// 可通过环境变量 REFRESH_TOKEN_TTL_DAYS 覆盖
+    pub const DEFAULT_REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+    /// 找回密码令牌有效期，可通过环境变量 PASSWORD_RESET_TTL_MINUTES 覆盖
+    pub const DEFAULT_PASSWORD_RESET_TTL_MINUTES: i64 = 30;
+    /// 同一用户名连续失败达到该次数后，每次重试需等待的时间逐次翻倍（LOGIN_DELAY_AFTER_FAILURES）
+    pub const DEFAULT_LOGIN_DELAY_AFTER_FAILURES: i64 = 3;
+    /// 同一用户名连续失败达到该次数后临时锁定（LOGIN_LOCKOUT_THRESHOLD）
+    pub const DEFAULT_LOGIN_LOCKOUT_THRESHOLD: i64 = 10;
+    /// 同一 IP 的失败次数门槛，多个账号共用出口 IP 时需放宽（LOGIN_IP_DELAY_AFTER_FAILURES / LOGIN_IP_LOCKOUT_THRESHOLD）
+    pub const DEFAULT_LOGIN_IP_DELAY_AFTER_FAILURES: i64 = 20;
+    pub const DEFAULT_LOGIN_IP_LOCKOUT_THRESHOLD: i64 = 100;
+    /// 失败次数的统计窗口，同时也是锁定时长（LOGIN_LOCKOUT_MINUTES）
+    pub const DEFAULT_LOGIN_LOCKOUT_MINUTES: i64 = 15;
+    /// 逐次翻倍的等待时间上限（秒）
+    pub const LOGIN_MAX_DELAY_SECS: i64 = 60;
+
+    pub fn access_token_ttl_secs() -> i64 {
+        positive_from_env("ACCESS_TOKEN_TTL_MINUTES", DEFAULT_ACCESS_TOKEN_TTL_MINUTES) * 60
+    }
+
+    pub fn refresh_token_ttl_secs() -> i64 {
+        positive_from_env("REFRESH_TOKEN_TTL_DAYS", DEFAULT_REFRESH_TOKEN_TTL_DAYS) * 24 * 3600
+    }
+
+    pub fn password_reset_ttl_secs() -> i64 {
+        positive_from_env("PASSWORD_RESET_TTL_MINUTES", DEFAULT_PASSWORD_RESET_TTL_MINUTES) * 60
+    }
+
+    /// 返回 (开始逐次延迟的失败次数, 锁定的失败次数)
+    pub fn login_username_thresholds() -> (i64, i64) {
+        (
+            positive_from_env("LOGIN_DELAY_AFTER_FAILURES", DEFAULT_LOGIN_DELAY_AFTER_FAILURES),
+            positive_from_env("LOGIN_LOCKOUT_THRESHOLD", DEFAULT_LOGIN_LOCKOUT_THRESHOLD),
+        )
+    }
+
+    pub fn login_ip_thresholds() -> (i64, i64) {
+        (
+            positive_from_env("LOGIN_IP_DELAY_AFTER_FAILURES", DEFAULT_LOGIN_IP_DELAY_AFTER_FAILURES),
+            positive_from_env("LOGIN_IP_LOCKOUT_THRESHOLD", DEFAULT_LOGIN_IP_LOCKOUT_THRESHOLD),
+        )
+    }
+
+    pub fn login_lockout_secs() -> i64 {
+        positive_from_env("LOGIN_LOCKOUT_MINUTES", DEFAULT_LOGIN_LOCKOUT_MINUTES) * 60
+    }
+
+    fn positive_from_env(key: &str, default: i64) -> i64 {
+        std::env::var(key)
+            .ok()
+            .and_then(|v| v.trim().parse::<i64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(default)
+    }
+}
+
+/// 会话自动分配策略
+pub mod routing_policy {
+    pub const STRATEGY_ROUND_ROBIN: &str = "round_robin";
+    pub const STRATEGY_LEAST_ACTIVE: &str = "least_active";
+    pub const STRATEGY_STICKY: &str = "sticky";
+    /// 不自动分配，新会话全部进入未分配队列
+    pub const STRATEGY_MANUAL: &str = "manual";
+    pub const DEFAULT_STRATEGY: &str = STRATEGY_LEAST_ACTIVE;
+    /// 客服默认最大并发会话数
+    pub const DEFAULT_MAX_CONCURRENT: i32 = 5;
+    pub const MAX_CONCURRENT_LIMIT: i32 = 100;
 }
 
 pub mod upload_policy {
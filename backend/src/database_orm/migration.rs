@@ -2,7 +2,7 @@
 //! 职责：验证数据库架构，确保与 Sea-ORM 实体一致
 
 use anyhow::Result;
-use sea_orm::{DatabaseConnection, Statement, DbBackend, ConnectionTrait};
+use sea_orm::{DatabaseConnection, Statement, DbBackend, ConnectionTrait, TransactionTrait};
 use tracing::{info, warn};
 use std::collections::{HashMap, HashSet};
 
@@ -21,6 +21,15 @@ pub async fn run_migrations(db: &DatabaseConnection) -> Result<()> {
         "ALTER TABLE messages ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT 0",
         "ALTER TABLE messages ADD COLUMN deleted_at TIMESTAMP",
         "ALTER TABLE messages ADD COLUMN updated_at TIMESTAMP", // 移除 NOT NULL DEFAULT
+        "ALTER TABLE online_status ADD COLUMN last_seen TIMESTAMP",
+        "ALTER TABLE sessions ADD COLUMN priority INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE shop_staffs ADD COLUMN max_concurrent_chats INTEGER",
+        "ALTER TABLE customers ADD COLUMN blocked_reason TEXT",
+        "ALTER TABLE customers ADD COLUMN blocked_until TIMESTAMP",
+        "ALTER TABLE customers ADD COLUMN blocked_at TIMESTAMP",
+        "ALTER TABLE customers ADD COLUMN blocked_by INTEGER",
+        "ALTER TABLE shop_staffs ADD COLUMN permissions TEXT",
+        "ALTER TABLE shops ADD COLUMN require_2fa BOOLEAN NOT NULL DEFAULT 0",
     ];
     
     for sql in alter_sqls {
@@ -36,6 +45,233 @@ pub async fn run_migrations(db: &DatabaseConnection) -> Result<()> {
         }
     }
     
+    reconcile_unread_counts(db).await?;
+
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS shop_routing_settings (
+            shop_id INTEGER PRIMARY KEY,
+            strategy TEXT NOT NULL DEFAULT 'least_active',
+            default_max_concurrent INTEGER NOT NULL DEFAULT 5,
+            last_assigned_user_id INTEGER,
+            updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS message_edits (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message_id INTEGER NOT NULL,
+            session_id INTEGER NOT NULL,
+            editor_type VARCHAR(10) NOT NULL,
+            editor_id INTEGER NOT NULL,
+            previous_content TEXT NOT NULL,
+            edited_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE INDEX IF NOT EXISTS idx_message_edits_message_id ON message_edits(message_id)".to_string(),
+    ))
+    .await?;
+
+    ensure_message_search_index(db).await?;
+
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS canned_responses (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            shop_id INTEGER NOT NULL,
+            owner_user_id INTEGER,
+            title VARCHAR(100) NOT NULL,
+            shortcut VARCHAR(32),
+            content TEXT NOT NULL,
+            usage_count INTEGER NOT NULL DEFAULT 0,
+            created_by INTEGER NOT NULL,
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE INDEX IF NOT EXISTS idx_canned_responses_shop_owner ON canned_responses(shop_id, owner_user_id)".to_string(),
+    ))
+    .await?;
+
+    // 客户标签与自定义属性
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS customer_tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            shop_id INTEGER NOT NULL,
+            customer_id INTEGER NOT NULL,
+            tag VARCHAR(32) NOT NULL,
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_customer_tags_customer_tag ON customer_tags(customer_id, tag)".to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE INDEX IF NOT EXISTS idx_customer_tags_shop_tag ON customer_tags(shop_id, tag)".to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS customer_attributes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            shop_id INTEGER NOT NULL,
+            customer_id INTEGER NOT NULL,
+            attr_key VARCHAR(64) NOT NULL,
+            attr_value TEXT NOT NULL,
+            updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_customer_attributes_customer_key ON customer_attributes(customer_id, attr_key)".to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE INDEX IF NOT EXISTS idx_customer_attributes_shop_key ON customer_attributes(shop_id, attr_key)".to_string(),
+    ))
+    .await?;
+
+    // 登录会话与刷新令牌
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS auth_sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            jti VARCHAR(64) NOT NULL UNIQUE,
+            user_id INTEGER NOT NULL,
+            refresh_token_hash VARCHAR(64) NOT NULL UNIQUE,
+            previous_token_hash VARCHAR(64),
+            user_agent TEXT,
+            ip_address VARCHAR(64),
+            expires_at TIMESTAMP NOT NULL,
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            refreshed_at TIMESTAMP,
+            revoked_at TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE INDEX IF NOT EXISTS idx_auth_sessions_user_id ON auth_sessions(user_id)".to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS password_reset_tokens (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            token_hash VARCHAR(64) NOT NULL UNIQUE,
+            expires_at TIMESTAMP NOT NULL,
+            used_at TIMESTAMP,
+            ip_address VARCHAR(64),
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE INDEX IF NOT EXISTS idx_password_reset_tokens_user_id ON password_reset_tokens(user_id)".to_string(),
+    ))
+    .await?;
+
+    // 双因素认证
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS user_totp (
+            user_id INTEGER PRIMARY KEY,
+            secret VARCHAR(64) NOT NULL,
+            enabled_at TIMESTAMP,
+            last_used_step BIGINT NOT NULL DEFAULT 0,
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS user_recovery_codes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            code_hash VARCHAR(64) NOT NULL,
+            used_at TIMESTAMP,
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE INDEX IF NOT EXISTS idx_user_recovery_codes_user_id ON user_recovery_codes(user_id)".to_string(),
+    ))
+    .await?;
+
+    // 登录审计
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS login_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER,
+            username VARCHAR(100) NOT NULL,
+            ip_address VARCHAR(64),
+            user_agent VARCHAR(512),
+            result VARCHAR(32) NOT NULL,
+            success BOOLEAN NOT NULL DEFAULT 0,
+            two_factor BOOLEAN NOT NULL DEFAULT 0,
+            created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    for sql in [
+        "CREATE INDEX IF NOT EXISTS idx_login_events_user_id ON login_events(user_id, created_at)",
+        "CREATE INDEX IF NOT EXISTS idx_login_events_username ON login_events(username, created_at)",
+        "CREATE INDEX IF NOT EXISTS idx_login_events_ip_address ON login_events(ip_address, created_at)",
+    ] {
+        db.execute(Statement::from_string(DbBackend::Sqlite, sql.to_string())).await?;
+    }
+
+    // 在线状态的跨节点连接明细
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS presence_connections (
+            connection_id VARCHAR(64) PRIMARY KEY,
+            node_id VARCHAR(64) NOT NULL,
+            user_type VARCHAR(16) NOT NULL,
+            user_id INTEGER NOT NULL,
+            shop_id INTEGER NOT NULL,
+            last_ping_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"#
+        .to_string(),
+    ))
+    .await?;
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        "CREATE INDEX IF NOT EXISTS idx_presence_connections_user ON presence_connections(shop_id, user_type, user_id)".to_string(),
+    ))
+    .await?;
+
     info!("✅ 数据库迁移执行完成");
     
     // 验证数据库架构
@@ -61,6 +297,152 @@ pub async fn run_migrations(db: &DatabaseConnection) -> Result<()> {
     Ok(())
 }
 
+/// 消息全文索引：messages_fts（FTS5 + trigram，兼顾中文子串）覆盖消息内容与客户名，
+/// 由触发器与 messages / customers 保持同步；首次创建时回填未删除的历史消息
+///
+/// 同时把历史上以 'T' 分隔（可能带时区）的 created_at 统一为 UTC 的 'YYYY-MM-DD HH:MM:SS.SSS'，
+/// 搜索的日期范围筛选才能直接按字符串比较并使用 idx_messages_created_at
+async fn ensure_message_search_index(db: &DatabaseConnection) -> Result<()> {
+    let normalized = db
+        .execute(Statement::from_string(
+            DbBackend::Sqlite,
+            "UPDATE messages SET created_at = strftime('%Y-%m-%d %H:%M:%f', created_at) \
+             WHERE created_at GLOB '[0-9][0-9][0-9][0-9]-[0-9][0-9]-[0-9][0-9]T*' \
+             AND strftime('%Y-%m-%d %H:%M:%f', created_at) IS NOT NULL"
+                .to_string(),
+        ))
+        .await?;
+    if normalized.rows_affected() > 0 {
+        info!("统一 {} 条消息的 created_at 格式", normalized.rows_affected());
+    }
+
+    let existing = db
+        .query_one(Statement::from_string(
+            DbBackend::Sqlite,
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'messages_fts'".to_string(),
+        ))
+        .await?;
+
+    let statements = [
+        "CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(content, customer_name, tokenize = 'trigram')",
+        r#"CREATE TRIGGER IF NOT EXISTS messages_fts_ai AFTER INSERT ON messages WHEN NEW.is_deleted = 0 BEGIN
+            INSERT INTO messages_fts(rowid, content, customer_name)
+            VALUES (NEW.id, NEW.content, (SELECT c.customer_name FROM sessions s JOIN customers c ON c.id = s.customer_id WHERE s.id = NEW.session_id));
+        END"#,
+        r#"CREATE TRIGGER IF NOT EXISTS messages_fts_au AFTER UPDATE OF content, is_deleted ON messages BEGIN
+            DELETE FROM messages_fts WHERE rowid = OLD.id;
+            INSERT INTO messages_fts(rowid, content, customer_name)
+            SELECT NEW.id, NEW.content, (SELECT c.customer_name FROM sessions s JOIN customers c ON c.id = s.customer_id WHERE s.id = NEW.session_id)
+            WHERE NEW.is_deleted = 0;
+        END"#,
+        r#"CREATE TRIGGER IF NOT EXISTS messages_fts_ad AFTER DELETE ON messages BEGIN
+            DELETE FROM messages_fts WHERE rowid = OLD.id;
+        END"#,
+        r#"CREATE TRIGGER IF NOT EXISTS customers_fts_au AFTER UPDATE OF customer_name ON customers
+            WHEN OLD.customer_name IS NOT NEW.customer_name BEGIN
+            UPDATE messages_fts SET customer_name = NEW.customer_name
+            WHERE rowid IN (SELECT m.id FROM messages m JOIN sessions s ON s.id = m.session_id WHERE s.customer_id = NEW.id);
+        END"#,
+    ];
+    for sql in statements {
+        db.execute(Statement::from_string(DbBackend::Sqlite, sql.to_string())).await?;
+    }
+
+    if existing.is_none() {
+        info!("回填消息全文索引 messages_fts");
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            r#"INSERT INTO messages_fts(rowid, content, customer_name)
+               SELECT m.id, m.content, c.customer_name
+               FROM messages m
+               JOIN sessions s ON s.id = m.session_id
+               LEFT JOIN customers c ON c.id = s.customer_id
+               WHERE m.is_deleted = 0"#
+                .to_string(),
+        ))
+        .await?;
+    }
+    Ok(())
+}
+
+/// 对齐 unread_counts 表结构并回填：
+/// 1. 创建客服个人已读游标表 staff_read_cursors
+/// 2. 旧表 unread_count / updated_at 可为空时重建为与 entities::unread_counts 一致的结构
+/// 3. 重建时按 messages.is_read 回填各客户未读数（仅执行一次）
+async fn reconcile_unread_counts(db: &DatabaseConnection) -> Result<()> {
+    db.execute(Statement::from_string(
+        DbBackend::Sqlite,
+        r#"CREATE TABLE IF NOT EXISTS staff_read_cursors (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            shop_id INTEGER NOT NULL,
+            customer_id INTEGER NOT NULL,
+            user_id INTEGER NOT NULL,
+            last_read_message_id INTEGER NOT NULL DEFAULT 0,
+            updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(shop_id, customer_id, user_id)
+        )"#
+        .to_string(),
+    ))
+    .await?;
+
+    let info = db
+        .query_all(Statement::from_string(
+            DbBackend::Sqlite,
+            "PRAGMA table_info(unread_counts)".to_string(),
+        ))
+        .await?;
+    let table_exists = !info.is_empty();
+    let needs_rebuild = info.iter().any(|row| {
+        let name = row.try_get::<String>("", "name").unwrap_or_default();
+        let not_null = row.try_get::<i32>("", "notnull").unwrap_or(0);
+        (name == "unread_count" || name == "updated_at") && not_null == 0
+    });
+    if table_exists && !needs_rebuild {
+        return Ok(());
+    }
+
+    info!("重建 unread_counts 表并按 messages.is_read 回填未读数");
+    let txn = db.begin().await?;
+    let mut statements = vec![
+        r#"CREATE TABLE unread_counts_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            shop_id INTEGER NOT NULL,
+            customer_id INTEGER NOT NULL,
+            unread_count INTEGER NOT NULL DEFAULT 0,
+            last_read_message_id INTEGER,
+            updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(shop_id, customer_id)
+        )"#,
+    ];
+    if table_exists {
+        statements.push(
+            r#"INSERT OR IGNORE INTO unread_counts_new (id, shop_id, customer_id, unread_count, last_read_message_id, updated_at)
+               SELECT id, shop_id, customer_id, 0, last_read_message_id, COALESCE(updated_at, CURRENT_TIMESTAMP)
+               FROM unread_counts"#,
+        );
+        statements.push("DROP TABLE unread_counts");
+    }
+    statements.extend([
+        "ALTER TABLE unread_counts_new RENAME TO unread_counts",
+        "CREATE INDEX IF NOT EXISTS idx_unread_counts_shop_customer ON unread_counts(shop_id, customer_id)",
+        r#"INSERT INTO unread_counts (shop_id, customer_id, unread_count, updated_at)
+           SELECT s.shop_id, s.customer_id, COUNT(m.id), CURRENT_TIMESTAMP
+           FROM messages m
+           JOIN sessions s ON s.id = m.session_id
+           WHERE m.sender_type = 'customer' AND m.is_read = 0 AND m.is_deleted = 0
+           GROUP BY s.shop_id, s.customer_id
+           ON CONFLICT(shop_id, customer_id) DO UPDATE SET
+               unread_count = excluded.unread_count,
+               updated_at = CURRENT_TIMESTAMP"#,
+    ]);
+    for sql in statements {
+        txn.execute(Statement::from_string(DbBackend::Sqlite, sql.to_string())).await?;
+    }
+    txn.commit().await?;
+    info!("✅ unread_counts 重建与回填完成");
+    Ok(())
+}
+
 /// 获取现有表列表
 async fn get_existing_tables(db: &DatabaseConnection) -> Result<Vec<String>> {
     let stmt = Statement::from_string(
@@ -99,14 +481,27 @@ async fn check_schema_consistency(db: &DatabaseConnection, existing_tables: &[St
     // 定义期望的表结构
     let expected_tables: HashMap<&str, Vec<&str>> = HashMap::from([
         ("users", vec!["id","username","password_hash","email","phone","avatar_url","status","created_at","updated_at"]),
-        ("shops", vec!["id","owner_id","shop_name","shop_url","api_key","status","created_at","updated_at"]),
-        ("customers", vec!["id","shop_id","customer_id","customer_name","customer_email","customer_avatar","ip_address","user_agent","first_visit_at","last_active_at","status"]),
-        ("sessions", vec!["id","shop_id","customer_id","staff_id","session_status","created_at","closed_at","last_message_at"]),
+        ("shops", vec!["id","owner_id","shop_name","shop_url","api_key","status","created_at","updated_at","require_2fa"]),
+        ("customers", vec!["id","shop_id","customer_id","customer_name","customer_email","customer_avatar","ip_address","user_agent","first_visit_at","last_active_at","status","blocked_reason","blocked_until","blocked_at","blocked_by"]),
+        ("sessions", vec!["id","shop_id","customer_id","staff_id","session_status","created_at","closed_at","last_message_at","priority"]),
         ("staff_assignments", vec!["id","session_id","staff_id","assigned_at","unassigned_at"]),
         ("messages", vec!["id","session_id","sender_type","sender_id","sender_name","message_type","content","rich_content","metadata","reply_to","is_read","read_at","is_deleted","deleted_at","created_at","updated_at"]),
         ("unread_counts", vec!["id","shop_id","customer_id","unread_count","last_read_message_id","updated_at"]),
-        ("online_status", vec!["id","user_type","user_id","shop_id","websocket_id","last_ping_at","status"]),
-        ("shop_staffs", vec!["id","shop_id","user_id","role","created_at"]),
+        ("online_status", vec!["id","user_type","user_id","shop_id","websocket_id","last_ping_at","status","last_seen"]),
+        ("shop_staffs", vec!["id","shop_id","user_id","role","created_at","max_concurrent_chats","permissions"]),
+        ("canned_responses", vec!["id","shop_id","owner_user_id","title","shortcut","content","usage_count","created_by","created_at","updated_at"]),
+        ("customer_tags", vec!["id","shop_id","customer_id","tag","created_at"]),
+        ("customer_attributes", vec!["id","shop_id","customer_id","attr_key","attr_value","updated_at"]),
+        ("auth_sessions", vec!["id","jti","user_id","refresh_token_hash","previous_token_hash","user_agent","ip_address","expires_at","created_at","refreshed_at","revoked_at"]),
+        ("user_totp", vec!["user_id","secret","enabled_at","last_used_step","created_at"]),
+        ("user_recovery_codes", vec!["id","user_id","code_hash","used_at","created_at"]),
+        ("password_reset_tokens", vec!["id","user_id","token_hash","expires_at","used_at","ip_address","created_at"]),
+        ("login_events", vec!["id","user_id","username","ip_address","user_agent","result","success","two_factor","created_at"]),
+        ("presence_connections", vec!["connection_id","node_id","user_type","user_id","shop_id","last_ping_at"]),
+        ("message_edits", vec!["id","message_id","session_id","editor_type","editor_id","previous_content","edited_at"]),
+        ("shop_routing_settings", vec!["shop_id","strategy","default_max_concurrent","last_assigned_user_id","updated_at"]),
+        ("staff_read_cursors", vec!["id","shop_id","customer_id","user_id","last_read_message_id","updated_at"]),
+        ("ws_outbox", vec!["id","origin_node","target_kind","shop_id","customer_code","user_id","payload","created_at"]),
     ]);
 
     // 检查每个期望的表
@@ -0,0 +1,26 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// 快捷回复模板：owner_user_id 为空表示店铺公共模板，否则为该客服的个人模板
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "canned_responses")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub shop_id: i32,
+    pub owner_user_id: Option<i32>,
+    pub title: String,
+    /// 输入框中的快捷指令，例如 /refund
+    pub shortcut: Option<String>,
+    /// 支持 {customer_name} / {shop_name} / {agent_name} 变量
+    pub content: String,
+    pub usage_count: i32,
+    pub created_by: i32,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
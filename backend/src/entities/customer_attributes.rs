@@ -0,0 +1,21 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// 客户自定义属性（键值对，值统一以字符串存储）
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "customer_attributes")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub shop_id: i32,
+    /// 客户（customers.id）
+    pub customer_id: i32,
+    pub attr_key: String,
+    pub attr_value: String,
+    pub updated_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -25,6 +25,12 @@ pub struct Model {
     pub first_visit_at: Option<DateTime>,
     pub last_active_at: Option<DateTime>,
     pub status: Option<i32>,
+
+    // 拉黑信息：status = 0 时有效，blocked_until 为空表示永久
+    pub blocked_reason: Option<String>,
+    pub blocked_until: Option<DateTime>,
+    pub blocked_at: Option<DateTime>,
+    pub blocked_by: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,36 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// 消息编辑历史：每次编辑保存编辑前的内容
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "message_edits")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub message_id: i32,
+    pub session_id: i32,
+    /// staff / customer
+    #[sea_orm(column_type = "String(Some(10))")]
+    pub editor_type: String,
+    pub editor_id: i32,
+    pub previous_content: String,
+    pub edited_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::messages::Entity",
+        from = "Column::MessageId",
+        to = "super::messages::Column::Id"
+    )]
+    Message,
+}
+
+impl Related<super::messages::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Message.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -8,6 +8,12 @@ pub mod messages;
 pub mod shop_staffs;
 pub mod unread_counts;
 pub mod online_status;
+pub mod staff_read_cursors;
+pub mod shop_routing_settings;
+pub mod message_edits;
+pub mod canned_responses;
+pub mod customer_tags;
+pub mod customer_attributes;
 
 pub use users::Entity as Users;
 pub use shops::Entity as Shops;
@@ -28,4 +34,10 @@ pub mod prelude {
     pub use super::shop_staffs::Entity as ShopStaffs;
     pub use super::unread_counts::Entity as UnreadCounts;
     pub use super::online_status::Entity as OnlineStatus;
+    pub use super::staff_read_cursors::Entity as StaffReadCursors;
+    pub use super::shop_routing_settings::Entity as ShopRoutingSettings;
+    pub use super::message_edits::Entity as MessageEdits;
+    pub use super::canned_responses::Entity as CannedResponses;
+    pub use super::customer_tags::Entity as CustomerTags;
+    pub use super::customer_attributes::Entity as CustomerAttributes;
 }
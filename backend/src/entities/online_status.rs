@@ -1,21 +1,28 @@
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
+/// 在线状态：每个 (user_type, user_id, shop_id) 一行，连接/断开时更新
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "online_status")]
 pub struct Model {
     #[sea_orm(primary_key)]
     pub id: i32,
     
-    #[sea_orm(column_type = "String(Some(20))")]
+    /// staff / customer
+    #[sea_orm(column_type = "String(Some(10))")]
     pub user_type: String,
     
+    /// staff 为 users.id，customer 为 customers.id
     pub user_id: i32,
     pub shop_id: Option<i32>,
-    pub is_online: bool,
+    #[sea_orm(column_type = "String(Some(100))")]
+    pub websocket_id: Option<String>,
+    pub last_ping_at: Option<DateTime>,
+    /// online / offline
+    #[sea_orm(column_type = "String(Some(20))")]
+    pub status: Option<String>,
+    /// 最近一次在线时间（断开时写入）
     pub last_seen: Option<DateTime>,
-    pub connection_id: Option<String>,
-    pub updated_at: DateTime,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
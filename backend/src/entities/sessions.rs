@@ -21,6 +21,9 @@ pub struct Model {
     
     // 实际数据库有这个字段
     pub last_message_at: Option<DateTime>,
+
+    // 0-10，数值越大越优先
+    pub priority: i32,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,21 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// 店铺会话自动分配配置：分配策略、客服默认最大并发会话数，
+/// 以及轮询策略上一次分配到的客服（多节点共享轮询位置）。
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "shop_routing_settings")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub shop_id: i32,
+    /// round_robin / least_active / sticky / manual
+    pub strategy: String,
+    pub default_max_concurrent: i32,
+    pub last_assigned_user_id: Option<i32>,
+    pub updated_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
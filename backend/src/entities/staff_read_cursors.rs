@@ -0,0 +1,23 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// 客服个人已读游标：按 (shop_id, customer_id, user_id) 记录该客服读到的最大消息 id，
+/// 用于计算"我的未读"；店铺维度的聚合未读仍在 unread_counts 中维护。
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "staff_read_cursors")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub shop_id: i32,
+    /// 客户（customers.id）
+    pub customer_id: i32,
+    /// 客服（users.id）
+    pub user_id: i32,
+    pub last_read_message_id: i32,
+    pub updated_at: DateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
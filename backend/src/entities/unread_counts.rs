@@ -24,4 +24,5 @@ pub enum Relation {}
 impl ActiveModelBehavior for ActiveModel {}
 // 注意：原设计包含 session_id / user_type / user_id / count / last_message_id 等列，
 // 实际数据库采用 (shop_id, customer_id, unread_count, last_read_message_id) 聚合粒度。
-// 本实体已与现行物理表对齐，若未来需要区分多客服未读，需要新建扩展表或执行数据迁移。
+// 本实体已与现行物理表对齐（unread_count / updated_at 由迁移收紧为 NOT NULL），
+// 多客服各自的未读通过 staff_read_cursors 游标表计算。
@@ -1,5 +1,5 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -16,8 +16,12 @@ pub struct ErrorBody<'a> {
 pub enum AppError {
     Unauthorized,
     Forbidden,
+    /// 店铺要求员工启用双因素认证而当前用户尚未启用
+    TwoFactorRequired,
     NotFound,
     BadRequest(String),
+    /// 触发限流，retry_after_secs 写入 Retry-After 响应头
+    TooManyRequests { retry_after_secs: u64 },
     Internal(String),
 }
 
@@ -26,8 +30,12 @@ impl fmt::Display for AppError {
         match self {
             AppError::Unauthorized => write!(f, "Unauthorized"),
             AppError::Forbidden => write!(f, "Forbidden"),
+            AppError::TwoFactorRequired => write!(f, "Two-factor authentication required"),
             AppError::NotFound => write!(f, "Resource not found"),
             AppError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
+            AppError::TooManyRequests { retry_after_secs } => {
+                write!(f, "Too many requests, retry after {}s", retry_after_secs)
+            }
             AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
         }
     }
@@ -54,6 +62,14 @@ impl IntoResponse for AppError {
                 }),
             )
                 .into_response(),
+            AppError::TwoFactorRequired => (
+                StatusCode::FORBIDDEN,
+                Json(ErrorBody {
+                    code: "TWO_FACTOR_REQUIRED",
+                    message: "Two-factor authentication required",
+                }),
+            )
+                .into_response(),
             AppError::NotFound => (
                 StatusCode::NOT_FOUND,
                 Json(ErrorBody {
@@ -70,6 +86,15 @@ impl IntoResponse for AppError {
                 }),
             )
                 .into_response(),
+            AppError::TooManyRequests { retry_after_secs } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(ErrorBody {
+                    code: "RATE_LIMITED",
+                    message: "Too many requests",
+                }),
+            )
+                .into_response(),
             AppError::Internal(msg) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorBody {
@@ -1,19 +1,42 @@
-use axum::{extract::State, http::StatusCode, Json};
-use chrono::{Duration, Utc};
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
 
 use crate::{
-    auth::jwt_secret_from_env,
-    jwt::{encode_token, Claims},
+    auth::AuthSession,
     models::*,
     error::AppError,
+    rate_limit::Scope,
+    services::{
+        auth_session::{self, TokenPair},
+        login_audit::{self, LoginAttempt},
+        password_reset, two_factor, UserService,
+    },
     AppState,
 };
 
+// Purpose: 用户名密码登录；已启用双因素认证时返回挑战令牌，需再调用 /api/auth/login/2fa
+// Input: LoginRequest { username, password }
+// Output: LoginResponse（AuthResponse 或 TwoFactorChallenge）
+// Errors: 401（用户名或密码错误）、403（账号停用）、429（失败次数过多，带 Retry-After）、500
 pub async fn login(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     Json(payload): Json<LoginRequest>,
-) -> Result<Json<AuthResponse>, StatusCode> {
+) -> Result<Json<LoginResponse>, AppError> {
     tracing::info!("🔍 开始登录处理，用户名: {}", payload.username);
+    let ip = state.rate_limiter.client_ip(&headers, connect_info.map(|info| info.0));
+    let attempt = LoginAttempt {
+        username: &payload.username,
+        ip_address: &ip,
+        user_agent: user_agent(&headers),
+    };
+    login_audit::check_allowed(&state.db, &attempt).await?;
     
     // 使用 UserService 进行身份验证（已添加详细日志）
     let user = match state
@@ -28,49 +51,100 @@ pub async fn login(
         Err(e) => {
             tracing::error!("❌ 用户认证失败: {}", e);
             match e.to_string().as_str() {
-                "用户不存在" | "密码错误" | "invalid_credentials" => return Err(StatusCode::UNAUTHORIZED),
-                "user_inactive" => return Err(StatusCode::FORBIDDEN),
+                "用户不存在" | "密码错误" | "invalid_credentials" => {
+                    login_audit::record(&state.db, &attempt, login_audit::RESULT_INVALID_CREDENTIALS, false).await;
+                    return Err(AppError::Unauthorized);
+                }
+                "user_inactive" => {
+                    login_audit::record(&state.db, &attempt, login_audit::RESULT_USER_INACTIVE, false).await;
+                    return Err(AppError::Forbidden);
+                }
                 _ => {
                     tracing::error!("❌ 认证过程中出现意外错误: {}", e);
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                    return Err(AppError::Internal("authenticate_failed".to_string()));
                 }
             }
         }
     };
 
-    tracing::info!("🔑 开始生成 JWT token");
-    
-    // 生成 JWT
-    let exp = Utc::now()
-        .checked_add_signed(Duration::hours(24))
-        .unwrap()
-        .timestamp() as usize;
-
-    let claims = Claims {
-        sub: user.id.to_string(),
-        exp,
-    };
+    // 已启用双因素认证：先返回挑战令牌，验证码通过后再创建会话
+    let user_id = user.id as i64;
+    if two_factor::is_enabled(&state.db, user_id).await? {
+        tracing::info!("🔐 用户 {} 需要二次验证", user_id);
+        let challenge_token = two_factor::issue_challenge(user_id)?;
+        login_audit::record(&state.db, &attempt, login_audit::RESULT_TWO_FACTOR_PENDING, true).await;
+        return Ok(Json(LoginResponse::TwoFactorRequired(TwoFactorChallenge {
+            two_factor_required: true,
+            challenge_token,
+            expires_in: two_factor::CHALLENGE_TTL_SECS,
+        })));
+    }
 
-    let secret = jwt_secret_from_env();
-    let token = encode_token(&claims, &secret).map_err(|e| {
-        tracing::error!("❌ JWT token 生成失败: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
+    tracing::info!("🔑 开始创建登录会话");
+    let tokens = start_session(&state, &headers, connect_info, user.id as i64)
+        .await
+        .map_err(|_| AppError::Internal("create_session_failed".to_string()))?;
+    login_audit::record(&state.db, &attempt, login_audit::RESULT_SUCCESS, false).await;
 
     tracing::info!("🔄 开始用户数据转换");
-    let user_public = UserPublic::from(user);
-    
-    let response = AuthResponse {
-        token,
-        user: user_public,
-    };
+    let response = auth_response(tokens, UserPublic::from(user));
 
     tracing::info!("✅ 登录处理完成");
-    Ok(Json(response))
+    Ok(Json(LoginResponse::Authenticated(response)))
+}
+
+// Purpose: 登录第二步，用挑战令牌 + 验证码（或恢复码）换取登录会话
+// Input: TwoFactorLoginRequest { challenge_token, code }
+// Output: AuthResponse
+// Errors: 401（挑战令牌无效或过期）、400（验证码错误 / 已使用）、429（按 IP 与按用户限流、失败次数过多）、500
+pub async fn login_two_factor(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Json(payload): Json<TwoFactorLoginRequest>,
+) -> Result<Json<AuthResponse>, AppError> {
+    let user_id = two_factor::verify_challenge(&payload.challenge_token)?;
+    // 同一挑战令牌可在有效期内多次提交，按用户再限流一次防止穷举验证码
+    state
+        .rate_limiter
+        .check(Scope::Login, &format!("2fa:{user_id}"))
+        .map_err(|retry_after_secs| AppError::TooManyRequests { retry_after_secs })?;
+    let user = state
+        .user_service
+        .get_user_info(user_id as i32)
+        .await
+        .map_err(|e| match e.to_string().as_str() {
+            "user_not_found" => AppError::Unauthorized,
+            _ => AppError::Internal(e.to_string()),
+        })?;
+
+    // 验证码错误与密码错误一样计入该用户名的失败次数
+    let ip = state.rate_limiter.client_ip(&headers, connect_info.map(|info| info.0));
+    let attempt = LoginAttempt {
+        username: &user.username,
+        ip_address: &ip,
+        user_agent: user_agent(&headers),
+    };
+    login_audit::check_allowed(&state.db, &attempt).await?;
+    if let Err(e) = two_factor::verify(&state.db, user_id, &payload.code).await {
+        if matches!(&e, AppError::BadRequest(code) if code == "invalid_two_factor_code") {
+            login_audit::record(&state.db, &attempt, login_audit::RESULT_TWO_FACTOR_FAILED, true).await;
+        }
+        return Err(e);
+    }
+
+    let tokens = start_session(&state, &headers, connect_info, user_id)
+        .await
+        .map_err(|_| AppError::Internal("create_session_failed".to_string()))?;
+    login_audit::record(&state.db, &attempt, login_audit::RESULT_SUCCESS, true).await;
+    tracing::info!("✅ 用户 {} 完成二次验证登录", user_id);
+    Ok(Json(auth_response(tokens, user.into())))
 }
 
 pub async fn register(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
     Json(payload): Json<RegisterRequest>,
 ) -> Result<Json<AuthResponse>, StatusCode> {
     println!("注册请求: {:?}", payload);
@@ -99,24 +173,109 @@ pub async fn register(
         }
     };
 
-    // 生成 JWT
-    let exp = Utc::now()
-        .checked_add_signed(Duration::hours(24))
-        .unwrap()
-        .timestamp() as usize;
+    let tokens = start_session(&state, &headers, connect_info, user.id as i64).await?;
+    Ok(Json(auth_response(tokens, user.into())))
+}
+
+// Purpose: 用 refresh token 换取新的 access token，同时轮换 refresh token
+// Input: RefreshTokenRequest { refresh_token }
+// Output: AuthResponse（新的 token / refresh_token，旧 refresh token 立即失效）
+// Errors: 401（令牌无效、过期、已注销或被重放；用户被停用）、500
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshTokenRequest>,
+) -> Result<Json<AuthResponse>, AppError> {
+    let (user_id, tokens) = auth_session::refresh(&state.db, &payload.refresh_token).await?;
+    let user = state
+        .user_service
+        .get_user_info(user_id as i32)
+        .await
+        .map_err(|e| match e.to_string().as_str() {
+            "user_not_found" => AppError::Unauthorized,
+            _ => AppError::Internal(e.to_string()),
+        })?;
+    Ok(Json(auth_response(tokens, user.into())))
+}
 
-    let claims = Claims {
-        sub: user.id.to_string(),
-        exp,
-    };
+// Purpose: 注销当前登录会话（当前 access token 与对应 refresh token 立即失效），并断开以该会话建立的客服 WebSocket 连接
+// Output: 204
+// Errors: 401
+pub async fn logout(State(state): State<AppState>, session: AuthSession) -> Result<StatusCode, AppError> {
+    auth_session::revoke(&state.db, session.user_id, &session.jti).await?;
+    state.connections.lock().unwrap().close_staff_session(session.user_id, &session.jti);
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    let secret = jwt_secret_from_env();
-    let token = encode_token(&claims, &secret).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+// Purpose: 吊销当前用户的全部登录会话（含当前会话），并断开其客服 WebSocket 连接
+// Output: {"revoked": n}
+// Errors: 401、500
+pub async fn revoke_all_sessions(
+    State(state): State<AppState>,
+    session: AuthSession,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let revoked = auth_session::revoke_all(&state.db, session.user_id).await?;
+    state.connections.lock().unwrap().close_staff_user(session.user_id);
+    tracing::info!("🔒 用户 {} 吊销全部登录会话: {}", session.user_id, revoked);
+    Ok(Json(serde_json::json!({ "revoked": revoked })))
+}
 
-    let response = AuthResponse {
-        token,
-        user: user.into(),
-    };
+// Purpose: 找回密码，向账号绑定的邮箱发送一次性重置链接
+// Input: ForgotPasswordRequest { account }（用户名或邮箱）
+// Output: 202（无论账号是否存在都相同，避免枚举账号）
+// Errors: 400（account 为空）、429、500
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let ip = state.rate_limiter.client_ip(&headers, connect_info.map(|info| info.0));
+    password_reset::request_reset(&state.db, state.mailer.clone(), &payload.account, Some(&ip)).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+// Purpose: 用重置令牌设置新密码，成功后吊销该用户全部登录会话并断开其客服 WebSocket 连接
+// Input: ResetPasswordRequest { token, new_password }
+// Output: 204
+// Errors: 400（密码不符合要求、令牌无效 / 已使用 / 已过期）、429、500
+pub async fn reset_password(
+    State(state): State<AppState>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    UserService::validate_password(&payload.new_password).map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let user_id = password_reset::consume_token(&state.db, &payload.token).await?;
+    state
+        .user_service
+        .reset_password(user_id as i32, &payload.new_password)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let revoked = auth_session::revoke_all(&state.db, user_id).await?;
+    state.connections.lock().unwrap().close_staff_user(user_id);
+    tracing::info!("🔑 用户 {} 通过邮件重置密码，吊销登录会话: {}", user_id, revoked);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn start_session(
+    state: &AppState,
+    headers: &HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    user_id: i64,
+) -> Result<TokenPair, StatusCode> {
+    let ip = state.rate_limiter.client_ip(headers, connect_info.map(|info| info.0));
+    auth_session::create_session(&state.db, user_id, user_agent(headers), Some(&ip))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<&str> {
+    headers.get(header::USER_AGENT).and_then(|v| v.to_str().ok())
+}
 
-    Ok(Json(response))
+fn auth_response(tokens: TokenPair, user: UserPublic) -> AuthResponse {
+    AuthResponse {
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        expires_in: tokens.expires_in,
+        user,
+    }
 }
@@ -0,0 +1,119 @@
+use axum::{extract::{Path, Query, State}, Json};
+use serde::Deserialize;
+
+use crate::{
+    error::AppError,
+    services::{
+        canned_response::{self, CannedResponse, CannedResponseInput},
+        permissions::{Capability, ShopAccess},
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    /// shop | personal，为空返回全部可见模板
+    pub scope: Option<String>,
+    pub keyword: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateCannedResponsePayload {
+    /// 默认 personal；shop 需要 manage_settings 能力
+    pub scope: Option<String>,
+    pub title: String,
+    pub shortcut: Option<String>,
+    pub content: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateCannedResponsePayload {
+    pub title: String,
+    pub shortcut: Option<String>,
+    pub content: String,
+}
+
+// Purpose: 列出当前客服可用的快捷回复（店铺公共模板 + 个人模板）
+// Input: shop_id（路径参数）、scope / keyword（查询参数）
+// Output: CannedResponse 列表（按使用次数降序）
+// Errors: 403（非店铺成员）、400（scope 无效）、500
+pub async fn list_canned_responses(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<CannedResponse>>, AppError> {
+    let items = canned_response::list(&state, access.shop_id, access.user_id, query.scope.as_deref(), query.keyword.as_deref())
+        .await
+        .map_err(map_canned_response_error)?;
+    Ok(Json(items))
+}
+
+// Purpose: 创建快捷回复
+// Input: shop_id（路径参数）、CreateCannedResponsePayload
+// Output: 新建的 CannedResponse
+// Errors: 403（非店铺成员，或缺少 manage_settings 时创建公共模板）、400（字段无效）、500
+pub async fn create_canned_response(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Json(payload): Json<CreateCannedResponsePayload>,
+) -> Result<Json<CannedResponse>, AppError> {
+    let scope = payload.scope.as_deref().unwrap_or(canned_response::SCOPE_PERSONAL);
+    let input = CannedResponseInput {
+        title: payload.title,
+        shortcut: payload.shortcut,
+        content: payload.content,
+    };
+    let manage_shop = access.can(Capability::ManageSettings);
+    let created = canned_response::create(&state, access.shop_id, access.user_id, manage_shop, scope, input)
+        .await
+        .map_err(map_canned_response_error)?;
+    Ok(Json(created))
+}
+
+// Purpose: 修改快捷回复
+// Input: shop_id、id（路径参数）、UpdateCannedResponsePayload
+// Output: 更新后的 CannedResponse
+// Errors: 403（非店铺成员，或缺少 manage_settings 时修改公共模板）、404（模板不存在或为他人个人模板）、400、500
+pub async fn update_canned_response(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, id)): Path<(i64, i64)>,
+    Json(payload): Json<UpdateCannedResponsePayload>,
+) -> Result<Json<CannedResponse>, AppError> {
+    let input = CannedResponseInput {
+        title: payload.title,
+        shortcut: payload.shortcut,
+        content: payload.content,
+    };
+    let manage_shop = access.can(Capability::ManageSettings);
+    let updated = canned_response::update(&state, access.shop_id, id, access.user_id, manage_shop, input)
+        .await
+        .map_err(map_canned_response_error)?;
+    Ok(Json(updated))
+}
+
+// Purpose: 删除快捷回复
+// Input: shop_id、id（路径参数）
+// Output: {"ok": true}
+// Errors: 403、404、500
+pub async fn delete_canned_response(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, id)): Path<(i64, i64)>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let manage_shop = access.can(Capability::ManageSettings);
+    canned_response::delete(&state, access.shop_id, id, access.user_id, manage_shop)
+        .await
+        .map_err(map_canned_response_error)?;
+    Ok(Json(serde_json::json!({"ok": true})))
+}
+
+fn map_canned_response_error(e: anyhow::Error) -> AppError {
+    let msg = e.to_string();
+    match msg.as_str() {
+        "canned_response_not_found" => AppError::NotFound,
+        "permission_denied" => AppError::Forbidden,
+        "invalid_scope" | "invalid_title" | "invalid_shortcut" | "invalid_content" => AppError::BadRequest(msg),
+        _ => AppError::Internal(msg),
+    }
+}
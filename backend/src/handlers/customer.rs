@@ -4,8 +4,12 @@ use axum::{
 };
 use serde::Deserialize;
 
-use crate::{auth::AuthUser, error::AppError, models::*, AppState};
-use crate::services::permissions as perms;
+use crate::{error::AppError, models::*, AppState};
+use crate::entities::{customers, messages, sessions};
+use crate::services::{
+    customer_service::{BlockInfo, CustomerListFilter, TagUsage},
+    permissions::{Capability, ShopAccess},
+};
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct CustomerListQuery {
@@ -17,20 +21,42 @@ pub(crate) struct CustomerListQuery {
     pub keyword: Option<String>,
     #[serde(default, alias = "order", alias = "sortBy")]
     pub sort: Option<String>, // 支持: last_active_desc(默认) | name_asc | name_desc
+    /// 逗号分隔，需全部命中
+    #[serde(default, alias = "tag")]
+    pub tags: Option<String>,
+    #[serde(default, alias = "attrKey")]
+    pub attr_key: Option<String>,
+    /// 为空时只要求存在该属性
+    #[serde(default, alias = "attrValue")]
+    pub attr_value: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateTagsRequest {
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct BlockCustomerRequest {
+    pub reason: Option<String>,
+    /// RFC3339 到期时间；与 durationMinutes 都为空表示永久拉黑
+    #[serde(default, alias = "expiresAt")]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, alias = "durationMinutes")]
+    pub duration_minutes: Option<i64>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct UpdateAttributesRequest {
+    pub attributes: serde_json::Map<String, serde_json::Value>,
+}
+
+type CustomerOverviewRow = (customers::Model, Option<sessions::Model>, Option<messages::Model>, i64);
+
 pub async fn get_customers(
     State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Path(shop_id): Path<i64>,
+    ShopAccess { user_id, shop_id, .. }: ShopAccess,
 ) -> Result<Json<Vec<CustomerWithSession>>, AppError> {
-    // SQLx 权限校验（店主或成员）
-    if let Err(e) = perms::ensure_member_or_owner_sqlx(&state.db, user_id, shop_id).await {
-        return match e {
-            AppError::Unauthorized => Err(AppError::Forbidden),
-            other => Err(other),
-        };
-    }
     eprintln!("🔍 get_customers: user_id={}, shop_id={}", user_id, shop_id);
     
     // 🔧 修复：使用完整的客户概览查询（包含 last_message 和 unread_count）
@@ -51,17 +77,7 @@ pub async fn get_customers(
     eprintln!("✅ 查询到 {} 个客户（含完整信息）", customers_raw.len());
     
     // 将 (customers::Model, Option<sessions::Model>, Option<messages::Model>, i64) 转换为 CustomerWithSession
-    let customer_sessions: Vec<CustomerWithSession> = customers_raw
-        .into_iter()
-        .map(|(customer, session, last_message, unread)| {
-            CustomerWithSession {
-                customer: customer.into(),
-                session: session.map(|s| s.into()),
-                last_message: last_message.map(|m| m.into()),
-                unread_count: unread as i32,
-            }
-        })
-        .collect();
+    let customer_sessions = with_traits(&state, customers_raw).await?;
     
     eprintln!("✅ 成功转换 {} 个客户响应", customer_sessions.len());
     Ok(Json(customer_sessions))
@@ -70,17 +86,9 @@ pub async fn get_customers(
 /// 分页获取客户概览（含最后消息与未读）
 pub async fn get_customers_paged(
     State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Path(shop_id): Path<i64>,
+    ShopAccess { user_id, shop_id, .. }: ShopAccess,
     Query(q): Query<CustomerListQuery>,
 ) -> Result<Json<PageResult<CustomerWithSession>>, AppError> {
-    // SQLx 权限校验（店主或成员）
-    if let Err(e) = perms::ensure_member_or_owner_sqlx(&state.db, user_id, shop_id).await {
-        return match e {
-            AppError::Unauthorized => Err(AppError::Forbidden),
-            other => Err(other),
-        };
-    }
     let mut limit = q.limit.unwrap_or(50);
     let mut offset = q.offset.unwrap_or(0);
     if limit <= 0 { limit = 50; }
@@ -94,7 +102,16 @@ pub async fn get_customers_paged(
             shop_id.try_into().unwrap(),
             limit,
             offset,
-            q.keyword.clone(),
+            CustomerListFilter {
+                keyword: q.keyword.clone(),
+                tags: q
+                    .tags
+                    .as_deref()
+                    .map(|raw| raw.split(',').map(str::trim).filter(|t| !t.is_empty()).map(String::from).collect())
+                    .unwrap_or_default(),
+                attr_key: q.attr_key.clone(),
+                attr_value: q.attr_value.clone(),
+            },
             q.sort.clone(),
         )
         .await
@@ -103,14 +120,7 @@ pub async fn get_customers_paged(
             if msg.contains("access_denied") { AppError::Forbidden } else { AppError::Internal(msg) }
         })?;
 
-    let items: Vec<CustomerWithSession> = items_raw.into_iter().map(|(customer, session, last_message, unread)| {
-        CustomerWithSession {
-            customer: customer.into(),
-            session: session.map(|s| s.into()),
-            last_message: last_message.map(|m| m.into()),
-            unread_count: unread as i32,
-        }
-    }).collect();
+    let items = with_traits(&state, items_raw).await?;
 
     Ok(Json(PageResult { items, total, limit, offset }))
 }
@@ -118,16 +128,9 @@ pub async fn get_customers_paged(
 // 标记为已读：将某店铺下某客户的未读数清零（仅店主可操作）
 pub async fn reset_unread(
     State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Path((shop_id, customer_id)): Path<(i64, i64)>,
+    ShopAccess { user_id, shop_id, .. }: ShopAccess,
+    Path((_, customer_id)): Path<(i64, i64)>,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    // SQLx 权限校验（店主或成员）
-    if let Err(e) = perms::ensure_member_or_owner_sqlx(&state.db, user_id, shop_id).await {
-        return match e {
-            AppError::Unauthorized => Err(AppError::Forbidden),
-            other => Err(other),
-        };
-    }
     match state
         .session_service
         .reset_unread_count(user_id, shop_id.try_into().unwrap(), customer_id.try_into().unwrap())
@@ -141,16 +144,8 @@ pub async fn reset_unread(
 // 批量标记已读：将某店铺下所有客户的未读数清零（仅店主可操作）
 pub async fn reset_unread_all(
     State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Path(shop_id): Path<i64>,
+    ShopAccess { user_id, shop_id, .. }: ShopAccess,
 ) -> Result<Json<serde_json::Value>, AppError> {
-    // SQLx 权限校验（店主或成员）
-    if let Err(e) = perms::ensure_member_or_owner_sqlx(&state.db, user_id, shop_id).await {
-        return match e {
-            AppError::Unauthorized => Err(AppError::Forbidden),
-            other => Err(other),
-        };
-    }
     match state
         .session_service
         .reset_all_unread_in_shop(user_id, shop_id.try_into().unwrap())
@@ -160,3 +155,185 @@ pub async fn reset_unread_all(
         Err(e) => Err(AppError::Internal(e.to_string())),
     }
 }
+
+// 客服个人未读：按自己的已读游标统计各客户未读数
+pub async fn get_my_unread(
+    State(state): State<AppState>,
+    ShopAccess { user_id, shop_id, .. }: ShopAccess,
+) -> Result<Json<serde_json::Value>, AppError> {
+    let rows = state
+        .session_service
+        .staff_unread_by_shop(user_id, shop_id)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+
+    let total: i64 = rows.iter().map(|(_, unread)| unread).sum();
+    let items: Vec<serde_json::Value> = rows
+        .into_iter()
+        .map(|(customer_id, unread)| serde_json::json!({"customer_id": customer_id, "unread_count": unread}))
+        .collect();
+    Ok(Json(serde_json::json!({"total": total, "items": items})))
+}
+
+// 店铺在线状态：客服与客户的在线/离线及最近在线时间
+pub async fn get_shop_presence(
+    State(state): State<AppState>,
+    ShopAccess { shop_id, .. }: ShopAccess,
+) -> Result<Json<crate::services::presence::ShopPresence>, AppError> {
+    let presence = crate::services::presence::shop_presence(&state, shop_id)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(Json(presence))
+}
+
+// Purpose: 店铺内使用中的客户标签（用于筛选下拉）
+// Input: shop_id（路径参数）
+// Output: [{ tag, customers }]（按客户数降序）
+// Errors: 403（非店铺成员）、500
+pub async fn get_shop_tags(
+    State(state): State<AppState>,
+    ShopAccess { shop_id, .. }: ShopAccess,
+) -> Result<Json<Vec<TagUsage>>, AppError> {
+    let tags = state
+        .customer_service
+        .list_shop_tags(shop_id as i32)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(Json(tags))
+}
+
+// Purpose: 设置客户标签（整体替换）
+// Input: shop_id、customer_id（路径参数）、UpdateTagsRequest { tags }
+// Output: {"tags": [...]}（去重排序后）
+// Errors: 403（缺少 manage_customers）、404（客户不属于该店铺）、400（标签无效或过多）、500
+pub async fn update_customer_tags(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, customer_id)): Path<(i64, i64)>,
+    Json(payload): Json<UpdateTagsRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    access.require(Capability::ManageCustomers)?;
+    let shop_id = access.shop_id;
+    let tags = state
+        .customer_service
+        .set_customer_tags(shop_id as i32, customer_id as i32, payload.tags)
+        .await
+        .map_err(map_customer_traits_error)?;
+    Ok(Json(serde_json::json!({ "tags": tags })))
+}
+
+// Purpose: 更新客户自定义属性（按键合并，值为 null 删除该键）
+// Input: shop_id、customer_id（路径参数）、UpdateAttributesRequest { attributes }
+// Output: {"attributes": {...}}（合并后的全部属性）
+// Errors: 403（缺少 manage_customers）、404（客户不属于该店铺）、400（键/值无效或属性过多）、500
+pub async fn update_customer_attributes(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, customer_id)): Path<(i64, i64)>,
+    Json(payload): Json<UpdateAttributesRequest>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    access.require(Capability::ManageCustomers)?;
+    let shop_id = access.shop_id;
+    let attributes = state
+        .customer_service
+        .update_customer_attributes(shop_id as i32, customer_id as i32, payload.attributes)
+        .await
+        .map_err(map_customer_traits_error)?;
+    Ok(Json(serde_json::json!({ "attributes": attributes })))
+}
+
+// Purpose: 拉黑客户，并断开其在线连接
+// Input: shop_id、customer_id（路径参数）、BlockCustomerRequest { reason?, expiresAt? | durationMinutes? }（可省略请求体）
+// Output: 更新后的 Customer
+// Errors: 403（缺少 manage_customers）、404（客户不属于该店铺）、400（到期时间无效或原因过长）、500
+pub async fn block_customer(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, customer_id)): Path<(i64, i64)>,
+    payload: Option<Json<BlockCustomerRequest>>,
+) -> Result<Json<Customer>, AppError> {
+    access.require(Capability::ManageCustomers)?;
+    let (user_id, shop_id) = (access.user_id, access.shop_id);
+    let Json(payload) = payload.unwrap_or_default();
+    let until = match (payload.expires_at, payload.duration_minutes) {
+        (Some(_), Some(_)) => return Err(AppError::BadRequest("invalid_block_until".into())),
+        (Some(at), None) => Some(at),
+        (None, Some(minutes)) if minutes > 0 => Some(chrono::Utc::now() + chrono::Duration::minutes(minutes)),
+        (None, Some(_)) => return Err(AppError::BadRequest("invalid_block_until".into())),
+        (None, None) => None,
+    };
+    let customer = state
+        .customer_service
+        .block_customer(shop_id as i32, customer_id as i32, user_id as i32, payload.reason, until)
+        .await
+        .map_err(map_customer_block_error)?;
+
+    let block = BlockInfo {
+        reason: customer.blocked_reason.clone(),
+        blocked_until: customer.blocked_until.map(|t| t.and_utc()),
+    };
+    {
+        let mut manager = state.connections.lock().unwrap();
+        manager.send_to_customer(shop_id, &customer.customer_id, &crate::websocket::blocked_event(&block));
+        manager.close_customer(shop_id, &customer.customer_id);
+    }
+    Ok(Json(customer.into()))
+}
+
+// Purpose: 解除客户拉黑
+// Input: shop_id、customer_id（路径参数）
+// Output: 更新后的 Customer
+// Errors: 403（缺少 manage_customers）、404（客户不属于该店铺）、500
+pub async fn unblock_customer(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, customer_id)): Path<(i64, i64)>,
+) -> Result<Json<Customer>, AppError> {
+    access.require(Capability::ManageCustomers)?;
+    let shop_id = access.shop_id;
+    let customer = state
+        .customer_service
+        .unblock_customer(shop_id as i32, customer_id as i32)
+        .await
+        .map_err(map_customer_block_error)?;
+    Ok(Json(customer.into()))
+}
+
+/// 组装客户列表项，并附带标签与属性
+async fn with_traits(state: &AppState, rows: Vec<CustomerOverviewRow>) -> Result<Vec<CustomerWithSession>, AppError> {
+    let ids: Vec<i32> = rows.iter().map(|(customer, ..)| customer.id).collect();
+    let (mut tags, mut attributes) = state
+        .customer_service
+        .get_customer_traits(&ids)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(rows
+        .into_iter()
+        .map(|(customer, session, last_message, unread)| CustomerWithSession {
+            tags: tags.remove(&customer.id).unwrap_or_default(),
+            attributes: attributes.remove(&customer.id).unwrap_or_default(),
+            customer: customer.into(),
+            session: session.map(|s| s.into()),
+            last_message: last_message.map(|m| m.into()),
+            unread_count: unread as i32,
+        })
+        .collect())
+}
+
+fn map_customer_traits_error(e: anyhow::Error) -> AppError {
+    let msg = e.to_string();
+    match msg.as_str() {
+        "customer_not_found" => AppError::NotFound,
+        "invalid_tag" | "too_many_tags" | "invalid_attribute" | "too_many_attributes" => AppError::BadRequest(msg),
+        _ => AppError::Internal(msg),
+    }
+}
+
+fn map_customer_block_error(e: anyhow::Error) -> AppError {
+    let msg = e.to_string();
+    match msg.as_str() {
+        "customer_not_found" => AppError::NotFound,
+        "invalid_block_until" | "invalid_block_reason" => AppError::BadRequest(msg),
+        _ => AppError::Internal(msg),
+    }
+}
@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::{
+    auth::AuthUser,
+    error::AppError,
+    services::{
+        login_audit::{self, LoginEvent},
+        permissions::ShopAccess,
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct LoginEventQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// 仅店铺查询使用：按员工过滤
+    pub user_id: Option<i64>,
+}
+
+impl LoginEventQuery {
+    fn page(&self) -> (i64, i64) {
+        (self.limit.unwrap_or(50).clamp(1, 200), self.offset.unwrap_or(0).max(0))
+    }
+}
+
+// Purpose: 当前用户的登录记录（含失败、被限制与二次验证），按时间倒序
+// Input: limit（默认 50，最大 200）、offset
+// Output: Vec<LoginEvent>
+// Errors: 401、500
+pub async fn list_my_login_events(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Query(q): Query<LoginEventQuery>,
+) -> Result<Json<Vec<LoginEvent>>, AppError> {
+    let (limit, offset) = q.page();
+    Ok(Json(login_audit::list_for_user(&state.db, user_id, limit, offset).await?))
+}
+
+// Purpose: 店主查看本店员工的登录记录
+// Input: shop_id（路径参数）、user_id（可选，按员工过滤）、limit、offset
+// Output: Vec<LoginEvent>
+// Errors: 401、403（非店主）、404、500
+pub async fn list_shop_staff_login_events(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Query(q): Query<LoginEventQuery>,
+) -> Result<Json<Vec<LoginEvent>>, AppError> {
+    if !access.is_owner() {
+        return Err(AppError::Forbidden);
+    }
+    let (limit, offset) = q.page();
+    Ok(Json(
+        login_audit::list_for_shop_staff(&state.db, access.shop_id, q.user_id, limit, offset).await?,
+    ))
+}
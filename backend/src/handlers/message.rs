@@ -1,11 +1,18 @@
 use axum::{
     extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
     Json,
 };
 use serde::Deserialize;
 
-use crate::{auth::AuthUser, error::AppError, models::*, services::chat::ChatService, AppState};
-use crate::services::permissions as perms;
+use crate::{auth::AuthUser, entities::{message_edits, messages, sessions}, error::AppError, models::*, services::chat::ChatService, AppState};
+use crate::services::{
+    chat::broadcast_message_change,
+    message_service::{MessageAuthor, MessageSearchHit, MessageSearchQuery, StaffMessageInput},
+    permissions::{self as perms, Capability, ShopAccess},
+};
+use crate::constants::ws_events;
 
 #[derive(Deserialize)]
 pub struct PageQuery {
@@ -19,16 +26,14 @@ pub async fn get_messages(
     Path(session_id): Path<i64>,
     Query(p): Query<PageQuery>,
 ) -> Result<Json<Vec<Message>>, AppError> {
-    // SQLx 权限校验：根据 session_id 解析 shop_id，再判定是否为店主或成员
+    // SQLx 权限校验：根据 session_id 解析 shop_id；无 view_all_sessions 时只能查看分配给自己或未分配的会话
     let session = crate::repositories::SessionRepository::find_by_id(&state.db_connection, session_id as i32)
         .await
         .map_err(|e| AppError::Internal(e.to_string()))?
         .ok_or(AppError::NotFound)?;
-    if let Err(e) = perms::ensure_member_or_owner_sqlx(&state.db, user_id, session.shop_id as i64).await {
-        return match e {
-            AppError::Unauthorized => Err(AppError::Forbidden),
-            other => Err(other),
-        };
+    let access = perms::require_access(&state.db, user_id, session.shop_id as i64).await?;
+    if !access.can_view_session(session.staff_id.map(i64::from)) {
+        return Err(AppError::Forbidden);
     }
     let limit = p.limit.unwrap_or(50);
     let offset = p.offset.unwrap_or(0);
@@ -62,22 +67,83 @@ pub async fn get_messages(
     }
 }
 
+// Purpose: 导出会话聊天记录（CSV 附件，含内部备注，不含已删除消息）
+// Input: session_id（路径参数）
+// Output: text/csv；列为 id、created_at、sender_type、sender_id、sender_name、message_type、content、file_url
+// Errors: 404（会话不存在）、403（缺少 export 或无权查看该会话）、500
+pub async fn export_session_messages(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(session_id): Path<i64>,
+) -> Result<Response, AppError> {
+    let session = crate::repositories::SessionRepository::find_by_id(&state.db_connection, session_id as i32)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .ok_or(AppError::NotFound)?;
+    let access = perms::require_access(&state.db, user_id, session.shop_id as i64).await?;
+    access.require(Capability::Export)?;
+    if !access.can_view_session(session.staff_id.map(i64::from)) {
+        return Err(AppError::Forbidden);
+    }
+    let messages = crate::repositories::MessageRepository::find_by_session(&state.db_connection, session_id as i32, None)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+
+    let mut csv = String::from("id,created_at,sender_type,sender_id,sender_name,message_type,content,file_url\r\n");
+    for m in &messages {
+        let row = [
+            m.id.to_string(),
+            m.created_at.and_utc().to_rfc3339(),
+            m.sender_type.clone(),
+            m.sender_id.map(|id| id.to_string()).unwrap_or_default(),
+            m.sender_name.clone().unwrap_or_default(),
+            m.message_type.clone(),
+            m.content.clone(),
+            m.file_url.clone().unwrap_or_default(),
+        ];
+        csv.push_str(&row.iter().map(|cell| csv_cell(cell)).collect::<Vec<_>>().join(","));
+        csv.push_str("\r\n");
+    }
+    tracing::info!("📤 用户 {} 导出会话 {} 的 {} 条消息", user_id, session_id, messages.len());
+
+    let disposition = format!("attachment; filename=\"session-{}.csv\"", session_id);
+    // 带 BOM，便于 Excel 按 UTF-8 打开
+    Ok((
+        [(header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+        format!("\u{feff}{csv}"),
+    )
+        .into_response())
+}
+
+/// CSV 单元格：含逗号 / 引号 / 换行时加引号；以 = + - @ 开头时前置单引号，防止表格软件按公式执行
+fn csv_cell(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
 pub async fn send_message(
     State(state): State<AppState>,
     Path(session_id): Path<i64>,
     AuthUser { user_id }: AuthUser,
     Json(payload): Json<SendMessageRequest>,
 ) -> Result<Json<Message>, AppError> {
-    // SQLx 权限校验：解析会话所属店铺，验证是否成员/店主
+    // SQLx 权限校验：解析会话所属店铺，验证 send_messages 能力
     let session = crate::repositories::SessionRepository::find_by_id(&state.db_connection, session_id as i32)
         .await
         .map_err(|e| AppError::Internal(e.to_string()))?
         .ok_or(AppError::NotFound)?;
-    if let Err(e) = perms::ensure_member_or_owner_sqlx(&state.db, user_id, session.shop_id as i64).await {
-        return match e {
-            AppError::Unauthorized => Err(AppError::Forbidden),
-            other => Err(other),
-        };
+    let access = perms::require_access(&state.db, user_id, session.shop_id as i64).await?;
+    access.require(Capability::SendMessages)?;
+    if !access.can_view_session(session.staff_id.map(i64::from)) {
+        return Err(AppError::Forbidden);
     }
     let message_type = payload
         .message_type
@@ -93,33 +159,49 @@ pub async fn send_message(
             user_id.try_into().unwrap(),
             session_id,
             &payload.content,
-            payload.message_type.clone(),
-            payload.file_url.clone(),
-            payload.file_name.clone(),
+            StaffMessageInput {
+                message_type: payload.message_type.clone(),
+                file_url: payload.file_url.clone(),
+                file_name: payload.file_name.clone(),
+                reply_to: payload.reply_to,
+                rich_content: payload.rich_content.clone(),
+            },
         )
         .await
     {
         Ok(message) => {
             eprintln!("✅ 消息创建成功，准备广播");
             
+            // 引用回复附带被引用消息的精简预览
+            let mut metadata = serde_json::json!({
+                "messageType": message_type,
+            });
+            if let Some(reply_to) = message.reply_to {
+                if let Ok(Some(target)) = crate::repositories::MessageRepository::find_by_id(&state.db_connection, reply_to).await {
+                    metadata["replyTo"] = crate::services::chat::reply_preview(&target);
+                }
+            }
+            if let Some(rich) = message.rich_content.clone() {
+                metadata["richContent"] = rich;
+            }
+
             // 构建WebSocket消息
             let ws_message = crate::models::WebSocketMessage {
                 message_type: "new_message".to_string(),
-                content: Some(payload.content.clone()),
+                content: Some(message.content.clone()),
                 session_id: Some(session_id),
                 sender_id: Some(user_id),
                 sender_type: Some("staff".to_string()),
                 timestamp: Some(chrono::Utc::now()),
-                metadata: Some(serde_json::json!({
-                    "messageType": message_type,
-                })),
+                metadata: Some(metadata),
                 file_url: payload.file_url.clone(),
                 file_name: payload.file_name.clone(),
                 file_size: None,
                 media_duration: None,
+                message_id: Some(message.id as i64),
             };
             
-            // 广播给所有店铺客服（包括自己）
+            // 广播给能查看该会话的店铺客服（包括自己）
             if let Ok(sessions) = crate::repositories::SessionRepository::find_by_id(
                 &state.db_connection,
                 session_id as i32
@@ -127,7 +209,7 @@ pub async fn send_message(
                 if let Some(session) = sessions {
                     eprintln!("📡 广播消息到店铺 {}", session.shop_id);
                     state.connections.lock().unwrap()
-                        .broadcast_to_staff(session.shop_id as i64, &ws_message);
+                        .broadcast_to_session_staff(session.shop_id as i64, session.staff_id.map(i64::from), &ws_message);
                     
                     // 获取客户信息以发送消息
                     if let Ok(Some(customer)) = crate::repositories::CustomerRepository::find_by_id(
@@ -149,7 +231,308 @@ pub async fn send_message(
         }
         Err(e) => {
             eprintln!("❌ send_message 错误: {:?}", e);
-            Err(AppError::Internal(e.to_string()))
+            let msg = e.to_string();
+            match msg.as_str() {
+                "invalid_reply_to"
+                | "invalid_rich_content"
+                | "missing_rich_content"
+                | "unexpected_rich_content"
+                | "rich_type_not_allowed" => Err(AppError::BadRequest(msg)),
+                _ => Err(AppError::Internal(msg)),
+            }
         }
     }
 }
+
+#[derive(Deserialize)]
+pub struct CreateNoteRequest {
+    pub content: String,
+    #[serde(default, alias = "replyTo")]
+    pub reply_to: Option<i64>,
+}
+
+// Purpose: 在会话中添加内部备注（交接说明等），客户不可见
+// Input: session_id（路径参数）、CreateNoteRequest { content, reply_to }
+// Output: 新建的 Message（sender_type = note）；仅向店铺客服推送 new_message
+// Errors: 404（会话不存在）、403（缺少 send_messages 或无权查看该会话）、400（内容为空/过长、引用消息无效）
+pub async fn create_note(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(session_id): Path<i64>,
+    Json(payload): Json<CreateNoteRequest>,
+) -> Result<Json<Message>, AppError> {
+    let chat = ChatService::new(&state);
+    let (session, customer) = chat
+        .resolve_session(session_id)
+        .await
+        .map_err(|_| AppError::NotFound)?;
+    let shop_id = session.shop_id as i64;
+    let session_staff_id = session.staff_id.map(i64::from);
+    let access = perms::require_access(&state.db, user_id, shop_id).await?;
+    access.require(Capability::SendMessages)?;
+    if !access.can_view_session(session_staff_id) {
+        return Err(AppError::Forbidden);
+    }
+
+    let persisted = chat
+        .persist_note(&session.into(), user_id, payload.content, payload.reply_to, &customer.into())
+        .await
+        .map_err(|e| {
+            let msg = e.to_string();
+            match msg.as_str() {
+                "message_content_empty" | "message_content_too_long" | "invalid_reply_to" => AppError::BadRequest(msg),
+                _ => AppError::Internal(msg),
+            }
+        })?;
+
+    // 备注只推送给店铺客服，绝不发送给客户
+    state
+        .connections
+        .lock()
+        .unwrap()
+        .broadcast_to_session_staff(shop_id, session_staff_id, &persisted.ws_message);
+    Ok(Json(persisted.message))
+}
+
+#[derive(Deserialize)]
+pub struct EditMessageRequest {
+    pub content: String,
+}
+
+#[derive(Deserialize)]
+pub struct BatchDeleteRequest {
+    #[serde(alias = "messageIds")]
+    pub message_ids: Vec<i64>,
+}
+
+// Purpose: 客服编辑自己发送的文本消息（发送后时限内）
+// Input: message_id（路径参数）、EditMessageRequest { content }
+// Output: 编辑后的 Message；同时向客户与店铺客服推送 message_edited
+// Errors: 404（消息不存在/已撤回）、403（缺少 send_messages/无权查看该会话/非发送者）、400（超出编辑时限、非文本消息、内容为空或未变化）
+pub async fn edit_message(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(message_id): Path<i64>,
+    Json(payload): Json<EditMessageRequest>,
+) -> Result<Json<Message>, AppError> {
+    authorize_message(&state, user_id, message_id, Some(Capability::SendMessages)).await?;
+    let message = state
+        .message_service
+        .edit_message(MessageAuthor::Staff(user_id), message_id, &payload.content)
+        .await
+        .map_err(map_message_error)?;
+    broadcast_message_change(&state, ws_events::MESSAGE_EDITED, &message, ("staff", user_id)).await;
+    Ok(Json(message.into()))
+}
+
+// Purpose: 客服撤回自己发送的消息（发送后时限内）
+// Input: message_id（路径参数）
+// Output: 撤回后的 Message（status = deleted）；同时推送 message_recalled
+// Errors: 404、403（缺少 send_messages/无权查看该会话/非发送者）、400（超出撤回时限）
+pub async fn recall_message(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(message_id): Path<i64>,
+) -> Result<Json<Message>, AppError> {
+    authorize_message(&state, user_id, message_id, Some(Capability::SendMessages)).await?;
+    let message = state
+        .message_service
+        .recall_message(MessageAuthor::Staff(user_id), message_id)
+        .await
+        .map_err(map_message_error)?;
+    broadcast_message_change(&state, ws_events::MESSAGE_RECALLED, &message, ("staff", user_id)).await;
+    Ok(Json(message.into()))
+}
+
+// Purpose: 删除店铺内任意消息（不受发送者与时限限制，需要 delete_messages 能力）
+// Input: message_id（路径参数）
+// Output: 删除后的 Message；同时推送 message_recalled
+// Errors: 404、403（缺少 delete_messages）
+pub async fn delete_message(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(message_id): Path<i64>,
+) -> Result<Json<Message>, AppError> {
+    let (_, session) = load_message(&state, message_id).await?;
+    ensure_can_delete(&state, user_id, session.shop_id as i64).await?;
+    let message = state
+        .message_service
+        .delete_message(message_id)
+        .await
+        .map_err(map_message_error)?;
+    broadcast_message_change(&state, ws_events::MESSAGE_RECALLED, &message, ("staff", user_id)).await;
+    Ok(Json(message.into()))
+}
+
+// Purpose: 批量删除消息
+// Input: BatchDeleteRequest { message_ids }（所有消息所属店铺都须具备 delete_messages 能力）
+// Output: 实际被删除的消息列表（已删除的跳过）；逐条推送 message_recalled
+// Errors: 404（任一消息不存在）、403（任一消息所属店铺缺少 delete_messages）
+pub async fn delete_messages_batch(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<BatchDeleteRequest>,
+) -> Result<Json<Vec<Message>>, AppError> {
+    // 先校验全部消息的归属，避免部分删除后才发现越权
+    let mut checked_shops = Vec::new();
+    for message_id in &payload.message_ids {
+        let (_, session) = load_message(&state, *message_id).await?;
+        let shop_id = session.shop_id as i64;
+        if !checked_shops.contains(&shop_id) {
+            ensure_can_delete(&state, user_id, shop_id).await?;
+            checked_shops.push(shop_id);
+        }
+    }
+    let deleted = state
+        .message_service
+        .delete_messages_batch(&payload.message_ids)
+        .await
+        .map_err(map_message_error)?;
+    for message in &deleted {
+        broadcast_message_change(&state, ws_events::MESSAGE_RECALLED, message, ("staff", user_id)).await;
+    }
+    Ok(Json(deleted.into_iter().map(Message::from).collect()))
+}
+
+// Purpose: 查看消息的编辑历史
+// Input: message_id（路径参数）
+// Output: 编辑记录列表（previous_content 为每次编辑前的内容，最早的在前）
+// Errors: 404、403（非店铺成员或无权查看该会话）
+pub async fn get_message_edits(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(message_id): Path<i64>,
+) -> Result<Json<Vec<message_edits::Model>>, AppError> {
+    authorize_message(&state, user_id, message_id, None).await?;
+    let edits = state
+        .message_service
+        .get_edit_history(message_id)
+        .await
+        .map_err(map_message_error)?;
+    Ok(Json(edits))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default, alias = "keyword")]
+    pub q: Option<String>,
+    /// YYYY-MM-DD 或 RFC3339
+    pub from: Option<String>,
+    /// YYYY-MM-DD（含当天）或 RFC3339（不含该时刻）
+    pub to: Option<String>,
+    #[serde(default, alias = "senderType")]
+    pub sender_type: Option<String>,
+    #[serde(default, alias = "messageType")]
+    pub message_type: Option<String>,
+    #[serde(default, alias = "staffId")]
+    pub staff_id: Option<i64>,
+    #[serde(default, alias = "pageSize")]
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+// Purpose: 店铺内全文搜索消息（内容与客户名），支持日期范围、发送方、消息类型、客服过滤
+// Input: shop_id（路径参数）、SearchQuery { q, from, to, sender_type, message_type, staff_id, limit, offset }
+// Output: PageResult<MessageSearchHit>（按消息 id 倒序，content_highlight 为带 <mark> 的转义片段）
+// Errors: 403（缺少 view_all_sessions）、400（关键词为空、日期格式/范围无效、sender_type 无效）
+pub async fn search_shop_messages(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Query(q): Query<SearchQuery>,
+) -> Result<Json<PageResult<MessageSearchHit>>, AppError> {
+    // 搜索覆盖店铺内全部会话
+    access.require(Capability::ViewAllSessions)?;
+    let shop_id = access.shop_id;
+    let mut limit = q.limit.unwrap_or(20);
+    let mut offset = q.offset.unwrap_or(0);
+    if limit <= 0 { limit = 20; }
+    if limit > 100 { limit = 100; }
+    if offset < 0 { offset = 0; }
+
+    let query = MessageSearchQuery {
+        shop_id,
+        keyword: q.q.unwrap_or_default(),
+        from: q.from.as_deref().map(|v| parse_search_time(v, false)).transpose()?,
+        to: q.to.as_deref().map(|v| parse_search_time(v, true)).transpose()?,
+        sender_type: q.sender_type.filter(|v| !v.is_empty()),
+        message_type: q.message_type.filter(|v| !v.is_empty()),
+        staff_id: q.staff_id,
+        limit: limit as u64,
+        offset: offset as u64,
+    };
+    let (items, total) = state
+        .message_service
+        .search_messages(query)
+        .await
+        .map_err(|e| {
+            let msg = e.to_string();
+            match msg.as_str() {
+                "keyword_empty" | "invalid_sender_type" | "invalid_date_range" => AppError::BadRequest(msg),
+                _ => AppError::Internal(msg),
+            }
+        })?;
+    Ok(Json(PageResult { items, total, limit, offset }))
+}
+
+/// 解析搜索时间（UTC）：纯日期作为上界时取次日零点，使当天整天包含在内
+fn parse_search_time(value: &str, upper_bound: bool) -> Result<chrono::NaiveDateTime, AppError> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.naive_utc());
+    }
+    let date = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| AppError::BadRequest("invalid_date".to_string()))?;
+    let date = if upper_bound { date.succ_opt().unwrap_or(date) } else { date };
+    Ok(date.and_time(chrono::NaiveTime::MIN))
+}
+
+/// 加载消息及其所属店铺
+async fn load_message(state: &AppState, message_id: i64) -> Result<(messages::Model, sessions::Model), AppError> {
+    let message = crate::repositories::MessageRepository::find_by_id(&state.db_connection, message_id as i32)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .ok_or(AppError::NotFound)?;
+    let session = crate::repositories::SessionRepository::find_by_id(&state.db_connection, message.session_id)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .ok_or(AppError::NotFound)?;
+    Ok((message, session))
+}
+
+/// 加载消息并校验操作者能查看其所在会话；capability 为 Some 时还需具备该能力
+async fn authorize_message(
+    state: &AppState,
+    user_id: i64,
+    message_id: i64,
+    capability: Option<Capability>,
+) -> Result<messages::Model, AppError> {
+    let (message, session) = load_message(state, message_id).await?;
+    let access = perms::require_access(&state.db, user_id, session.shop_id as i64).await?;
+    if !access.can_view_session(session.staff_id.map(i64::from)) {
+        return Err(AppError::Forbidden);
+    }
+    if let Some(capability) = capability {
+        access.require(capability)?;
+    }
+    Ok(message)
+}
+
+async fn ensure_can_delete(state: &AppState, user_id: i64, shop_id: i64) -> Result<(), AppError> {
+    perms::require_access(&state.db, user_id, shop_id)
+        .await?
+        .require(Capability::DeleteMessages)
+}
+
+fn map_message_error(e: anyhow::Error) -> AppError {
+    let msg = e.to_string();
+    match msg.as_str() {
+        "message_not_found" => AppError::NotFound,
+        "not_message_sender" => AppError::Forbidden,
+        "edit_window_expired"
+        | "recall_window_expired"
+        | "message_not_editable"
+        | "message_unchanged"
+        | "message_content_empty"
+        | "message_content_too_long" => AppError::BadRequest(msg),
+        _ => AppError::Internal(msg),
+    }
+}
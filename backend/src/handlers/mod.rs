@@ -9,4 +9,8 @@ pub mod upload;
 pub mod staff;
 pub mod user;
 pub mod session;
+pub mod routing;
+pub mod canned_response;
 pub mod sdk_version;
+pub mod two_factor;
+pub mod login_events;
@@ -0,0 +1,79 @@
+use axum::{extract::{Path, State}, Json};
+use serde::Deserialize;
+
+use crate::{
+    error::AppError,
+    services::{
+        permissions::{Capability, ShopAccess},
+        routing::{self, RoutingSettings},
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRoutingPayload {
+    pub strategy: String,
+    pub default_max_concurrent: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StaffMaxConcurrentPayload {
+    /// 为空表示跟随店铺默认值；0 表示不再自动分配给该客服
+    pub max_concurrent_chats: Option<i32>,
+}
+
+// Purpose: 查看店铺会话自动分配配置
+// Input: shop_id（路径参数）
+// Output: RoutingSettings（strategy / default_max_concurrent）
+// Errors: 403（非店铺成员）、500
+pub async fn get_routing_settings(
+    State(state): State<AppState>,
+    access: ShopAccess,
+) -> Result<Json<RoutingSettings>, AppError> {
+    let settings = routing::shop_settings(&state, access.shop_id)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(Json(settings))
+}
+
+// Purpose: 修改店铺分配策略（round_robin / least_active / sticky / manual）与默认最大并发
+// Input: shop_id（路径参数）、UpdateRoutingPayload
+// Output: 更新后的 RoutingSettings
+// Errors: 403（缺少 manage_settings）、400（策略或并发数无效）、500
+pub async fn update_routing_settings(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Json(payload): Json<UpdateRoutingPayload>,
+) -> Result<Json<RoutingSettings>, AppError> {
+    access.require(Capability::ManageSettings)?;
+    let settings = routing::update_shop_settings(&state, access.shop_id, &payload.strategy, payload.default_max_concurrent)
+        .await
+        .map_err(map_routing_error)?;
+    Ok(Json(settings))
+}
+
+// Purpose: 设置员工最大并发会话数
+// Input: shop_id、user_id（路径参数）、StaffMaxConcurrentPayload
+// Output: {"ok": true}
+// Errors: 403（缺少 manage_staff）、404（该用户不是店铺员工）、400（数值无效）、500
+pub async fn set_staff_max_concurrent(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, target_user_id)): Path<(i64, i64)>,
+    Json(payload): Json<StaffMaxConcurrentPayload>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    access.require(Capability::ManageStaff)?;
+    routing::set_staff_max_concurrent(&state, access.shop_id, target_user_id, payload.max_concurrent_chats)
+        .await
+        .map_err(map_routing_error)?;
+    Ok(Json(serde_json::json!({"ok": true})))
+}
+
+fn map_routing_error(e: anyhow::Error) -> AppError {
+    let msg = e.to_string();
+    match msg.as_str() {
+        "staff_not_in_shop" => AppError::NotFound,
+        "invalid_strategy" | "invalid_max_concurrent" => AppError::BadRequest(msg),
+        _ => AppError::Internal(msg),
+    }
+}
@@ -1,7 +1,18 @@
 use axum::{extract::{Path, State}, Json};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{auth::AuthUser, error::AppError, models::{Session, Customer}, services::chat::ChatService, AppState};
+use crate::{
+    auth::AuthUser,
+    entities::sessions,
+    error::AppError,
+    models::{Session, Customer},
+    services::{
+        chat::{broadcast_session_updated, ChatService},
+        permissions::{self as perms, Capability, ShopAccess},
+        queue,
+    },
+    AppState,
+};
 
 /// 会话及其关联客户信息的响应结构
 #[derive(Debug, Serialize)]
@@ -14,7 +25,7 @@ pub struct SessionWithCustomer {
 // Purpose: 获取会话元信息（含 shop_id 和完整客户信息），便于前端展示统一的客户名称
 // Input: session_id（路径参数）
 // Output: SessionWithCustomer（会话结构 + 完整客户对象）
-// Errors: 404（会话不存在）、403（用户非该店铺店主/员工）、500（内部错误）
+// Errors: 404（会话不存在）、403（用户非该店铺店主/员工，或无权查看该会话）、500（内部错误）
 pub async fn get_session(
     State(state): State<AppState>,
     AuthUser { user_id }: AuthUser,
@@ -25,10 +36,225 @@ pub async fn get_session(
         .resolve_session(session_id)
         .await
         .map_err(|_| AppError::NotFound)?;
+    let access = perms::require_access(&state.db, user_id, session.shop_id as i64).await?;
+    if !access.can_view_session(session.staff_id.map(i64::from)) {
+        return Err(AppError::Forbidden);
+    }
 
-    // 使用 SessionService 进行权限检查 (通过 ChatService 已经验证了权限)
     Ok(Json(SessionWithCustomer {
         session: session.into(),
         customer: customer.into(),
     }))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct AssignSessionRequest {
+    pub staff_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionPriorityRequest {
+    pub priority: i32,
+}
+
+// Purpose: 关闭会话
+// Input: session_id（路径参数）
+// Output: 更新后的 Session；同时向店铺客服推送 session_updated
+// Errors: 404（会话不存在）、403（缺少 send_messages 或无权查看该会话）、400（会话已关闭）
+pub async fn close_session(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(session_id): Path<i64>,
+) -> Result<Json<Session>, AppError> {
+    authorize_session(&state, user_id, session_id).await?;
+    let session = state
+        .session_service
+        .close_session(session_id as i32)
+        .await
+        .map_err(map_session_error)?;
+    let session = notify_session_updated(&state, session, "closed", user_id);
+    // 关闭会话会释放客服容量，也可能直接移出等待队列
+    queue::refresh(&state, session.shop_id).await;
+    Ok(Json(session))
+}
+
+// Purpose: 重新打开已关闭的会话
+// Input: session_id（路径参数）
+// Output: 更新后的 Session；同时向店铺客服推送 session_updated
+// Errors: 404、403、400（会话未关闭 / 客户已有进行中的会话）
+pub async fn reopen_session(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(session_id): Path<i64>,
+) -> Result<Json<Session>, AppError> {
+    authorize_session(&state, user_id, session_id).await?;
+    let session = state
+        .session_service
+        .reopen_session(session_id as i32)
+        .await
+        .map_err(map_session_error)?;
+    let session = notify_session_updated(&state, session, "reopened", user_id);
+    queue::refresh(&state, session.shop_id).await;
+    Ok(Json(session))
+}
+
+// Purpose: 将未分配（或已分配给自己）的会话分配给指定客服
+// Input: session_id（路径参数）、AssignSessionRequest
+// Output: 更新后的 Session；同时向店铺客服推送 session_updated
+// Errors: 404、403、400（目标客服不属于该店铺 / 会话已关闭 / 已分配给他人）
+pub async fn assign_session(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(session_id): Path<i64>,
+    Json(req): Json<AssignSessionRequest>,
+) -> Result<Json<Session>, AppError> {
+    let current = authorize_session(&state, user_id, session_id).await?;
+    ensure_target_staff(&state, req.staff_id, current.shop_id as i64).await?;
+    let session = state
+        .session_service
+        .assign_staff_to_session(session_id as i32, req.staff_id as i32)
+        .await
+        .map_err(map_session_error)?;
+    let session = notify_session_updated(&state, session, "assigned", user_id);
+    if current.staff_id.is_none() {
+        queue::notify_session_assigned(&state, &session).await;
+        queue::refresh(&state, session.shop_id).await;
+    }
+    Ok(Json(session))
+}
+
+// Purpose: 将会话转接给店铺内其他客服
+// Input: session_id（路径参数）、AssignSessionRequest
+// Output: 更新后的 Session；同时向店铺客服推送 session_updated
+// Errors: 404、403、400（目标客服不属于该店铺 / 会话已关闭 / 目标即当前客服）
+pub async fn transfer_session(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(session_id): Path<i64>,
+    Json(req): Json<AssignSessionRequest>,
+) -> Result<Json<Session>, AppError> {
+    let current = authorize_session(&state, user_id, session_id).await?;
+    ensure_target_staff(&state, req.staff_id, current.shop_id as i64).await?;
+    let session = state
+        .session_service
+        .transfer_session(session_id as i32, req.staff_id as i32)
+        .await
+        .map_err(map_session_error)?;
+    Ok(Json(notify_session_updated(&state, session, "transferred", user_id)))
+}
+
+// Purpose: 设置会话优先级（0-10）
+// Input: session_id（路径参数）、SessionPriorityRequest
+// Output: 更新后的 Session；同时向店铺客服推送 session_updated
+// Errors: 404、403、400（优先级超出范围）
+pub async fn set_session_priority(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Path(session_id): Path<i64>,
+    Json(req): Json<SessionPriorityRequest>,
+) -> Result<Json<Session>, AppError> {
+    authorize_session(&state, user_id, session_id).await?;
+    let session = state
+        .session_service
+        .set_session_priority(session_id as i32, req.priority)
+        .await
+        .map_err(map_session_error)?;
+    let session = notify_session_updated(&state, session, "priority_changed", user_id);
+    // 优先级决定排队顺序
+    if session.staff_id.is_none() {
+        queue::refresh(&state, session.shop_id).await;
+    }
+    Ok(Json(session))
+}
+
+// Purpose: 店铺内未分配客服的进行中会话（优先级高的在前）
+// Input: shop_id（路径参数）
+// Output: Vec<Session>
+// Errors: 403（非店铺成员）、500
+pub async fn get_unassigned_sessions(
+    State(state): State<AppState>,
+    ShopAccess { shop_id, .. }: ShopAccess,
+) -> Result<Json<Vec<Session>>, AppError> {
+    let sessions = state
+        .session_service
+        .get_unassigned_sessions(shop_id as i32)
+        .await
+        .map_err(map_session_error)?;
+    Ok(Json(sessions.into_iter().map(Session::from).collect()))
+}
+
+// Purpose: 店铺等待队列（未分配且客户已发消息的会话，含排队位置）
+// Input: shop_id（路径参数）
+// Output: Vec<QueueEntry>
+// Errors: 403（非店铺成员）、500
+pub async fn get_shop_queue(
+    State(state): State<AppState>,
+    ShopAccess { shop_id, .. }: ShopAccess,
+) -> Result<Json<Vec<queue::QueueEntry>>, AppError> {
+    let entries = queue::shop_queue(&state, shop_id)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(Json(entries))
+}
+
+// Purpose: 当前客服认领队首会话
+// Input: shop_id（路径参数）
+// Output: 被认领的 Session；同时推送 session_updated / queue_updated，并通知客户客服已接入
+// Errors: 403（缺少 send_messages）、404（队列为空）、500
+pub async fn claim_next_in_queue(
+    State(state): State<AppState>,
+    access: ShopAccess,
+) -> Result<Json<Session>, AppError> {
+    access.require(Capability::SendMessages)?;
+    let session = queue::claim_next(&state, access.shop_id, access.user_id)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .ok_or(AppError::NotFound)?;
+    Ok(Json(session))
+}
+
+/// 加载会话并校验操作者具备 send_messages 能力且可以查看该会话
+async fn authorize_session(state: &AppState, user_id: i64, session_id: i64) -> Result<sessions::Model, AppError> {
+    let session = crate::repositories::SessionRepository::find_by_id(&state.db_connection, session_id as i32)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .ok_or(AppError::NotFound)?;
+    let access = perms::require_access(&state.db, user_id, session.shop_id as i64).await?;
+    access.require(Capability::SendMessages)?;
+    if !access.can_view_session(session.staff_id.map(i64::from)) {
+        return Err(AppError::Forbidden);
+    }
+    Ok(session)
+}
+
+/// 分配/转接的目标客服必须是该店铺的店主或员工，且能回复客户（viewer 不可接待）
+async fn ensure_target_staff(state: &AppState, staff_id: i64, shop_id: i64) -> Result<(), AppError> {
+    let access = perms::load_access(&state.db, shop_id, staff_id)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("staff_not_in_shop".to_string()))?;
+    if !access.can(Capability::SendMessages) {
+        return Err(AppError::BadRequest("staff_cannot_send_messages".to_string()));
+    }
+    Ok(())
+}
+
+fn map_session_error(e: anyhow::Error) -> AppError {
+    let msg = e.to_string();
+    match msg.as_str() {
+        "session_not_found" => AppError::NotFound,
+        "session_closed"
+        | "session_not_closed"
+        | "session_already_assigned"
+        | "session_already_assigned_to_target"
+        | "customer_has_active_session"
+        | "invalid_priority" => AppError::BadRequest(msg),
+        _ => AppError::Internal(msg),
+    }
+}
+
+/// 向店铺客服推送 session_updated，返回对外的 Session
+fn notify_session_updated(state: &AppState, session: sessions::Model, action: &str, operator_id: i64) -> Session {
+    let session = Session::from(session);
+    broadcast_session_updated(state, &session, action, Some(operator_id));
+    session
+}
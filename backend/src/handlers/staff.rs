@@ -1,57 +1,94 @@
 use axum::{extract::{Path, State}, Json};
 
-use crate::{auth::AuthUser, error::AppError, services, AppState};
-use crate::services::permissions as perms;
+use crate::{error::AppError, services, AppState};
+use crate::services::permissions::{Capability, CapabilityOverrides, ShopAccess};
 use axum::http::StatusCode;
 
 
 #[derive(serde::Deserialize)]
 pub struct AddStaffPayload {
     pub username: String,
+    /// admin | agent | viewer，默认 agent
+    pub role: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateStaffRolePayload {
+    pub role: String,
+    /// 按人能力覆盖，如 {"export": true, "view_all_sessions": false}；为空保留原有覆盖
+    pub permissions: Option<CapabilityOverrides>,
 }
 
 pub async fn list_staff(
     State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Path(shop_id): Path<i64>,
+    access: ShopAccess,
 ) -> Result<Json<Vec<services::staff::StaffItem>>, AppError> {
-    // SQLx 权限校验（店主或成员）
-    if let Err(e) = perms::ensure_member_or_owner_sqlx(&state.db, user_id, shop_id).await {
-        return match e {
-            AppError::Unauthorized => Err(AppError::Forbidden),
-            other => Err(other),
-        };
-    }
-    // 暂时保持兼容性，直到完全迁移
-    let items = services::staff::list_staff(&state.db_connection, user_id, shop_id).await?;
+    let items = services::staff::list_staff(&state.db, access.shop_id).await?;
     Ok(Json(items))
 }
 
 pub async fn add_staff(
     State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Path(shop_id): Path<i64>,
+    access: ShopAccess,
     Json(payload): Json<AddStaffPayload>,
-) -> Result<Json<serde_json::Value>, AppError> {
-    // 仅店主可操作
-    let is_owner = perms::is_shop_owner_sqlx(&state.db, shop_id, user_id)
-        .await
-        .map_err(|_| AppError::Internal("check_owner_failed".into()))?;
-    if !is_owner { return Err(AppError::Forbidden); }
-    services::staff::add_staff(&state.db_connection, user_id, shop_id, &payload.username).await?;
-    Ok(Json(serde_json::json!({"ok": true})))
+) -> Result<Json<services::staff::StaffItem>, AppError> {
+    access.require(Capability::ManageStaff)?;
+    let item = services::staff::add_staff(&state.db, &access, &payload.username, payload.role.as_deref()).await?;
+    Ok(Json(item))
 }
 
+// Purpose: 将员工移出店铺，并断开其在该店铺的客服 WebSocket 连接
+// Input: shop_id、user_id（路径参数）
+// Output: 204
+// Errors: 403（缺少 manage_staff，或非店主操作 admin）、404（不是店铺员工）、400（目标为店主）、500
 pub async fn remove_staff(
     State(state): State<AppState>,
-    AuthUser { user_id }: AuthUser,
-    Path((shop_id, target_user_id)): Path<(i64, i64)>,
+    access: ShopAccess,
+    Path((_, target_user_id)): Path<(i64, i64)>,
 ) -> Result<StatusCode, AppError> {
-    // 仅店主可操作
-    let is_owner = perms::is_shop_owner_sqlx(&state.db, shop_id, user_id)
-        .await
-        .map_err(|_| AppError::Internal("check_owner_failed".into()))?;
-    if !is_owner { return Err(AppError::Forbidden); }
-    services::staff::remove_staff(&state.db_connection, user_id, shop_id, target_user_id).await?;
+    access.require(Capability::ManageStaff)?;
+    services::staff::remove_staff(&state.db, &access, target_user_id).await?;
+    // 断开其在本店铺的连接，否则仍会收到店铺推送
+    state.connections.lock().unwrap().close_shop_staff_user(access.shop_id, target_user_id);
     Ok(StatusCode::NO_CONTENT)
 }
+
+// Purpose: 吊销员工的全部登录会话并断开其客服 WebSocket 连接（如员工离职）
+// Input: shop_id、user_id（路径参数）
+// Output: {"revoked": n}
+// Errors: 403（缺少 manage_staff，或非店主操作 admin）、404（不是店铺员工）、400（目标为店主）、500
+pub async fn revoke_staff_sessions(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, target_user_id)): Path<(i64, i64)>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    access.require(Capability::ManageStaff)?;
+    let revoked = services::staff::revoke_staff_sessions(&state.db, &access, target_user_id).await?;
+    state.connections.lock().unwrap().close_staff_user(target_user_id);
+    Ok(Json(serde_json::json!({ "revoked": revoked })))
+}
+
+// Purpose: 调整员工角色与按人能力覆盖，并断开其在该店铺的连接以按新能力重新认证
+// Input: shop_id、user_id（路径参数）、UpdateStaffRolePayload { role, permissions? }
+// Output: 更新后的 StaffItem（含生效能力）
+// Errors: 403（缺少 manage_staff、非店主操作 admin，或授予自己没有的能力）、404（不是店铺员工）、400（角色/能力无效、修改店主或自己）、500
+pub async fn update_staff_role(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Path((_, target_user_id)): Path<(i64, i64)>,
+    Json(payload): Json<UpdateStaffRolePayload>,
+) -> Result<Json<services::staff::StaffItem>, AppError> {
+    access.require(Capability::ManageStaff)?;
+    let item = services::staff::update_staff_role(&state.db, &access, target_user_id, &payload.role, payload.permissions).await?;
+    // 连接认证时缓存了会话可见范围，断开后客户端重连并按新能力重新认证
+    state.connections.lock().unwrap().close_shop_staff_user(access.shop_id, target_user_id);
+    Ok(Json(item))
+}
+
+// Purpose: 当前用户在店铺内的角色与能力（前端据此隐藏无权限的操作）
+// Input: shop_id（路径参数）
+// Output: ShopAccess { user_id, shop_id, role, capabilities }
+// Errors: 401、403（非店铺成员）
+pub async fn get_my_permissions(access: ShopAccess) -> Json<ShopAccess> {
+    Json(access)
+}
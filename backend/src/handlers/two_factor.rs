@@ -0,0 +1,128 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthUser,
+    error::AppError,
+    models::{DisableTwoFactorRequest, TwoFactorCodeRequest},
+    services::{
+        permissions::ShopAccess,
+        two_factor::{self, Enrollment, TwoFactorStatus},
+    },
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// 明文只返回这一次，请提示用户妥善保存
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShopTwoFactorPolicy {
+    pub require_2fa: bool,
+}
+
+// Purpose: 当前用户的双因素认证状态
+// Output: TwoFactorStatus { enabled, pending, recovery_codes_remaining }
+// Errors: 401、500
+pub async fn get_status(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> Result<Json<TwoFactorStatus>, AppError> {
+    Ok(Json(two_factor::status(&state.db, user_id).await?))
+}
+
+// Purpose: 开始绑定验证器 App，生成密钥与 otpauth:// 链接（确认前不影响登录）
+// Output: Enrollment { secret, otpauth_uri }
+// Errors: 401、400（已启用）、500
+pub async fn begin_enrollment(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+) -> Result<Json<Enrollment>, AppError> {
+    let user = state
+        .user_service
+        .get_user_info(user_id as i32)
+        .await
+        .map_err(|_| AppError::Unauthorized)?;
+    Ok(Json(two_factor::begin_enrollment(&state.db, user_id, &user.username).await?))
+}
+
+// Purpose: 提交验证器 App 中的验证码完成绑定并启用
+// Input: TwoFactorCodeRequest { code }
+// Output: RecoveryCodesResponse（一次性恢复码）
+// Errors: 401、400（未开始绑定、已启用、验证码错误）、500
+pub async fn confirm_enrollment(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<TwoFactorCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    let recovery_codes = two_factor::confirm_enrollment(&state.db, user_id, &payload.code).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+// Purpose: 停用双因素认证（需要当前密码与验证码 / 恢复码）
+// Input: DisableTwoFactorRequest { password, code }
+// Output: 204
+// Errors: 401（密码错误）、400（未启用、验证码错误）、500
+pub async fn disable(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<DisableTwoFactorRequest>,
+) -> Result<StatusCode, AppError> {
+    let user = state
+        .user_service
+        .get_user_info(user_id as i32)
+        .await
+        .map_err(|_| AppError::Unauthorized)?;
+    state
+        .user_service
+        .authenticate(&user.username, &payload.password)
+        .await
+        .map_err(|_| AppError::Unauthorized)?;
+    two_factor::verify(&state.db, user_id, &payload.code).await?;
+    two_factor::disable(&state.db, user_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Purpose: 重新生成恢复码（旧恢复码全部作废）
+// Input: TwoFactorCodeRequest { code }（验证码或恢复码）
+// Output: RecoveryCodesResponse
+// Errors: 401、400（未启用、验证码错误）、500
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    AuthUser { user_id }: AuthUser,
+    Json(payload): Json<TwoFactorCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    two_factor::verify(&state.db, user_id, &payload.code).await?;
+    let recovery_codes = two_factor::regenerate_recovery_codes(&state.db, user_id).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+// Purpose: 查看店铺是否要求员工启用双因素认证
+// Input: shop_id（路径参数）
+// Output: ShopTwoFactorPolicy { require_2fa }
+// Errors: 401、403（非店铺成员）、404、500
+pub async fn get_shop_policy(
+    State(state): State<AppState>,
+    access: ShopAccess,
+) -> Result<Json<ShopTwoFactorPolicy>, AppError> {
+    let require_2fa = two_factor::shop_requires(&state.db, access.shop_id).await?;
+    Ok(Json(ShopTwoFactorPolicy { require_2fa }))
+}
+
+// Purpose: 店主设置是否要求全部员工启用双因素认证（未启用的员工在该店铺内没有任何能力）
+// Input: shop_id（路径参数）、ShopTwoFactorPolicy { require_2fa }
+// Output: ShopTwoFactorPolicy
+// Errors: 401、403（非店主）、400（店主自己尚未启用）、500
+pub async fn update_shop_policy(
+    State(state): State<AppState>,
+    access: ShopAccess,
+    Json(payload): Json<ShopTwoFactorPolicy>,
+) -> Result<Json<ShopTwoFactorPolicy>, AppError> {
+    if !access.is_owner() {
+        return Err(AppError::Forbidden);
+    }
+    two_factor::set_shop_requirement(&state.db, access.shop_id, access.user_id, payload.require_2fa).await?;
+    Ok(Json(payload))
+}
@@ -87,6 +87,7 @@ struct UploadData {
 struct CustomerUploadData {
     api_key: String,
     customer_code: Option<String>,
+    visitor_token: Option<String>,
     message_type: String,
     original_name: String,
     content_type: Option<String>,
@@ -176,6 +177,7 @@ async fn parse_customer_multipart(mut multipart: Multipart) -> Result<CustomerUp
     
     let mut api_key: Option<String> = None;
     let mut customer_code: Option<String> = None;
+    let mut visitor_token: Option<String> = None;
     let mut message_type = String::from("file");
     let mut original_name: Option<String> = None;
     let mut content_type: Option<String> = None;
@@ -213,6 +215,15 @@ async fn parse_customer_multipart(mut multipart: Multipart) -> Result<CustomerUp
                     customer_code = Some(value);
                 }
             }
+            "visitorToken" => {
+                let value = field
+                    .text()
+                    .await
+                    .map_err(|_| AppError::BadRequest("visitorToken 无效".to_string()))?;
+                if !value.is_empty() {
+                    visitor_token = Some(value);
+                }
+            }
             "messageType" => {
                 let value = field
                     .text()
@@ -260,6 +271,7 @@ async fn parse_customer_multipart(mut multipart: Multipart) -> Result<CustomerUp
     Ok(CustomerUploadData {
         api_key,
         customer_code,
+        visitor_token,
         message_type: final_message_type,
         original_name,
         content_type,
@@ -387,7 +399,7 @@ pub async fn handle_upload(
     }))
 }
 
-// 客户端上传处理函数（无需认证）
+// 客户端上传处理函数（校验访客令牌）
 pub async fn handle_customer_upload(
     State(state): State<AppState>,
     headers: HeaderMap,
@@ -399,31 +411,46 @@ pub async fn handle_customer_upload(
     tracing::info!("解析上传数据成功: api_key={}, original_name={}", upload_data.api_key, upload_data.original_name);
     
     // 根据 shopId 或 API Key 查找店铺（使用 SQLx 避免 Sea-ORM 列映射问题）
-    let shop_id: i64 = if upload_data.api_key.chars().all(|c| c.is_ascii_digit()) {
-        // 如果是纯数字，当作店铺ID处理
-        upload_data.api_key.parse::<i64>()
-            .map_err(|_| AppError::BadRequest("无效的店铺ID".to_string()))?
-    } else {
-        // 否则当作API key处理，用 SQLx 查询
-        let shop_id_opt = sqlx::query_scalar!(
-            r#"SELECT id FROM shops WHERE api_key = ? LIMIT 1"#,
-            upload_data.api_key
-        )
-        .fetch_optional(state.db.pool())
+    let (shop_id, api_key) = crate::services::shop_utils::resolve_shop_with_key(&state.db, &upload_data.api_key)
         .await
         .map_err(|e| {
             tracing::error!("查询店铺失败: {}", e);
             AppError::Internal("查询店铺失败".to_string())
+        })?
+        .ok_or_else(|| {
+            tracing::error!("未找到店铺: api_key={}", upload_data.api_key);
+            AppError::NotFound
         })?;
-        
-        match shop_id_opt {
-            Some(Some(id)) => id,
-            _ => {
-                tracing::error!("未找到店铺: api_key={}", upload_data.api_key);
-                return Err(AppError::NotFound);
-            }
-        }
+
+    // 访客上传必须携带 WebSocket 认证时签发的访客令牌
+    let (Some(customer_code), Some(visitor_token)) = (&upload_data.customer_code, &upload_data.visitor_token) else {
+        tracing::error!("上传请求缺少 customerCode 或 visitorToken");
+        return Err(AppError::Unauthorized);
     };
+    if !crate::services::visitor_token::verify_visitor_token(visitor_token, shop_id, &api_key, customer_code) {
+        tracing::error!("访客令牌校验失败: shop_id={}, customer_code={}", shop_id, customer_code);
+        return Err(AppError::Unauthorized);
+    }
+
+    // 按访客限流（按 IP 的限流由路由中间件完成）
+    if let Err(retry_after_secs) = state
+        .rate_limiter
+        .check(crate::rate_limit::Scope::Upload, &format!("{}:{}", shop_id, customer_code))
+    {
+        tracing::warn!("访客上传过于频繁: shop_id={}, customer_code={}", shop_id, customer_code);
+        return Err(AppError::TooManyRequests { retry_after_secs });
+    }
+
+    // 被拉黑的客户不允许上传
+    let blocked = state
+        .customer_service
+        .active_block(shop_id as i32, customer_code)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if blocked.is_some() {
+        tracing::warn!("已拉黑客户尝试上传: shop_id={}, customer_code={}", shop_id, customer_code);
+        return Err(AppError::Forbidden);
+    }
     
     tracing::info!("找到店铺: id={}", shop_id);
 
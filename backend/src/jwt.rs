@@ -13,12 +13,17 @@ pub type HmacSha256 = Hmac<Sha256>;
 pub struct Claims {
     pub sub: String,
     pub exp: usize,
+    /// 登录会话 ID（auth_sessions.jti）；访客令牌不携带
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
 }
 
 #[derive(Debug, Error)]
 pub enum JwtError {
     #[error("invalid token format")]
     InvalidFormat,
+    #[error("unknown or retired signing key: {0}")]
+    UnknownKey(String),
     #[error("signature verification failed")]
     InvalidSignature,
     #[error("token expired at {0}")]
@@ -29,10 +34,101 @@ pub enum JwtError {
     Decoding(String),
 }
 
-pub fn encode_token(claims: &Claims, secret: &[u8]) -> Result<String, JwtError> {
+/// 单个 HMAC 签名密钥；retire_at 之后不再接受该密钥签发的 token
+#[derive(Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    pub secret: Vec<u8>,
+    pub retire_at: Option<DateTime<Utc>>,
+}
+
+impl JwtKey {
+    fn is_retired(&self, now: DateTime<Utc>) -> bool {
+        self.retire_at.is_some_and(|at| at <= now)
+    }
+}
+
+impl std::fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("kid", &self.kid)
+            .field("retire_at", &self.retire_at)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 密钥环：新 token 用 active 密钥签名并在头部写入 kid，校验时按 kid 选择密钥
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    active: usize,
+    keys: Vec<JwtKey>,
+}
+
+impl Keyring {
+    /// active_kid 必须存在于 keys 中且未退役
+    pub fn new(keys: Vec<JwtKey>, active_kid: &str) -> Result<Self, JwtError> {
+        let active = keys
+            .iter()
+            .position(|k| k.kid == active_kid)
+            .ok_or_else(|| JwtError::UnknownKey(active_kid.to_string()))?;
+        if keys[active].is_retired(Utc::now()) {
+            return Err(JwtError::UnknownKey(active_kid.to_string()));
+        }
+        Ok(Self { active, keys })
+    }
+
+    /// 单密钥（kid = default），未配置密钥环时使用
+    pub fn single(secret: Vec<u8>) -> Self {
+        Self {
+            active: 0,
+            keys: vec![JwtKey { kid: DEFAULT_KID.to_string(), secret, retire_at: None }],
+        }
+    }
+
+    pub fn active(&self) -> &JwtKey {
+        &self.keys[self.active]
+    }
+
+    pub fn keys(&self) -> &[JwtKey] {
+        &self.keys
+    }
+
+    /// 以相同 kid 派生出另一组密钥（如访客令牌：密钥 + 店铺 api_key）
+    pub fn derive(&self, f: impl Fn(&[u8]) -> Vec<u8>) -> Self {
+        Self {
+            active: self.active,
+            keys: self
+                .keys
+                .iter()
+                .map(|k| JwtKey { kid: k.kid.clone(), secret: f(&k.secret), retire_at: k.retire_at })
+                .collect(),
+        }
+    }
+
+    /// 按 kid 查找仍可用于校验的密钥；旧 token 没有 kid 时依次尝试所有未退役密钥
+    fn verification_keys(&self, kid: Option<&str>) -> Result<Vec<&JwtKey>, JwtError> {
+        let now = Utc::now();
+        let usable = self.keys.iter().filter(|k| !k.is_retired(now));
+        match kid {
+            Some(kid) => usable
+                .filter(|k| k.kid == kid)
+                .map(|k| vec![k])
+                .next()
+                .ok_or_else(|| JwtError::UnknownKey(kid.to_string())),
+            None => Ok(usable.collect()),
+        }
+    }
+}
+
+/// 未配置 kid 的单密钥（JWT_SECRET）使用的 kid
+pub const DEFAULT_KID: &str = "default";
+
+pub fn encode_token(claims: &Claims, keyring: &Keyring) -> Result<String, JwtError> {
+    let key = keyring.active();
     let header = serde_json::json!({
         "alg": "HS256",
-        "typ": "JWT"
+        "typ": "JWT",
+        "kid": key.kid,
     });
 
     let header_json =
@@ -45,7 +141,7 @@ pub fn encode_token(claims: &Claims, secret: &[u8]) -> Result<String, JwtError>
     let signing_input = format!("{}.{}", header_b64, payload_b64);
 
     let mut mac =
-        HmacSha256::new_from_slice(secret).map_err(|e| JwtError::Encoding(e.to_string()))?;
+        HmacSha256::new_from_slice(&key.secret).map_err(|e| JwtError::Encoding(e.to_string()))?;
     mac.update(signing_input.as_bytes());
     let signature = mac.finalize().into_bytes();
     let signature_b64 = URL_SAFE_NO_PAD.encode(signature);
@@ -53,22 +149,42 @@ pub fn encode_token(claims: &Claims, secret: &[u8]) -> Result<String, JwtError>
     Ok(format!("{}.{}", signing_input, signature_b64))
 }
 
-pub fn decode_token(token: &str, secret: &[u8]) -> Result<Claims, JwtError> {
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+pub fn decode_token(token: &str, keyring: &Keyring) -> Result<Claims, JwtError> {
     let parts: Vec<&str> = token.split('.').collect();
     if parts.len() != 3 {
         return Err(JwtError::InvalidFormat);
     }
 
+    let header_json = URL_SAFE_NO_PAD
+        .decode(parts[0])
+        .map_err(|e| JwtError::Decoding(e.to_string()))?;
+    let header: Header =
+        serde_json::from_slice(&header_json).map_err(|e| JwtError::Decoding(e.to_string()))?;
+
     let signing_input = format!("{}.{}", parts[0], parts[1]);
 
     let signature = URL_SAFE_NO_PAD
         .decode(parts[2])
         .map_err(|e| JwtError::Decoding(e.to_string()))?;
-    let mut mac =
-        HmacSha256::new_from_slice(secret).map_err(|e| JwtError::Decoding(e.to_string()))?;
-    mac.update(signing_input.as_bytes());
-    mac.verify_slice(&signature)
-        .map_err(|_| JwtError::InvalidSignature)?;
+    let mut verified = false;
+    for key in keyring.verification_keys(header.kid.as_deref())? {
+        let mut mac =
+            HmacSha256::new_from_slice(&key.secret).map_err(|e| JwtError::Decoding(e.to_string()))?;
+        mac.update(signing_input.as_bytes());
+        if mac.verify_slice(&signature).is_ok() {
+            verified = true;
+            break;
+        }
+    }
+    if !verified {
+        return Err(JwtError::InvalidSignature);
+    }
 
     let payload_json = URL_SAFE_NO_PAD
         .decode(parts[1])
@@ -91,3 +207,127 @@ pub fn decode_token(token: &str, secret: &[u8]) -> Result<Claims, JwtError> {
 
     Ok(claims)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(kid: &str, secret: &str, retire_at: Option<DateTime<Utc>>) -> JwtKey {
+        JwtKey { kid: kid.to_string(), secret: secret.as_bytes().to_vec(), retire_at }
+    }
+
+    fn claims(exp_offset: i64) -> Claims {
+        Claims {
+            sub: "42".to_string(),
+            exp: (Utc::now().timestamp() + exp_offset) as usize,
+            jti: Some("session-1".to_string()),
+        }
+    }
+
+    fn header_kid(token: &str) -> Option<String> {
+        let header = URL_SAFE_NO_PAD.decode(token.split('.').next().unwrap()).unwrap();
+        serde_json::from_slice::<Header>(&header).unwrap().kid
+    }
+
+    /// 旧版 token：头部不带 kid
+    fn legacy_token(claims: &Claims, secret: &str) -> String {
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_string(claims).unwrap());
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{header}.{payload}").as_bytes());
+        format!("{header}.{payload}.{}", URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn signs_with_active_kid_and_verifies_by_kid() {
+        let keyring = Keyring::new(vec![key("k1", "secret-one", None), key("k2", "secret-two", None)], "k2").unwrap();
+        let token = encode_token(&claims(60), &keyring).unwrap();
+        assert_eq!(header_kid(&token).as_deref(), Some("k2"));
+        assert_eq!(decode_token(&token, &keyring).unwrap().jti.as_deref(), Some("session-1"));
+
+        // 只认 kid 指定的密钥：同 kid 换了密钥即签名无效
+        let swapped = Keyring::new(vec![key("k1", "secret-two", None), key("k2", "secret-one", None)], "k1").unwrap();
+        assert!(matches!(decode_token(&token, &swapped), Err(JwtError::InvalidSignature)));
+    }
+
+    #[test]
+    fn rotation_keeps_old_tokens_until_retired() {
+        let before = Keyring::new(vec![key("k1", "secret-one", None)], "k1").unwrap();
+        let old_token = encode_token(&claims(60), &before).unwrap();
+
+        // 轮换：k2 成为签名密钥，k1 保留用于校验
+        let rotated = Keyring::new(vec![key("k1", "secret-one", None), key("k2", "secret-two", None)], "k2").unwrap();
+        assert!(decode_token(&old_token, &rotated).is_ok());
+        assert_eq!(header_kid(&encode_token(&claims(60), &rotated).unwrap()).as_deref(), Some("k2"));
+
+        // k1 到达 retire_at 后其签发的 token 不再被接受
+        let retired = Keyring::new(
+            vec![key("k1", "secret-one", Some(Utc::now() - chrono::Duration::seconds(1))), key("k2", "secret-two", None)],
+            "k2",
+        )
+        .unwrap();
+        assert!(matches!(decode_token(&old_token, &retired), Err(JwtError::UnknownKey(kid)) if kid == "k1"));
+
+        // 退役时间尚未到达时仍可校验
+        let retiring = Keyring::new(
+            vec![key("k1", "secret-one", Some(Utc::now() + chrono::Duration::hours(1))), key("k2", "secret-two", None)],
+            "k2",
+        )
+        .unwrap();
+        assert!(decode_token(&old_token, &retiring).is_ok());
+    }
+
+    #[test]
+    fn active_key_must_exist_and_not_be_retired() {
+        assert!(matches!(Keyring::new(vec![key("k1", "s", None)], "k9"), Err(JwtError::UnknownKey(_))));
+        let retired = key("k1", "s", Some(Utc::now() - chrono::Duration::seconds(1)));
+        assert!(matches!(Keyring::new(vec![retired], "k1"), Err(JwtError::UnknownKey(_))));
+    }
+
+    #[test]
+    fn unknown_kid_is_rejected() {
+        let issuer = Keyring::new(vec![key("other", "secret-one", None)], "other").unwrap();
+        let token = encode_token(&claims(60), &issuer).unwrap();
+        let keyring = Keyring::new(vec![key("k1", "secret-one", None)], "k1").unwrap();
+        assert!(matches!(decode_token(&token, &keyring), Err(JwtError::UnknownKey(kid)) if kid == "other"));
+    }
+
+    #[test]
+    fn legacy_token_without_kid_tries_unretired_keys() {
+        let keyring = Keyring::new(vec![key("k1", "secret-one", None), key("k2", "secret-two", None)], "k2").unwrap();
+        assert!(decode_token(&legacy_token(&claims(60), "secret-one"), &keyring).is_ok());
+        assert!(decode_token(&legacy_token(&claims(60), "secret-two"), &keyring).is_ok());
+        assert!(matches!(
+            decode_token(&legacy_token(&claims(60), "unknown"), &keyring),
+            Err(JwtError::InvalidSignature)
+        ));
+
+        let retired = Keyring::new(
+            vec![key("k1", "secret-one", Some(Utc::now() - chrono::Duration::seconds(1))), key("k2", "secret-two", None)],
+            "k2",
+        )
+        .unwrap();
+        assert!(matches!(
+            decode_token(&legacy_token(&claims(60), "secret-one"), &retired),
+            Err(JwtError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn expired_and_malformed_tokens() {
+        let keyring = Keyring::single(b"secret-one".to_vec());
+        let expired = encode_token(&claims(-10), &keyring).unwrap();
+        assert!(matches!(decode_token(&expired, &keyring), Err(JwtError::Expired(_))));
+        assert!(matches!(decode_token("a.b", &keyring), Err(JwtError::InvalidFormat)));
+    }
+
+    #[test]
+    fn derived_keyring_keeps_kids() {
+        let keyring = Keyring::new(vec![key("k1", "secret-one", None), key("k2", "secret-two", None)], "k2").unwrap();
+        let derived = keyring.derive(|secret| [secret, b":shop-key"].concat());
+        assert_eq!(derived.active().kid, "k2");
+        let token = encode_token(&claims(60), &derived).unwrap();
+        assert!(decode_token(&token, &derived).is_ok());
+        assert!(matches!(decode_token(&token, &keyring), Err(JwtError::InvalidSignature)));
+    }
+}
@@ -0,0 +1,140 @@
+//! 邮件发送
+//!
+//! 职责：
+//! - Mailer trait：业务代码只依赖它发送纯文本邮件（找回密码等）
+//! - SmtpMailer：内置的最小 SMTP 客户端（STARTTLS / 隐式 TLS / 明文，AUTH PLAIN / LOGIN）
+//! - OutboxMailer：每封邮件写成一个 .eml 文件，用于测试与无外网部署
+//!
+//! MAIL_TRANSPORT=smtp|file 选择实现，默认 file
+
+mod outbox;
+mod smtp;
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use axum::async_trait;
+use chrono::Utc;
+use tracing::{info, warn};
+
+pub use outbox::OutboxMailer;
+pub use smtp::SmtpMailer;
+
+const DEFAULT_FROM: &str = "noreply@localhost";
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<()>;
+}
+
+/// 按环境变量构造邮件发送实现；SMTP 配置不完整时返回错误，避免上线后才发现发不出邮件
+pub fn from_env() -> Result<Arc<dyn Mailer>> {
+    let from = std::env::var("MAIL_FROM")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| DEFAULT_FROM.to_string());
+    validate_mailbox(&from)?;
+
+    let transport = std::env::var("MAIL_TRANSPORT").unwrap_or_else(|_| "file".to_string());
+    match transport.trim().to_lowercase().as_str() {
+        "smtp" => {
+            let mailer = SmtpMailer::from_env(from)?;
+            info!("📧 邮件发送方式: SMTP ({})", mailer.endpoint());
+            Ok(Arc::new(mailer))
+        }
+        "file" | "outbox" | "" => {
+            let mailer = OutboxMailer::from_env(from);
+            warn!("📧 邮件发送方式: 写入本地 outbox 目录 {}（不会真正投递）", mailer.dir().display());
+            Ok(Arc::new(mailer))
+        }
+        other => bail!("不支持的 MAIL_TRANSPORT: {}（可选 smtp / file）", other),
+    }
+}
+
+/// 组装 RFC 5322 报文（CRLF 换行，UTF-8 正文按 8bit 传输，主题按 RFC 2047 编码）
+fn format_message(from: &str, email: &Email) -> Result<String> {
+    validate_mailbox(&email.to)?;
+    let domain = envelope_address(from).rsplit('@').next().unwrap_or("localhost");
+    let body = email.body.replace("\r\n", "\n").replace('\n', "\r\n");
+    Ok(format!(
+        "From: {from}\r\nTo: {to}\r\nSubject: {subject}\r\nDate: {date}\r\nMessage-ID: <{id}@{domain}>\r\n\
+         MIME-Version: 1.0\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{body}\r\n",
+        to = email.to,
+        subject = encode_header(&email.subject),
+        date = Utc::now().to_rfc2822(),
+        id = uuid::Uuid::new_v4().simple(),
+    ))
+}
+
+fn encode_header(value: &str) -> String {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    if value.is_ascii() && !value.contains(['\r', '\n']) {
+        return value.to_string();
+    }
+    format!("=?UTF-8?B?{}?=", STANDARD.encode(value))
+}
+
+/// `Name <addr>` 形式取尖括号内的地址，否则原样返回
+fn envelope_address(mailbox: &str) -> &str {
+    match (mailbox.rfind('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox,
+    }
+}
+
+/// 拒绝换行与明显不合法的地址，防止头部注入
+fn validate_mailbox(mailbox: &str) -> Result<()> {
+    let address = envelope_address(mailbox);
+    if mailbox.contains(['\r', '\n'])
+        || address.contains(|c: char| c.is_whitespace() || c == '<' || c == '>')
+        || !address.contains('@')
+    {
+        bail!("无效的邮件地址: {:?}", mailbox);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn email(to: &str, subject: &str, body: &str) -> Email {
+        Email { to: to.to_string(), subject: subject.to_string(), body: body.to_string() }
+    }
+
+    #[test]
+    fn formats_headers_and_crlf_body() {
+        let message = format_message("Support <noreply@shop.example>", &email("a@b.example", "Reset", "line1\nline2\r\nline3"))
+            .unwrap();
+        let (headers, body) = message.split_once("\r\n\r\n").unwrap();
+        assert!(headers.starts_with("From: Support <noreply@shop.example>\r\nTo: a@b.example\r\nSubject: Reset\r\n"));
+        assert!(headers.contains("@shop.example>\r\n"));
+        assert!(headers.contains("Content-Type: text/plain; charset=UTF-8"));
+        assert_eq!(body, "line1\r\nline2\r\nline3\r\n");
+    }
+
+    #[test]
+    fn encodes_non_ascii_and_multiline_subjects() {
+        assert_eq!(encode_header("Reset password"), "Reset password");
+        assert_eq!(encode_header("重置密码"), "=?UTF-8?B?6YeN572u5a+G56CB?=");
+        // 换行会被 base64 包住，不会变成新的头部
+        let encoded = encode_header("Hi\r\nBcc: x@evil.example");
+        assert!(encoded.starts_with("=?UTF-8?B?") && !encoded.contains(['\r', '\n']));
+    }
+
+    #[test]
+    fn rejects_header_injection_in_recipient() {
+        assert!(format_message("noreply@localhost", &email("a@b.example\r\nBcc: x@evil.example", "s", "b")).is_err());
+        assert!(format_message("noreply@localhost", &email("not-an-address", "s", "b")).is_err());
+        assert_eq!(envelope_address("Name <a@b.example>"), "a@b.example");
+        assert_eq!(envelope_address("a@b.example"), "a@b.example");
+    }
+}
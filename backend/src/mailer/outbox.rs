@@ -0,0 +1,52 @@
+// Purpose: 把邮件写入本地目录（MAIL_OUTBOX_DIR，默认 ./mail_outbox）而不投递
+// Output: 每封邮件一个 <时间>-<uuid>.eml 文件，可直接用邮件客户端打开
+// Errors: 目录创建或文件写入失败
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use axum::async_trait;
+use chrono::Utc;
+use tracing::info;
+
+use super::{format_message, Email, Mailer};
+
+pub struct OutboxMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+impl OutboxMailer {
+    pub fn new(dir: impl Into<PathBuf>, from: String) -> Self {
+        Self { dir: dir.into(), from }
+    }
+
+    pub fn from_env(from: String) -> Self {
+        let dir = std::env::var("MAIL_OUTBOX_DIR").unwrap_or_else(|_| "mail_outbox".to_string());
+        Self::new(dir, from)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+#[async_trait]
+impl Mailer for OutboxMailer {
+    async fn send(&self, email: &Email) -> Result<()> {
+        let message = format_message(&self.from, email)?;
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("创建 outbox 目录失败: {}", self.dir.display()))?;
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            uuid::Uuid::new_v4().simple()
+        ));
+        tokio::fs::write(&path, message)
+            .await
+            .with_context(|| format!("写入邮件失败: {}", path.display()))?;
+        info!("📧 邮件已写入 outbox: {} -> {}", email.to, path.display());
+        Ok(())
+    }
+}
@@ -2,15 +2,16 @@ use anyhow::Result;
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        ConnectInfo, Path, State,
+        ConnectInfo, Path, Query, State,
     },
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    routing::{get, post, delete, put},
+    routing::{get, post, delete, put, patch},
     Json, Router,
 };
 use chrono::Utc;
 use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
 use serde_json::json;
 use std::{
     net::SocketAddr,
@@ -28,7 +29,9 @@ mod entities;
 mod error;
 mod handlers;
 mod jwt;
+mod mailer;
 mod models;
+mod rate_limit;
 mod repositories;
 mod server;
 mod services;
@@ -41,7 +44,7 @@ use server::{HttpsServer, ServerConfig, start_http_redirect};
 use services::chat::ChatService;
 use tls::TlsConfig;
 use websocket::ConnectionManager;
-use websocket::{handle_customer_ws_message, handle_staff_ws_message, CustomerWsCtx};
+use websocket::{handle_customer_ws_message, handle_staff_ws_message, CustomerWsCtx, StaffWsCtx};
 
 /// 终止旧的程序进程
 async fn terminate_old_processes() {
@@ -173,6 +176,8 @@ pub struct AppState {
     pub customer_service: services::CustomerService,
     pub session_service: services::SessionService,
     pub message_service: services::MessageService,
+    pub rate_limiter: Arc<rate_limit::RateLimiter>,
+    pub mailer: Arc<dyn mailer::Mailer>,
 }
 
 #[tokio::main]
@@ -206,6 +211,9 @@ async fn main() -> Result<()> {
         .unwrap_or(false);
     info!("ℹ️  is_dev_mode = {}", is_dev_mode);
     info!("ℹ️  is_dev_mode = {}", is_dev_mode);
+
+    // JWT 密钥：非开发模式下未配置安全密钥时拒绝启动
+    auth::init_jwt_keyring(is_dev_mode)?;
     
     // 检查是否强制禁用HTTPS
     info!("🔍 检查 FORCE_HTTP 设置...");
@@ -285,6 +293,20 @@ async fn main() -> Result<()> {
     
     info!("🔗 初始化 WebSocket 连接管理器...");
     let connections = Arc::new(Mutex::new(ConnectionManager::new()));
+    // 多节点部署：WS_BROADCASTER=sqlite 时通过共享数据库的 outbox 表在节点间扇出消息
+    if std::env::var("WS_BROADCASTER").map(|v| v == "sqlite").unwrap_or(false) {
+        websocket::SqliteOutboxBroadcaster::start(
+            db.pool().clone(),
+            connections.clone(),
+            websocket::OutboxConfig::from_env(),
+        )
+        .await?;
+    } else {
+        // 单节点：上次进程退出时遗留的 online 记录已不可能有对应连接
+        if let Err(e) = repositories::OnlineStatusRepository::reset_all_offline(db_orm.get_connection()).await {
+            warn!("重置在线状态失败: {:?}", e);
+        }
+    }
     info!("✅ 连接管理器初始化完成");
 
     // 创建 Services 实例 - 使用 Sea-ORM DatabaseConnection
@@ -307,8 +329,13 @@ async fn main() -> Result<()> {
         customer_service,
         session_service,
         message_service,
+        rate_limiter: Arc::new(rate_limit::RateLimiter::from_env()),
+        mailer: mailer::from_env()?,
     };
 
+    // 在线状态：定期刷新本节点连接心跳，清理崩溃节点遗留的连接
+    services::presence::spawn_keepalive(state.clone());
+
     // 创建应用路由
     let app = create_router(state);
 
@@ -366,8 +393,34 @@ fn create_router(state: AppState) -> Router {
             tracing::info!("📊 健康检查请求收到");
             axum::Json(serde_json::json!({"status":"ok"})) 
         }))
-        .route("/api/auth/login", post(handlers::auth::login))
+        .route(
+            "/api/auth/login",
+            post(handlers::auth::login)
+                .layer(axum::middleware::from_fn_with_state(state.clone(), rate_limit::limit_login)),
+        )
+        .route(
+            "/api/auth/login/2fa",
+            post(handlers::auth::login_two_factor)
+                .layer(axum::middleware::from_fn_with_state(state.clone(), rate_limit::limit_login)),
+        )
         .route("/api/auth/register", post(handlers::auth::register))
+        .route(
+            "/api/auth/refresh",
+            post(handlers::auth::refresh)
+                .layer(axum::middleware::from_fn_with_state(state.clone(), rate_limit::limit_login)),
+        )
+        .route(
+            "/api/auth/forgot",
+            post(handlers::auth::forgot_password)
+                .layer(axum::middleware::from_fn_with_state(state.clone(), rate_limit::limit_login)),
+        )
+        .route(
+            "/api/auth/reset",
+            post(handlers::auth::reset_password)
+                .layer(axum::middleware::from_fn_with_state(state.clone(), rate_limit::limit_login)),
+        )
+        .route("/api/auth/logout", post(handlers::auth::logout))
+        .route("/api/auth/sessions/revoke-all", post(handlers::auth::revoke_all_sessions))
     .route("/api/shops", get(handlers::shop::get_shops))
     .route("/api/shops/overview", get(handlers::shop::get_shops_overview))
     .route("/api/shops/paged", get(handlers::shop::get_shops_paged))
@@ -391,6 +444,34 @@ fn create_router(state: AppState) -> Router {
             "/api/shops/:shop_id/customers/read_all",
             post(handlers::customer::reset_unread_all),
         )
+        .route(
+            "/api/shops/:shop_id/customers/:customer_id/tags",
+            put(handlers::customer::update_customer_tags),
+        )
+        .route(
+            "/api/shops/:shop_id/customers/:customer_id/attributes",
+            patch(handlers::customer::update_customer_attributes),
+        )
+        .route(
+            "/api/shops/:shop_id/customers/:customer_id/block",
+            post(handlers::customer::block_customer),
+        )
+        .route(
+            "/api/shops/:shop_id/customers/:customer_id/unblock",
+            post(handlers::customer::unblock_customer),
+        )
+        .route(
+            "/api/shops/:shop_id/customer-tags",
+            get(handlers::customer::get_shop_tags),
+        )
+        .route(
+            "/api/shops/:shop_id/unread/me",
+            get(handlers::customer::get_my_unread),
+        )
+        .route(
+            "/api/shops/:shop_id/presence",
+            get(handlers::customer::get_shop_presence),
+        )
         .route(
             "/api/shops/:shop_id/staff",
             get(handlers::staff::list_staff),
@@ -403,6 +484,58 @@ fn create_router(state: AppState) -> Router {
             "/api/shops/:shop_id/staff/:user_id",
             delete(handlers::staff::remove_staff),
         )
+        .route(
+            "/api/shops/:shop_id/staff/:user_id/role",
+            put(handlers::staff::update_staff_role),
+        )
+        .route(
+            "/api/shops/:shop_id/staff/:user_id/revoke-sessions",
+            post(handlers::staff::revoke_staff_sessions),
+        )
+        .route(
+            "/api/shops/:shop_id/permissions/me",
+            get(handlers::staff::get_my_permissions),
+        )
+        .route(
+            "/api/shops/:shop_id/staff/:user_id/max-concurrent",
+            put(handlers::routing::set_staff_max_concurrent),
+        )
+        .route(
+            "/api/shops/:shop_id/search",
+            get(handlers::message::search_shop_messages),
+        )
+        .route(
+            "/api/shops/:shop_id/canned-responses",
+            get(handlers::canned_response::list_canned_responses),
+        )
+        .route(
+            "/api/shops/:shop_id/canned-responses",
+            post(handlers::canned_response::create_canned_response),
+        )
+        .route(
+            "/api/shops/:shop_id/canned-responses/:id",
+            put(handlers::canned_response::update_canned_response),
+        )
+        .route(
+            "/api/shops/:shop_id/canned-responses/:id",
+            delete(handlers::canned_response::delete_canned_response),
+        )
+        .route(
+            "/api/shops/:shop_id/routing",
+            get(handlers::routing::get_routing_settings),
+        )
+        .route(
+            "/api/shops/:shop_id/routing",
+            put(handlers::routing::update_routing_settings),
+        )
+        .route(
+            "/api/shops/:shop_id/security",
+            get(handlers::two_factor::get_shop_policy).put(handlers::two_factor::update_shop_policy),
+        )
+        .route(
+            "/api/shops/:shop_id/staff/login-events",
+            get(handlers::login_events::list_shop_staff_login_events),
+        )
         .route(
             "/api/sessions/:session_id/messages",
             get(handlers::message::get_messages),
@@ -411,12 +544,76 @@ fn create_router(state: AppState) -> Router {
             "/api/sessions/:session_id/messages",
             post(handlers::message::send_message),
         )
+        .route(
+            "/api/sessions/:session_id/notes",
+            post(handlers::message::create_note),
+        )
+        .route(
+            "/api/sessions/:session_id/export",
+            get(handlers::message::export_session_messages),
+        )
+        .route(
+            "/api/messages/batch-delete",
+            post(handlers::message::delete_messages_batch),
+        )
+        .route(
+            "/api/messages/:message_id",
+            put(handlers::message::edit_message),
+        )
+        .route(
+            "/api/messages/:message_id",
+            delete(handlers::message::delete_message),
+        )
+        .route(
+            "/api/messages/:message_id/recall",
+            post(handlers::message::recall_message),
+        )
+        .route(
+            "/api/messages/:message_id/edits",
+            get(handlers::message::get_message_edits),
+        )
         .route(
             "/api/sessions/:session_id",
             get(handlers::session::get_session),
         )
+        .route(
+            "/api/sessions/:session_id/close",
+            post(handlers::session::close_session),
+        )
+        .route(
+            "/api/sessions/:session_id/reopen",
+            post(handlers::session::reopen_session),
+        )
+        .route(
+            "/api/sessions/:session_id/assign",
+            post(handlers::session::assign_session),
+        )
+        .route(
+            "/api/sessions/:session_id/transfer",
+            post(handlers::session::transfer_session),
+        )
+        .route(
+            "/api/sessions/:session_id/priority",
+            put(handlers::session::set_session_priority),
+        )
+        .route(
+            "/api/shops/:shop_id/sessions/unassigned",
+            get(handlers::session::get_unassigned_sessions),
+        )
+        .route(
+            "/api/shops/:shop_id/queue",
+            get(handlers::session::get_shop_queue),
+        )
+        .route(
+            "/api/shops/:shop_id/queue/claim",
+            post(handlers::session::claim_next_in_queue),
+        )
         .route("/api/upload", post(handlers::upload::handle_upload))
-        .route("/api/customer/upload", post(handlers::upload::handle_customer_upload))
+        .route(
+            "/api/customer/upload",
+            post(handlers::upload::handle_customer_upload)
+                .layer(axum::middleware::from_fn_with_state(state.clone(), rate_limit::limit_customer_upload)),
+        )
         .route("/api/sdk/version", get(handlers::sdk_version::get_latest_version))
         .route("/api/sdk/version/:version", get(handlers::sdk_version::get_specific_version))
         .route("/api/config", get(handlers::config::get_server_config))
@@ -426,6 +623,12 @@ fn create_router(state: AppState) -> Router {
         )
         .route("/api/user/profile", put(handlers::user::update_profile))
         .route("/api/user/password", put(handlers::user::change_password))
+        .route("/api/user/2fa", get(handlers::two_factor::get_status))
+        .route("/api/user/2fa/setup", post(handlers::two_factor::begin_enrollment))
+        .route("/api/user/2fa/enable", post(handlers::two_factor::confirm_enrollment))
+        .route("/api/user/2fa/disable", post(handlers::two_factor::disable))
+        .route("/api/user/2fa/recovery-codes", post(handlers::two_factor::regenerate_recovery_codes))
+        .route("/api/user/login-events", get(handlers::login_events::list_my_login_events))
         .route("/ws/staff/:user_id", get(websocket_handler_staff))
         .route(
             "/ws/customer/:shop_ref/:customer_id",
@@ -461,66 +664,143 @@ fn create_router(state: AppState) -> Router {
         .with_state(state)
 }
 
+#[derive(Debug, Deserialize)]
+struct StaffWsQuery {
+    token: Option<String>,
+}
+
 // WebSocket: staff upgrade handler
+// token 可通过 ?token=、Sec-WebSocket-Protocol: bearer, <token> 或首帧 auth 的 metadata.token 提供
 async fn websocket_handler_staff(
     Path(user_id): Path<i64>,
+    Query(query): Query<StaffWsQuery>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-) -> impl IntoResponse {
+) -> Response {
     info!("Staff WebSocket connection from: {}", addr);
-    ws.on_upgrade(move |socket| handle_staff_socket(socket, state, user_id))
+
+    let via_protocol = auth::token_from_ws_protocol(&headers);
+    let token = query.token.or_else(|| via_protocol.clone());
+
+    let auth_jti = match token {
+        Some(token) => match auth::authenticate_token(&state.db, &token).await {
+            Ok(session) if session.user_id == user_id => Some(session.jti),
+            Ok(session) => {
+                warn!("Staff WS token user {} does not match path user {}", session.user_id, user_id);
+                return (
+                    StatusCode::FORBIDDEN,
+                    Json(json!({"error":"user_mismatch","message":"Token does not belong to this user"})),
+                )
+                    .into_response();
+            }
+            Err(status) => {
+                return (
+                    status,
+                    Json(json!({"error":"invalid_token","message":"Invalid or expired token"})),
+                )
+                    .into_response();
+            }
+        },
+        // 未在握手阶段提供 token：首帧必须是携带 token 的 auth
+        None => None,
+    };
+
+    let ws = if via_protocol.is_some() {
+        ws.protocols([auth::WS_BEARER_PROTOCOL])
+    } else {
+        ws
+    };
+
+    ws.on_upgrade(move |socket| handle_staff_socket(socket, state, user_id, auth_jti))
+}
+
+#[derive(Debug, Deserialize)]
+struct CustomerWsQuery {
+    token: Option<String>,
+}
+
+/// 客户连接的店铺解析结果
+struct CustomerShopRef {
+    shop_id: i64,
+    api_key: String,
+    /// 是否已通过访客令牌校验；未校验的连接只能以新访客身份完成首次 auth
+    verified: bool,
 }
 
 // WebSocket: customer upgrade handler (shop_ref can be id or api key)
 async fn websocket_handler_customer(
     Path((shop_ref, customer_code)): Path<(String, String)>,
+    Query(query): Query<CustomerWsQuery>,
+    headers: HeaderMap,
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> impl IntoResponse {
     info!("Customer WebSocket connection from: {}", addr);
-    match resolve_shop_id(&state, &shop_ref).await {
-        Ok(shop_id) => {
+    let client_ip = state.rate_limiter.client_ip(&headers, Some(addr));
+    match resolve_shop_id(&state, &shop_ref, &customer_code, query.token.as_deref()).await {
+        Ok(shop) => {
             let st = state.clone();
-            ws.on_upgrade(move |socket| handle_customer_socket(socket, st, shop_id, customer_code))
+            ws.on_upgrade(move |socket| handle_customer_socket(socket, st, shop, customer_code, client_ip))
         }
         Err(resp) => resp,
     }
 }
 
-async fn resolve_shop_id(state: &AppState, shop_ref: &str) -> Result<i64, Response> {
-    // 允许直接传入数字 ID
-    if let Ok(id) = shop_ref.parse::<i64>() {
-        return Ok(id);
-    }
-
-    // 使用 SQLx 运行时查询通过 api_key 查找
-    match sqlx::query_scalar::<_, i64>(
-        "SELECT id FROM shops WHERE api_key = ? LIMIT 1"
-    )
-    .bind(shop_ref)
-    .fetch_optional(state.db.pool())
-    .await
-    {
-        Ok(Some(id)) => Ok(id),
-        Ok(None) => Err((
-            StatusCode::NOT_FOUND,
-            Json(json!({"error":"shop_not_found","message":"No shop matches the provided identifier"})),
-        )
-            .into_response()),
+async fn resolve_shop_id(
+    state: &AppState,
+    shop_ref: &str,
+    customer_code: &str,
+    token: Option<&str>,
+) -> Result<CustomerShopRef, Response> {
+    let (shop_id, api_key) = match services::shop_utils::resolve_shop_with_key(&state.db, shop_ref).await {
+        Ok(Some(found)) => found,
+        Ok(None) => {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(json!({"error":"shop_not_found","message":"No shop matches the provided identifier"})),
+            )
+                .into_response())
+        }
         Err(err) => {
             error!("Failed to resolve shop identifier {}: {:?}", shop_ref, err);
-            Err((
+            return Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(json!({"error":"shop_lookup_failed","message":"Unable to resolve shop identifier"})),
             )
-                .into_response())
+                .into_response());
         }
-    }
+    };
+
+    let verified = match token {
+        Some(token) => {
+            if !services::visitor_token::verify_visitor_token(token, shop_id, &api_key, customer_code) {
+                return Err((
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({"error":"invalid_visitor_token","message":"Visitor token is invalid or expired"})),
+                )
+                    .into_response());
+            }
+            true
+        }
+        // 仅凭数字店铺 ID 无法证明身份，必须携带令牌
+        None if shop_ref == shop_id.to_string() => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error":"visitor_token_required","message":"A visitor token is required"})),
+            )
+                .into_response());
+        }
+        None => false,
+    };
+
+    Ok(CustomerShopRef { shop_id, api_key, verified })
 }
 
-async fn handle_staff_socket(socket: WebSocket, state: AppState, user_id: i64) {
+/// auth_jti 为握手阶段已校验的登录会话；为空时首帧必须是携带 token 的 auth
+async fn handle_staff_socket(socket: WebSocket, state: AppState, user_id: i64, auth_jti: Option<String>) {
     info!("🔌 开始处理 Staff WebSocket，用户 ID: {}", user_id);
     
     let (mut sender, mut receiver) = socket.split();
@@ -537,6 +817,9 @@ async fn handle_staff_socket(socket: WebSocket, state: AppState, user_id: i64) {
     let chat_service = ChatService::new(&state);
     let mut connection_id: Option<String> = None;
     let mut active_shop: Option<i64> = None;
+    let mut authenticated = auth_jti.is_some();
+    let mut auth_jti = auth_jti;
+    let mut frame_bucket = state.rate_limiter.connection_bucket();
 
     info!("✅ Staff WebSocket 初始化完成，开始监听消息");
 
@@ -547,22 +830,34 @@ async fn handle_staff_socket(socket: WebSocket, state: AppState, user_id: i64) {
                 match serde_json::from_str::<WebSocketIncomingMessage>(&text) {
                     Ok(incoming) => {
                         debug!("✅ 解析成功，消息类型: {}", incoming.message_type);
-                        if let Err(err) = handle_staff_ws_message(
-                            &state,
-                            &chat_service,
-                            user_id,
-                            &tx,
-                            &mut connection_id,
-                            &mut active_shop,
-                            incoming,
-                        )
-                        .await
+                        if let Err(retry_after) =
+                            state.rate_limiter.check_staff_frame(&mut frame_bucket, user_id, &incoming.message_type)
                         {
+                            warn!("🚦 Staff {} 发送过快，丢弃 {} 帧", user_id, incoming.message_type);
+                            websocket::send_rate_limited(&tx, &incoming.message_type, retry_after);
+                            continue;
+                        }
+                        let mut ctx = StaffWsCtx {
+                            state: &state,
+                            chat: &chat_service,
+                            user_id,
+                            outbound: &tx,
+                            connection_id: &mut connection_id,
+                            active_shop: &mut active_shop,
+                            authenticated: &mut authenticated,
+                            auth_jti: &mut auth_jti,
+                        };
+                        if let Err(err) = handle_staff_ws_message(&mut ctx, incoming).await {
                             warn!("⚠️ Staff WS 处理错误: {err:?}");
                         }
                     }
                     Err(err) => warn!("❌ 解析 Staff payload 失败: {err}"),
                 }
+                // 首帧未能完成认证、或心跳时发现登录会话已被吊销，直接断开
+                if !authenticated {
+                    warn!("🚫 Staff WebSocket 未认证，关闭连接");
+                    break;
+                }
             },
             Ok(Message::Close(_)) => {
                 info!("👋 Staff 连接正常关闭");
@@ -581,8 +876,10 @@ async fn handle_staff_socket(socket: WebSocket, state: AppState, user_id: i64) {
     info!("🔄 清理 Staff WebSocket 连接");
 
     if let Some(id) = connection_id {
-        let mut manager = state.connections.lock().unwrap();
-        manager.remove_connection(&id);
+        state.connections.lock().unwrap().remove_connection(&id);
+        if let Some(shop_id) = active_shop {
+            services::presence::staff_offline_if_idle(&state, user_id, shop_id, &id).await;
+        }
     }
 
     drop(tx);
@@ -592,20 +889,40 @@ async fn handle_staff_socket(socket: WebSocket, state: AppState, user_id: i64) {
 async fn handle_customer_socket(
     socket: WebSocket,
     state: AppState,
-    shop_id: i64,
+    shop: CustomerShopRef,
     customer_code: String,
+    client_ip: String,
 ) {
     let (mut sender, mut receiver) = socket.split();
     let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+    let shop_id = shop.shop_id;
+    let mut verified = shop.verified;
+
+    // 被拉黑的客户：推送 blocked 事件后直接关闭
+    match state.customer_service.active_block(shop_id as i32, &customer_code).await {
+        Ok(Some(block)) => {
+            warn!("🚫 客户 {} (shop: {}) 已被拉黑，拒绝连接", customer_code, shop_id);
+            if let Ok(payload) = serde_json::to_string(&websocket::blocked_event(&block)) {
+                let _ = sender.send(Message::Text(payload)).await;
+            }
+            let _ = sender.send(Message::Close(None)).await;
+            return;
+        }
+        Ok(None) => {}
+        Err(err) => warn!("检查客户拉黑状态失败: {err:?}"),
+    }
 
-    let connection_id = {
+    // 只有通过令牌校验的连接才注册到 ConnectionManager，避免冒充访客接收回复
+    let mut connection_id: Option<String> = None;
+    let presence_id = uuid::Uuid::new_v4().to_string();
+    if verified {
         let mut manager = state.connections.lock().unwrap();
-        manager.add_customer_connection(shop_id, &customer_code, tx.clone())
-    };
+        connection_id = Some(manager.add_customer_connection(shop_id, &customer_code, tx.clone()));
+    }
 
     info!(
-        "Customer connection established: {} (shop: {}, customer: {})",
-        connection_id, shop_id, customer_code
+        "Customer connection established: {:?} (shop: {}, customer: {}, verified: {})",
+        connection_id, shop_id, customer_code, verified
     );
 
     let welcome = WebSocketMessage {
@@ -620,6 +937,7 @@ async fn handle_customer_socket(
         file_name: None,
         file_size: None,
         media_duration: None,
+        message_id: None,
     };
     if let Ok(payload) = serde_json::to_string(&welcome) {
         let _ = tx.send(Message::Text(payload));
@@ -636,6 +954,7 @@ async fn handle_customer_socket(
     let chat_service = ChatService::new(&state);
     let mut customer: Option<Customer> = None;
     let mut session: Option<Session> = None;
+    let mut frame_bucket = state.rate_limiter.connection_bucket();
 
     while let Some(result) = receiver.next().await {
         match result {
@@ -649,17 +968,43 @@ async fn handle_customer_socket(
                                 incoming.content, incoming.metadata);
                         eprintln!("🎯 [Customer WS Loop] content字段长度: {}", 
                                 incoming.content.as_ref().map(|c| c.len()).unwrap_or(0));
+                        if let Err(retry_after) = state.rate_limiter.check_customer_frame(
+                            &mut frame_bucket,
+                            &client_ip,
+                            shop_id,
+                            &customer_code,
+                            &incoming.message_type,
+                        ) {
+                            warn!("🚦 Customer {} ({}) 发送过快，丢弃 {} 帧", customer_code, client_ip, incoming.message_type);
+                            websocket::send_rate_limited(&tx, &incoming.message_type, retry_after);
+                            continue;
+                        }
                         let mut ctx = CustomerWsCtx {
                             state: &state,
                             chat: &chat_service,
                             shop_id,
                             customer_code: &customer_code,
+                            api_key: &shop.api_key,
                             outbound: &tx,
                             customer: &mut customer,
                             session: &mut session,
+                            verified: &mut verified,
+                            presence_id: &presence_id,
                         };
                         if let Err(err) = handle_customer_ws_message(&mut ctx, incoming).await {
                             warn!("❌ Customer WS error: {err:?}");
+                            if err.downcast_ref::<websocket::CustomerBlocked>().is_some() {
+                                info!("🚫 Customer {} 已被拉黑，关闭连接", customer_code);
+                                break;
+                            }
+                        }
+                        if !verified {
+                            warn!("🚫 Customer WebSocket 未通过身份校验，关闭连接");
+                            break;
+                        }
+                        if connection_id.is_none() {
+                            let mut manager = state.connections.lock().unwrap();
+                            connection_id = Some(manager.add_customer_connection(shop_id, &customer_code, tx.clone()));
                         }
                     }
                     Err(err) => {
@@ -669,7 +1014,7 @@ async fn handle_customer_socket(
                 }
             }
             Ok(Message::Close(_)) => {
-                info!("Customer connection {} closed", connection_id);
+                info!("Customer connection {:?} closed", connection_id);
                 break;
             }
             Ok(msg) => {
@@ -682,9 +1027,12 @@ async fn handle_customer_socket(
         }
     }
 
-    {
+    if let Some(id) = connection_id {
         let mut manager = state.connections.lock().unwrap();
-        manager.remove_connection(&connection_id);
+        manager.remove_connection(&id);
+    }
+    if let Some(customer) = customer.as_ref() {
+        services::presence::customer_offline_if_idle(&state, shop_id, customer.id, &customer_code, &presence_id).await;
     }
 
     drop(tx);
@@ -43,6 +43,10 @@ pub struct Customer {
     pub first_visit_at: DateTime<Utc>,
     pub last_active_at: DateTime<Utc>,
     pub status: i32,
+    #[sqlx(default)]
+    pub blocked_reason: Option<String>,
+    #[sqlx(default)]
+    pub blocked_until: Option<DateTime<Utc>>,
 }
 
 // 会话模型
@@ -56,6 +60,8 @@ pub struct Session {
     pub created_at: DateTime<Utc>,
     pub closed_at: Option<DateTime<Utc>>,
     pub last_message_at: DateTime<Utc>,
+    #[sqlx(default)]
+    pub priority: i32,
 }
 
 // 消息模型
@@ -71,6 +77,16 @@ pub struct Message {
     pub file_name: Option<String>,
     pub status: String,
     pub created_at: DateTime<Utc>,
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<i64>,
+    /// 最近一次编辑时间（未编辑过为空）
+    #[sqlx(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<DateTime<Utc>>,
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rich_content: Option<serde_json::Value>,
 }
 
 // 未读消息统计
@@ -109,6 +125,9 @@ pub struct WebSocketMessage {
     pub file_size: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub media_duration: Option<f64>,
+    /// 消息主键（单调递增），客户端据此记录游标并在重连 auth 时回传 lastMessageId
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_id: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -129,6 +148,12 @@ pub struct WebSocketIncomingMessage {
     pub file_size: Option<i64>,
     #[serde(default)]
     pub media_duration: Option<f64>,
+    /// 引用回复的消息 id（须属于同一会话）
+    #[serde(default)]
+    pub reply_to: Option<i64>,
+    /// 富消息结构（商品卡片 / 订单卡片 / 按钮列表 / 快捷回复），type 与 metadata.messageType 一致
+    #[serde(default)]
+    pub rich_content: Option<serde_json::Value>,
 }
 
 // API 请求/响应模型
@@ -138,6 +163,26 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RefreshTokenRequest {
+    #[serde(alias = "refreshToken")]
+    pub refresh_token: String,
+}
+
+/// 找回密码：用户名或邮箱
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    #[serde(alias = "username", alias = "email")]
+    pub account: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    #[serde(alias = "newPassword")]
+    pub new_password: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RegisterRequest {
     pub username: String,
@@ -148,10 +193,54 @@ pub struct RegisterRequest {
 
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
+    /// 短期 access token
     pub token: String,
+    /// 用于 POST /api/auth/refresh 换取新令牌，每次刷新后轮换
+    pub refresh_token: String,
+    /// access token 有效期（秒）
+    pub expires_in: i64,
     pub user: UserPublic,
 }
 
+/// 登录结果：未启用双因素认证时直接返回令牌，否则返回二次验证挑战
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+#[derive(Debug, Serialize)]
+pub struct TwoFactorChallenge {
+    /// 恒为 true，前端据此进入验证码输入步骤
+    pub two_factor_required: bool,
+    /// 提交给 POST /api/auth/login/2fa
+    pub challenge_token: String,
+    /// 挑战令牌有效期（秒）
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    #[serde(alias = "challengeToken")]
+    pub challenge_token: String,
+    /// 6 位验证码或恢复码
+    pub code: String,
+}
+
+/// 启用双因素认证、重新生成恢复码时提交的验证码
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactorRequest {
+    pub password: String,
+    /// 6 位验证码或恢复码
+    pub code: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserPublic {
     pub id: i64,
@@ -209,6 +298,8 @@ pub struct CustomerWithSession {
     pub session: Option<Session>,
     pub last_message: Option<Message>,
     pub unread_count: i32,
+    pub tags: Vec<String>,
+    pub attributes: std::collections::BTreeMap<String, String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -217,6 +308,10 @@ pub struct SendMessageRequest {
     pub message_type: Option<String>,
     pub file_url: Option<String>,
     pub file_name: Option<String>,
+    #[serde(default, alias = "replyTo")]
+    pub reply_to: Option<i64>,
+    #[serde(default, alias = "richContent")]
+    pub rich_content: Option<serde_json::Value>,
 }
 
 impl From<User> for UserPublic {
@@ -254,6 +349,7 @@ impl From<crate::entities::sessions::Model> for Session {
             created_at: session.created_at.map(|dt| dt.and_utc()).unwrap_or_else(|| chrono::Utc::now()),
             closed_at: session.closed_at.map(|dt| dt.and_utc()),
             last_message_at: session.last_message_at.map(|dt| dt.and_utc()).unwrap_or_else(|| session.created_at.map(|dt| dt.and_utc()).unwrap_or_else(|| chrono::Utc::now())),
+            priority: session.priority,
         }
     }
 }
@@ -272,6 +368,8 @@ impl From<crate::entities::customers::Model> for Customer {
             first_visit_at: customer.first_visit_at.map(|dt| dt.and_utc()).unwrap_or_else(|| chrono::Utc::now()),
             last_active_at: customer.last_active_at.map(|dt| dt.and_utc()).unwrap_or_else(|| chrono::Utc::now()),
             status: customer.status.unwrap_or(1), // 默认状态为1 (active)
+            blocked_reason: customer.blocked_reason,
+            blocked_until: customer.blocked_until.map(|dt| dt.and_utc()),
         }
     }
 }
@@ -293,6 +391,13 @@ impl From<crate::entities::messages::Model> for Message {
             .and_then(|v| v.as_str())
             .map(|s| s.to_string());
         
+        // 编辑时间记录在 metadata.edited_at
+        let edited_at = message.metadata.as_ref()
+            .and_then(|m| m.get("edited_at"))
+            .and_then(|v| v.as_str())
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+            .map(|t| t.with_timezone(&Utc));
+        
         // sender_id 在数据库中是 INTEGER，直接转换为 i64
         let sender_id = message.sender_id.map(|id| id as i64);
         
@@ -307,6 +412,9 @@ impl From<crate::entities::messages::Model> for Message {
             file_name, // 从 metadata 提取
             status: if message.is_deleted { "deleted".to_string() } else { "active".to_string() },
             created_at: message.created_at.and_utc(),
+            reply_to: message.reply_to.map(|id| id as i64),
+            edited_at,
+            rich_content: message.rich_content,
         };
         
         eprintln!("✅ 转换后的消息: id={}, content='{}'", result.id, result.content);
@@ -322,6 +430,10 @@ pub struct CustomerUpsert<'a> {
     pub avatar: Option<&'a str>,
     pub ip: Option<&'a str>,
     pub user_agent: Option<&'a str>,
+    /// 追加的客户标签
+    pub tags: Option<&'a [String]>,
+    /// 客户自定义属性（值为 null 表示删除）
+    pub attributes: Option<&'a serde_json::Map<String, serde_json::Value>>,
 }
 
 // 用户资料更新请求
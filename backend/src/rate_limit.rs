@@ -0,0 +1,462 @@
+//! 限流（令牌桶）
+//!
+//! 职责：
+//! - 从环境变量加载各类限流策略（默认值见 constants::rate_limit_policy）
+//! - HTTP 中间件：登录按客户端 IP 与账号（请求体中的 username / email / account）、访客上传按客户端 IP 限流，
+//!   超限返回 429 + Retry-After
+//! - WebSocket：单连接帧速率 + 按 IP / 访客 / 客服的发消息速率，超限由调用方推送 rate_limited 事件
+//!
+//! 计数保存在本进程内存中，多节点部署时每个节点各自计数
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::Mutex,
+    time::Instant,
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    constants::{rate_limit_policy, ws_incoming},
+    error::AppError,
+    AppState,
+};
+
+/// 桶数量超过该值时清理已回满（空闲）的桶
+const PRUNE_THRESHOLD: usize = 10_000;
+/// 登录类请求体的读取上限，超过时只按 IP 限流并交给 handler 处理
+const LOGIN_BODY_LIMIT: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Login,
+    LoginAccount,
+    Upload,
+    WsFrame,
+    CustomerMessage,
+    IpMessage,
+    StaffMessage,
+}
+
+impl Scope {
+    fn env_key(self) -> &'static str {
+        match self {
+            Scope::Login => "RATE_LIMIT_LOGIN",
+            Scope::LoginAccount => "RATE_LIMIT_LOGIN_ACCOUNT",
+            Scope::Upload => "RATE_LIMIT_UPLOAD",
+            Scope::WsFrame => "RATE_LIMIT_WS_FRAME",
+            Scope::CustomerMessage => "RATE_LIMIT_CUSTOMER_MESSAGE",
+            Scope::IpMessage => "RATE_LIMIT_IP_MESSAGE",
+            Scope::StaffMessage => "RATE_LIMIT_STAFF_MESSAGE",
+        }
+    }
+
+    fn default_policy(self) -> (u32, u32) {
+        match self {
+            Scope::Login => rate_limit_policy::LOGIN,
+            Scope::LoginAccount => rate_limit_policy::LOGIN_ACCOUNT,
+            Scope::Upload => rate_limit_policy::UPLOAD,
+            Scope::WsFrame => rate_limit_policy::WS_FRAME,
+            Scope::CustomerMessage => rate_limit_policy::CUSTOMER_MESSAGE,
+            Scope::IpMessage => rate_limit_policy::IP_MESSAGE,
+            Scope::StaffMessage => rate_limit_policy::STAFF_MESSAGE,
+        }
+    }
+
+    const ALL: [Scope; 7] = [
+        Scope::Login,
+        Scope::LoginAccount,
+        Scope::Upload,
+        Scope::WsFrame,
+        Scope::CustomerMessage,
+        Scope::IpMessage,
+        Scope::StaffMessage,
+    ];
+}
+
+/// 令牌桶策略：容量 burst，每分钟补充 per_minute 个令牌；burst 为 0 表示不限
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Policy {
+    /// 解析 `<突发容量>:<每分钟补充数>`，格式错误时使用默认值
+    fn from_env(scope: Scope) -> Self {
+        let (burst, per_minute) = std::env::var(scope.env_key())
+            .ok()
+            .and_then(|raw| {
+                let (burst, per_minute) = raw.trim().split_once(':')?;
+                Some((burst.trim().parse().ok()?, per_minute.trim().parse().ok()?))
+            })
+            .unwrap_or_else(|| scope.default_policy());
+        Self { burst, per_minute }
+    }
+
+    fn unlimited(&self) -> bool {
+        self.burst == 0
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.per_minute as f64 / 60.0
+    }
+}
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(policy: Policy) -> Self {
+        Self { tokens: policy.burst as f64, updated: Instant::now() }
+    }
+
+    /// 取一个令牌；不足时返回建议的重试等待秒数（至少 1 秒）
+    fn try_take(&mut self, policy: Policy) -> Result<(), u64> {
+        self.try_take_at(policy, Instant::now())
+    }
+
+    fn try_take_at(&mut self, policy: Policy, now: Instant) -> Result<(), u64> {
+        if policy.unlimited() {
+            return Ok(());
+        }
+        self.refill_at(policy, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        let rate = policy.refill_per_sec();
+        if rate <= 0.0 {
+            return Err(60);
+        }
+        Err(((1.0 - self.tokens) / rate).ceil().max(1.0) as u64)
+    }
+
+    fn refill_at(&mut self, policy: Policy, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * policy.refill_per_sec()).min(policy.burst as f64);
+        self.updated = now;
+    }
+
+    fn is_full(&mut self, policy: Policy) -> bool {
+        self.refill_at(policy, Instant::now());
+        self.tokens >= policy.burst as f64
+    }
+}
+
+pub struct RateLimiter {
+    enabled: bool,
+    /// 部署在反向代理之后时，从 X-Forwarded-For / X-Real-IP 取客户端 IP
+    trust_proxy: bool,
+    /// 受信任的代理层数：X-Forwarded-For 从右数第 trusted_hops 个条目是客户端 IP
+    trusted_hops: usize,
+    policies: HashMap<Scope, Policy>,
+    buckets: Mutex<HashMap<(Scope, String), TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// RATE_LIMIT_ENABLED=false 关闭限流；RATE_LIMIT_TRUST_PROXY=true 信任代理转发的客户端 IP，
+    /// RATE_LIMIT_TRUSTED_HOPS 为客户端与本服务之间的代理层数（默认 1）
+    pub fn from_env() -> Self {
+        let flag = |key: &str, default: bool| {
+            std::env::var(key)
+                .map(|v| matches!(v.trim().to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(default)
+        };
+        Self {
+            enabled: flag("RATE_LIMIT_ENABLED", true),
+            trust_proxy: flag("RATE_LIMIT_TRUST_PROXY", false),
+            trusted_hops: std::env::var("RATE_LIMIT_TRUSTED_HOPS")
+                .ok()
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .filter(|hops| *hops > 0)
+                .unwrap_or(1),
+            policies: Scope::ALL.iter().map(|scope| (*scope, Policy::from_env(*scope))).collect(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn policy(&self, scope: Scope) -> Policy {
+        self.policies
+            .get(&scope)
+            .copied()
+            .unwrap_or_else(|| {
+                let (burst, per_minute) = scope.default_policy();
+                Policy { burst, per_minute }
+            })
+    }
+
+    /// 按 (scope, key) 取一个令牌；超限返回重试等待秒数
+    /// 登录请求：先按 IP 计数，IP 未超限时才消耗账号令牌，避免已超限的 IP 继续耗尽他人账号的令牌而锁住账号
+    ///
+    /// 超限时返回触发的 Scope 与建议重试秒数
+    pub fn check_login(&self, ip: &str, account: Option<&str>) -> Result<(), (Scope, u64)> {
+        self.check(Scope::Login, ip).map_err(|secs| (Scope::Login, secs))?;
+        match account {
+            Some(account) => self.check(Scope::LoginAccount, account).map_err(|secs| (Scope::LoginAccount, secs)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn check(&self, scope: Scope, key: &str) -> Result<(), u64> {
+        let policy = self.policy(scope);
+        if !self.enabled || policy.unlimited() {
+            return Ok(());
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > PRUNE_THRESHOLD {
+            let policies = &self.policies;
+            buckets.retain(|(scope, _), bucket| {
+                policies.get(scope).is_none_or(|policy| !bucket.is_full(*policy))
+            });
+        }
+        buckets
+            .entry((scope, key.to_string()))
+            .or_insert_with(|| TokenBucket::new(policy))
+            .try_take(policy)
+    }
+
+    /// 单个 WebSocket 连接自己持有的帧速率桶
+    pub fn connection_bucket(&self) -> TokenBucket {
+        TokenBucket::new(self.policy(Scope::WsFrame))
+    }
+
+    /// 访客 WebSocket 帧：先按连接计帧，发消息类帧再按 IP 与访客计数
+    pub fn check_customer_frame(
+        &self,
+        bucket: &mut TokenBucket,
+        ip: &str,
+        shop_id: i64,
+        customer_code: &str,
+        message_type: &str,
+    ) -> Result<(), u64> {
+        if !self.enabled {
+            return Ok(());
+        }
+        bucket.try_take(self.policy(Scope::WsFrame))?;
+        if is_message_frame(message_type) {
+            self.check(Scope::IpMessage, ip)?;
+            self.check(Scope::CustomerMessage, &format!("{shop_id}:{customer_code}"))?;
+        }
+        Ok(())
+    }
+
+    /// 客服 WebSocket 帧：先按连接计帧，发消息类帧再按用户计数
+    pub fn check_staff_frame(&self, bucket: &mut TokenBucket, user_id: i64, message_type: &str) -> Result<(), u64> {
+        if !self.enabled {
+            return Ok(());
+        }
+        bucket.try_take(self.policy(Scope::WsFrame))?;
+        if is_message_frame(message_type) {
+            self.check(Scope::StaffMessage, &user_id.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// 解析客户端 IP：仅在 trust_proxy 时采用代理头，否则使用 TCP 对端地址
+    ///
+    /// X-Forwarded-For 最左侧的条目可由客户端任意伪造，只有受信任代理追加的右侧条目可信：
+    /// 取从右数第 trusted_hops 个条目，条目不足时取最左侧（此时整条链都由受信任代理写入）
+    pub fn client_ip(&self, headers: &HeaderMap, addr: Option<SocketAddr>) -> String {
+        if self.trust_proxy {
+            let forwarded = headers
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| {
+                    let hops: Vec<&str> = v.split(',').map(str::trim).filter(|hop| !hop.is_empty()).collect();
+                    let index = hops.len().saturating_sub(self.trusted_hops);
+                    hops.get(index).copied()
+                })
+                .or_else(|| headers.get("x-real-ip").and_then(|v| v.to_str().ok()).map(str::trim))
+                .filter(|v| !v.is_empty());
+            if let Some(ip) = forwarded {
+                return ip.to_string();
+            }
+        }
+        addr.map(|a| a.ip().to_string()).unwrap_or_else(|| "unknown".to_string())
+    }
+}
+
+/// 会产生或改写消息的入站帧
+fn is_message_frame(message_type: &str) -> bool {
+    matches!(
+        message_type,
+        ws_incoming::SEND_MESSAGE
+            | ws_incoming::QUICK_REPLY
+            | ws_incoming::EDIT_MESSAGE
+            | ws_incoming::SEND_TEMPLATE
+            | ws_incoming::ADD_NOTE
+    )
+}
+
+// Purpose: 登录类接口限流中间件（按客户端 IP；请求体带 username / email / account 时再按账号计数）
+// Errors: 429（超限，附 Retry-After）
+pub async fn limit_login(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let addr = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0);
+    let ip = state.rate_limiter.client_ip(req.headers(), addr);
+    let (parts, body) = req.into_parts();
+    let bytes = match axum::body::to_bytes(body, LOGIN_BODY_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(_) => return AppError::BadRequest("invalid_body".into()).into_response(),
+    };
+    let account = login_account_key(&bytes);
+    if let Err((scope, retry_after_secs)) = state.rate_limiter.check_login(&ip, account.as_deref()) {
+        tracing::warn!("🚦 {:?} 请求超限: ip={}, account={:?}, uri={}", scope, ip, account, parts.uri);
+        return AppError::TooManyRequests { retry_after_secs }.into_response();
+    }
+    next.run(Request::from_parts(parts, Body::from(bytes))).await
+}
+
+/// 登录类请求体中的账号标识（username / email / account），统一小写；无法解析时返回 None
+fn login_account_key(body: &[u8]) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    ["username", "email", "account"]
+        .iter()
+        .filter_map(|field| value.get(field)?.as_str())
+        .map(|account| account.trim().to_lowercase())
+        .find(|account| !account.is_empty())
+}
+
+// Purpose: 访客上传限流中间件（按客户端 IP；按访客的限流在 handler 内完成令牌校验后进行）
+// Errors: 429（超限，附 Retry-After）
+pub async fn limit_customer_upload(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    limit_by_ip(&state, Scope::Upload, req, next).await
+}
+
+async fn limit_by_ip(state: &AppState, scope: Scope, req: Request, next: Next) -> Response {
+    let addr = req.extensions().get::<ConnectInfo<SocketAddr>>().map(|info| info.0);
+    let ip = state.rate_limiter.client_ip(req.headers(), addr);
+    match state.rate_limiter.check(scope, &ip) {
+        Ok(()) => next.run(req).await,
+        Err(retry_after_secs) => {
+            tracing::warn!("🚦 {:?} 请求超限: ip={}, uri={}", scope, ip, req.uri());
+            AppError::TooManyRequests { retry_after_secs }.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const POLICY: Policy = Policy { burst: 3, per_minute: 30 };
+
+    fn limiter(trust_proxy: bool, trusted_hops: usize) -> RateLimiter {
+        RateLimiter {
+            enabled: true,
+            trust_proxy,
+            trusted_hops,
+            policies: HashMap::from([(Scope::Login, POLICY)]),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_reports_retry_after() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket { tokens: POLICY.burst as f64, updated: start };
+        for _ in 0..3 {
+            assert_eq!(bucket.try_take_at(POLICY, start), Ok(()));
+        }
+        // 每分钟 30 个 = 每 2 秒 1 个
+        assert_eq!(bucket.try_take_at(POLICY, start), Err(2));
+        // 过了 1 秒攒了半个令牌，还需 1 秒
+        assert_eq!(bucket.try_take_at(POLICY, start + Duration::from_secs(1)), Err(1));
+    }
+
+    #[test]
+    fn bucket_refills_over_time_up_to_burst() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket { tokens: 0.0, updated: start };
+        assert_eq!(bucket.try_take_at(POLICY, start + Duration::from_secs(2)), Ok(()));
+        assert_eq!(bucket.try_take_at(POLICY, start + Duration::from_secs(2)), Err(2));
+
+        // 空闲很久也最多攒满 burst 个
+        let later = start + Duration::from_secs(600);
+        for _ in 0..3 {
+            assert_eq!(bucket.try_take_at(POLICY, later), Ok(()));
+        }
+        assert!(bucket.try_take_at(POLICY, later).is_err());
+    }
+
+    #[test]
+    fn bucket_edge_policies() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket { tokens: 0.0, updated: now };
+        // burst 为 0 表示不限
+        assert_eq!(bucket.try_take_at(Policy { burst: 0, per_minute: 0 }, now), Ok(()));
+        // 不补充时建议 60 秒后重试
+        assert_eq!(bucket.try_take_at(Policy { burst: 1, per_minute: 0 }, now), Err(60));
+        // 补充很快时至少等待 1 秒
+        assert_eq!(bucket.try_take_at(Policy { burst: 1, per_minute: 6000 }, now), Err(1));
+    }
+
+    #[test]
+    fn check_counts_keys_separately() {
+        let limiter = limiter(false, 1);
+        for _ in 0..3 {
+            assert!(limiter.check(Scope::Login, "1.1.1.1").is_ok());
+        }
+        assert!(limiter.check(Scope::Login, "1.1.1.1").is_err());
+        assert!(limiter.check(Scope::Login, "2.2.2.2").is_ok());
+    }
+
+    #[test]
+    fn login_over_ip_limit_does_not_spend_account_tokens() {
+        let mut limiter = limiter(false, 1);
+        limiter.policies.insert(Scope::LoginAccount, POLICY);
+
+        // 攻击者 IP 用完自己的令牌后，被拒绝的请求不再消耗受害账号的令牌
+        for _ in 0..3 {
+            assert!(limiter.check_login("6.6.6.6", Some("other")).is_ok());
+        }
+        for _ in 0..10 {
+            assert!(matches!(limiter.check_login("6.6.6.6", Some("victim")), Err((Scope::Login, _))));
+        }
+        for _ in 0..3 {
+            assert!(limiter.check_login("1.1.1.1", Some("victim")).is_ok());
+        }
+        // 账号自己的限额仍然生效
+        assert!(matches!(limiter.check_login("2.2.2.2", Some("victim")), Err((Scope::LoginAccount, _))));
+        assert!(limiter.check_login("2.2.2.2", None).is_ok());
+    }
+
+    #[test]
+    fn client_ip_uses_trusted_hop_from_the_right() {
+        let addr: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "6.6.6.6, 203.0.113.9".parse().unwrap());
+
+        assert_eq!(limiter(false, 1).client_ip(&headers, Some(addr)), "10.0.0.1");
+        // 客户端伪造的最左侧条目不被采用
+        assert_eq!(limiter(true, 1).client_ip(&headers, Some(addr)), "203.0.113.9");
+        assert_eq!(limiter(true, 2).client_ip(&headers, Some(addr)), "6.6.6.6");
+        assert_eq!(limiter(true, 5).client_ip(&headers, Some(addr)), "6.6.6.6");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-real-ip", " 198.51.100.7 ".parse().unwrap());
+        assert_eq!(limiter(true, 1).client_ip(&headers, Some(addr)), "198.51.100.7");
+        assert_eq!(limiter(true, 1).client_ip(&HeaderMap::new(), None), "unknown");
+    }
+
+    #[test]
+    fn login_account_key_from_body() {
+        assert_eq!(login_account_key(br#"{"username":" Alice ","password":"x"}"#).as_deref(), Some("alice"));
+        assert_eq!(login_account_key(br#"{"email":"Bob@Example.com"}"#).as_deref(), Some("bob@example.com"));
+        assert_eq!(login_account_key(br#"{"username":"","email":"c@d.e"}"#).as_deref(), Some("c@d.e"));
+        assert_eq!(login_account_key(br#"{"account":"Dave"}"#).as_deref(), Some("dave"));
+        assert_eq!(login_account_key(br#"{"refresh_token":"t"}"#), None);
+        assert_eq!(login_account_key(b"not json"), None);
+    }
+}
@@ -0,0 +1,134 @@
+//! Canned Response Repository - 快捷回复模板数据访问层
+
+use anyhow::Result;
+use sea_orm::{sea_query::Expr, *};
+use crate::entities::{canned_responses, prelude::CannedResponses};
+
+pub struct CannedResponseRepository;
+
+impl CannedResponseRepository {
+    /// 客服可见的模板：店铺公共模板 + 本人的个人模板；keyword 匹配标题 / 快捷指令 / 内容
+    pub async fn find_visible(
+        db: &DatabaseConnection,
+        shop_id: i64,
+        user_id: i64,
+        personal_only: Option<bool>,
+        keyword: Option<&str>,
+    ) -> Result<Vec<canned_responses::Model>> {
+        let scope = match personal_only {
+            Some(true) => Condition::all().add(canned_responses::Column::OwnerUserId.eq(user_id as i32)),
+            Some(false) => Condition::all().add(canned_responses::Column::OwnerUserId.is_null()),
+            None => Condition::any()
+                .add(canned_responses::Column::OwnerUserId.is_null())
+                .add(canned_responses::Column::OwnerUserId.eq(user_id as i32)),
+        };
+        let mut query = CannedResponses::find()
+            .filter(canned_responses::Column::ShopId.eq(shop_id as i32))
+            .filter(scope);
+        if let Some(keyword) = keyword.map(str::trim).filter(|k| !k.is_empty()) {
+            query = query.filter(
+                Condition::any()
+                    .add(canned_responses::Column::Title.contains(keyword))
+                    .add(canned_responses::Column::Shortcut.contains(keyword))
+                    .add(canned_responses::Column::Content.contains(keyword)),
+            );
+        }
+        let rows = query
+            .order_by_desc(canned_responses::Column::UsageCount)
+            .order_by_asc(canned_responses::Column::Id)
+            .all(db)
+            .await?;
+        Ok(rows)
+    }
+
+    pub async fn find_by_id(db: &DatabaseConnection, id: i64) -> Result<Option<canned_responses::Model>> {
+        Ok(CannedResponses::find_by_id(id as i32).one(db).await?)
+    }
+
+    pub async fn create(
+        db: &DatabaseConnection,
+        shop_id: i64,
+        owner_user_id: Option<i64>,
+        title: String,
+        shortcut: Option<String>,
+        content: String,
+        created_by: i64,
+    ) -> Result<canned_responses::Model> {
+        let now = chrono::Utc::now().naive_utc();
+        let record = canned_responses::ActiveModel {
+            shop_id: Set(shop_id as i32),
+            owner_user_id: Set(owner_user_id.map(|id| id as i32)),
+            title: Set(title),
+            shortcut: Set(shortcut),
+            content: Set(content),
+            usage_count: Set(0),
+            created_by: Set(created_by as i32),
+            created_at: Set(now),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+        Ok(record.insert(db).await?)
+    }
+
+    pub async fn update(
+        db: &DatabaseConnection,
+        record: canned_responses::Model,
+        title: String,
+        shortcut: Option<String>,
+        content: String,
+    ) -> Result<canned_responses::Model> {
+        let mut active: canned_responses::ActiveModel = record.into();
+        active.title = Set(title);
+        active.shortcut = Set(shortcut);
+        active.content = Set(content);
+        active.updated_at = Set(chrono::Utc::now().naive_utc());
+        Ok(active.update(db).await?)
+    }
+
+    pub async fn delete(db: &DatabaseConnection, id: i64) -> Result<()> {
+        CannedResponses::delete_by_id(id as i32).exec(db).await?;
+        Ok(())
+    }
+
+    /// 使用次数 +1（用于按常用程度排序）
+    pub async fn increment_usage(db: &DatabaseConnection, id: i64) -> Result<()> {
+        CannedResponses::update_many()
+            .col_expr(
+                canned_responses::Column::UsageCount,
+                Expr::col(canned_responses::Column::UsageCount).add(1),
+            )
+            .filter(canned_responses::Column::Id.eq(id as i32))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// 模板变量取值：会话的客户名 / 店铺名 / 客服名
+    ///
+    /// shops 实体的列名与线上表不一致（name / shop_name），这里直接联表查询
+    pub async fn find_variables(db: &DatabaseConnection, session_id: i64, staff_id: i64) -> Result<Option<TemplateVariablesRow>> {
+        let row = TemplateVariablesRow::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"SELECT c.customer_name,
+                      c.customer_id AS customer_code,
+                      sh.shop_name,
+                      (SELECT u.username FROM users u WHERE u.id = ?) AS agent_name
+               FROM sessions s
+               JOIN customers c ON c.id = s.customer_id
+               LEFT JOIN shops sh ON sh.id = s.shop_id
+               WHERE s.id = ?"#,
+            [staff_id.into(), session_id.into()],
+        ))
+        .one(db)
+        .await?;
+        Ok(row)
+    }
+}
+
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct TemplateVariablesRow {
+    pub customer_name: Option<String>,
+    pub customer_code: String,
+    pub shop_name: Option<String>,
+    pub agent_name: Option<String>,
+}
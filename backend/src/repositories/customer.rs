@@ -2,10 +2,22 @@
 
 use anyhow::Result;
 use sea_orm::*;
-use crate::entities::{customers, sessions, messages, unread_counts, prelude::*};
+use std::collections::{BTreeMap, HashMap};
+
+use sea_orm::sea_query::{Expr, OnConflict};
+use crate::entities::{customer_attributes, customer_tags, customers, sessions, messages, unread_counts, prelude::*};
 
 pub struct CustomerRepository;
 
+/// 客户列表过滤条件：标签需全部命中；attr_value 为空时只要求存在该属性
+#[derive(Debug, Clone, Default)]
+pub struct CustomerFilter<'a> {
+    pub keyword: Option<&'a str>,
+    pub tags: &'a [String],
+    pub attr_key: Option<&'a str>,
+    pub attr_value: Option<&'a str>,
+}
+
 impl CustomerRepository {
     /// 根据 ID 查找客户
     pub async fn find_by_id(db: &DatabaseConnection, id: i32) -> Result<Option<customers::Model>> {
@@ -193,18 +205,11 @@ impl CustomerRepository {
     }
 
     /// 统计店铺下的客户总数
-    pub async fn count_by_shop(db: &DatabaseConnection, shop_id: i32, keyword: Option<&str>) -> Result<i64> {
+    pub async fn count_by_shop(db: &DatabaseConnection, shop_id: i32, filter: &CustomerFilter<'_>) -> Result<i64> {
         let mut query = Customers::find()
             .filter(customers::Column::ShopId.eq(shop_id));
 
-        if let Some(kw) = keyword.and_then(|s| if s.trim().is_empty() { None } else { Some(s) }) {
-            query = query.filter(
-                Condition::any()
-                    .add(customers::Column::CustomerName.contains(kw))
-                    .add(customers::Column::CustomerEmail.contains(kw))
-                    .add(customers::Column::CustomerId.contains(kw))
-            );
-        }
+        query = query.filter(Self::filter_condition(filter));
 
         let count = query.count(db).await?;
         Ok(count as i64)
@@ -216,21 +221,14 @@ impl CustomerRepository {
         shop_id: i32,
         limit: i64,
         offset: i64,
-        keyword: Option<&str>,
+        filter: &CustomerFilter<'_>,
         sort: Option<&str>,
     ) -> Result<Vec<(customers::Model, Option<sessions::Model>, Option<messages::Model>, i64)>> {
-        // 1. 分页获取客户列表（可选关键字过滤 + 排序）
+        // 1. 分页获取客户列表（可选关键字 / 标签 / 属性过滤 + 排序）
         let mut query = Customers::find()
             .filter(customers::Column::ShopId.eq(shop_id));
 
-        if let Some(kw) = keyword.and_then(|s| if s.trim().is_empty() { None } else { Some(s) }) {
-            query = query.filter(
-                Condition::any()
-                    .add(customers::Column::CustomerName.contains(kw))
-                    .add(customers::Column::CustomerEmail.contains(kw))
-                    .add(customers::Column::CustomerId.contains(kw))
-            );
-        }
+        query = query.filter(Self::filter_condition(filter));
 
         // 排序：last_active_desc(默认) | name_asc | name_desc
         match sort.unwrap_or("last_active_desc").to_lowercase().as_str() {
@@ -290,8 +288,14 @@ impl CustomerRepository {
         Ok(result)
     }
     
-    /// 阻止客户
-    pub async fn block(db: &DatabaseConnection, customer_id: i32) -> Result<()> {
+    /// 阻止客户（记录原因、到期时间与操作人；到期时间为空表示永久）
+    pub async fn block(
+        db: &DatabaseConnection,
+        customer_id: i32,
+        reason: Option<String>,
+        until: Option<chrono::NaiveDateTime>,
+        operator_user_id: i32,
+    ) -> Result<customers::Model> {
         let mut customer: customers::ActiveModel = Customers::find_by_id(customer_id)
             .one(db)
             .await?
@@ -300,14 +304,16 @@ impl CustomerRepository {
         
         // 数据库使用 status 字段，1=活跃，0=禁用
         customer.status = Set(Some(0)); // 设置为禁用
-        customer.last_active_at = Set(Some(chrono::Utc::now().naive_utc()));
-        customer.update(db).await?;
+        customer.blocked_reason = Set(reason);
+        customer.blocked_until = Set(until);
+        customer.blocked_at = Set(Some(chrono::Utc::now().naive_utc()));
+        customer.blocked_by = Set(Some(operator_user_id));
         
-        Ok(())
+        Ok(customer.update(db).await?)
     }
     
-    /// 解除阻止
-    pub async fn unblock(db: &DatabaseConnection, customer_id: i32) -> Result<()> {
+    /// 解除阻止（清空拉黑信息）
+    pub async fn unblock(db: &DatabaseConnection, customer_id: i32) -> Result<customers::Model> {
         let mut customer: customers::ActiveModel = Customers::find_by_id(customer_id)
             .one(db)
             .await?
@@ -316,10 +322,12 @@ impl CustomerRepository {
         
         // 数据库使用 status 字段，1=活跃，0=禁用
         customer.status = Set(Some(1)); // 设置为活跃
-        customer.last_active_at = Set(Some(chrono::Utc::now().naive_utc()));
-        customer.update(db).await?;
+        customer.blocked_reason = Set(None);
+        customer.blocked_until = Set(None);
+        customer.blocked_at = Set(None);
+        customer.blocked_by = Set(None);
         
-        Ok(())
+        Ok(customer.update(db).await?)
     }
     
     /// 统计店铺的活跃客户数（最近7天）
@@ -335,6 +343,163 @@ impl CustomerRepository {
         Ok(count)
     }
     
+    fn filter_condition(filter: &CustomerFilter<'_>) -> Condition {
+        let mut condition = Condition::all();
+        if let Some(kw) = filter.keyword.filter(|s| !s.trim().is_empty()) {
+            condition = condition.add(
+                Condition::any()
+                    .add(customers::Column::CustomerName.contains(kw))
+                    .add(customers::Column::CustomerEmail.contains(kw))
+                    .add(customers::Column::CustomerId.contains(kw))
+            );
+        }
+        for tag in filter.tags {
+            condition = condition.add(Expr::cust_with_values(
+                "EXISTS (SELECT 1 FROM customer_tags t WHERE t.customer_id = customers.id AND t.tag = ?)",
+                [tag.clone()],
+            ));
+        }
+        if let Some(key) = filter.attr_key {
+            condition = condition.add(match filter.attr_value {
+                Some(value) => Expr::cust_with_values(
+                    "EXISTS (SELECT 1 FROM customer_attributes a WHERE a.customer_id = customers.id AND a.attr_key = ? AND a.attr_value = ?)",
+                    [key.to_string(), value.to_string()],
+                ),
+                None => Expr::cust_with_values(
+                    "EXISTS (SELECT 1 FROM customer_attributes a WHERE a.customer_id = customers.id AND a.attr_key = ?)",
+                    [key.to_string()],
+                ),
+            });
+        }
+        condition
+    }
+
+    /// 批量获取客户标签（按客户 id 分组，标签按名称排序）
+    pub async fn find_tags_by_customers(db: &DatabaseConnection, customer_ids: &[i32]) -> Result<HashMap<i32, Vec<String>>> {
+        let rows = CustomerTags::find()
+            .filter(customer_tags::Column::CustomerId.is_in(customer_ids.iter().copied()))
+            .order_by_asc(customer_tags::Column::Tag)
+            .all(db)
+            .await?;
+        let mut grouped: HashMap<i32, Vec<String>> = HashMap::new();
+        for row in rows {
+            grouped.entry(row.customer_id).or_default().push(row.tag);
+        }
+        Ok(grouped)
+    }
+
+    /// 批量获取客户自定义属性（按客户 id 分组）
+    pub async fn find_attributes_by_customers(
+        db: &DatabaseConnection,
+        customer_ids: &[i32],
+    ) -> Result<HashMap<i32, BTreeMap<String, String>>> {
+        let rows = CustomerAttributes::find()
+            .filter(customer_attributes::Column::CustomerId.is_in(customer_ids.iter().copied()))
+            .all(db)
+            .await?;
+        let mut grouped: HashMap<i32, BTreeMap<String, String>> = HashMap::new();
+        for row in rows {
+            grouped.entry(row.customer_id).or_default().insert(row.attr_key, row.attr_value);
+        }
+        Ok(grouped)
+    }
+
+    /// 用给定集合替换客户的全部标签
+    pub async fn replace_tags(db: &DatabaseConnection, shop_id: i32, customer_id: i32, tags: &[String]) -> Result<()> {
+        let txn = db.begin().await?;
+        CustomerTags::delete_many()
+            .filter(customer_tags::Column::CustomerId.eq(customer_id))
+            .exec(&txn)
+            .await?;
+        Self::insert_tags(&txn, shop_id, customer_id, tags).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// 追加标签，已存在的忽略
+    pub async fn add_tags(db: &DatabaseConnection, shop_id: i32, customer_id: i32, tags: &[String]) -> Result<()> {
+        Self::insert_tags(db, shop_id, customer_id, tags).await
+    }
+
+    async fn insert_tags<C: ConnectionTrait>(db: &C, shop_id: i32, customer_id: i32, tags: &[String]) -> Result<()> {
+        if tags.is_empty() {
+            return Ok(());
+        }
+        let now = chrono::Utc::now().naive_utc();
+        let models = tags.iter().map(|tag| customer_tags::ActiveModel {
+            shop_id: Set(shop_id),
+            customer_id: Set(customer_id),
+            tag: Set(tag.clone()),
+            created_at: Set(now),
+            ..Default::default()
+        });
+        CustomerTags::insert_many(models)
+            .on_conflict(
+                OnConflict::columns([customer_tags::Column::CustomerId, customer_tags::Column::Tag])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .do_nothing()
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// 合并客户属性：upserts 写入（覆盖同名键），removals 删除
+    pub async fn merge_attributes(
+        db: &DatabaseConnection,
+        shop_id: i32,
+        customer_id: i32,
+        upserts: &[(String, String)],
+        removals: &[String],
+    ) -> Result<()> {
+        let txn = db.begin().await?;
+        if !removals.is_empty() {
+            CustomerAttributes::delete_many()
+                .filter(customer_attributes::Column::CustomerId.eq(customer_id))
+                .filter(customer_attributes::Column::AttrKey.is_in(removals.iter().cloned()))
+                .exec(&txn)
+                .await?;
+        }
+        if !upserts.is_empty() {
+            let now = chrono::Utc::now().naive_utc();
+            let models = upserts.iter().map(|(key, value)| customer_attributes::ActiveModel {
+                shop_id: Set(shop_id),
+                customer_id: Set(customer_id),
+                attr_key: Set(key.clone()),
+                attr_value: Set(value.clone()),
+                updated_at: Set(now),
+                ..Default::default()
+            });
+            CustomerAttributes::insert_many(models)
+                .on_conflict(
+                    OnConflict::columns([customer_attributes::Column::CustomerId, customer_attributes::Column::AttrKey])
+                        .update_columns([customer_attributes::Column::AttrValue, customer_attributes::Column::UpdatedAt])
+                        .to_owned(),
+                )
+                .exec(&txn)
+                .await?;
+        }
+        txn.commit().await?;
+        Ok(())
+    }
+
+    /// 店铺内使用中的标签及对应客户数（按客户数降序）
+    pub async fn count_tags_by_shop(db: &DatabaseConnection, shop_id: i32) -> Result<Vec<(String, i64)>> {
+        let rows: Vec<(String, i64)> = CustomerTags::find()
+            .select_only()
+            .column(customer_tags::Column::Tag)
+            .column_as(Expr::col(customer_tags::Column::Id).count(), "customers")
+            .filter(customer_tags::Column::ShopId.eq(shop_id))
+            .group_by(customer_tags::Column::Tag)
+            .order_by_desc(Expr::cust("customers"))
+            .order_by_asc(customer_tags::Column::Tag)
+            .into_tuple()
+            .all(db)
+            .await?;
+        Ok(rows)
+    }
+
     /// 搜索客户
     pub async fn search(
         db: &DatabaseConnection,
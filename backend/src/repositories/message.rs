@@ -2,7 +2,8 @@
 
 use anyhow::Result;
 use sea_orm::{*, sea_query::Expr};
-use crate::entities::{messages, prelude::*};
+use crate::constants::message_policy::NOTE_SENDER_TYPE;
+use crate::entities::{message_edits, messages, prelude::*};
 
 pub struct MessageRepository;
 
@@ -18,6 +19,8 @@ impl MessageRepository {
         content: String,
         file_url: Option<String>,
         file_name: Option<String>,
+        reply_to: Option<i32>,
+        rich_content: Option<serde_json::Value>,
     ) -> Result<messages::Model> {
         eprintln!("🔍 MessageRepository::create - session_id: {}, sender_type: {}, message_type: {}, content: {}", 
                   session_id, sender_type, message_type, &content[..content.len().min(50)]);
@@ -42,6 +45,8 @@ impl MessageRepository {
             message_type: Set(message_type),
             content: Set(content),
             metadata: Set(metadata),
+            rich_content: Set(rich_content),
+            reply_to: Set(reply_to),
             is_read: Set(false),
             is_deleted: Set(false),
             created_at: Set(now),
@@ -61,6 +66,44 @@ impl MessageRepository {
         }
     }
     
+    /// 获取会话中 id 大于游标的消息（按 id 升序，不含内部备注），用于客户断线重连补发
+    pub async fn find_by_session_after(
+        db: &DatabaseConnection,
+        session_id: i32,
+        after_id: i32,
+        limit: u64,
+    ) -> Result<Vec<messages::Model>> {
+        let messages = Messages::find()
+            .filter(messages::Column::SessionId.eq(session_id))
+            .filter(messages::Column::Id.gt(after_id))
+            .filter(messages::Column::IsDeleted.eq(false))
+            .filter(messages::Column::SenderType.ne(NOTE_SENDER_TYPE))
+            .order_by_asc(messages::Column::Id)
+            .limit(limit)
+            .all(db)
+            .await?;
+        Ok(messages)
+    }
+
+    /// 获取店铺所有会话中 id 大于游标的消息（按 id 升序），用于客服断线重连补发
+    pub async fn find_by_shop_after(
+        db: &DatabaseConnection,
+        shop_id: i32,
+        after_id: i32,
+        limit: u64,
+    ) -> Result<Vec<(messages::Model, Option<crate::entities::sessions::Model>)>> {
+        let rows = Messages::find()
+            .find_also_related(Sessions)
+            .filter(crate::entities::sessions::Column::ShopId.eq(shop_id))
+            .filter(messages::Column::Id.gt(after_id))
+            .filter(messages::Column::IsDeleted.eq(false))
+            .order_by_asc(messages::Column::Id)
+            .limit(limit)
+            .all(db)
+            .await?;
+        Ok(rows)
+    }
+
     /// 获取会话的所有消息
     pub async fn find_by_session(
         db: &DatabaseConnection,
@@ -106,6 +149,83 @@ impl MessageRepository {
         Ok(())
     }
     
+    /// 将会话中对方发送、id 不超过 up_to_id 的未读消息标记为已读，返回受影响行数
+    pub async fn mark_read_up_to(
+        db: &DatabaseConnection,
+        session_id: i32,
+        reader_type: &str,
+        up_to_id: i32,
+    ) -> Result<u64> {
+        let result = Messages::update_many()
+            .filter(messages::Column::SessionId.eq(session_id))
+            .filter(messages::Column::Id.lte(up_to_id))
+            .filter(messages::Column::SenderType.ne(reader_type))
+            .filter(messages::Column::SenderType.ne(NOTE_SENDER_TYPE))
+            .filter(messages::Column::IsRead.eq(false))
+            .col_expr(messages::Column::IsRead, Expr::value(true))
+            .col_expr(messages::Column::ReadAt, Expr::value(chrono::Utc::now().naive_utc()))
+            .col_expr(messages::Column::Status, Expr::value("read"))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    /// 将会话中对方发送、id 不超过 up_to_id 且尚未送达/已读的消息标记为已送达
+    pub async fn mark_delivered_up_to(
+        db: &DatabaseConnection,
+        session_id: i32,
+        reader_type: &str,
+        up_to_id: i32,
+    ) -> Result<u64> {
+        let result = Messages::update_many()
+            .filter(messages::Column::SessionId.eq(session_id))
+            .filter(messages::Column::Id.lte(up_to_id))
+            .filter(messages::Column::SenderType.ne(reader_type))
+            .filter(messages::Column::SenderType.ne(NOTE_SENDER_TYPE))
+            .filter(messages::Column::IsRead.eq(false))
+            .filter(
+                Condition::any()
+                    .add(messages::Column::Status.is_null())
+                    .add(messages::Column::Status.eq("sent")),
+            )
+            .col_expr(messages::Column::Status, Expr::value("delivered"))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
+    /// 将店铺内某客户（为空时为全部客户）发来的消息标记为已读，可限定 id 上限，返回受影响行数
+    pub async fn mark_customer_messages_read(
+        db: &DatabaseConnection,
+        shop_id: i32,
+        customer_id: Option<i32>,
+        up_to_id: Option<i32>,
+    ) -> Result<u64> {
+        let mut sessions_query = sea_orm::sea_query::Query::select()
+            .column(crate::entities::sessions::Column::Id)
+            .from(Sessions)
+            .and_where(Expr::col(crate::entities::sessions::Column::ShopId).eq(shop_id))
+            .to_owned();
+        if let Some(customer_id) = customer_id {
+            sessions_query.and_where(Expr::col(crate::entities::sessions::Column::CustomerId).eq(customer_id));
+        }
+
+        let mut update = Messages::update_many()
+            .filter(messages::Column::SessionId.in_subquery(sessions_query))
+            .filter(messages::Column::SenderType.eq("customer"))
+            .filter(messages::Column::IsRead.eq(false));
+        if let Some(up_to_id) = up_to_id {
+            update = update.filter(messages::Column::Id.lte(up_to_id));
+        }
+        let result = update
+            .col_expr(messages::Column::IsRead, Expr::value(true))
+            .col_expr(messages::Column::ReadAt, Expr::value(chrono::Utc::now().naive_utc()))
+            .col_expr(messages::Column::Status, Expr::value("read"))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+
     /// 获取未读消息数量
     pub async fn count_unread(db: &DatabaseConnection, session_id: i32, user_type: &str) -> Result<u64> {
         let count = Messages::find()
@@ -153,41 +273,109 @@ impl MessageRepository {
         Ok(message.insert(db).await?)
     }
     
+    /// 查找会话内未删除的消息（用于校验引用回复的目标）
+    pub async fn find_in_session(db: &DatabaseConnection, session_id: i32, message_id: i32) -> Result<Option<messages::Model>> {
+        let message = Messages::find_by_id(message_id)
+            .filter(messages::Column::SessionId.eq(session_id))
+            .filter(messages::Column::IsDeleted.eq(false))
+            .one(db)
+            .await?;
+        Ok(message)
+    }
+    
     /// 根据 ID 查找消息
     pub async fn find_by_id(db: &DatabaseConnection, id: i32) -> Result<Option<messages::Model>> {
         let message = Messages::find_by_id(id).one(db).await?;
         Ok(message)
     }
     
-    /// 软删除消息
-    pub async fn soft_delete(db: &DatabaseConnection, message_id: i32) -> Result<()> {
+    /// 软删除消息（撤回 / 删除共用），返回删除后的消息
+    pub async fn soft_delete(db: &DatabaseConnection, message_id: i32) -> Result<messages::Model> {
         let mut message: messages::ActiveModel = Messages::find_by_id(message_id)
             .one(db)
             .await?
             .ok_or_else(|| anyhow::anyhow!("Message not found"))?
             .into();
         
+        let now = chrono::Utc::now().naive_utc();
         message.is_deleted = Set(true);
-        message.updated_at = Set(Some(chrono::Utc::now().naive_utc()));
-        message.update(db).await?;
+        message.deleted_at = Set(Some(now));
+        message.updated_at = Set(Some(now));
+        let message = message.update(db).await?;
         
-        Ok(())
+        Ok(message)
     }
     
-    /// 批量软删除消息
-    pub async fn soft_delete_many(db: &DatabaseConnection, message_ids: Vec<i32>) -> Result<()> {
-        if message_ids.is_empty() {
-            return Ok(());
+    /// 批量软删除：同一事务内完成，任一条失败则全部回滚；已删除或不存在的消息跳过
+    pub async fn soft_delete_batch(db: &DatabaseConnection, message_ids: &[i32]) -> Result<Vec<messages::Model>> {
+        let now = chrono::Utc::now().naive_utc();
+        let txn = db.begin().await?;
+        let mut deleted = Vec::new();
+        for message_id in message_ids {
+            let Some(message) = Messages::find_by_id(*message_id).one(&txn).await? else {
+                continue;
+            };
+            if message.is_deleted {
+                continue;
+            }
+            let mut active: messages::ActiveModel = message.into();
+            active.is_deleted = Set(true);
+            active.deleted_at = Set(Some(now));
+            active.updated_at = Set(Some(now));
+            deleted.push(active.update(&txn).await?);
         }
+        txn.commit().await?;
+        Ok(deleted)
+    }
+    
+    /// 编辑消息内容：同一事务内写入编辑历史（保存旧内容）并更新消息，
+    /// metadata.edited_at 记录最近一次编辑时间
+    pub async fn update_content(
+        db: &DatabaseConnection,
+        message: messages::Model,
+        content: String,
+        editor_type: &str,
+        editor_id: i32,
+    ) -> Result<messages::Model> {
+        let now = chrono::Utc::now();
+        let txn = db.begin().await?;
         
-        Messages::update_many()
-            .filter(messages::Column::Id.is_in(message_ids))
-            .col_expr(messages::Column::IsDeleted, Expr::value(true))
-            .col_expr(messages::Column::UpdatedAt, Expr::value(chrono::Utc::now().naive_utc()))
-            .exec(db)
-            .await?;
+        message_edits::ActiveModel {
+            message_id: Set(message.id),
+            session_id: Set(message.session_id),
+            editor_type: Set(editor_type.to_string()),
+            editor_id: Set(editor_id),
+            previous_content: Set(message.content.clone()),
+            edited_at: Set(now.naive_utc()),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await?;
         
-        Ok(())
+        let mut metadata = match message.metadata.clone() {
+            Some(serde_json::Value::Object(map)) => map,
+            _ => serde_json::Map::new(),
+        };
+        metadata.insert("edited_at".to_string(), serde_json::json!(now));
+        
+        let mut active: messages::ActiveModel = message.into();
+        active.content = Set(content);
+        active.metadata = Set(Some(serde_json::Value::Object(metadata)));
+        active.updated_at = Set(Some(now.naive_utc()));
+        let updated = active.update(&txn).await?;
+        
+        txn.commit().await?;
+        Ok(updated)
+    }
+    
+    /// 消息的编辑历史（最早的在前）
+    pub async fn find_edits(db: &DatabaseConnection, message_id: i32) -> Result<Vec<message_edits::Model>> {
+        let edits = MessageEdits::find()
+            .filter(message_edits::Column::MessageId.eq(message_id))
+            .order_by_asc(message_edits::Column::Id)
+            .all(db)
+            .await?;
+        Ok(edits)
     }
     
     /// 获取会话的最后一条消息
@@ -238,22 +426,115 @@ impl MessageRepository {
         }
     }
     
-    /// 搜索消息
+    /// 店铺内全文搜索（messages_fts，trigram 分词），按消息 id 倒序分页，返回 (命中行, 总数)
     pub async fn search(
         db: &DatabaseConnection,
-        shop_id: i32,
-        keyword: &str,
-        limit: Option<u64>,
-    ) -> Result<Vec<messages::Model>> {
-        let mut query = Messages::find()
-            .filter(messages::Column::Content.contains(keyword))
-            .filter(messages::Column::IsDeleted.eq(false))
-            .order_by_desc(messages::Column::CreatedAt);
-        
-        if let Some(l) = limit {
-            query = query.limit(l);
+        filter: &MessageSearchFilter,
+        limit: u64,
+        offset: u64,
+    ) -> Result<(Vec<MessageSearchRow>, i64)> {
+        let mut conditions = vec!["s.shop_id = ?".to_string(), "m.is_deleted = 0".to_string()];
+        let mut values: Vec<Value> = vec![filter.shop_id.into()];
+        if let Some(match_query) = &filter.match_query {
+            conditions.push("messages_fts MATCH ?".to_string());
+            values.push(match_query.clone().into());
         }
-        
-        Ok(query.all(db).await?)
+        // trigram 索引无法匹配不足 3 个字符的词，退化为在索引表上 LIKE
+        for term in &filter.like_terms {
+            conditions.push(r"(f.content LIKE ? ESCAPE '\' OR f.customer_name LIKE ? ESCAPE '\')".to_string());
+            let pattern = format!("%{}%", term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+            values.push(pattern.clone().into());
+            values.push(pattern.into());
+        }
+        // created_at 统一为 'YYYY-MM-DD HH:MM:SS[.f]'（旧的 'T' 格式由迁移回填），边界值按同一格式直接比较，可走 idx_messages_created_at
+        if let Some(from) = filter.from {
+            conditions.push("m.created_at >= ?".to_string());
+            values.push(from.format("%Y-%m-%d %H:%M:%S").to_string().into());
+        }
+        if let Some(to) = filter.to {
+            conditions.push("m.created_at < ?".to_string());
+            values.push(to.format("%Y-%m-%d %H:%M:%S").to_string().into());
+        }
+        if let Some(sender_type) = &filter.sender_type {
+            conditions.push("m.sender_type = ?".to_string());
+            values.push(sender_type.clone().into());
+        }
+        if let Some(message_type) = &filter.message_type {
+            conditions.push("m.message_type = ?".to_string());
+            values.push(message_type.clone().into());
+        }
+        if let Some(staff_id) = filter.staff_id {
+            conditions.push("m.sender_type = 'staff' AND m.sender_id = ?".to_string());
+            values.push(staff_id.into());
+        }
+        let from_where = format!(
+            r#"FROM messages_fts f
+               JOIN messages m ON m.id = f.rowid
+               JOIN sessions s ON s.id = m.session_id
+               LEFT JOIN customers c ON c.id = s.customer_id
+               WHERE {}"#,
+            conditions.join(" AND ")
+        );
+
+        let total = db
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                format!("SELECT COUNT(*) AS total {}", from_where),
+                values.clone(),
+            ))
+            .await?
+            .map(|row| row.try_get::<i64>("", "total"))
+            .transpose()?
+            .unwrap_or(0);
+
+        let mut page_values = values;
+        page_values.push((limit as i64).into());
+        page_values.push((offset as i64).into());
+        let rows = MessageSearchRow::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            format!(
+                r#"SELECT m.id, m.session_id, m.sender_type, m.sender_id, m.message_type, m.content, m.created_at,
+                          s.customer_id, s.staff_id,
+                          c.customer_id AS customer_code,
+                          c.customer_name
+                   {}
+                   ORDER BY m.id DESC
+                   LIMIT ? OFFSET ?"#,
+                from_where
+            ),
+            page_values,
+        ))
+        .all(db)
+        .await?;
+
+        Ok((rows, total))
     }
 }
+
+/// 全文搜索条件；match_query 为 FTS5 查询串（已转义），like_terms 为不足 3 个字符的关键词
+#[derive(Debug, Clone, Default)]
+pub struct MessageSearchFilter {
+    pub shop_id: i64,
+    pub match_query: Option<String>,
+    pub like_terms: Vec<String>,
+    pub from: Option<chrono::NaiveDateTime>,
+    pub to: Option<chrono::NaiveDateTime>,
+    pub sender_type: Option<String>,
+    pub message_type: Option<String>,
+    pub staff_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct MessageSearchRow {
+    pub id: i32,
+    pub session_id: i32,
+    pub sender_type: String,
+    pub sender_id: Option<i32>,
+    pub message_type: String,
+    pub content: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub customer_id: i32,
+    pub staff_id: Option<i32>,
+    pub customer_code: Option<String>,
+    pub customer_name: Option<String>,
+}
@@ -14,6 +14,9 @@ pub mod session;
 pub mod message;
 pub mod shop_staff;
 pub mod unread_count_repository;
+pub mod online_status;
+pub mod routing;
+pub mod canned_response;
 
 pub use user::UserRepository;
 pub use shop::ShopRepository;
@@ -22,3 +25,6 @@ pub use session::SessionRepository;
 pub use message::MessageRepository;
 pub use shop_staff::ShopStaffRepository;
 pub use unread_count_repository::UnreadCountRepository;
+pub use online_status::OnlineStatusRepository;
+pub use routing::RoutingRepository;
+pub use canned_response::CannedResponseRepository;
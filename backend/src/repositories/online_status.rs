@@ -0,0 +1,257 @@
+//! OnlineStatus Repository - 在线状态数据访问层
+
+use anyhow::Result;
+use sea_orm::*;
+use crate::entities::{online_status, prelude::*};
+
+pub struct OnlineStatusRepository;
+
+impl OnlineStatusRepository {
+    /// 标记上线：存在记录则更新，否则插入
+    pub async fn mark_online(
+        db: &DatabaseConnection,
+        user_type: &str,
+        user_id: i32,
+        shop_id: i32,
+        websocket_id: Option<&str>,
+    ) -> Result<online_status::Model> {
+        let now = chrono::Utc::now().naive_utc();
+        let existing = Self::find(db, user_type, user_id, shop_id).await?;
+
+        let model = match existing {
+            Some(record) => {
+                let mut active: online_status::ActiveModel = record.into();
+                active.websocket_id = Set(websocket_id.map(|id| id.to_string()));
+                active.status = Set(Some("online".to_string()));
+                active.last_ping_at = Set(Some(now));
+                active.last_seen = Set(Some(now));
+                active.update(db).await?
+            }
+            None => {
+                online_status::ActiveModel {
+                    user_type: Set(user_type.to_string()),
+                    user_id: Set(user_id),
+                    shop_id: Set(Some(shop_id)),
+                    websocket_id: Set(websocket_id.map(|id| id.to_string())),
+                    last_ping_at: Set(Some(now)),
+                    status: Set(Some("online".to_string())),
+                    last_seen: Set(Some(now)),
+                    ..Default::default()
+                }
+                .insert(db)
+                .await?
+            }
+        };
+        Ok(model)
+    }
+
+    /// 标记离线并记录 last_seen
+    pub async fn mark_offline(
+        db: &DatabaseConnection,
+        user_type: &str,
+        user_id: i32,
+        shop_id: i32,
+    ) -> Result<Option<online_status::Model>> {
+        let Some(record) = Self::find(db, user_type, user_id, shop_id).await? else {
+            return Ok(None);
+        };
+        let now = chrono::Utc::now().naive_utc();
+        let mut active: online_status::ActiveModel = record.into();
+        active.websocket_id = Set(None);
+        active.status = Set(Some("offline".to_string()));
+        active.last_seen = Set(Some(now));
+        Ok(Some(active.update(db).await?))
+    }
+
+    /// 心跳：刷新 last_ping_at / last_seen
+    pub async fn touch(db: &DatabaseConnection, user_type: &str, user_id: i32, shop_id: i32) -> Result<()> {
+        let now = chrono::Utc::now().naive_utc();
+        OnlineStatus::update_many()
+            .col_expr(online_status::Column::LastPingAt, sea_query::Expr::value(now))
+            .col_expr(online_status::Column::LastSeen, sea_query::Expr::value(now))
+            .filter(online_status::Column::UserType.eq(user_type))
+            .filter(online_status::Column::UserId.eq(user_id))
+            .filter(online_status::Column::ShopId.eq(shop_id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn find(
+        db: &DatabaseConnection,
+        user_type: &str,
+        user_id: i32,
+        shop_id: i32,
+    ) -> Result<Option<online_status::Model>> {
+        let record = OnlineStatus::find()
+            .filter(online_status::Column::UserType.eq(user_type))
+            .filter(online_status::Column::UserId.eq(user_id))
+            .filter(online_status::Column::ShopId.eq(shop_id))
+            .one(db)
+            .await?;
+        Ok(record)
+    }
+
+    /// 店铺内所有在线状态记录（附带客服用户名 / 客户编码与昵称）
+    ///
+    /// live：任一节点上仍有未失效的连接（last_ping_at 在 stale_secs 内）
+    pub async fn find_by_shop(db: &DatabaseConnection, shop_id: i32, stale_secs: i64) -> Result<Vec<PresenceRow>> {
+        let rows = PresenceRow::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"SELECT o.user_type, o.user_id, o.status, o.last_seen,
+                      u.username AS staff_name,
+                      c.customer_id AS customer_code,
+                      c.customer_name,
+                      EXISTS (SELECT 1 FROM presence_connections p
+                              WHERE p.shop_id = o.shop_id AND p.user_type = o.user_type AND p.user_id = o.user_id
+                                AND p.last_ping_at > datetime('now', ?)) AS live
+               FROM online_status o
+               LEFT JOIN users u ON o.user_type = 'staff' AND u.id = o.user_id
+               LEFT JOIN customers c ON o.user_type = 'customer' AND c.id = o.user_id
+               WHERE o.shop_id = ?
+               ORDER BY o.last_seen DESC"#,
+            [stale_window(stale_secs).into(), shop_id.into()],
+        ))
+        .all(db)
+        .await?;
+        Ok(rows)
+    }
+
+    /// 记录一条连接（同一连接重复认证时只刷新心跳）
+    pub async fn add_connection(
+        db: &DatabaseConnection,
+        connection_id: &str,
+        node_id: &str,
+        user_type: &str,
+        user_id: i32,
+        shop_id: i32,
+    ) -> Result<()> {
+        db.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"INSERT INTO presence_connections (connection_id, node_id, user_type, user_id, shop_id, last_ping_at)
+               VALUES (?, ?, ?, ?, ?, CURRENT_TIMESTAMP)
+               ON CONFLICT(connection_id) DO UPDATE SET last_ping_at = CURRENT_TIMESTAMP"#,
+            [connection_id.into(), node_id.into(), user_type.into(), user_id.into(), shop_id.into()],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    pub async fn remove_connection(db: &DatabaseConnection, connection_id: &str) -> Result<()> {
+        db.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "DELETE FROM presence_connections WHERE connection_id = ?",
+            [connection_id.into()],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// 所有节点上该用户在该店铺未失效的连接数
+    pub async fn count_live_connections(
+        db: &DatabaseConnection,
+        user_type: &str,
+        user_id: i32,
+        shop_id: i32,
+        stale_secs: i64,
+    ) -> Result<i64> {
+        let row = db
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"SELECT COUNT(*) AS n FROM presence_connections
+                   WHERE user_type = ? AND user_id = ? AND shop_id = ? AND last_ping_at > datetime('now', ?)"#,
+                [user_type.into(), user_id.into(), shop_id.into(), stale_window(stale_secs).into()],
+            ))
+            .await?;
+        Ok(row.map(|r| r.try_get::<i64>("", "n")).transpose()?.unwrap_or(0))
+    }
+
+    /// 店铺内在任一节点上有未失效连接的客服（去重，按 user_id 升序）
+    pub async fn live_staff_in_shop(db: &DatabaseConnection, shop_id: i32, stale_secs: i64) -> Result<Vec<i64>> {
+        let rows = db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"SELECT DISTINCT user_id FROM presence_connections
+                   WHERE user_type = 'staff' AND shop_id = ? AND last_ping_at > datetime('now', ?)
+                   ORDER BY user_id"#,
+                [shop_id.into(), stale_window(stale_secs).into()],
+            ))
+            .await?;
+        rows.iter()
+            .map(|r| r.try_get::<i32>("", "user_id").map(i64::from).map_err(Into::into))
+            .collect()
+    }
+
+    /// 刷新本节点全部连接的心跳
+    pub async fn touch_node_connections(db: &DatabaseConnection, node_id: &str) -> Result<u64> {
+        let result = db
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "UPDATE presence_connections SET last_ping_at = CURRENT_TIMESTAMP WHERE node_id = ?",
+                [node_id.into()],
+            ))
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// 删除失效连接（节点崩溃遗留），返回仍标记为 online 但已没有任何未失效连接的 (user_type, user_id, shop_id)
+    pub async fn sweep_stale(db: &DatabaseConnection, stale_secs: i64) -> Result<Vec<(String, i32, i32)>> {
+        db.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            "DELETE FROM presence_connections WHERE last_ping_at <= datetime('now', ?)",
+            [stale_window(stale_secs).into()],
+        ))
+        .await?;
+        let rows = db
+            .query_all(Statement::from_string(
+                DbBackend::Sqlite,
+                r#"SELECT o.user_type, o.user_id, o.shop_id FROM online_status o
+                   WHERE o.status = 'online' AND o.shop_id IS NOT NULL
+                     AND NOT EXISTS (SELECT 1 FROM presence_connections p
+                                     WHERE p.shop_id = o.shop_id AND p.user_type = o.user_type AND p.user_id = o.user_id)"#
+                    .to_string(),
+            ))
+            .await?;
+        rows.iter()
+            .map(|r| {
+                Ok((
+                    r.try_get::<String>("", "user_type")?,
+                    r.try_get::<i32>("", "user_id")?,
+                    r.try_get::<i32>("", "shop_id")?,
+                ))
+            })
+            .collect()
+    }
+
+    /// 单节点启动时清理上次异常退出遗留的在线记录
+    pub async fn reset_all_offline(db: &DatabaseConnection) -> Result<u64> {
+        db.execute(Statement::from_string(
+            DbBackend::Sqlite,
+            "DELETE FROM presence_connections".to_string(),
+        ))
+        .await?;
+        let result = OnlineStatus::update_many()
+            .col_expr(online_status::Column::Status, sea_query::Expr::value("offline"))
+            .col_expr(online_status::Column::WebsocketId, sea_query::Expr::value(Option::<String>::None))
+            .filter(online_status::Column::Status.eq("online"))
+            .exec(db)
+            .await?;
+        Ok(result.rows_affected)
+    }
+}
+
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct PresenceRow {
+    pub user_type: String,
+    pub user_id: i32,
+    pub status: Option<String>,
+    pub last_seen: Option<chrono::NaiveDateTime>,
+    pub staff_name: Option<String>,
+    pub customer_code: Option<String>,
+    pub customer_name: Option<String>,
+    pub live: bool,
+}
+
+fn stale_window(stale_secs: i64) -> String {
+    format!("-{} seconds", stale_secs)
+}
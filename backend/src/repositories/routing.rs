@@ -0,0 +1,150 @@
+//! Routing Repository - 会话自动分配配置与负载统计
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ConnectionTrait, DatabaseConnection, DbBackend, EntityTrait, Set, Statement,
+};
+use crate::entities::{prelude::ShopRoutingSettings, shop_routing_settings};
+
+pub struct RoutingRepository;
+
+impl RoutingRepository {
+    pub async fn find_settings(db: &DatabaseConnection, shop_id: i64) -> Result<Option<shop_routing_settings::Model>> {
+        Ok(ShopRoutingSettings::find_by_id(shop_id as i32).one(db).await?)
+    }
+
+    /// 写入店铺分配策略与默认最大并发（保留轮询位置）
+    pub async fn upsert_settings(
+        db: &DatabaseConnection,
+        shop_id: i64,
+        strategy: &str,
+        default_max_concurrent: i32,
+    ) -> Result<shop_routing_settings::Model> {
+        let now = chrono::Utc::now().naive_utc();
+        let record = shop_routing_settings::ActiveModel {
+            shop_id: Set(shop_id as i32),
+            strategy: Set(strategy.to_string()),
+            default_max_concurrent: Set(default_max_concurrent),
+            last_assigned_user_id: Set(None),
+            updated_at: Set(now),
+        };
+        ShopRoutingSettings::insert(record)
+            .on_conflict(
+                OnConflict::column(shop_routing_settings::Column::ShopId)
+                    .update_columns([
+                        shop_routing_settings::Column::Strategy,
+                        shop_routing_settings::Column::DefaultMaxConcurrent,
+                        shop_routing_settings::Column::UpdatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
+        Self::find_settings(db, shop_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("routing_settings_not_found"))
+    }
+
+    /// 记录轮询策略最近一次分配到的客服
+    pub async fn set_last_assigned(db: &DatabaseConnection, shop_id: i64, user_id: i64) -> Result<()> {
+        let now = chrono::Utc::now().naive_utc();
+        let record = shop_routing_settings::ActiveModel {
+            shop_id: Set(shop_id as i32),
+            strategy: Set(crate::constants::routing_policy::DEFAULT_STRATEGY.to_string()),
+            default_max_concurrent: Set(crate::constants::routing_policy::DEFAULT_MAX_CONCURRENT),
+            last_assigned_user_id: Set(Some(user_id as i32)),
+            updated_at: Set(now),
+        };
+        ShopRoutingSettings::insert(record)
+            .on_conflict(
+                OnConflict::column(shop_routing_settings::Column::ShopId)
+                    .value(shop_routing_settings::Column::LastAssignedUserId, Expr::value(user_id as i32))
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// 店铺内每个客服当前进行中的会话数：user_id -> count
+    pub async fn active_session_counts(db: &DatabaseConnection, shop_id: i64) -> Result<HashMap<i64, i64>> {
+        let rows = db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"SELECT staff_id, COUNT(*) AS active
+                   FROM sessions
+                   WHERE shop_id = ? AND session_status = 'active' AND staff_id IS NOT NULL
+                   GROUP BY staff_id"#,
+                [shop_id.into()],
+            ))
+            .await?;
+        let mut counts = HashMap::new();
+        for row in rows {
+            let staff_id: i64 = row.try_get("", "staff_id")?;
+            let active: i64 = row.try_get("", "active")?;
+            counts.insert(staff_id, active);
+        }
+        Ok(counts)
+    }
+
+    /// 员工单独设置的最大并发会话数：user_id -> max（未设置的员工不在结果中）
+    pub async fn staff_max_concurrent(db: &DatabaseConnection, shop_id: i64) -> Result<HashMap<i64, i32>> {
+        let rows = db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "SELECT user_id, max_concurrent_chats FROM shop_staffs WHERE shop_id = ? AND max_concurrent_chats IS NOT NULL",
+                [shop_id.into()],
+            ))
+            .await?;
+        let mut limits = HashMap::new();
+        for row in rows {
+            let user_id: i64 = row.try_get("", "user_id")?;
+            let max: i32 = row.try_get("", "max_concurrent_chats")?;
+            limits.insert(user_id, max);
+        }
+        Ok(limits)
+    }
+
+    /// 设置员工最大并发会话数（None 表示跟随店铺默认值），返回受影响行数
+    pub async fn set_staff_max_concurrent(
+        db: &DatabaseConnection,
+        shop_id: i64,
+        user_id: i64,
+        max_concurrent: Option<i32>,
+    ) -> Result<u64> {
+        let result = db
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "UPDATE shop_staffs SET max_concurrent_chats = ? WHERE shop_id = ? AND user_id = ?",
+                [max_concurrent.into(), shop_id.into(), user_id.into()],
+            ))
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// 该客户最近一次会话的接待客服（不含当前会话）
+    pub async fn last_agent_for_customer(
+        db: &DatabaseConnection,
+        shop_id: i64,
+        customer_id: i64,
+        exclude_session_id: i64,
+    ) -> Result<Option<i64>> {
+        let row = db
+            .query_one(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"SELECT staff_id FROM sessions
+                   WHERE shop_id = ? AND customer_id = ? AND staff_id IS NOT NULL AND id != ?
+                   ORDER BY COALESCE(closed_at, last_message_at, created_at) DESC, id DESC
+                   LIMIT 1"#,
+                [shop_id.into(), customer_id.into(), exclude_session_id.into()],
+            ))
+            .await?;
+        match row {
+            Some(row) => Ok(Some(row.try_get("", "staff_id")?)),
+            None => Ok(None),
+        }
+    }
+}
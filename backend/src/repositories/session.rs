@@ -52,7 +52,7 @@ impl SessionRepository {
     }
     
     /// 分配客服
-    pub async fn assign_staff(db: &DatabaseConnection, session_id: i32, staff_id: i32) -> Result<()> {
+    pub async fn assign_staff(db: &DatabaseConnection, session_id: i32, staff_id: i32) -> Result<sessions::Model> {
         let mut session: sessions::ActiveModel = Sessions::find_by_id(session_id)
             .one(db)
             .await?
@@ -61,9 +61,27 @@ impl SessionRepository {
         
         session.staff_id = Set(Some(staff_id));
         session.last_message_at = Set(Some(chrono::Utc::now().naive_utc()));
-        session.update(db).await?;
         
-        Ok(())
+        Ok(session.update(db).await?)
+    }
+    
+    /// 仅当会话仍未分配且未关闭时分配客服（并发分配时只有一方成功）
+    pub async fn assign_if_unassigned(
+        db: &DatabaseConnection,
+        session_id: i32,
+        staff_id: i32,
+    ) -> Result<Option<sessions::Model>> {
+        let result = Sessions::update_many()
+            .col_expr(sessions::Column::StaffId, sea_query::Expr::value(staff_id))
+            .filter(sessions::Column::Id.eq(session_id))
+            .filter(sessions::Column::StaffId.is_null())
+            .filter(sessions::Column::SessionStatus.eq("active"))
+            .exec(db)
+            .await?;
+        if result.rows_affected == 0 {
+            return Ok(None);
+        }
+        Self::find_by_id(db, session_id).await
     }
     
     /// 更新最后消息时间
@@ -119,7 +137,7 @@ impl SessionRepository {
     }
     
     /// 关闭会话
-    pub async fn close(db: &DatabaseConnection, session_id: i32) -> Result<()> {
+    pub async fn close(db: &DatabaseConnection, session_id: i32) -> Result<sessions::Model> {
         let mut session: sessions::ActiveModel = Sessions::find_by_id(session_id)
             .one(db)
             .await?
@@ -128,24 +146,36 @@ impl SessionRepository {
         
         session.session_status = Set(Some("closed".to_string()));
         session.closed_at = Set(Some(chrono::Utc::now().naive_utc()));
-        session.update(db).await?;
         
-        Ok(())
+        Ok(session.update(db).await?)
     }
     
-    /// 设置会话优先级 (当前表结构不支持，仅更新时间)
-    pub async fn set_priority(db: &DatabaseConnection, session_id: i32, _priority: i32) -> Result<()> {
+    /// 重新打开已关闭的会话
+    pub async fn reopen(db: &DatabaseConnection, session_id: i32) -> Result<sessions::Model> {
         let mut session: sessions::ActiveModel = Sessions::find_by_id(session_id)
             .one(db)
             .await?
             .ok_or_else(|| anyhow::anyhow!("Session not found"))?
             .into();
         
-        // 由于表结构不支持priority字段，仅更新时间
+        session.session_status = Set(Some("active".to_string()));
+        session.closed_at = Set(None);
         session.last_message_at = Set(Some(chrono::Utc::now().naive_utc()));
-        session.update(db).await?;
         
-        Ok(())
+        Ok(session.update(db).await?)
+    }
+    
+    /// 设置会话优先级
+    pub async fn set_priority(db: &DatabaseConnection, session_id: i32, priority: i32) -> Result<sessions::Model> {
+        let mut session: sessions::ActiveModel = Sessions::find_by_id(session_id)
+            .one(db)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?
+            .into();
+        
+        session.priority = Set(priority);
+        
+        Ok(session.update(db).await?)
     }
     
     /// 获取客服的活跃会话
@@ -165,26 +195,26 @@ impl SessionRepository {
             .filter(sessions::Column::ShopId.eq(shop_id))
             .filter(sessions::Column::StaffId.is_null())
             .filter(sessions::Column::SessionStatus.eq("active"))
-            .order_by_desc(sessions::Column::CreatedAt)
+            .order_by_desc(sessions::Column::Priority)
+            .order_by_asc(sessions::Column::CreatedAt)
             .all(db)
             .await?;
         Ok(sessions)
     }
 
-    /// 重置客户在店铺中的未读计数
+    /// 重置客户在店铺中的未读计数（同时将该客户消息标记为已读，保持与 messages.is_read 一致）
     pub async fn reset_customer_unread_count(
         db: &DatabaseConnection,
         shop_id: i32,
         customer_id: i32,
     ) -> Result<()> {
-        // TODO: 修复unread_counts表schema后启用
-        // crate::repositories::UnreadCountRepository::reset_unread_count(
-        //     db,
-        //     shop_id as i64,
-        //     customer_id as i64,
-        // ).await
-        let _ = (db, shop_id, customer_id); // 避免未使用变量警告
-        Ok(())
+        crate::repositories::MessageRepository::mark_customer_messages_read(db, shop_id, Some(customer_id), None).await?;
+        crate::repositories::UnreadCountRepository::reset_unread_count(
+            db,
+            shop_id as i64,
+            customer_id as i64,
+            None,
+        ).await
     }
 
     /// 重置店铺所有未读计数
@@ -192,17 +222,37 @@ impl SessionRepository {
         db: &DatabaseConnection,
         shop_id: i32,
     ) -> Result<()> {
-        // TODO: 修复unread_counts表schema后启用
-        // use crate::entities::{unread_counts, prelude::*};
-        
-        // let update_result = UnreadCounts::update_many()
-        //     .col_expr(unread_counts::Column::UnreadCount, sea_orm::sea_query::Expr::value(0))
-        //     .filter(unread_counts::Column::ShopId.eq(shop_id))
-        //     .exec(db)
-        //     .await?;
-        
-        // tracing::info!("重置店铺 {} 的 {} 个未读计数", shop_id, update_result.rows_affected);
-        let _ = (db, shop_id); // 避免未使用变量警告
+        crate::repositories::MessageRepository::mark_customer_messages_read(db, shop_id, None, None).await?;
+        let affected = crate::repositories::UnreadCountRepository::reset_all_in_shop(db, shop_id as i64).await?;
+        tracing::info!("重置店铺 {} 的 {} 个未读计数", shop_id, affected);
         Ok(())
     }
+
+    /// 店铺等待队列：未分配、进行中且客户已发过消息的会话，按优先级从高到低、创建时间从早到晚
+    pub async fn find_waiting_by_shop(db: &DatabaseConnection, shop_id: i32) -> Result<Vec<WaitingSessionRow>> {
+        let rows = WaitingSessionRow::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"SELECT s.id AS session_id, s.customer_id, c.customer_id AS customer_code, c.customer_name,
+                      s.priority, s.created_at
+               FROM sessions s
+               JOIN customers c ON c.id = s.customer_id
+               WHERE s.shop_id = ? AND s.staff_id IS NULL AND s.session_status = 'active'
+                 AND EXISTS (SELECT 1 FROM messages m WHERE m.session_id = s.id AND m.sender_type = 'customer')
+               ORDER BY s.priority DESC, s.created_at ASC, s.id ASC"#,
+            [shop_id.into()],
+        ))
+        .all(db)
+        .await?;
+        Ok(rows)
+    }
+}
+
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct WaitingSessionRow {
+    pub session_id: i32,
+    pub customer_id: i32,
+    pub customer_code: String,
+    pub customer_name: Option<String>,
+    pub priority: i32,
+    pub created_at: Option<chrono::NaiveDateTime>,
 }
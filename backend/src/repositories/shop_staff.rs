@@ -7,27 +7,6 @@ use crate::entities::{shop_staffs, users, shops, prelude::*};
 pub struct ShopStaffRepository;
 
 impl ShopStaffRepository {
-    /// 添加员工到店铺
-    pub async fn add_staff(
-        db: &DatabaseConnection,
-        shop_id: i32,
-        user_id: i32,
-        role: String,
-    ) -> Result<shop_staffs::Model> {
-        let staff = shop_staffs::ActiveModel {
-            shop_id: Set(shop_id),
-            user_id: Set(user_id),
-            role: Set(role),
-            is_active: Set(true),
-            joined_at: Set(chrono::Utc::now().naive_utc()),
-            created_at: Set(chrono::Utc::now().naive_utc()),
-            updated_at: Set(chrono::Utc::now().naive_utc()),
-            ..Default::default()
-        };
-        
-        Ok(staff.insert(db).await?)
-    }
-    
     /// 获取店铺的所有员工
     pub async fn find_by_shop(db: &DatabaseConnection, shop_id: i32) -> Result<Vec<shop_staffs::Model>> {
         let staffs = ShopStaffs::find()
@@ -38,18 +17,6 @@ impl ShopStaffRepository {
         Ok(staffs)
     }
     
-    /// 检查用户是否是店铺员工
-    pub async fn is_staff_of_shop(db: &DatabaseConnection, user_id: i32, shop_id: i32) -> Result<bool> {
-        let count = ShopStaffs::find()
-            .filter(shop_staffs::Column::UserId.eq(user_id))
-            .filter(shop_staffs::Column::ShopId.eq(shop_id))
-            .filter(shop_staffs::Column::IsActive.eq(true))
-            .count(db)
-            .await?;
-        
-        Ok(count > 0)
-    }
-    
     /// 移除员工
     pub async fn remove_staff(db: &DatabaseConnection, shop_id: i32, user_id: i32) -> Result<()> {
         let staff = ShopStaffs::find()
@@ -158,44 +125,6 @@ impl ShopStaffRepository {
         Ok(staff.insert(db).await?)
     }
     
-    /// 检查用户是否是店铺所有者
-    /// 
-    /// 对应 database.rs 中的 is_shop_owner 方法
-    pub async fn is_shop_owner(db: &DatabaseConnection, shop_id: i64, user_id: i64) -> Result<bool> {
-        let shop = Shops::find_by_id(shop_id as i32).one(db).await?;
-        
-        match shop {
-            Some(s) => {
-                match s.owner_id {
-                    Some(owner_id) => Ok(owner_id == user_id as i32),
-                    None => Ok(false),
-                }
-            },
-            None => Ok(false),
-        }
-    }
-    
-    /// 检查用户是否是店铺成员（所有者或员工）
-    /// 
-    /// 对应 database.rs 中的 is_shop_member 方法
-    pub async fn is_shop_member(db: &DatabaseConnection, shop_id: i64, user_id: i64) -> Result<bool> {
-        eprintln!("🔍 is_shop_member: shop_id={}, user_id={}", shop_id, user_id);
-        
-        // 先检查是否是所有者
-        let is_owner = Self::is_shop_owner(db, shop_id, user_id).await?;
-        eprintln!("📊 is_owner: {}", is_owner);
-        
-        if is_owner {
-            return Ok(true);
-        }
-        
-        // 再检查是否是员工
-        let is_staff = Self::is_staff_of_shop(db, user_id as i32, shop_id as i32).await?;
-        eprintln!("📊 is_staff: {}", is_staff);
-        
-        Ok(is_staff)
-    }
-    
     /// 永久删除员工（硬删除）
     pub async fn hard_delete(db: &DatabaseConnection, shop_id: i32, user_id: i32) -> Result<u64> {
         let result = ShopStaffs::delete_many()
@@ -252,42 +181,4 @@ impl ShopStaffRepository {
         
         Ok(shops)
     }
-
-
-
-    /// 通过用户名添加员工
-    pub async fn add_shop_staff_by_username(
-        db: &DatabaseConnection,
-        shop_id: i64,
-        username: &str,
-        role: Option<&str>,
-    ) -> Result<()> {
-        // 查找用户
-        let user = Users::find()
-            .filter(users::Column::Username.eq(username))
-            .one(db)
-            .await?;
-
-        if let Some(user) = user {
-            let role = role.unwrap_or("staff").to_string();
-            Self::add_staff(db, shop_id as i32, user.id, role).await?;
-        }
-
-        Ok(())
-    }
-
-    /// 移除员工
-    pub async fn remove_shop_staff(
-        db: &DatabaseConnection,
-        shop_id: i64,
-        user_id: i64,
-    ) -> Result<u64> {
-        let result = ShopStaffs::delete_many()
-            .filter(shop_staffs::Column::ShopId.eq(shop_id as i32))
-            .filter(shop_staffs::Column::UserId.eq(user_id as i32))
-            .exec(db)
-            .await?;
-        
-        Ok(result.rows_affected)
-    }
 }
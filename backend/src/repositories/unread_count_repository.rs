@@ -1,69 +1,182 @@
-use sea_orm::{DatabaseConnection, EntityTrait, Set, ActiveModelTrait, ColumnTrait, QueryFilter, ActiveValue};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ConnectionTrait, DatabaseConnection, DbBackend, EntityTrait, Set, ColumnTrait, QueryFilter, Statement,
+};
 use anyhow::Result;
-use crate::entities::unread_counts;
+use crate::entities::{prelude::StaffReadCursors, staff_read_cursors, unread_counts};
 
 pub struct UnreadCountRepository;
 
 impl UnreadCountRepository {
-    /// 更新未读消息计数
+    /// 更新未读消息计数（原子 upsert，依赖 UNIQUE(shop_id, customer_id)）
     pub async fn update_unread_count(
         db: &DatabaseConnection,
         shop_id: i64,
         customer_id: i64,
         increment: i32,
     ) -> Result<()> {
-        // 查找现有记录
-        let existing = unread_counts::Entity::find()
-            .filter(unread_counts::Column::ShopId.eq(shop_id as i32))
-            .filter(unread_counts::Column::CustomerId.eq(customer_id as i32))
-            .one(db)
-            .await?;
+        let now = chrono::Utc::now().naive_utc();
+        let record = unread_counts::ActiveModel {
+            shop_id: Set(shop_id as i32),
+            customer_id: Set(customer_id as i32),
+            unread_count: Set(increment.max(0)),
+            updated_at: Set(now),
+            ..Default::default()
+        };
 
-        if let Some(record) = existing {
-            // 更新现有记录
-            let mut active_model: unread_counts::ActiveModel = record.into();
-            // ActiveValue 读取：转换为 Option<i32>
-            let current: i32 = match &active_model.unread_count {
-                ActiveValue::Set(val) => *val,
-                ActiveValue::Unchanged(val) => *val,
-                _ => 0,
-            };
-            active_model.unread_count = Set(current + increment);
-            active_model.update(db).await?;
-        } else {
-            // 创建新记录
-            let new_record = unread_counts::ActiveModel {
-                shop_id: Set(shop_id as i32),
-                customer_id: Set(customer_id as i32),
-                unread_count: Set(increment),
-                ..Default::default()
-            };
-            new_record.insert(db).await?;
-        }
+        unread_counts::Entity::insert(record)
+            .on_conflict(
+                OnConflict::columns([unread_counts::Column::ShopId, unread_counts::Column::CustomerId])
+                    .value(
+                        unread_counts::Column::UnreadCount,
+                        Expr::cust_with_values("MAX(unread_counts.unread_count + ?, 0)", [increment]),
+                    )
+                    .value(unread_counts::Column::UpdatedAt, Expr::value(now))
+                    .to_owned(),
+            )
+            .exec(db)
+            .await?;
 
         Ok(())
     }
 
-    /// 重置未读消息计数
+    /// 重置未读消息计数，可同时记录最后已读消息 id
     pub async fn reset_unread_count(
         db: &DatabaseConnection,
         shop_id: i64,
         customer_id: i64,
+        last_read_message_id: Option<i32>,
     ) -> Result<()> {
-        // 查找现有记录
-        let existing = unread_counts::Entity::find()
+        let mut update = unread_counts::Entity::update_many()
+            .col_expr(unread_counts::Column::UnreadCount, Expr::value(0))
+            .col_expr(unread_counts::Column::UpdatedAt, Expr::value(chrono::Utc::now().naive_utc()))
+            .filter(unread_counts::Column::ShopId.eq(shop_id as i32))
+            .filter(unread_counts::Column::CustomerId.eq(customer_id as i32));
+        if let Some(message_id) = last_read_message_id {
+            update = update.col_expr(unread_counts::Column::LastReadMessageId, Expr::value(message_id));
+        }
+        update.exec(db).await?;
+        Ok(())
+    }
+
+    /// 重置店铺下所有客户的未读计数
+    pub async fn reset_all_in_shop(db: &DatabaseConnection, shop_id: i64) -> Result<u64> {
+        let result = unread_counts::Entity::update_many()
+            .col_expr(unread_counts::Column::UnreadCount, Expr::value(0))
+            .col_expr(unread_counts::Column::UpdatedAt, Expr::value(chrono::Utc::now().naive_utc()))
             .filter(unread_counts::Column::ShopId.eq(shop_id as i32))
-            .filter(unread_counts::Column::CustomerId.eq(customer_id as i32))
-            .one(db)
+            .exec(db)
             .await?;
+        Ok(result.rows_affected)
+    }
 
-        if let Some(record) = existing {
-            // 重置为0
-            let mut active_model: unread_counts::ActiveModel = record.into();
-            active_model.unread_count = Set(0);
-            active_model.update(db).await?;
-        }
+    /// 以 messages.is_read 为准重新计算某客户的未读数（部分已读回执后使用）
+    pub async fn recount(db: &DatabaseConnection, shop_id: i64, customer_id: i64) -> Result<()> {
+        db.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"UPDATE unread_counts
+               SET unread_count = (
+                   SELECT COUNT(m.id) FROM messages m
+                   JOIN sessions s ON s.id = m.session_id
+                   WHERE s.shop_id = ? AND s.customer_id = ?
+                     AND m.sender_type = 'customer' AND m.is_read = 0 AND m.is_deleted = 0
+               ),
+               updated_at = CURRENT_TIMESTAMP
+               WHERE shop_id = ? AND customer_id = ?"#,
+            [shop_id.into(), customer_id.into(), shop_id.into(), customer_id.into()],
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// 推进客服个人已读游标（只前进不后退）
+    pub async fn advance_staff_cursor(
+        db: &DatabaseConnection,
+        shop_id: i64,
+        customer_id: i64,
+        user_id: i64,
+        message_id: i64,
+    ) -> Result<()> {
+        let now = chrono::Utc::now().naive_utc();
+        let cursor = staff_read_cursors::ActiveModel {
+            shop_id: Set(shop_id as i32),
+            customer_id: Set(customer_id as i32),
+            user_id: Set(user_id as i32),
+            last_read_message_id: Set(message_id as i32),
+            updated_at: Set(now),
+            ..Default::default()
+        };
+
+        StaffReadCursors::insert(cursor)
+            .on_conflict(
+                OnConflict::columns([
+                    staff_read_cursors::Column::ShopId,
+                    staff_read_cursors::Column::CustomerId,
+                    staff_read_cursors::Column::UserId,
+                ])
+                .value(
+                    staff_read_cursors::Column::LastReadMessageId,
+                    Expr::cust("MAX(staff_read_cursors.last_read_message_id, excluded.last_read_message_id)"),
+                )
+                .value(staff_read_cursors::Column::UpdatedAt, Expr::value(now))
+                .to_owned(),
+            )
+            .exec(db)
+            .await?;
+        Ok(())
+    }
 
+    /// 将客服个人游标推进到该客户（为空时为店铺内所有客户）的最新消息
+    pub async fn advance_staff_cursors_to_latest(
+        db: &DatabaseConnection,
+        shop_id: i64,
+        customer_id: Option<i64>,
+        user_id: i64,
+    ) -> Result<()> {
+        db.execute(Statement::from_sql_and_values(
+            DbBackend::Sqlite,
+            r#"INSERT INTO staff_read_cursors (shop_id, customer_id, user_id, last_read_message_id, updated_at)
+               SELECT s.shop_id, s.customer_id, ?, MAX(m.id), CURRENT_TIMESTAMP
+               FROM messages m
+               JOIN sessions s ON s.id = m.session_id
+               WHERE s.shop_id = ? AND (? IS NULL OR s.customer_id = ?)
+               GROUP BY s.shop_id, s.customer_id
+               ON CONFLICT(shop_id, customer_id, user_id) DO UPDATE SET
+                   last_read_message_id = MAX(staff_read_cursors.last_read_message_id, excluded.last_read_message_id),
+                   updated_at = CURRENT_TIMESTAMP"#,
+            [user_id.into(), shop_id.into(), customer_id.into(), customer_id.into()],
+        ))
+        .await?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// 客服个人未读：按客户统计该客服游标之后的客户消息；没有游标时退回店铺维度的 is_read
+    pub async fn staff_unread_by_shop(
+        db: &DatabaseConnection,
+        shop_id: i64,
+        user_id: i64,
+    ) -> Result<Vec<(i64, i64)>> {
+        let rows = db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                r#"SELECT s.customer_id AS customer_id, COUNT(m.id) AS unread
+                   FROM messages m
+                   JOIN sessions s ON s.id = m.session_id
+                   LEFT JOIN staff_read_cursors c
+                     ON c.shop_id = s.shop_id AND c.customer_id = s.customer_id AND c.user_id = ?
+                   WHERE s.shop_id = ? AND m.sender_type = 'customer' AND m.is_deleted = 0
+                     AND CASE WHEN c.id IS NULL THEN m.is_read = 0 ELSE m.id > c.last_read_message_id END
+                   GROUP BY s.customer_id"#,
+                [user_id.into(), shop_id.into()],
+            ))
+            .await?;
+
+        let mut result = Vec::with_capacity(rows.len());
+        for row in rows {
+            let customer_id: i64 = row.try_get("", "customer_id")?;
+            let unread: i64 = row.try_get("", "unread")?;
+            result.push((customer_id, unread));
+        }
+        Ok(result)
+    }
+}
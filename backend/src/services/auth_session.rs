@@ -0,0 +1,334 @@
+//! 登录会话（access token + refresh token）
+//!
+//! 职责：
+//! - 登录 / 注册时创建会话，签发短期 access token（jti = 会话 ID）与 refresh token
+//! - refresh token 只保存 SHA-256，每次刷新轮换；已轮换的旧令牌被再次使用时视为泄露，吊销整个会话
+//! - 注销、吊销全部会话后，携带对应 jti 的 access token 立即失效（AuthUser 与 WebSocket 握手都会校验）
+
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{error, warn};
+
+use crate::{
+    auth::jwt_keyring,
+    constants::auth_policy,
+    database::Database,
+    error::AppError,
+    jwt::{encode_token, Claims},
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// access token 有效期（秒）
+    pub expires_in: i64,
+}
+
+fn db_error(code: &'static str) -> impl Fn(sqlx::Error) -> AppError {
+    move |e| {
+        error!(target: "auth_session", "{}: {}", code, e);
+        AppError::Internal(code.to_string())
+    }
+}
+
+/// 令牌只保存 SHA-256（十六进制）
+pub(crate) fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 256 位随机串（两个 v4 UUID 拼接）
+pub(crate) fn new_random_token() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+}
+
+fn refresh_expires_at() -> NaiveDateTime {
+    (Utc::now() + chrono::Duration::seconds(auth_policy::refresh_token_ttl_secs())).naive_utc()
+}
+
+fn issue_access_token(user_id: i64, jti: &str) -> Result<(String, i64), AppError> {
+    let ttl = auth_policy::access_token_ttl_secs();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        exp: (Utc::now().timestamp() + ttl) as usize,
+        jti: Some(jti.to_string()),
+    };
+    let token = encode_token(&claims, jwt_keyring()).map_err(|e| {
+        error!(target: "auth_session", "JWT token 生成失败: {}", e);
+        AppError::Internal("token_issue_failed".to_string())
+    })?;
+    Ok((token, ttl))
+}
+
+/// 创建登录会话并签发令牌
+pub async fn create_session(
+    db: &Database,
+    user_id: i64,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> Result<TokenPair, AppError> {
+    let jti = uuid::Uuid::new_v4().to_string();
+    let refresh_token = new_random_token();
+    sqlx::query(
+        "INSERT INTO auth_sessions (jti, user_id, refresh_token_hash, user_agent, ip_address, expires_at) VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&jti)
+    .bind(user_id)
+    .bind(hash_token(&refresh_token))
+    .bind(user_agent.map(|ua| ua.chars().take(512).collect::<String>()))
+    .bind(ip_address)
+    .bind(refresh_expires_at())
+    .execute(db.pool())
+    .await
+    .map_err(db_error("create_session_failed"))?;
+
+    let (access_token, expires_in) = issue_access_token(user_id, &jti)?;
+    Ok(TokenPair { access_token, refresh_token, expires_in })
+}
+
+/// 用 refresh token 换取新的令牌对
+///
+/// 业务逻辑：
+/// 1. 按哈希查找会话；已吊销、已过期或用户被停用返回 401
+/// 2. 轮换 refresh token（旧哈希记入 previous_token_hash），并顺延会话有效期
+/// 3. 找不到但命中某会话的 previous_token_hash：旧令牌被重放，吊销该会话
+pub async fn refresh(db: &Database, refresh_token: &str) -> Result<(i64, TokenPair), AppError> {
+    let token_hash = hash_token(refresh_token.trim());
+    let row = sqlx::query_as::<_, (i64, String, i64, NaiveDateTime, Option<NaiveDateTime>)>(
+        "SELECT id, jti, user_id, expires_at, revoked_at FROM auth_sessions WHERE refresh_token_hash = ?",
+    )
+    .bind(&token_hash)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(db_error("refresh_lookup_failed"))?;
+
+    let Some((id, jti, user_id, expires_at, revoked_at)) = row else {
+        let replayed = sqlx::query_scalar::<_, i64>(
+            "SELECT id FROM auth_sessions WHERE previous_token_hash = ? AND revoked_at IS NULL",
+        )
+        .bind(&token_hash)
+        .fetch_optional(db.pool())
+        .await
+        .map_err(db_error("refresh_lookup_failed"))?;
+        if let Some(session_id) = replayed {
+            warn!(target: "auth_session", "refresh token 被重放，吊销会话 {}", session_id);
+            sqlx::query("UPDATE auth_sessions SET revoked_at = CURRENT_TIMESTAMP WHERE id = ?")
+                .bind(session_id)
+                .execute(db.pool())
+                .await
+                .map_err(db_error("revoke_session_failed"))?;
+        }
+        return Err(AppError::Unauthorized);
+    };
+    if revoked_at.is_some() || expires_at <= Utc::now().naive_utc() {
+        return Err(AppError::Unauthorized);
+    }
+    let active_user = sqlx::query_scalar::<_, i32>("SELECT status FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await
+        .map_err(db_error("refresh_lookup_failed"))?
+        .is_some_and(|status| status == 1);
+    if !active_user {
+        return Err(AppError::Unauthorized);
+    }
+
+    let new_token = new_random_token();
+    let rotated = sqlx::query(
+        "UPDATE auth_sessions SET previous_token_hash = refresh_token_hash, refresh_token_hash = ?, expires_at = ?, refreshed_at = CURRENT_TIMESTAMP \
+         WHERE id = ? AND refresh_token_hash = ? AND revoked_at IS NULL",
+    )
+    .bind(hash_token(&new_token))
+    .bind(refresh_expires_at())
+    .bind(id)
+    .bind(&token_hash)
+    .execute(db.pool())
+    .await
+    .map_err(db_error("refresh_rotate_failed"))?;
+    // 并发刷新时只有一个请求能完成轮换
+    if rotated.rows_affected() == 0 {
+        return Err(AppError::Unauthorized);
+    }
+
+    let (access_token, expires_in) = issue_access_token(user_id, &jti)?;
+    Ok((user_id, TokenPair { access_token, refresh_token: new_token, expires_in }))
+}
+
+/// access token 所属会话是否仍有效
+pub async fn is_active(db: &Database, user_id: i64, jti: &str) -> Result<bool, AppError> {
+    let found = sqlx::query_scalar::<_, i64>(
+        "SELECT id FROM auth_sessions WHERE jti = ? AND user_id = ? AND revoked_at IS NULL",
+    )
+    .bind(jti)
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(db_error("session_lookup_failed"))?;
+    Ok(found.is_some())
+}
+
+/// 注销单个会话
+pub async fn revoke(db: &Database, user_id: i64, jti: &str) -> Result<(), AppError> {
+    sqlx::query("UPDATE auth_sessions SET revoked_at = CURRENT_TIMESTAMP WHERE jti = ? AND user_id = ? AND revoked_at IS NULL")
+        .bind(jti)
+        .bind(user_id)
+        .execute(db.pool())
+        .await
+        .map_err(db_error("revoke_session_failed"))?;
+    Ok(())
+}
+
+/// 吊销用户的全部会话，返回吊销数量
+pub async fn revoke_all(db: &Database, user_id: i64) -> Result<u64, AppError> {
+    let result = sqlx::query("UPDATE auth_sessions SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = ? AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(db.pool())
+        .await
+        .map_err(db_error("revoke_sessions_failed"))?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn test_db() -> (Database, String) {
+        crate::auth::init_jwt_keyring(true).unwrap();
+        let path = std::env::temp_dir().join(format!("auth_session_{}.db", uuid::Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        let db = Database::new(&format!("sqlite://{}?mode=rwc", path)).await.unwrap();
+        for ddl in [
+            "CREATE TABLE users (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL DEFAULT '',
+                email TEXT,
+                phone TEXT,
+                avatar_url TEXT,
+                status INTEGER NOT NULL DEFAULT 1
+            )",
+            "CREATE TABLE shops (id INTEGER PRIMARY KEY, owner_id INTEGER NOT NULL)",
+            "CREATE TABLE shop_staffs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                shop_id INTEGER NOT NULL,
+                user_id INTEGER NOT NULL,
+                role TEXT NOT NULL DEFAULT 'agent',
+                permissions TEXT
+            )",
+            "CREATE TABLE auth_sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                jti VARCHAR(64) NOT NULL UNIQUE,
+                user_id INTEGER NOT NULL,
+                refresh_token_hash VARCHAR(64) NOT NULL UNIQUE,
+                previous_token_hash VARCHAR(64),
+                user_agent TEXT,
+                ip_address VARCHAR(64),
+                expires_at TIMESTAMP NOT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                refreshed_at TIMESTAMP,
+                revoked_at TIMESTAMP
+            )",
+            "INSERT INTO users (id, status) VALUES (1, 1), (2, 0), (10, 1), (11, 1)",
+            // 店铺 1：店主 10，员工 1（agent）、11（admin）
+            "INSERT INTO shops (id, owner_id) VALUES (1, 10)",
+            "INSERT INTO shop_staffs (shop_id, user_id, role) VALUES (1, 1, 'agent'), (1, 11, 'admin')",
+        ] {
+            sqlx::query(ddl).execute(db.pool()).await.unwrap();
+        }
+        (db, path)
+    }
+
+    fn jti_of(pair: &TokenPair) -> String {
+        crate::auth::verify_token(&pair.access_token).unwrap().jti.unwrap()
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_token_and_keeps_session() {
+        let (db, path) = test_db().await;
+        let first = create_session(&db, 1, Some("test"), None).await.unwrap();
+        let jti = jti_of(&first);
+
+        let (user_id, second) = refresh(&db, &first.refresh_token).await.unwrap();
+        assert_eq!(user_id, 1);
+        assert_ne!(second.refresh_token, first.refresh_token);
+        // 同一会话：jti 不变，access token 仍有效
+        assert_eq!(jti_of(&second), jti);
+        assert!(is_active(&db, 1, &jti).await.unwrap());
+
+        let (_, third) = refresh(&db, &second.refresh_token).await.unwrap();
+        assert_eq!(jti_of(&third), jti);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn replayed_refresh_token_revokes_session() {
+        let (db, path) = test_db().await;
+        let first = create_session(&db, 1, None, None).await.unwrap();
+        let jti = jti_of(&first);
+        let (_, second) = refresh(&db, &first.refresh_token).await.unwrap();
+
+        // 已轮换的旧令牌被再次使用：拒绝并吊销整个会话
+        assert!(matches!(refresh(&db, &first.refresh_token).await, Err(AppError::Unauthorized)));
+        assert!(!is_active(&db, 1, &jti).await.unwrap());
+        // 合法持有者手中的新令牌也随之失效
+        assert!(matches!(refresh(&db, &second.refresh_token).await, Err(AppError::Unauthorized)));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn refresh_rejects_unknown_revoked_and_inactive() {
+        let (db, path) = test_db().await;
+        assert!(matches!(refresh(&db, "not-a-token").await, Err(AppError::Unauthorized)));
+
+        let pair = create_session(&db, 1, None, None).await.unwrap();
+        revoke(&db, 1, &jti_of(&pair)).await.unwrap();
+        assert!(matches!(refresh(&db, &pair.refresh_token).await, Err(AppError::Unauthorized)));
+
+        let inactive = create_session(&db, 2, None, None).await.unwrap();
+        assert!(matches!(refresh(&db, &inactive.refresh_token).await, Err(AppError::Unauthorized)));
+
+        let a = create_session(&db, 1, None, None).await.unwrap();
+        let b = create_session(&db, 1, None, None).await.unwrap();
+        assert_eq!(revoke_all(&db, 1).await.unwrap(), 2);
+        assert!(!is_active(&db, 1, &jti_of(&a)).await.unwrap());
+        assert!(!is_active(&db, 1, &jti_of(&b)).await.unwrap());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn shop_admin_revokes_staff_sessions() {
+        use crate::services::{
+            permissions::{CapabilityOverrides, Role, ShopAccess},
+            staff::revoke_staff_sessions,
+        };
+        let (db, path) = test_db().await;
+        let owner = ShopAccess::with_overrides(10, 1, Role::Owner, &CapabilityOverrides::new());
+        let admin = ShopAccess::with_overrides(11, 1, Role::Admin, &CapabilityOverrides::new());
+
+        let a = create_session(&db, 1, None, None).await.unwrap();
+        let b = create_session(&db, 1, None, None).await.unwrap();
+        let own = create_session(&db, 11, None, None).await.unwrap();
+
+        // 离职员工的全部会话被吊销，操作者自己的会话不受影响
+        assert_eq!(revoke_staff_sessions(&db, &admin, 1).await.unwrap(), 2);
+        assert!(!is_active(&db, 1, &jti_of(&a)).await.unwrap());
+        assert!(!is_active(&db, 1, &jti_of(&b)).await.unwrap());
+        assert!(matches!(refresh(&db, &a.refresh_token).await, Err(AppError::Unauthorized)));
+        assert!(is_active(&db, 11, &jti_of(&own)).await.unwrap());
+
+        // 不能吊销店主；admin 只能由店主吊销；非本店员工返回 404
+        assert!(matches!(revoke_staff_sessions(&db, &admin, 10).await, Err(AppError::BadRequest(ref e)) if e == "cannot_modify_owner"));
+        assert!(matches!(revoke_staff_sessions(&db, &admin, 11).await, Err(AppError::Forbidden)));
+        assert!(matches!(revoke_staff_sessions(&db, &owner, 2).await, Err(AppError::NotFound)));
+        assert_eq!(revoke_staff_sessions(&db, &owner, 11).await.unwrap(), 1);
+        assert!(!is_active(&db, 11, &jti_of(&own)).await.unwrap());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
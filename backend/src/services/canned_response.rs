@@ -0,0 +1,252 @@
+//! 快捷回复模板（Canned Responses）
+//!
+//! 职责：
+//! - 店铺公共模板（需 manage_settings 能力）与客服个人模板（仅本人维护）的增删改查
+//! - 按会话展开模板变量：{customer_name} / {shop_name} / {agent_name}
+//! - 记录模板使用次数，列表按常用程度排序
+//!
+//! 未知变量原样保留，便于客服发现模板书写错误
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    entities::canned_responses,
+    repositories::CannedResponseRepository,
+    AppState,
+};
+
+pub const SCOPE_SHOP: &str = "shop";
+pub const SCOPE_PERSONAL: &str = "personal";
+
+const MAX_TITLE_CHARS: usize = 100;
+const MAX_SHORTCUT_CHARS: usize = 32;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CannedResponse {
+    pub id: i64,
+    pub shop_id: i64,
+    /// shop | personal
+    pub scope: String,
+    pub title: String,
+    pub shortcut: Option<String>,
+    pub content: String,
+    pub usage_count: i32,
+    pub created_by: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<canned_responses::Model> for CannedResponse {
+    fn from(model: canned_responses::Model) -> Self {
+        Self {
+            id: model.id as i64,
+            shop_id: model.shop_id as i64,
+            scope: if model.owner_user_id.is_some() { SCOPE_PERSONAL } else { SCOPE_SHOP }.to_string(),
+            title: model.title,
+            shortcut: model.shortcut,
+            content: model.content,
+            usage_count: model.usage_count,
+            created_by: model.created_by as i64,
+            created_at: model.created_at.and_utc(),
+            updated_at: model.updated_at.and_utc(),
+        }
+    }
+}
+
+/// 模板写入参数（创建与修改共用）
+#[derive(Debug, Clone)]
+pub struct CannedResponseInput {
+    pub title: String,
+    pub shortcut: Option<String>,
+    pub content: String,
+}
+
+/// 客服可见的模板列表；scope 为空时返回公共模板 + 本人个人模板
+pub async fn list(
+    state: &AppState,
+    shop_id: i64,
+    user_id: i64,
+    scope: Option<&str>,
+    keyword: Option<&str>,
+) -> anyhow::Result<Vec<CannedResponse>> {
+    let personal_only = match scope {
+        None => None,
+        Some(SCOPE_SHOP) => Some(false),
+        Some(SCOPE_PERSONAL) => Some(true),
+        Some(_) => anyhow::bail!("invalid_scope"),
+    };
+    let rows = CannedResponseRepository::find_visible(&state.db_connection, shop_id, user_id, personal_only, keyword).await?;
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// 创建模板
+///
+/// 业务逻辑：
+/// 1. scope 为 shop 时需要 manage_settings 能力（manage_shop 由调用方校验）
+/// 2. 校验标题、快捷指令、内容
+/// 3. personal 模板归属当前用户
+pub async fn create(
+    state: &AppState,
+    shop_id: i64,
+    user_id: i64,
+    manage_shop: bool,
+    scope: &str,
+    input: CannedResponseInput,
+) -> anyhow::Result<CannedResponse> {
+    let owner_user_id = match scope {
+        SCOPE_SHOP if manage_shop => None,
+        SCOPE_SHOP => anyhow::bail!("permission_denied"),
+        SCOPE_PERSONAL => Some(user_id),
+        _ => anyhow::bail!("invalid_scope"),
+    };
+    let input = validate(input)?;
+    let created = CannedResponseRepository::create(
+        &state.db_connection,
+        shop_id,
+        owner_user_id,
+        input.title,
+        input.shortcut,
+        input.content,
+        user_id,
+    )
+    .await?;
+    Ok(created.into())
+}
+
+/// 修改模板：公共模板需 manage_settings 能力，个人模板仅本人可改
+pub async fn update(
+    state: &AppState,
+    shop_id: i64,
+    id: i64,
+    user_id: i64,
+    manage_shop: bool,
+    input: CannedResponseInput,
+) -> anyhow::Result<CannedResponse> {
+    let record = load_editable(state, shop_id, id, user_id, manage_shop).await?;
+    let input = validate(input)?;
+    let updated = CannedResponseRepository::update(
+        &state.db_connection,
+        record,
+        input.title,
+        input.shortcut,
+        input.content,
+    )
+    .await?;
+    Ok(updated.into())
+}
+
+/// 删除模板（权限同修改）
+pub async fn delete(state: &AppState, shop_id: i64, id: i64, user_id: i64, manage_shop: bool) -> anyhow::Result<()> {
+    load_editable(state, shop_id, id, user_id, manage_shop).await?;
+    CannedResponseRepository::delete(&state.db_connection, id).await
+}
+
+/// 按会话展开模板，返回可直接发送的文本
+///
+/// 业务逻辑：
+/// 1. 模板必须属于会话所在店铺，且为公共模板或该客服的个人模板
+/// 2. 查询客户名 / 店铺名 / 客服名并替换变量
+/// 3. 使用次数 +1（失败不影响发送）
+pub async fn render(
+    state: &AppState,
+    template_id: i64,
+    shop_id: i64,
+    session_id: i64,
+    staff_id: i64,
+) -> anyhow::Result<String> {
+    let template = CannedResponseRepository::find_by_id(&state.db_connection, template_id)
+        .await?
+        .filter(|t| t.shop_id as i64 == shop_id)
+        .filter(|t| t.owner_user_id.is_none_or(|owner| owner as i64 == staff_id))
+        .ok_or_else(|| anyhow::anyhow!("canned_response_not_found"))?;
+
+    let vars = CannedResponseRepository::find_variables(&state.db_connection, session_id, staff_id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
+    let customer_name = vars
+        .customer_name
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or(vars.customer_code);
+    let content = expand(
+        &template.content,
+        &[
+            ("customer_name", customer_name.as_str()),
+            ("shop_name", vars.shop_name.as_deref().unwrap_or_default()),
+            ("agent_name", vars.agent_name.as_deref().unwrap_or_default()),
+        ],
+    );
+
+    if let Err(e) = CannedResponseRepository::increment_usage(&state.db_connection, template_id).await {
+        tracing::warn!("更新模板 {} 使用次数失败: {:?}", template_id, e);
+    }
+
+    Ok(content)
+}
+
+async fn load_editable(
+    state: &AppState,
+    shop_id: i64,
+    id: i64,
+    user_id: i64,
+    manage_shop: bool,
+) -> anyhow::Result<canned_responses::Model> {
+    let record = CannedResponseRepository::find_by_id(&state.db_connection, id)
+        .await?
+        .filter(|r| r.shop_id as i64 == shop_id)
+        .ok_or_else(|| anyhow::anyhow!("canned_response_not_found"))?;
+    match record.owner_user_id {
+        None if manage_shop => Ok(record),
+        None => anyhow::bail!("permission_denied"),
+        Some(owner) if owner as i64 == user_id => Ok(record),
+        // 他人的个人模板对当前用户不可见
+        Some(_) => anyhow::bail!("canned_response_not_found"),
+    }
+}
+
+fn validate(input: CannedResponseInput) -> anyhow::Result<CannedResponseInput> {
+    let title = input.title.trim().to_string();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+        anyhow::bail!("invalid_title");
+    }
+    let shortcut = input
+        .shortcut
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    if let Some(shortcut) = &shortcut {
+        if shortcut.chars().count() > MAX_SHORTCUT_CHARS || shortcut.chars().any(char::is_whitespace) {
+            anyhow::bail!("invalid_shortcut");
+        }
+    }
+    if input.content.trim().is_empty()
+        || input.content.len() > crate::constants::message_policy::MAX_CONTENT_BYTES
+    {
+        anyhow::bail!("invalid_content");
+    }
+    Ok(CannedResponseInput { title, shortcut, content: input.content })
+}
+
+/// 单次扫描替换 {name} 变量，替换值中的花括号不会被再次展开
+fn expand(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after
+            .find('}')
+            .and_then(|end| vars.iter().find(|(name, _)| *name == &after[..end]).map(|(_, v)| (end, *v)));
+        match value {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -3,7 +3,9 @@ use chrono::Utc;
 use serde_json::{Map, Value};
 
 use crate::{
+    constants::message_policy::NOTE_SENDER_TYPE,
     models::{Customer, CustomerUpsert, Message, Session, WebSocketMessage},
+    services::rich_content,
     AppState,
 };
 
@@ -14,6 +16,8 @@ pub struct CustomerProfile<'a> {
     pub avatar: Option<&'a str>,
     pub ip: Option<&'a str>,
     pub user_agent: Option<&'a str>,
+    pub tags: Option<&'a [String]>,
+    pub attributes: Option<&'a Map<String, Value>>,
 }
 
 #[derive(Clone, Debug)]
@@ -25,12 +29,18 @@ pub struct MessagePayload {
     pub file_size: Option<i64>,
     pub media_duration: Option<f64>,
     pub metadata: Option<Value>,
+    /// 引用回复的消息 id
+    pub reply_to: Option<i64>,
+    /// 富消息结构，持久化时校验并规范化（见 services::rich_content）
+    pub rich_content: Option<Value>,
 }
 
 #[derive(Clone, Debug)]
 pub struct PersistedMessage {
     pub message: Message,
     pub ws_message: WebSocketMessage,
+    /// 本条消息触发自动分配时，分配后的会话
+    pub routed_session: Option<Session>,
 }
 
 pub struct ChatService<'a> {
@@ -42,6 +52,17 @@ impl<'a> ChatService<'a> {
         Self { state }
     }
 
+    /// 访客是否已在该店铺建档（用于判断首次 AUTH 能否免令牌）
+    pub async fn customer_exists(&self, shop_id: i64, external_customer_id: &str) -> Result<bool> {
+        let found = crate::repositories::CustomerRepository::find_by_shop_and_customer_id(
+            &self.state.db_connection,
+            shop_id as i32,
+            external_customer_id,
+        )
+        .await?;
+        Ok(found.is_some())
+    }
+
     pub async fn ensure_customer_session(
         &self,
         shop_id: i64,
@@ -54,6 +75,8 @@ impl<'a> ChatService<'a> {
             avatar: profile.avatar,
             ip: profile.ip,
             user_agent: profile.user_agent,
+            tags: profile.tags,
+            attributes: profile.attributes,
         };
 
         let customer = self
@@ -77,13 +100,13 @@ impl<'a> ChatService<'a> {
 
     pub async fn persist_customer_message(
         &self,
-        _shop_id: i64,  // 暂时未使用（等unread_counts表修复后启用）
+        shop_id: i64,
         customer: &Customer,
         session: &Session,
-        payload: MessagePayload,
+        mut payload: MessagePayload,
     ) -> Result<PersistedMessage> {
-        let persisted = self
-            .persist_message(session, "customer", Some(customer.id), &payload)
+        let (persisted, reply_preview) = self
+            .persist_message(session, "customer", Some(customer.id), &mut payload)
             .await?;
 
         // 🔧 修复：客户发送消息时更新活跃时间
@@ -94,14 +117,21 @@ impl<'a> ChatService<'a> {
             eprintln!("⚠️ 更新客户活跃时间失败: {:?}", e);
         }
 
-        // TODO: 修复unread_counts表schema后启用
-        // crate::repositories::UnreadCountRepository::update_unread_count(
-        //     &self.state.db_connection,
-        //     shop_id,
-        //     customer.id,
-        //     1
-        // ).await?;
+        crate::repositories::UnreadCountRepository::update_unread_count(
+            &self.state.db_connection,
+            shop_id,
+            customer.id,
+            1,
+        ).await?;
 
+        // 会话尚未分配客服时按店铺策略自动分配，无可用客服则进入等待队列
+        let routed_session = if session.staff_id.is_none() {
+            crate::services::queue::on_customer_message(self.state, shop_id, session.id, &customer.customer_id).await
+        } else {
+            None
+        };
+
+        let message_id = persisted.id;
         Ok(PersistedMessage {
             message: persisted,
             ws_message: self.build_ws_message(
@@ -109,7 +139,10 @@ impl<'a> ChatService<'a> {
                 Some("customer".to_string()),
                 None,
                 session.id,
+                Some(message_id),
+                reply_preview,
             ),
+            routed_session,
         })
     }
 
@@ -117,11 +150,11 @@ impl<'a> ChatService<'a> {
         &self,
         session: &Session,
         staff_id: i64,
-        payload: MessagePayload,
+        mut payload: MessagePayload,
         customer: &Customer,
     ) -> Result<PersistedMessage> {
-        let persisted = self
-            .persist_message(session, "staff", Some(staff_id), &payload)
+        let (persisted, reply_preview) = self
+            .persist_message(session, "staff", Some(staff_id), &mut payload)
             .await?;
 
         // 🔧 修复：客服回复时也更新客户活跃时间（表示会话仍在活跃）
@@ -132,13 +165,10 @@ impl<'a> ChatService<'a> {
             eprintln!("⚠️ 更新客户活跃时间失败: {:?}", e);
         }
 
-        // TODO: 修复unread_counts表schema后启用
-        // crate::repositories::UnreadCountRepository::reset_unread_count(
-        //     &self.state.db_connection,
-        //     session.shop_id as i64,
-        //     customer.id
-        // ).await?;
+        // 客服回复即视为已读该客户此前的消息
+        self.record_staff_read(session.shop_id, customer.id, staff_id, persisted.id).await?;
 
+        let message_id = persisted.id;
         Ok(PersistedMessage {
             message: persisted,
             ws_message: self.build_ws_message(
@@ -146,7 +176,60 @@ impl<'a> ChatService<'a> {
                 Some("staff".to_string()),
                 Some(staff_id),
                 session.id,
+                Some(message_id),
+                reply_preview,
+            ),
+            routed_session: None,
+        })
+    }
+
+    /// 客服内部备注：仅文本，不计入未读、不触发分配，调用方只推送给店铺客服
+    pub async fn persist_note(
+        &self,
+        session: &Session,
+        staff_id: i64,
+        content: String,
+        reply_to: Option<i64>,
+        customer: &Customer,
+    ) -> Result<PersistedMessage> {
+        if content.trim().is_empty() {
+            return Err(anyhow!("message_content_empty"));
+        }
+        if content.len() > crate::constants::message_policy::MAX_CONTENT_BYTES {
+            return Err(anyhow!("message_content_too_long"));
+        }
+        let mut payload = MessagePayload {
+            content: Some(content),
+            message_type: "text".to_string(),
+            file_url: None,
+            file_name: None,
+            file_size: None,
+            media_duration: None,
+            metadata: Some(serde_json::json!({
+                "customerId": customer.id,
+                "customerCode": customer.customer_id,
+                "shopId": session.shop_id,
+                "staffId": staff_id,
+            })),
+            reply_to,
+            rich_content: None,
+        };
+        let (persisted, reply_preview) = self
+            .persist_message(session, NOTE_SENDER_TYPE, Some(staff_id), &mut payload)
+            .await?;
+
+        let message_id = persisted.id;
+        Ok(PersistedMessage {
+            message: persisted,
+            ws_message: self.build_ws_message(
+                &payload,
+                Some(NOTE_SENDER_TYPE.to_string()),
+                Some(staff_id),
+                session.id,
+                Some(message_id),
+                reply_preview,
             ),
+            routed_session: None,
         })
     }
 
@@ -155,10 +238,25 @@ impl<'a> ChatService<'a> {
         session: &Session,
         sender_type: &str,
         sender_id: Option<i64>,
-        payload: &MessagePayload,
-    ) -> Result<Message> {
+        payload: &mut MessagePayload,
+    ) -> Result<(Message, Option<Value>)> {
+        // 引用回复：目标消息必须属于同一会话且未被删除；只有备注可以引用备注，避免预览泄露给客户
+        let reply_target = match payload.reply_to {
+            Some(reply_to) => Some(
+                crate::repositories::MessageRepository::find_in_session(
+                    &self.state.db_connection,
+                    session.id as i32,
+                    reply_to as i32,
+                )
+                .await?
+                .filter(|target| target.sender_type != NOTE_SENDER_TYPE || sender_type == NOTE_SENDER_TYPE)
+                .ok_or_else(|| anyhow!("invalid_reply_to"))?,
+            ),
+            None => None,
+        };
+
         // 🔧 修复：保持原始content，不要将None转为空字符串
-        let content = payload.content.clone().unwrap_or_else(|| {
+        let mut content = payload.content.clone().unwrap_or_else(|| {
             // 只有在真正为None时才使用默认值
             if payload.message_type == "text" { 
                 String::new() 
@@ -173,6 +271,20 @@ impl<'a> ChatService<'a> {
             payload.message_type.clone()
         };
 
+        // 富消息：校验后写入 rich_content，未填写文字时以摘要作为 content（预览 / 搜索用）
+        let rich = if message_type == rich_content::QUICK_REPLY {
+            Some(self.prepare_quick_reply(session, sender_type, payload.rich_content.as_ref()).await?)
+        } else {
+            rich_content::prepare(&message_type, sender_type, payload.rich_content.as_ref())?
+        };
+        if let Some(rich) = &rich {
+            if content.trim().is_empty() {
+                content = rich.summary.clone();
+                payload.content = Some(content.clone());
+            }
+        }
+        payload.rich_content = rich.map(|r| r.value);
+
         eprintln!("💾 [persist_message] content='{:?}', message_type={}, file_name={:?}", 
                   &content, &message_type, &payload.file_name);
 
@@ -186,9 +298,35 @@ impl<'a> ChatService<'a> {
             content, // 修复：正确的参数顺序 - content 是第7个参数
             payload.file_url.clone(),
             payload.file_name.clone(),
+            reply_target.as_ref().map(|target| target.id),
+            payload.rich_content.clone(),
         ).await?;
 
-        Ok(message.into())
+        Ok((message.into(), reply_target.as_ref().map(reply_preview)))
+    }
+
+    /// 客户点击按钮 / 快捷回复：rich_content 为 { type: quick_reply, messageId, optionId }，
+    /// 被点击的消息须属于同一会话且包含该选项
+    async fn prepare_quick_reply(
+        &self,
+        session: &Session,
+        sender_type: &str,
+        raw: Option<&Value>,
+    ) -> Result<rich_content::PreparedRichContent> {
+        if sender_type != "customer" {
+            return Err(anyhow!("rich_type_not_allowed"));
+        }
+        let answer: rich_content::QuickReplyAnswer = raw
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+            .ok_or_else(|| anyhow!("invalid_quick_reply"))?;
+        let card = crate::repositories::MessageRepository::find_in_session(
+            &self.state.db_connection,
+            session.id as i32,
+            answer.message_id as i32,
+        )
+        .await?
+        .ok_or_else(|| anyhow!("invalid_quick_reply"))?;
+        rich_content::answer_quick_reply(&card, &answer.option_id)
     }
 
     pub fn build_ws_message(
@@ -197,6 +335,8 @@ impl<'a> ChatService<'a> {
         sender_type: Option<String>,
         sender_id: Option<i64>,
         session_id: i64,
+        message_id: Option<i64>,
+        reply_preview: Option<Value>,
     ) -> WebSocketMessage {
         let mut meta_map = match payload.metadata.clone() {
             Some(Value::Object(map)) => map,
@@ -215,6 +355,12 @@ impl<'a> ChatService<'a> {
                 meta_map.insert("duration".to_string(), Value::Number(num));
             }
         }
+        if let Some(preview) = reply_preview {
+            meta_map.insert("replyTo".to_string(), preview);
+        }
+        if let Some(rich) = payload.rich_content.clone() {
+            meta_map.insert("richContent".to_string(), rich);
+        }
 
         WebSocketMessage {
             // 顶层事件名统一为 new_message
@@ -229,9 +375,83 @@ impl<'a> ChatService<'a> {
             file_name: payload.file_name.clone(),
             file_size: payload.file_size,
             media_duration: payload.media_duration,
+            message_id,
         }
     }
 
+    /// 消息回执：reader_type 一方确认对方在 up_to_message_id 及之前的消息已读/已送达，返回更新条数
+    pub async fn apply_receipt(
+        &self,
+        session_id: i64,
+        reader_type: &str,
+        read: bool,
+        up_to_message_id: i64,
+    ) -> Result<u64> {
+        let db = &self.state.db_connection;
+        if read {
+            crate::repositories::MessageRepository::mark_read_up_to(db, session_id as i32, reader_type, up_to_message_id as i32).await
+        } else {
+            crate::repositories::MessageRepository::mark_delivered_up_to(db, session_id as i32, reader_type, up_to_message_id as i32).await
+        }
+    }
+
+    /// 客服已读到 up_to_message_id：标记客户消息已读、重算店铺未读并推进该客服的个人游标
+    pub async fn record_staff_read(
+        &self,
+        shop_id: i64,
+        customer_id: i64,
+        staff_id: i64,
+        up_to_message_id: i64,
+    ) -> Result<()> {
+        let db = &self.state.db_connection;
+        crate::repositories::MessageRepository::mark_customer_messages_read(
+            db,
+            shop_id as i32,
+            Some(customer_id as i32),
+            Some(up_to_message_id as i32),
+        )
+        .await?;
+        crate::repositories::UnreadCountRepository::recount(db, shop_id, customer_id).await?;
+        crate::repositories::UnreadCountRepository::advance_staff_cursor(db, shop_id, customer_id, staff_id, up_to_message_id).await
+    }
+
+    /// 客户重连补发：返回会话中 id 大于 last_message_id 的 new_message 事件
+    pub async fn replay_for_session(&self, session_id: i64, last_message_id: i64) -> Result<Vec<WebSocketMessage>> {
+        let messages = crate::repositories::MessageRepository::find_by_session_after(
+            &self.state.db_connection,
+            session_id as i32,
+            last_message_id as i32,
+            crate::constants::replay_policy::MAX_MESSAGES,
+        )
+        .await?;
+        Ok(messages.into_iter().map(|m| replay_ws_message(m, None)).collect())
+    }
+
+    /// 客服重连补发：返回店铺内该客服能查看的会话中 id 大于 last_message_id 的 new_message 事件
+    pub async fn replay_for_shop(
+        &self,
+        access: &crate::services::permissions::ShopAccess,
+        last_message_id: i64,
+    ) -> Result<Vec<WebSocketMessage>> {
+        let rows = crate::repositories::MessageRepository::find_by_shop_after(
+            &self.state.db_connection,
+            access.shop_id as i32,
+            last_message_id as i32,
+            crate::constants::replay_policy::MAX_MESSAGES,
+        )
+        .await?;
+        Ok(rows
+            .into_iter()
+            // 只补发该客服能查看的会话中的消息
+            .filter(|(_, session)| {
+                session
+                    .as_ref()
+                    .is_some_and(|s| access.can_view_session(s.staff_id.map(i64::from)))
+            })
+            .map(|(m, session)| replay_ws_message(m, session.map(|s| (s.shop_id as i64, s.customer_id as i64))))
+            .collect())
+    }
+
     pub async fn resolve_session(&self, session_id: i64) -> Result<(crate::entities::sessions::Model, crate::entities::customers::Model)> {
         let session = crate::repositories::SessionRepository::find_by_id(
             &self.state.db_connection,
@@ -246,3 +466,150 @@ impl<'a> ChatService<'a> {
         Ok((session, customer))
     }
 }
+
+/// 引用回复的精简预览：被引用消息的 id、发送方与截断后的内容
+pub fn reply_preview(message: &crate::entities::messages::Model) -> Value {
+    const PREVIEW_CHARS: usize = 80;
+    let preview = match message.message_type.as_str() {
+        "image" => "[图片]".to_string(),
+        "file" => "[文件]".to_string(),
+        "voice" | "audio" => "[语音]".to_string(),
+        "video" => "[视频]".to_string(),
+        _ if message.content.chars().count() > PREVIEW_CHARS => {
+            format!("{}…", message.content.chars().take(PREVIEW_CHARS).collect::<String>())
+        }
+        _ => message.content.clone(),
+    };
+    serde_json::json!({
+        "id": message.id,
+        "senderType": message.sender_type,
+        "senderId": message.sender_id,
+        "messageType": message.message_type,
+        "content": preview,
+    })
+}
+
+/// 向店铺客服推送 session_updated；operator_id 为 None 表示系统自动操作
+///
+/// 只含会话状态与分配信息、不含消息内容，推送给店铺全部客服，以便被转走会话的客服同步移除
+pub fn broadcast_session_updated(state: &AppState, session: &Session, action: &str, operator_id: Option<i64>) {
+    let notice = WebSocketMessage {
+        message_type: crate::constants::ws_events::SESSION_UPDATED.to_string(),
+        content: None,
+        session_id: Some(session.id),
+        sender_id: operator_id,
+        sender_type: Some("system".to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(serde_json::json!({
+            "action": action,
+            "sessionId": session.id,
+            "shopId": session.shop_id,
+            "customerId": session.customer_id,
+            "staffId": session.staff_id,
+            "status": session.session_status,
+            "priority": session.priority,
+            "closedAt": session.closed_at,
+            "operatorId": operator_id,
+        })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: None,
+    };
+    let mut manager = state.connections.lock().unwrap();
+    manager.broadcast_to_staff(session.shop_id, &notice);
+}
+
+/// 消息被编辑 / 撤回后推送给会话客户与店铺客服
+///
+/// event 为 ws_events::MESSAGE_EDITED 或 MESSAGE_RECALLED；operator 为 (发送方类型, id)
+pub async fn broadcast_message_change(
+    state: &AppState,
+    event: &str,
+    message: &crate::entities::messages::Model,
+    operator: (&str, i64),
+) {
+    let chat = ChatService::new(state);
+    let (session, customer) = match chat.resolve_session(message.session_id as i64).await {
+        Ok(found) => found,
+        Err(e) => {
+            tracing::warn!("推送消息 {} 的 {} 失败: {:?}", message.id, event, e);
+            return;
+        }
+    };
+    let changed_at = message.updated_at.map(|t| t.and_utc()).unwrap_or_else(Utc::now);
+    let edited = event == crate::constants::ws_events::MESSAGE_EDITED;
+
+    let notice = WebSocketMessage {
+        message_type: event.to_string(),
+        // 撤回后不再下发原内容
+        content: if edited { Some(message.content.clone()) } else { None },
+        session_id: Some(message.session_id as i64),
+        sender_id: Some(operator.1),
+        sender_type: Some(operator.0.to_string()),
+        timestamp: Some(changed_at),
+        metadata: Some(serde_json::json!({
+            "messageId": message.id,
+            "shopId": session.shop_id,
+            "customerId": customer.id,
+            "customerCode": customer.customer_id,
+            "originalSenderType": message.sender_type,
+            "originalSenderId": message.sender_id,
+            "operatorType": operator.0,
+            "operatorId": operator.1,
+            "changedAt": changed_at,
+        })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: Some(message.id as i64),
+    };
+    let mut manager = state.connections.lock().unwrap();
+    // 内部备注的变更同样只通知客服
+    if message.sender_type != NOTE_SENDER_TYPE {
+        manager.send_to_customer(session.shop_id as i64, &customer.customer_id, &notice);
+    }
+    manager.broadcast_to_session_staff(session.shop_id as i64, session.staff_id.map(i64::from), &notice);
+}
+
+/// 将已持久化的消息还原为 new_message 事件（metadata.replayed = true 标识补发）
+fn replay_ws_message(
+    message: crate::entities::messages::Model,
+    shop_customer: Option<(i64, i64)>,
+) -> WebSocketMessage {
+    let mut meta_map = match message.metadata.clone() {
+        Some(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    let file_url = meta_map.get("file_url").and_then(|v| v.as_str()).map(|s| s.to_string());
+    let file_name = meta_map.get("file_name").and_then(|v| v.as_str()).map(|s| s.to_string());
+    meta_map.insert("messageType".to_string(), Value::String(message.message_type.clone()));
+    meta_map.insert("replayed".to_string(), Value::Bool(true));
+    if let Some(reply_to) = message.reply_to {
+        meta_map.insert("replyTo".to_string(), serde_json::json!({ "id": reply_to }));
+    }
+    if let Some(rich) = message.rich_content.clone() {
+        meta_map.insert("richContent".to_string(), rich);
+    }
+    if let Some((shop_id, customer_id)) = shop_customer {
+        meta_map.insert("shopId".to_string(), Value::from(shop_id));
+        meta_map.insert("customerId".to_string(), Value::from(customer_id));
+    }
+
+    WebSocketMessage {
+        message_type: crate::constants::ws_events::NEW_MESSAGE.to_string(),
+        content: Some(message.content),
+        session_id: Some(message.session_id as i64),
+        sender_id: message.sender_id.map(|id| id as i64),
+        sender_type: Some(message.sender_type),
+        timestamp: Some(chrono::DateTime::from_naive_utc_and_offset(message.created_at, Utc)),
+        metadata: Some(Value::Object(meta_map)),
+        file_url,
+        file_name,
+        file_size: None,
+        media_duration: None,
+        message_id: Some(message.id as i64),
+    }
+}
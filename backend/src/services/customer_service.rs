@@ -5,12 +5,55 @@
 //! - 客户概览查询
 //! - 客户统计
 //! - 客户搜索与过滤
+//! - 客户标签与自定义属性
+//! - 客户拉黑 / 解除拉黑（支持到期自动解除）
+
+use std::collections::{BTreeMap, HashMap};
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use serde_json::{Map, Value};
 
-use crate::repositories::{CustomerRepository, ShopStaffRepository};
+use crate::repositories::{customer::CustomerFilter, CustomerRepository};
 use crate::entities::{customers, sessions, messages};
+use crate::services::permissions::{Capability, ShopAccess};
+
+const MAX_TAG_CHARS: usize = 32;
+const MAX_TAGS_PER_CUSTOMER: usize = 20;
+const MAX_ATTRIBUTE_KEY_CHARS: usize = 64;
+const MAX_ATTRIBUTE_VALUE_CHARS: usize = 1000;
+const MAX_ATTRIBUTES_PER_CUSTOMER: usize = 50;
+const MAX_BLOCK_REASON_CHARS: usize = 500;
+
+/// 店铺内使用中的标签
+#[derive(Debug, Clone, Serialize)]
+pub struct TagUsage {
+    pub tag: String,
+    pub customers: i64,
+}
+
+/// 客户列表查询条件（分页接口）
+#[derive(Debug, Clone, Default)]
+pub struct CustomerListFilter {
+    pub keyword: Option<String>,
+    pub tags: Vec<String>,
+    pub attr_key: Option<String>,
+    pub attr_value: Option<String>,
+}
+
+/// 客户当前生效的拉黑信息
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockInfo {
+    pub reason: Option<String>,
+    /// 为空表示永久拉黑
+    pub blocked_until: Option<DateTime<Utc>>,
+}
+
+/// 一批客户的标签与属性（按 customers.id 分组）
+pub type CustomerTraits = (HashMap<i32, Vec<String>>, HashMap<i32, BTreeMap<String, String>>);
 
 #[derive(Clone)]
 pub struct CustomerService {
@@ -25,7 +68,7 @@ impl CustomerService {
     /// 创建或更新客户
     /// 
     /// 业务逻辑：
-    /// 1. 验证店铺访问权限
+    /// 1. 有操作者（客服）时，须属于该店铺且具备 manage_customers
     /// 2. 创建或更新客户信息
     pub async fn upsert_customer(
         db: &DatabaseConnection,
@@ -34,11 +77,11 @@ impl CustomerService {
         name: Option<String>,
         email: Option<String>,
         avatar_url: Option<String>,
-        operator_user_id: Option<i32>,
+        operator: Option<&ShopAccess>,
     ) -> Result<customers::Model> {
         // 如果有操作者，验证权限
-        if let Some(user_id) = operator_user_id {
-            if !ShopStaffRepository::is_shop_member(db, shop_id as i64, user_id as i64).await? {
+        if let Some(access) = operator {
+            if access.shop_id != shop_id as i64 || !access.can(Capability::ManageCustomers) {
                 anyhow::bail!("permission_denied");
             }
         }
@@ -57,19 +100,13 @@ impl CustomerService {
     /// 获取店铺的所有客户
     /// 
     /// 业务逻辑：
-    /// 1. 验证访问权限
+    /// 1. 店铺与成员身份来自 ShopAccess（handler 提取器已校验）
     /// 2. 获取客户列表
     pub async fn get_shop_customers(
         db: &DatabaseConnection,
-        shop_id: i32,
-        requester_user_id: i32,
+        access: &ShopAccess,
     ) -> Result<Vec<customers::Model>> {
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        CustomerRepository::find_by_shop(db, shop_id).await
+        CustomerRepository::find_by_shop(db, access.shop_id as i32).await
     }
     
     /// 获取客户概览（包含会话和最后消息）
@@ -91,113 +128,90 @@ impl CustomerService {
     /// 搜索客户
     /// 
     /// 业务逻辑：
-    /// 1. 验证访问权限
+    /// 1. 店铺与成员身份来自 ShopAccess（handler 提取器已校验）
     /// 2. 搜索客户
     pub async fn search_customers(
         db: &DatabaseConnection,
-        shop_id: i32,
-        requester_user_id: i32,
+        access: &ShopAccess,
         keyword: &str,
     ) -> Result<Vec<customers::Model>> {
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        CustomerRepository::search(db, shop_id, keyword).await
+        CustomerRepository::search(db, access.shop_id as i32, keyword).await
     }
     
-    /// 阻止客户
+    /// 拉黑客户（权限由 handler 层校验）
     /// 
     /// 业务逻辑：
-    /// 1. 验证权限（需要管理员或店主权限）
-    /// 2. 阻止客户
+    /// 1. 客户必须属于该店铺
+    /// 2. 到期时间必须晚于当前时间；为空表示永久拉黑
+    /// 3. 记录原因、到期时间与操作人，status 置为 0
     pub async fn block_customer(
-        db: &DatabaseConnection,
+        &self,
         shop_id: i32,
         customer_id: i32,
         operator_user_id: i32,
-    ) -> Result<()> {
-        // 验证权限（需要是店铺成员）
-        if !ShopStaffRepository::is_shop_member(db, shop_id as i64, operator_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
+        reason: Option<String>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<customers::Model> {
+        self.find_in_shop(shop_id, customer_id).await?;
+        if until.is_some_and(|t| t <= Utc::now()) {
+            anyhow::bail!("invalid_block_until");
         }
-        
-        // 验证客户属于该店铺
-        let customer = CustomerRepository::find_by_id(db, customer_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("customer_not_found"))?;
-        
-        if customer.shop_id != shop_id {
-            anyhow::bail!("customer_not_in_shop");
+        let reason = reason.map(|r| r.trim().to_string()).filter(|r| !r.is_empty());
+        if reason.as_ref().is_some_and(|r| r.chars().count() > MAX_BLOCK_REASON_CHARS) {
+            anyhow::bail!("invalid_block_reason");
         }
-        
-        CustomerRepository::block(db, customer_id).await
+        CustomerRepository::block(&self.db, customer_id, reason, until.map(|t| t.naive_utc()), operator_user_id).await
+    }
+    
+    /// 解除拉黑（权限由 handler 层校验）
+    pub async fn unblock_customer(&self, shop_id: i32, customer_id: i32) -> Result<customers::Model> {
+        self.find_in_shop(shop_id, customer_id).await?;
+        CustomerRepository::unblock(&self.db, customer_id).await
     }
     
-    /// 解除阻止
+    /// 查询客户当前是否处于拉黑状态（WebSocket / 上传入口使用）
     /// 
     /// 业务逻辑：
-    /// 1. 验证权限
-    /// 2. 解除阻止
-    pub async fn unblock_customer(
-        db: &DatabaseConnection,
-        shop_id: i32,
-        customer_id: i32,
-        operator_user_id: i32,
-    ) -> Result<()> {
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, shop_id as i64, operator_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
+    /// 1. 客户不存在视为未拉黑
+    /// 2. 已过期的拉黑在此处自动解除
+    pub async fn active_block(&self, shop_id: i32, customer_code: &str) -> Result<Option<BlockInfo>> {
+        let Some(customer) = CustomerRepository::find_by_shop_and_customer_id(&self.db, shop_id, customer_code).await? else {
+            return Ok(None);
+        };
+        if customer.status != Some(0) {
+            return Ok(None);
         }
-        
-        // 验证客户属于该店铺
-        let customer = CustomerRepository::find_by_id(db, customer_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("customer_not_found"))?;
-        
-        if customer.shop_id != shop_id {
-            anyhow::bail!("customer_not_in_shop");
+        let blocked_until = customer.blocked_until.map(|t| t.and_utc());
+        if blocked_until.is_some_and(|t| t <= Utc::now()) {
+            CustomerRepository::unblock(&self.db, customer.id).await?;
+            return Ok(None);
         }
-        
-        CustomerRepository::unblock(db, customer_id).await
+        Ok(Some(BlockInfo { reason: customer.blocked_reason, blocked_until }))
     }
     
     /// 统计活跃客户数
     /// 
     /// 业务逻辑：
-    /// 1. 验证访问权限
+    /// 1. 店铺与成员身份来自 ShopAccess（handler 提取器已校验）
     /// 2. 统计指定天数内活跃的客户数
     pub async fn count_active_customers(
         db: &DatabaseConnection,
-        shop_id: i32,
-        requester_user_id: i32,
+        access: &ShopAccess,
         days: i64,
     ) -> Result<u64> {
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        CustomerRepository::count_active_by_shop(db, shop_id, days).await
+        CustomerRepository::count_active_by_shop(db, access.shop_id as i32, days).await
     }
     
-    /// 获取客户详情
+    /// 获取客户详情（不属于该店铺的客户按不存在处理）
     pub async fn get_customer_detail(
         db: &DatabaseConnection,
+        access: &ShopAccess,
         customer_id: i32,
-        requester_user_id: i32,
     ) -> Result<customers::Model> {
-        let customer = CustomerRepository::find_by_id(db, customer_id)
+        CustomerRepository::find_by_id(db, customer_id)
             .await?
-            .ok_or_else(|| anyhow::anyhow!("customer_not_found"))?;
-        
-        // 验证访问权限
-        if !ShopStaffRepository::is_shop_member(db, customer.shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        Ok(customer)
+            .filter(|c| c.shop_id as i64 == access.shop_id)
+            .ok_or_else(|| anyhow::anyhow!("customer_not_found"))
     }
     
     /// 更新客户最后活跃时间
@@ -239,25 +253,119 @@ impl CustomerService {
         shop_id: i32,
         limit: i64,
         offset: i64,
-        keyword: Option<String>,
+        filter: CustomerListFilter,
         sort: Option<String>,
     ) -> Result<(Vec<(customers::Model, Option<sessions::Model>, Option<messages::Model>, i64)>, i64)> {
         // 权限已在 handler 层通过 SQLx 校验，这里不再重复校验
 
-        let kw_ref = keyword.as_deref();
-        let total = CustomerRepository::count_by_shop(&self.db, shop_id, kw_ref).await?;
+        let filter = CustomerFilter {
+            keyword: filter.keyword.as_deref(),
+            tags: &filter.tags,
+            attr_key: filter.attr_key.as_deref().filter(|k| !k.is_empty()),
+            attr_value: filter.attr_value.as_deref(),
+        };
+        let total = CustomerRepository::count_by_shop(&self.db, shop_id, &filter).await?;
         let items = CustomerRepository::find_with_overview_by_shop_paged(
             &self.db,
             shop_id,
             limit,
             offset,
-            kw_ref,
+            &filter,
             sort.as_deref(),
         )
         .await?;
         Ok((items, total))
     }
 
+    /// 批量获取客户的标签与属性（列表展示用）
+    pub async fn get_customer_traits(&self, customer_ids: &[i32]) -> Result<CustomerTraits> {
+        if customer_ids.is_empty() {
+            return Ok((HashMap::new(), HashMap::new()));
+        }
+        let tags = CustomerRepository::find_tags_by_customers(&self.db, customer_ids).await?;
+        let attributes = CustomerRepository::find_attributes_by_customers(&self.db, customer_ids).await?;
+        Ok((tags, attributes))
+    }
+
+    /// 店铺内使用中的标签及客户数
+    pub async fn list_shop_tags(&self, shop_id: i32) -> Result<Vec<TagUsage>> {
+        let rows = CustomerRepository::count_tags_by_shop(&self.db, shop_id).await?;
+        Ok(rows.into_iter().map(|(tag, customers)| TagUsage { tag, customers }).collect())
+    }
+
+    /// 客服设置客户标签（整体替换）
+    ///
+    /// 业务逻辑：
+    /// 1. 客户必须属于该店铺
+    /// 2. 标签去首尾空白、去重，长度 1..=32，单个客户最多 20 个
+    /// 3. 替换客户现有的全部标签
+    pub async fn set_customer_tags(&self, shop_id: i32, customer_id: i32, tags: Vec<String>) -> Result<Vec<String>> {
+        self.find_in_shop(shop_id, customer_id).await?;
+        let tags = normalize_tags(tags.iter().map(String::as_str), true)?;
+        CustomerRepository::replace_tags(&self.db, shop_id, customer_id, &tags).await?;
+        Ok(tags)
+    }
+
+    /// 客服更新客户属性（按键合并，值为 null 表示删除该键）
+    ///
+    /// 业务逻辑：
+    /// 1. 客户必须属于该店铺
+    /// 2. 键由字母、数字、_ - . 组成；值为字符串 / 数字 / 布尔，统一存为字符串
+    /// 3. 合并后单个客户最多 50 个属性
+    pub async fn update_customer_attributes(
+        &self,
+        shop_id: i32,
+        customer_id: i32,
+        attributes: Map<String, Value>,
+    ) -> Result<BTreeMap<String, String>> {
+        self.find_in_shop(shop_id, customer_id).await?;
+        let NormalizedAttributes { upserts, removals } = normalize_attributes(&attributes, true)?;
+
+        let mut merged = CustomerRepository::find_attributes_by_customers(&self.db, &[customer_id])
+            .await?
+            .remove(&customer_id)
+            .unwrap_or_default();
+        for key in &removals {
+            merged.remove(key);
+        }
+        for (key, value) in &upserts {
+            merged.insert(key.clone(), value.clone());
+        }
+        if merged.len() > MAX_ATTRIBUTES_PER_CUSTOMER {
+            anyhow::bail!("too_many_attributes");
+        }
+
+        CustomerRepository::merge_attributes(&self.db, shop_id, customer_id, &upserts, &removals).await?;
+        Ok(merged)
+    }
+
+    /// SDK AUTH 携带的标签 / 属性：标签只追加，非法项直接忽略，不影响认证
+    async fn apply_sdk_traits(&self, customer: &customers::Model, upsert_data: &Value) -> Result<()> {
+        if let Some(raw_tags) = upsert_data.get("tags").and_then(|v| v.as_array()) {
+            let mut tags = normalize_tags(raw_tags.iter().filter_map(|v| v.as_str()), false)?;
+            let existing = CustomerRepository::find_tags_by_customers(&self.db, &[customer.id])
+                .await?
+                .remove(&customer.id)
+                .unwrap_or_default();
+            tags.retain(|tag| !existing.contains(tag));
+            tags.truncate(MAX_TAGS_PER_CUSTOMER.saturating_sub(existing.len()));
+            CustomerRepository::add_tags(&self.db, customer.shop_id, customer.id, &tags).await?;
+        }
+        if let Some(raw_attributes) = upsert_data.get("attributes").and_then(|v| v.as_object()) {
+            let NormalizedAttributes { mut upserts, removals } = normalize_attributes(raw_attributes, false)?;
+            upserts.truncate(MAX_ATTRIBUTES_PER_CUSTOMER);
+            CustomerRepository::merge_attributes(&self.db, customer.shop_id, customer.id, &upserts, &removals).await?;
+        }
+        Ok(())
+    }
+
+    async fn find_in_shop(&self, shop_id: i32, customer_id: i32) -> Result<customers::Model> {
+        CustomerRepository::find_by_id(&self.db, customer_id)
+            .await?
+            .filter(|c| c.shop_id == shop_id)
+            .ok_or_else(|| anyhow::anyhow!("customer_not_found"))
+    }
+
     /// Chat Service 需要的方法：创建或更新客户
     pub async fn create_or_update_customer(
         &self,
@@ -271,6 +379,77 @@ impl CustomerService {
         let avatar_url = upsert_data.get("avatar_url").and_then(|v| v.as_str()).map(|s| s.to_string());
 
         // 调用现有的 upsert 方法
-        CustomerRepository::create_or_update(&self.db, shop_id, customer_id.clone(), name, email, avatar_url).await
+        let customer = CustomerRepository::create_or_update(&self.db, shop_id, customer_id.clone(), name, email, avatar_url).await?;
+
+        if let Err(e) = self.apply_sdk_traits(&customer, &upsert_data).await {
+            tracing::warn!("写入客户 {} 的标签/属性失败: {:?}", customer.id, e);
+        }
+        Ok(customer)
+    }
+}
+
+/// 标签规范化：去首尾空白、去重；strict 时非法标签报错，否则忽略
+fn normalize_tags<'a>(raw: impl Iterator<Item = &'a str>, strict: bool) -> Result<Vec<String>> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in raw.map(str::trim) {
+        let valid = !tag.is_empty() && tag.chars().count() <= MAX_TAG_CHARS;
+        if !valid {
+            if strict {
+                anyhow::bail!("invalid_tag");
+            }
+            continue;
+        }
+        if !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    if tags.len() > MAX_TAGS_PER_CUSTOMER {
+        if strict {
+            anyhow::bail!("too_many_tags");
+        }
+        tags.truncate(MAX_TAGS_PER_CUSTOMER);
+    }
+    tags.sort();
+    Ok(tags)
+}
+
+/// 规范化后的属性变更
+struct NormalizedAttributes {
+    /// 写入的键值
+    upserts: Vec<(String, String)>,
+    /// 值为 null、需要删除的键
+    removals: Vec<String>,
+}
+
+/// 属性规范化；strict 时非法项报错，否则忽略
+fn normalize_attributes(raw: &Map<String, Value>, strict: bool) -> Result<NormalizedAttributes> {
+    let mut upserts = Vec::new();
+    let mut removals = Vec::new();
+    for (key, value) in raw {
+        let key = key.trim();
+        let key_valid = !key.is_empty()
+            && key.chars().count() <= MAX_ATTRIBUTE_KEY_CHARS
+            && key.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        let value = match value {
+            Value::Null => None,
+            Value::String(s) => Some(s.trim().to_string()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => Some(String::new()),
+        };
+        let value_valid = value
+            .as_ref()
+            .is_none_or(|v| !v.is_empty() && v.chars().count() <= MAX_ATTRIBUTE_VALUE_CHARS);
+        if !key_valid || !value_valid {
+            if strict {
+                anyhow::bail!("invalid_attribute");
+            }
+            continue;
+        }
+        match value {
+            Some(value) => upserts.push((key.to_string(), value)),
+            None => removals.push(key.to_string()),
+        }
     }
+    Ok(NormalizedAttributes { upserts, removals })
 }
@@ -0,0 +1,218 @@
+//! 登录审计与失败限制
+//!
+//! 职责：
+//! - 记录每次登录尝试（IP、User-Agent、结果、是否经过双因素认证）到 login_events
+//! - 按用户名与 IP 统计 LOGIN_LOCKOUT_MINUTES 内的失败次数：超过门槛后每次重试需等待的时间逐次翻倍，
+//!   再超过锁定门槛则临时锁定（即使密码正确也拒绝），以 429 + Retry-After 返回
+//! - 用户名的失败次数在该用户名成功登录后清零；IP 的失败次数不因成功登录清零，避免攻击者用自己的账号重置计数
+//! - 用户查询自己的登录记录，店主查询本店员工的登录记录
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use tracing::{error, warn};
+
+use crate::{constants::auth_policy, database::Database, error::AppError};
+
+/// 登录成功（两步登录时为第二步完成）
+pub const RESULT_SUCCESS: &str = "success";
+/// 用户名不存在或密码错误
+pub const RESULT_INVALID_CREDENTIALS: &str = "invalid_credentials";
+/// 账号已停用
+pub const RESULT_USER_INACTIVE: &str = "user_inactive";
+/// 密码正确，已下发双因素挑战，等待验证码
+pub const RESULT_TWO_FACTOR_PENDING: &str = "two_factor_pending";
+/// 双因素验证码或恢复码错误
+pub const RESULT_TWO_FACTOR_FAILED: &str = "two_factor_failed";
+/// 因失败次数过多被拒绝（不再计入失败次数，避免锁定被无限延长）
+pub const RESULT_LOCKED: &str = "locked";
+
+/// 计入失败次数的结果
+const COUNTED_FAILURES_SQL: &str = "('invalid_credentials', 'two_factor_failed')";
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct LoginEvent {
+    pub id: i64,
+    pub user_id: Option<i64>,
+    pub username: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub result: String,
+    pub success: bool,
+    pub two_factor: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 一次登录尝试的来源
+pub struct LoginAttempt<'a> {
+    pub username: &'a str,
+    pub ip_address: &'a str,
+    pub user_agent: Option<&'a str>,
+}
+
+fn db_error(code: &'static str) -> impl Fn(sqlx::Error) -> AppError {
+    move |e| {
+        error!(target: "login_audit", "{}: {}", code, e);
+        AppError::Internal(code.to_string())
+    }
+}
+
+/// 根据失败次数与距最近一次失败的秒数计算还需等待的秒数（0 表示可以尝试）
+fn wait_secs(failures: i64, elapsed_secs: i64, (delay_after, lockout_after): (i64, i64)) -> i64 {
+    let required = if failures >= lockout_after {
+        auth_policy::login_lockout_secs()
+    } else if failures >= delay_after {
+        let exponent = (failures - delay_after).min(16) as u32;
+        2_i64.pow(exponent).min(auth_policy::LOGIN_MAX_DELAY_SECS)
+    } else {
+        0
+    };
+    (required - elapsed_secs).max(0)
+}
+
+/// 检查该用户名与 IP 当前是否允许尝试登录
+///
+/// 业务逻辑：
+/// 1. 分别统计窗口内用户名（上次成功登录之后）与 IP 的失败次数，以及距最近一次失败的秒数
+/// 2. 任一方需要等待时记录一条 locked 事件并返回 TooManyRequests（取两者中较长的等待时间）
+pub async fn check_allowed(db: &Database, attempt: &LoginAttempt<'_>) -> Result<(), AppError> {
+    let window = format!("-{} seconds", auth_policy::login_lockout_secs());
+    let (user_failures, user_elapsed) = sqlx::query_as::<_, (i64, Option<i64>)>(&format!(
+        "SELECT COUNT(*), CAST(strftime('%s', 'now') - strftime('%s', MAX(created_at)) AS INTEGER) \
+         FROM login_events WHERE username = ? AND result IN {COUNTED_FAILURES_SQL} AND created_at > datetime('now', ?) \
+         AND created_at > COALESCE((SELECT MAX(created_at) FROM login_events WHERE username = ? AND success = 1), '')"
+    ))
+    .bind(attempt.username)
+    .bind(&window)
+    .bind(attempt.username)
+    .fetch_one(db.pool())
+    .await
+    .map_err(db_error("login_throttle_lookup_failed"))?;
+    let (ip_failures, ip_elapsed) = sqlx::query_as::<_, (i64, Option<i64>)>(&format!(
+        "SELECT COUNT(*), CAST(strftime('%s', 'now') - strftime('%s', MAX(created_at)) AS INTEGER) \
+         FROM login_events WHERE ip_address = ? AND result IN {COUNTED_FAILURES_SQL} AND created_at > datetime('now', ?)"
+    ))
+    .bind(attempt.ip_address)
+    .bind(&window)
+    .fetch_one(db.pool())
+    .await
+    .map_err(db_error("login_throttle_lookup_failed"))?;
+
+    let wait = wait_secs(user_failures, user_elapsed.unwrap_or(0), auth_policy::login_username_thresholds())
+        .max(wait_secs(ip_failures, ip_elapsed.unwrap_or(0), auth_policy::login_ip_thresholds()));
+    if wait == 0 {
+        return Ok(());
+    }
+    warn!(
+        target: "login_audit",
+        "登录受限：用户名 {}（失败 {} 次）IP {}（失败 {} 次），需等待 {} 秒",
+        attempt.username, user_failures, attempt.ip_address, ip_failures, wait
+    );
+    record(db, attempt, RESULT_LOCKED, false).await;
+    Err(AppError::TooManyRequests { retry_after_secs: wait as u64 })
+}
+
+/// 记录一次登录尝试；写入失败只记录日志，不影响登录流程
+///
+/// user_id 按用户名解析，用户名不存在时为空
+pub async fn record(db: &Database, attempt: &LoginAttempt<'_>, result: &str, two_factor: bool) {
+    let inserted = sqlx::query(
+        "INSERT INTO login_events (user_id, username, ip_address, user_agent, result, success, two_factor) \
+         VALUES ((SELECT id FROM users WHERE username = ?), ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(attempt.username)
+    .bind(attempt.username.chars().take(100).collect::<String>())
+    .bind(attempt.ip_address)
+    .bind(attempt.user_agent.map(|ua| ua.chars().take(512).collect::<String>()))
+    .bind(result)
+    .bind(result == RESULT_SUCCESS)
+    .bind(two_factor)
+    .execute(db.pool())
+    .await;
+    if let Err(e) = inserted {
+        error!(target: "login_audit", "记录登录事件失败（{}）: {}", attempt.username, e);
+    }
+}
+
+const EVENT_COLUMNS: &str =
+    "e.id, e.user_id, e.username, e.ip_address, e.user_agent, e.result, e.success, e.two_factor, e.created_at";
+
+/// 当前用户的登录记录，按时间倒序
+pub async fn list_for_user(db: &Database, user_id: i64, limit: i64, offset: i64) -> Result<Vec<LoginEvent>, AppError> {
+    sqlx::query_as::<_, LoginEvent>(&format!(
+        "SELECT {EVENT_COLUMNS} FROM login_events e WHERE e.user_id = ? ORDER BY e.id DESC LIMIT ? OFFSET ?"
+    ))
+    .bind(user_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(db.pool())
+    .await
+    .map_err(db_error("login_events_query_failed"))
+}
+
+/// 店铺员工的登录记录（可按员工过滤），按时间倒序；不包含店主本人
+pub async fn list_for_shop_staff(
+    db: &Database,
+    shop_id: i64,
+    staff_user_id: Option<i64>,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<LoginEvent>, AppError> {
+    sqlx::query_as::<_, LoginEvent>(&format!(
+        "SELECT {EVENT_COLUMNS} FROM login_events e \
+         JOIN shop_staffs s ON s.user_id = e.user_id AND s.shop_id = ? \
+         WHERE (? IS NULL OR e.user_id = ?) ORDER BY e.id DESC LIMIT ? OFFSET ?"
+    ))
+    .bind(shop_id)
+    .bind(staff_user_id)
+    .bind(staff_user_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(db.pool())
+    .await
+    .map_err(db_error("login_events_query_failed"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THRESHOLDS: (i64, i64) = (3, 10);
+
+    #[test]
+    fn no_wait_below_delay_threshold() {
+        for failures in 0..3 {
+            assert_eq!(wait_secs(failures, 0, THRESHOLDS), 0);
+        }
+    }
+
+    #[test]
+    fn delay_doubles_per_failure_up_to_cap() {
+        let waits: Vec<i64> = (3..10).map(|failures| wait_secs(failures, 0, THRESHOLDS)).collect();
+        assert_eq!(waits, vec![1, 2, 4, 8, 16, 32, auth_policy::LOGIN_MAX_DELAY_SECS]);
+        // 门槛很高时指数被限制，不会溢出
+        assert_eq!(wait_secs(999, 0, (1, 1000)), auth_policy::LOGIN_MAX_DELAY_SECS);
+    }
+
+    #[test]
+    fn elapsed_time_counts_towards_wait() {
+        assert_eq!(wait_secs(6, 3, THRESHOLDS), 5);
+        assert_eq!(wait_secs(6, 8, THRESHOLDS), 0);
+        assert_eq!(wait_secs(6, 100, THRESHOLDS), 0);
+    }
+
+    #[test]
+    fn lockout_after_threshold() {
+        let lockout = auth_policy::login_lockout_secs();
+        assert_eq!(wait_secs(10, 0, THRESHOLDS), lockout);
+        assert_eq!(wait_secs(25, 0, THRESHOLDS), lockout);
+        assert_eq!(wait_secs(10, 60, THRESHOLDS), lockout - 60);
+        assert_eq!(wait_secs(10, lockout, THRESHOLDS), 0);
+    }
+
+    #[test]
+    fn lockout_takes_precedence_over_delay() {
+        // 锁定门槛不高于延迟门槛时直接锁定
+        assert_eq!(wait_secs(5, 0, (5, 5)), auth_policy::login_lockout_secs());
+    }
+}
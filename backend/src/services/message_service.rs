@@ -5,12 +5,89 @@
 //! - 消息已读状态管理
 //! - 消息历史查询
 //! - 消息搜索
+//! - 消息编辑（保留编辑历史）、撤回与删除
 
 use anyhow::Result;
 use sea_orm::DatabaseConnection;
 
-use crate::repositories::{MessageRepository, SessionRepository, ShopStaffRepository};
-use crate::entities::messages;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+
+use crate::constants::message_policy::NOTE_SENDER_TYPE;
+use crate::repositories::{MessageRepository, SessionRepository};
+use crate::repositories::message::MessageSearchFilter;
+use crate::entities::{message_edits, messages};
+use crate::services::permissions::{Capability, ShopAccess};
+use crate::services::session_service::SessionService;
+
+/// 客服发送消息的可选字段
+#[derive(Debug, Clone, Default)]
+pub struct StaffMessageInput {
+    /// 默认 text
+    pub message_type: Option<String>,
+    pub file_url: Option<String>,
+    pub file_name: Option<String>,
+    /// 引用回复的消息 ID
+    pub reply_to: Option<i64>,
+    /// 富消息结构（见 rich_content）
+    pub rich_content: Option<serde_json::Value>,
+}
+
+/// 消息编辑 / 撤回的操作方
+#[derive(Debug, Clone, Copy)]
+pub enum MessageAuthor {
+    Staff(i64),
+    Customer(i64),
+}
+
+impl MessageAuthor {
+    pub fn sender_type(&self) -> &'static str {
+        match self {
+            MessageAuthor::Staff(_) => "staff",
+            MessageAuthor::Customer(_) => "customer",
+        }
+    }
+
+    pub fn id(&self) -> i64 {
+        match self {
+            MessageAuthor::Staff(id) | MessageAuthor::Customer(id) => *id,
+        }
+    }
+}
+
+/// 全文搜索条件；to 为开区间
+#[derive(Debug, Clone)]
+pub struct MessageSearchQuery {
+    pub shop_id: i64,
+    pub keyword: String,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub sender_type: Option<String>,
+    pub message_type: Option<String>,
+    /// 只搜索该客服发送的消息
+    pub staff_id: Option<i64>,
+    pub limit: u64,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageSearchHit {
+    pub message_id: i64,
+    pub session_id: i64,
+    pub customer_id: i64,
+    pub customer_code: Option<String>,
+    pub customer_name: Option<String>,
+    /// 会话当前负责的客服
+    pub staff_id: Option<i64>,
+    pub sender_type: String,
+    pub sender_id: Option<i64>,
+    pub message_type: String,
+    pub content: String,
+    /// HTML 转义后的内容片段，命中处包裹 <mark>
+    pub content_highlight: String,
+    pub customer_name_highlight: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
 
 #[derive(Clone)]
 pub struct MessageService {
@@ -52,15 +129,29 @@ impl MessageService {
         user_id: i32,
         session_id: i64,
         content: &str,
-        message_type: Option<String>,
-        file_url: Option<String>,
-        file_name: Option<String>,
+        input: StaffMessageInput,
     ) -> Result<messages::Model> {
+        let StaffMessageInput { message_type, file_url, file_name, reply_to, rich_content } = input;
         let message_type = message_type.unwrap_or_else(|| "text".to_string());
         
         eprintln!("🔍 send_staff_message - message_type: {}, file_url: {:?}, file_name: {:?}", 
                   message_type, file_url, file_name);
         
+        // 引用回复：目标消息必须属于同一会话且未被删除，且不能是内部备注（预览会下发给客户）
+        if let Some(reply_to) = reply_to {
+            let target = MessageRepository::find_in_session(&self.db, session_id as i32, reply_to as i32).await?;
+            if target.is_none_or(|t| t.sender_type == NOTE_SENDER_TYPE) {
+                anyhow::bail!("invalid_reply_to");
+            }
+        }
+        
+        // 富消息：校验结构，未填写文字时以摘要作为 content
+        let rich = crate::services::rich_content::prepare(&message_type, "staff", rich_content.as_ref())?;
+        let content = match &rich {
+            Some(rich) if content.trim().is_empty() => rich.summary.clone(),
+            _ => content.to_string(),
+        };
+        
         // 验证权限和创建消息的逻辑
         let message = MessageRepository::create(
             &self.db,
@@ -69,9 +160,11 @@ impl MessageService {
             Some(user_id),  // 直接传入 i32
             None, // sender_name
             message_type,
-            content.to_string(),
+            content,
             file_url,
             file_name,
+            reply_to.map(|id| id as i32),
+            rich.map(|r| r.value),
         ).await?;
 
         Ok(message)
@@ -81,31 +174,34 @@ impl MessageService {
     /// 
     /// 业务逻辑：
     /// 1. 验证会话存在
-    /// 2. 验证发送者权限
+    /// 2. 客服发送时必须携带 ShopAccess：会话可见且具备 send_messages，sender_id 取自 ShopAccess
     /// 3. 创建消息
     /// 4. 更新会话最后消息时间
+    #[allow(clippy::too_many_arguments)]
     pub async fn send_message(
         db: &DatabaseConnection,
         session_id: i32,
         sender_type: &str,
         sender_id: Option<i32>,  // 修正：数据库中是 INTEGER
+        staff_access: Option<&ShopAccess>,
         content: &str,
         message_type: &str,
         file_url: Option<&str>,
     ) -> Result<messages::Model> {
-        // 1. 验证会话存在
-        let session = SessionRepository::find_by_id(db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-        
-        // 2. 如果是客服发送，验证权限
-        if sender_type == "staff" {
-            if let Some(staff_id) = sender_id {
-                if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, staff_id as i64).await? {
-                    anyhow::bail!("permission_denied");
-                }
+        // 1. 验证会话存在；2. 如果是客服发送，验证权限
+        let sender_id = if sender_type == "staff" {
+            let access = staff_access.ok_or_else(|| anyhow::anyhow!("permission_denied"))?;
+            SessionService::find_visible_session(db, access, session_id).await?;
+            if !access.can(Capability::SendMessages) {
+                anyhow::bail!("permission_denied");
             }
-        }
+            Some(access.user_id as i32)
+        } else {
+            SessionRepository::find_by_id(db, session_id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
+            sender_id
+        };
         
         // 3. 验证消息内容
         Self::validate_message_content(content)?;
@@ -135,21 +231,14 @@ impl MessageService {
     /// 3. 获取消息列表
     pub async fn get_session_messages(
         db: &DatabaseConnection,
+        access: &ShopAccess,
         session_id: i32,
-        requester_user_id: i32,
         limit: Option<u64>,
     ) -> Result<Vec<messages::Model>> {
-        // 1. 获取会话
-        let session = SessionRepository::find_by_id(db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
+        // 1. 获取会话并验证可见性
+        SessionService::find_visible_session(db, access, session_id).await?;
         
-        // 2. 验证权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        // 3. 获取消息
+        // 2. 获取消息
         MessageRepository::find_by_session(db, session_id, limit).await
     }
     
@@ -160,22 +249,15 @@ impl MessageService {
     /// 2. 分页获取消息
     pub async fn get_session_messages_paginated(
         db: &DatabaseConnection,
+        access: &ShopAccess,
         session_id: i32,
-        requester_user_id: i32,
         page: u64,
         page_size: u64,
     ) -> Result<(Vec<messages::Model>, u64)> {
-        // 1. 获取会话
-        let session = SessionRepository::find_by_id(db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
+        // 1. 获取会话并验证可见性
+        SessionService::find_visible_session(db, access, session_id).await?;
         
-        // 2. 验证权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        // 3. 分页获取
+        // 2. 分页获取
         MessageRepository::find_by_session_paginated(db, session_id, page, page_size).await
     }
     
@@ -187,8 +269,8 @@ impl MessageService {
     /// 3. 标记为已读
     pub async fn mark_messages_as_read(
         db: &DatabaseConnection,
+        access: &ShopAccess,
         message_ids: Vec<i32>,
-        requester_user_id: i32,
     ) -> Result<()> {
         if message_ids.is_empty() {
             return Ok(());
@@ -200,13 +282,7 @@ impl MessageService {
                 .await?
                 .ok_or_else(|| anyhow::anyhow!("message_not_found"))?;
             
-            let session = SessionRepository::find_by_id(db, message.session_id)
-                .await?
-                .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-            
-            if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, requester_user_id as i64).await? {
-                anyhow::bail!("permission_denied");
-            }
+            SessionService::find_visible_session(db, access, message.session_id).await?;
         }
         
         MessageRepository::mark_as_read(db, message_ids).await
@@ -219,131 +295,193 @@ impl MessageService {
     /// 2. 统计未读数量
     pub async fn count_unread_messages(
         db: &DatabaseConnection,
+        access: &ShopAccess,
         session_id: i32,
         user_type: &str,
-        requester_user_id: i32,
     ) -> Result<u64> {
-        // 获取会话
-        let session = SessionRepository::find_by_id(db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-        
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
+        // 获取会话并验证可见性
+        SessionService::find_visible_session(db, access, session_id).await?;
         
         MessageRepository::count_unread(db, session_id, user_type).await
     }
     
-    /// 搜索消息
+    /// 店铺内全文搜索消息
     /// 
     /// 业务逻辑：
-    /// 1. 验证权限
-    /// 2. 搜索消息
-    pub async fn search_messages(
-        db: &DatabaseConnection,
-        shop_id: i32,
-        requester_user_id: i32,
-        keyword: &str,
-        limit: Option<u64>,
-    ) -> Result<Vec<messages::Model>> {
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        // 验证关键词
-        if keyword.trim().is_empty() {
+    /// 1. 关键词按空白拆分，所有词都需命中（消息内容或客户名）
+    /// 2. 不少于 3 个字符的词走 FTS5 索引，更短的词退化为 LIKE
+    /// 3. 按日期范围 / 发送方 / 消息类型 / 客服过滤，按消息 id 倒序分页
+    /// 4. 命中片段以 <mark> 高亮（其余内容已做 HTML 转义）
+    /// 
+    /// 权限由调用方校验
+    pub async fn search_messages(&self, query: MessageSearchQuery) -> Result<(Vec<MessageSearchHit>, i64)> {
+        let terms: Vec<String> = query.keyword.split_whitespace().map(|t| t.to_string()).collect();
+        if terms.is_empty() {
             anyhow::bail!("keyword_empty");
         }
+        if let Some(sender_type) = query.sender_type.as_deref() {
+            if !matches!(sender_type, "customer" | "staff" | NOTE_SENDER_TYPE) {
+                anyhow::bail!("invalid_sender_type");
+            }
+        }
+        if let (Some(from), Some(to)) = (query.from, query.to) {
+            if from >= to {
+                anyhow::bail!("invalid_date_range");
+            }
+        }
+        
+        let (long_terms, short_terms): (Vec<&String>, Vec<&String>) =
+            terms.iter().partition(|t| t.chars().count() >= 3);
+        // 每个词作为短语匹配，双引号转义后拼接（FTS5 隐式 AND）
+        let match_query = if long_terms.is_empty() {
+            None
+        } else {
+            Some(
+                long_terms
+                    .iter()
+                    .map(|t| format!("\"{}\"", t.replace('"', "\"\"")))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )
+        };
+        let filter = MessageSearchFilter {
+            shop_id: query.shop_id,
+            match_query,
+            like_terms: short_terms.into_iter().cloned().collect(),
+            from: query.from,
+            to: query.to,
+            sender_type: query.sender_type,
+            message_type: query.message_type,
+            staff_id: query.staff_id,
+        };
         
-        MessageRepository::search(db, shop_id, keyword, limit).await
+        let (rows, total) = MessageRepository::search(&self.db, &filter, query.limit, query.offset).await?;
+        let hits = rows
+            .into_iter()
+            .map(|row| MessageSearchHit {
+                content_highlight: highlight(&snippet(&row.content, &terms), &terms),
+                customer_name_highlight: row.customer_name.as_deref().map(|name| highlight(name, &terms)),
+                message_id: row.id as i64,
+                session_id: row.session_id as i64,
+                customer_id: row.customer_id as i64,
+                customer_code: row.customer_code,
+                customer_name: row.customer_name,
+                staff_id: row.staff_id.map(|id| id as i64),
+                sender_type: row.sender_type,
+                sender_id: row.sender_id.map(|id| id as i64),
+                message_type: row.message_type,
+                content: row.content,
+                created_at: row.created_at.and_utc(),
+            })
+            .collect();
+        Ok((hits, total))
     }
     
-    /// 删除消息（软删除）
+    /// 编辑消息
     /// 
     /// 业务逻辑：
-    /// 1. 验证消息存在
-    /// 2. 验证权限（只有发送者或店主可以删除）
-    /// 3. 软删除消息
-    pub async fn delete_message(
-        db: &DatabaseConnection,
-        message_id: i32,
-        requester_user_id: i32,
-    ) -> Result<()> {
-        // 1. 获取消息
-        let message = MessageRepository::find_by_id(db, message_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("message_not_found"))?;
-        
-        // 2. 获取会话
-        let session = SessionRepository::find_by_id(db, message.session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-        
-        // 3. 验证权限（必须是店主或消息发送者）
-        let is_owner = ShopStaffRepository::is_shop_owner(db, session.shop_id as i64, requester_user_id as i64).await?;
-        let is_sender = message.sender_id
-            .map(|id| id == requester_user_id)
-            .unwrap_or(false);
-        
-        if !is_owner && !is_sender {
-            anyhow::bail!("permission_denied");
+    /// 1. 消息存在且未被删除/撤回
+    /// 2. 只有发送者本人可以编辑，且仅限文本消息
+    /// 3. 自发送起超过编辑时限（message_policy::edit_window_secs）不可编辑
+    /// 4. 旧内容写入编辑历史后更新消息
+    pub async fn edit_message(
+        &self,
+        editor: MessageAuthor,
+        message_id: i64,
+        content: &str,
+    ) -> Result<messages::Model> {
+        let message = self.find_active(message_id).await?;
+        Self::ensure_sender(&message, editor)?;
+        if message.message_type != "text" {
+            anyhow::bail!("message_not_editable");
+        }
+        Self::ensure_within_window(&message, crate::constants::message_policy::edit_window_secs(), "edit_window_expired")?;
+        Self::validate_message_content(content)?;
+        if message.content == content {
+            anyhow::bail!("message_unchanged");
         }
         
-        // 4. 软删除
-        MessageRepository::soft_delete(db, message_id).await
+        MessageRepository::update_content(
+            &self.db,
+            message,
+            content.to_string(),
+            editor.sender_type(),
+            editor.id() as i32,
+        ).await
     }
     
-    /// 批量删除消息
+    /// 撤回消息（软删除）
     /// 
     /// 业务逻辑：
-    /// 1. 验证权限（只有店主可以批量删除）
-    /// 2. 批量软删除
-    pub async fn delete_messages_batch(
-        db: &DatabaseConnection,
-        message_ids: Vec<i32>,
-        requester_user_id: i32,
-    ) -> Result<()> {
-        if message_ids.is_empty() {
-            return Ok(());
-        }
+    /// 1. 消息存在且未被删除/撤回
+    /// 2. 只有发送者本人可以撤回
+    /// 3. 自发送起超过撤回时限（message_policy::recall_window_secs）不可撤回
+    pub async fn recall_message(&self, requester: MessageAuthor, message_id: i64) -> Result<messages::Model> {
+        let message = self.find_active(message_id).await?;
+        Self::ensure_sender(&message, requester)?;
+        Self::ensure_within_window(&message, crate::constants::message_policy::recall_window_secs(), "recall_window_expired")?;
         
-        // 验证第一条消息的权限
-        if let Some(first_id) = message_ids.first() {
-            let message = MessageRepository::find_by_id(db, *first_id)
-                .await?
-                .ok_or_else(|| anyhow::anyhow!("message_not_found"))?;
-            
-            let session = SessionRepository::find_by_id(db, message.session_id)
-                .await?
-                .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-            
-            // 必须是店主
-            if !ShopStaffRepository::is_shop_owner(db, session.shop_id as i64, requester_user_id as i64).await? {
-                anyhow::bail!("permission_denied");
-            }
+        MessageRepository::soft_delete(&self.db, message.id).await
+    }
+    
+    /// 删除消息（软删除，不受时限与发送者限制）
+    /// 
+    /// 权限（delete_messages 能力）由调用方校验
+    pub async fn delete_message(&self, message_id: i64) -> Result<messages::Model> {
+        let message = self.find_active(message_id).await?;
+        MessageRepository::soft_delete(&self.db, message.id).await
+    }
+    
+    /// 批量删除消息，返回实际被删除的消息（已删除的跳过）；同一事务内完成，失败时不会留下删除一半的批次
+    /// 
+    /// 权限（delete_messages 能力）由调用方校验
+    pub async fn delete_messages_batch(&self, message_ids: &[i64]) -> Result<Vec<messages::Model>> {
+        let ids: Vec<i32> = message_ids.iter().map(|id| *id as i32).collect();
+        MessageRepository::soft_delete_batch(&self.db, &ids).await
+    }
+    
+    /// 消息的编辑历史（最早的在前）
+    pub async fn get_edit_history(&self, message_id: i64) -> Result<Vec<message_edits::Model>> {
+        MessageRepository::find_edits(&self.db, message_id as i32).await
+    }
+    
+    async fn find_active(&self, message_id: i64) -> Result<messages::Model> {
+        MessageRepository::find_by_id(&self.db, message_id as i32)
+            .await?
+            .filter(|m| !m.is_deleted)
+            .ok_or_else(|| anyhow::anyhow!("message_not_found"))
+    }
+    
+    fn ensure_sender(message: &messages::Model, author: MessageAuthor) -> Result<()> {
+        // 内部备注由客服撰写，按客服身份校验
+        let sender_type = match message.sender_type.as_str() {
+            NOTE_SENDER_TYPE => "staff",
+            other => other,
+        };
+        let is_sender = sender_type == author.sender_type()
+            && message.sender_id.map(|id| id as i64) == Some(author.id());
+        if !is_sender {
+            anyhow::bail!("not_message_sender");
         }
-        
-        MessageRepository::soft_delete_many(db, message_ids).await
+        Ok(())
+    }
+    
+    fn ensure_within_window(message: &messages::Model, window_secs: i64, error: &str) -> Result<()> {
+        let elapsed = chrono::Utc::now().naive_utc() - message.created_at;
+        if elapsed > chrono::Duration::seconds(window_secs) {
+            anyhow::bail!("{}", error);
+        }
+        Ok(())
     }
     
     /// 获取会话的最后一条消息
     pub async fn get_last_message(
         db: &DatabaseConnection,
+        access: &ShopAccess,
         session_id: i32,
-        requester_user_id: i32,
     ) -> Result<Option<messages::Model>> {
-        // 获取会话
-        let session = SessionRepository::find_by_id(db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-        
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
+        // 获取会话并验证可见性
+        SessionService::find_visible_session(db, access, session_id).await?;
         
         MessageRepository::find_last_by_session(db, session_id).await
     }
@@ -353,7 +491,7 @@ impl MessageService {
         if content.trim().is_empty() {
             anyhow::bail!("message_content_empty");
         }
-        if content.len() > 10000 {
+        if content.len() > crate::constants::message_policy::MAX_CONTENT_BYTES {
             anyhow::bail!("message_content_too_long");
         }
         Ok(())
@@ -362,9 +500,155 @@ impl MessageService {
     /// 验证消息类型
     pub fn validate_message_type(message_type: &str) -> Result<()> {
         let valid_types = ["text", "image", "file", "audio", "video", "system"];
-        if !valid_types.contains(&message_type) {
+        if !valid_types.contains(&message_type) && !crate::services::rich_content::is_rich_type(message_type) {
             anyhow::bail!("invalid_message_type");
         }
         Ok(())
     }
 }
+
+/// 长消息只保留首个命中位置附近的片段
+fn snippet(text: &str, terms: &[String]) -> String {
+    const MAX_CHARS: usize = 120;
+    const CONTEXT_CHARS: usize = 40;
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= MAX_CHARS {
+        return text.to_string();
+    }
+    let first_hit = find_matches(&chars, terms).first().map(|(start, _)| *start).unwrap_or(0);
+    let start = first_hit.saturating_sub(CONTEXT_CHARS);
+    let end = (start + MAX_CHARS).min(chars.len());
+    let mut out: String = chars[start..end].iter().collect();
+    if start > 0 {
+        out.insert(0, '…');
+    }
+    if end < chars.len() {
+        out.push('…');
+    }
+    out
+}
+
+/// HTML 转义并用 <mark> 包裹命中的关键词（大小写不敏感）
+fn highlight(text: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len() + 16);
+    let mut cursor = 0;
+    for (start, end) in find_matches(&chars, terms) {
+        push_escaped(&mut out, &chars[cursor..start]);
+        out.push_str("<mark>");
+        push_escaped(&mut out, &chars[start..end]);
+        out.push_str("</mark>");
+        cursor = end;
+    }
+    push_escaped(&mut out, &chars[cursor..]);
+    out
+}
+
+/// 不重叠的命中区间（按字符下标），同一位置优先最长的词
+fn find_matches(chars: &[char], terms: &[String]) -> Vec<(usize, usize)> {
+    let lower: Vec<char> = chars.iter().map(|c| c.to_lowercase().next().unwrap_or(*c)).collect();
+    let mut needles: Vec<Vec<char>> = terms
+        .iter()
+        .map(|t| t.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect::<Vec<char>>())
+        .filter(|t| !t.is_empty())
+        .collect();
+    needles.sort_by_key(|t| std::cmp::Reverse(t.len()));
+
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i < lower.len() {
+        match needles.iter().find(|n| lower[i..].starts_with(n)) {
+            Some(needle) => {
+                matches.push((i, i + needle.len()));
+                i += needle.len();
+            }
+            None => i += 1,
+        }
+    }
+    matches
+}
+
+fn push_escaped(out: &mut String, chars: &[char]) {
+    for c in chars {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(*c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(items: &[&str]) -> Vec<String> {
+        items.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn matches_use_char_indices_for_cjk() {
+        let chars: Vec<char> = "你好世界你好".chars().collect();
+        assert_eq!(find_matches(&chars, &terms(&["你好"])), vec![(0, 2), (4, 6)]);
+        assert_eq!(highlight("退款订单号", &terms(&["订单"])), "退款<mark>订单</mark>号");
+    }
+
+    #[test]
+    fn matches_ignore_case_and_empty_terms() {
+        assert_eq!(highlight("Hello WORLD", &terms(&["world", ""])), "Hello <mark>WORLD</mark>");
+        assert_eq!(highlight("plain text", &terms(&[""])), "plain text");
+    }
+
+    #[test]
+    fn overlapping_terms_prefer_longest_without_overlap() {
+        let chars: Vec<char> = "xabcd".chars().collect();
+        assert_eq!(find_matches(&chars, &terms(&["ab", "abc"])), vec![(1, 4)]);
+        // 相互重叠的两个词只标记先出现的一个
+        assert_eq!(highlight("abcde", &terms(&["cde", "abc"])), "<mark>abc</mark>de");
+        assert_eq!(highlight("aaaa", &terms(&["aa"])), "<mark>aa</mark><mark>aa</mark>");
+    }
+
+    #[test]
+    fn highlight_escapes_html_around_marks() {
+        assert_eq!(
+            highlight("a<b & \"订单\" <mark>", &terms(&["订单", "mark"])),
+            "a&lt;b &amp; &quot;<mark>订单</mark>&quot; &lt;<mark>mark</mark>&gt;"
+        );
+        // 关键词本身含特殊字符时，命中部分同样转义
+        assert_eq!(highlight("x<y", &terms(&["<"])), "x<mark>&lt;</mark>y");
+    }
+
+    #[test]
+    fn snippet_keeps_short_text_whole() {
+        let text = "短消息".repeat(10);
+        assert_eq!(snippet(&text, &terms(&["消息"])), text);
+    }
+
+    #[test]
+    fn snippet_centers_first_hit_with_ellipses() {
+        let text = format!("{}订单{}", "前".repeat(100), "后".repeat(198));
+        let out = snippet(&text, &terms(&["订单"]));
+        let body: String = out.trim_start_matches('…').trim_end_matches('…').to_string();
+        assert!(out.starts_with('…') && out.ends_with('…'));
+        assert_eq!(body.chars().count(), 120);
+        // 命中前保留 40 个字符
+        assert!(body.starts_with(&format!("{}订单", "前".repeat(40))));
+    }
+
+    #[test]
+    fn snippet_at_edges() {
+        // 命中靠近结尾：只有前省略号
+        let text = format!("{}订单", "前".repeat(198));
+        let out = snippet(&text, &terms(&["订单"]));
+        assert!(out.starts_with('…') && out.ends_with("订单"));
+        assert_eq!(out.chars().count(), 1 + 42);
+
+        // 没有命中：从开头截取
+        let text = "字".repeat(200);
+        let out = snippet(&text, &terms(&["无"]));
+        assert_eq!(out, format!("{}…", "字".repeat(120)));
+    }
+}
@@ -12,6 +12,16 @@ pub mod staff;
 pub mod metrics;
 pub mod shop_utils;
 pub mod permissions;
+pub mod visitor_token;
+pub mod presence;
+pub mod routing;
+pub mod queue;
+pub mod rich_content;
+pub mod canned_response;
+pub mod auth_session;
+pub mod password_reset;
+pub mod two_factor;
+pub mod login_audit;
 
 // 新的模块化 Services
 pub mod user_service;
@@ -0,0 +1,166 @@
+//! 找回密码
+//!
+//! 职责：
+//! - 按用户名或邮箱申请重置：生成一次性令牌（只保存 SHA-256），通过 Mailer 发送重置链接
+//! - 令牌在 PASSWORD_RESET_TTL_MINUTES 后过期，使用一次即失效；再次申请时旧令牌一并作废
+//! - 无论账号是否存在都返回相同结果，邮件在后台发送，避免通过响应内容或耗时枚举账号
+
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, Utc};
+use tracing::{error, info, warn};
+
+use crate::{
+    constants::auth_policy,
+    database::Database,
+    error::AppError,
+    mailer::{Email, Mailer},
+    services::auth_session::{hash_token, new_random_token},
+};
+
+/// 同一用户两次申请的最小间隔（秒），防止向同一邮箱反复发信
+const REQUEST_COOLDOWN_SECS: i64 = 60;
+
+fn db_error(code: &'static str) -> impl Fn(sqlx::Error) -> AppError {
+    move |e| {
+        error!(target: "password_reset", "{}: {}", code, e);
+        AppError::Internal(code.to_string())
+    }
+}
+
+/// 重置链接前缀：PASSWORD_RESET_URL（前端地址），令牌以 reset_token 查询参数附加
+fn reset_link(token: &str) -> String {
+    let base = std::env::var("PASSWORD_RESET_URL")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "http://localhost:3000/".to_string());
+    let separator = if base.contains('?') { '&' } else { '?' };
+    format!("{base}{separator}reset_token={token}")
+}
+
+/// 申请重置密码
+///
+/// 业务逻辑：
+/// 1. 按用户名或邮箱（不区分大小写）查找启用中的用户；不存在、未设置邮箱或处于冷却期时静默返回
+/// 2. 作废该用户尚未使用的旧令牌，写入新令牌哈希
+/// 3. 后台发送包含重置链接的邮件，发送失败只记录日志
+pub async fn request_reset(
+    db: &Database,
+    mailer: Arc<dyn Mailer>,
+    account: &str,
+    ip_address: Option<&str>,
+) -> Result<(), AppError> {
+    let account = account.trim();
+    if account.is_empty() {
+        return Err(AppError::BadRequest("account_required".to_string()));
+    }
+
+    let user = sqlx::query_as::<_, (i64, String, Option<String>)>(
+        "SELECT id, username, email FROM users WHERE (username = ? OR lower(email) = lower(?)) AND status = 1 \
+         ORDER BY username = ? DESC LIMIT 1",
+    )
+    .bind(account)
+    .bind(account)
+    .bind(account)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(db_error("reset_lookup_failed"))?;
+    let user = user.and_then(|(id, username, email)| Some((id, username, email.filter(|e| !e.trim().is_empty())?)));
+    let Some((user_id, username, email)) = user else {
+        info!(target: "password_reset", "找回密码：账号不存在或未设置邮箱，忽略");
+        return Ok(());
+    };
+
+    let recent = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM password_reset_tokens WHERE user_id = ? AND created_at > datetime('now', ?)",
+    )
+    .bind(user_id)
+    .bind(format!("-{REQUEST_COOLDOWN_SECS} seconds"))
+    .fetch_one(db.pool())
+    .await
+    .map_err(db_error("reset_lookup_failed"))?;
+    if recent > 0 {
+        warn!(target: "password_reset", "用户 {} 找回密码申请过于频繁，忽略", user_id);
+        return Ok(());
+    }
+
+    sqlx::query("UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP WHERE user_id = ? AND used_at IS NULL")
+        .bind(user_id)
+        .execute(db.pool())
+        .await
+        .map_err(db_error("reset_token_create_failed"))?;
+    let token = new_random_token();
+    let ttl_secs = auth_policy::password_reset_ttl_secs();
+    sqlx::query("INSERT INTO password_reset_tokens (user_id, token_hash, expires_at, ip_address) VALUES (?, ?, ?, ?)")
+        .bind(user_id)
+        .bind(hash_token(&token))
+        .bind((Utc::now() + chrono::Duration::seconds(ttl_secs)).naive_utc())
+        .bind(ip_address)
+        .execute(db.pool())
+        .await
+        .map_err(db_error("reset_token_create_failed"))?;
+
+    let email = Email {
+        to: email.trim().to_string(),
+        subject: "重置密码".to_string(),
+        body: format!(
+            "{username}，您好：\n\n我们收到了重置您账号密码的请求。请在 {minutes} 分钟内打开以下链接设置新密码：\n\n{link}\n\n\
+             该链接只能使用一次。如果这不是您本人的操作，请忽略本邮件，您的密码不会被修改。\n",
+            minutes = ttl_secs / 60,
+            link = reset_link(&token),
+        ),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(&email).await {
+            error!(target: "password_reset", "重置密码邮件发送失败（用户 {}）: {:#}", user_id, e);
+        }
+    });
+    info!(target: "password_reset", "用户 {} 申请重置密码", user_id);
+    Ok(())
+}
+
+/// 校验并消费重置令牌，返回对应用户 ID
+///
+/// 业务逻辑：
+/// 1. 按哈希查找令牌；不存在或已使用返回 invalid_reset_token，过期返回 reset_token_expired
+/// 2. 用户已被停用时令牌同样无效
+/// 3. 条件更新 used_at，并发提交时只有一个请求能成功
+pub async fn consume_token(db: &Database, token: &str) -> Result<i64, AppError> {
+    let invalid = || AppError::BadRequest("invalid_reset_token".to_string());
+    let row = sqlx::query_as::<_, (i64, i64, NaiveDateTime, Option<NaiveDateTime>)>(
+        "SELECT id, user_id, expires_at, used_at FROM password_reset_tokens WHERE token_hash = ?",
+    )
+    .bind(hash_token(token.trim()))
+    .fetch_optional(db.pool())
+    .await
+    .map_err(db_error("reset_lookup_failed"))?;
+    let Some((id, user_id, expires_at, used_at)) = row else {
+        return Err(invalid());
+    };
+    if used_at.is_some() {
+        return Err(invalid());
+    }
+    if expires_at <= Utc::now().naive_utc() {
+        return Err(AppError::BadRequest("reset_token_expired".to_string()));
+    }
+    let active_user = sqlx::query_scalar::<_, i32>("SELECT status FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await
+        .map_err(db_error("reset_lookup_failed"))?
+        .is_some_and(|status| status == 1);
+    if !active_user {
+        return Err(invalid());
+    }
+
+    let consumed = sqlx::query("UPDATE password_reset_tokens SET used_at = CURRENT_TIMESTAMP WHERE id = ? AND used_at IS NULL")
+        .bind(id)
+        .execute(db.pool())
+        .await
+        .map_err(db_error("reset_token_consume_failed"))?;
+    if consumed.rows_affected() == 0 {
+        return Err(invalid());
+    }
+    Ok(user_id)
+}
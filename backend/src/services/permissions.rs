@@ -1,66 +1,272 @@
 // Purpose: 权限判定辅助（店铺维度）
 // Input: user_id, shop_id
 // Output: Ok(()) 表示允许；Err(AppError::Unauthorized) 表示拒绝
+// Note: 角色与能力模型见 ShopAccess —— 店主 / admin / agent / viewer 各有默认能力，
+//       shop_staffs.permissions（JSON：{"能力": true|false}）可按人增减；handler 通过 ShopAccess 提取器统一校验
 
+use std::collections::{BTreeMap, HashMap};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path},
+    http::request::Parts,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::AuthUser;
 use crate::error::AppError;
 use crate::database::Database;
+use crate::services::two_factor;
+use crate::AppState;
 
-/// 使用 SQLx 的权限检查：是否为店主
-pub async fn is_shop_owner_sqlx(db: &Database, shop_id: i64, user_id: i64) -> anyhow::Result<bool> {
-    let count: i64 = sqlx::query_scalar!(
-        r#"SELECT COUNT(*) as "count!: i64" FROM shops s WHERE s.id = ? AND s.owner_id = ?"#,
-        shop_id,
-        user_id
-    )
-    .fetch_one(db.pool())
-    .await?;
-    Ok(count > 0)
+/// 店铺内角色；店主来自 shops.owner_id，其余来自 shop_staffs.role
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Owner,
+    Admin,
+    Agent,
+    Viewer,
 }
 
-/// 使用 SQLx 的权限检查：是否为成员（店主或员工）
-pub async fn is_shop_member_sqlx(db: &Database, shop_id: i64, user_id: i64) -> anyhow::Result<bool> {
-    // 任意一个存在即为成员
-    let total: i64 = sqlx::query_scalar!(
-        r#"
-        SELECT (
-            (SELECT COUNT(*) FROM shops s WHERE s.id = ? AND s.owner_id = ?) +
-            (SELECT COUNT(*) FROM shop_staffs ss WHERE ss.shop_id = ? AND ss.user_id = ?)
-        ) as "count!: i64"
-        "#,
-        shop_id, user_id, shop_id, user_id
-    )
-    .fetch_one(db.pool())
-    .await?;
-    Ok(total > 0)
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Admin => "admin",
+            Role::Agent => "agent",
+            Role::Viewer => "viewer",
+        }
+    }
+
+    /// 解析 shop_staffs.role；历史数据中的 staff 及未知值按 agent 处理
+    pub fn from_staff_role(raw: &str) -> Self {
+        match raw.trim().to_lowercase().as_str() {
+            "admin" => Role::Admin,
+            "viewer" => Role::Viewer,
+            _ => Role::Agent,
+        }
+    }
+
+    /// 角色默认能力
+    pub fn default_capabilities(self) -> &'static [Capability] {
+        use Capability::*;
+        match self {
+            Role::Owner | Role::Admin => &[
+                ManageStaff,
+                ManageSettings,
+                ManageCustomers,
+                SendMessages,
+                DeleteMessages,
+                ViewAllSessions,
+                Export,
+            ],
+            Role::Agent => &[ManageCustomers, SendMessages, ViewAllSessions],
+            Role::Viewer => &[ViewAllSessions],
+        }
+    }
+}
+
+/// 细粒度能力
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// 添加 / 移除员工、调整角色与并发上限
+    ManageStaff,
+    /// 店铺分配策略、店铺公共快捷回复
+    ManageSettings,
+    /// 客户标签、属性、拉黑
+    ManageCustomers,
+    /// 回复客户、内部备注、处理会话（关闭 / 分配 / 转接 / 认领）
+    SendMessages,
+    /// 删除任意消息
+    DeleteMessages,
+    /// 查看所有会话；否则只能查看分配给自己或未分配的会话
+    ViewAllSessions,
+    /// 导出店铺数据（会话聊天记录 CSV）
+    Export,
 }
 
-/// 使用 SQLx 的权限断言：店主或员工
-pub async fn ensure_member_or_owner_sqlx(db: &Database, user_id: i64, shop_id: i64) -> Result<(), AppError> {
+impl Capability {
+    pub const ALL: [Capability; 7] = [
+        Capability::ManageStaff,
+        Capability::ManageSettings,
+        Capability::ManageCustomers,
+        Capability::SendMessages,
+        Capability::DeleteMessages,
+        Capability::ViewAllSessions,
+        Capability::Export,
+    ];
+}
+
+/// shop_staffs.permissions 中的按人覆盖：true 授予、false 收回
+pub type CapabilityOverrides = BTreeMap<Capability, bool>;
+
+/// 解析 permissions 列；格式错误或未知能力忽略
+pub fn parse_overrides(raw: Option<&str>) -> CapabilityOverrides {
+    let Some(map) = raw.and_then(|r| serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(r).ok()) else {
+        return CapabilityOverrides::new();
+    };
+    map.into_iter()
+        .filter_map(|(key, value)| {
+            let capability = serde_json::from_value::<Capability>(serde_json::Value::String(key)).ok()?;
+            Some((capability, value.as_bool()?))
+        })
+        .collect()
+}
+
+/// 当前用户在某店铺内的角色与能力
+#[derive(Debug, Clone, Serialize)]
+pub struct ShopAccess {
+    pub user_id: i64,
+    pub shop_id: i64,
+    pub role: Role,
+    pub capabilities: Vec<Capability>,
+    /// 店铺要求双因素认证而该员工尚未启用：此时没有任何能力
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub two_factor_required: bool,
+}
+
+impl ShopAccess {
+    pub fn with_overrides(user_id: i64, shop_id: i64, role: Role, overrides: &CapabilityOverrides) -> Self {
+        // 店主拥有全部能力，不受覆盖影响
+        let capabilities = Capability::ALL
+            .into_iter()
+            .filter(|cap| {
+                let default = role.default_capabilities().contains(cap);
+                match role {
+                    Role::Owner => true,
+                    _ => overrides.get(cap).copied().unwrap_or(default),
+                }
+            })
+            .collect();
+        Self { user_id, shop_id, role, capabilities, two_factor_required: false }
+    }
+
+    /// 未满足店铺双因素要求的员工：保留角色，收回全部能力
+    fn blocked_by_two_factor(mut self) -> Self {
+        self.capabilities.clear();
+        self.two_factor_required = true;
+        self
+    }
+
+    pub fn is_owner(&self) -> bool {
+        self.role == Role::Owner
+    }
+
+    pub fn can(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// 缺少能力时返回 403
+    pub fn require(&self, capability: Capability) -> Result<(), AppError> {
+        if self.can(capability) {
+            Ok(())
+        } else {
+            Err(AppError::Forbidden)
+        }
+    }
+
+    /// 会话可见性：view_all_sessions，或会话分配给自己 / 尚未分配
+    pub fn can_view_session(&self, staff_id: Option<i64>) -> bool {
+        self.can(Capability::ViewAllSessions) || staff_id.is_none_or(|id| id == self.user_id)
+    }
+}
+
+/// 加载用户在店铺内的角色与能力；非成员返回 None
+///
+/// 店铺要求双因素认证而员工尚未启用时仍返回 Some，但能力为空（见 two_factor_required）
+pub async fn load_access(db: &Database, shop_id: i64, user_id: i64) -> Result<Option<ShopAccess>, AppError> {
     if is_shop_owner_sqlx(db, shop_id, user_id).await.map_err(|_| AppError::Internal("check_owner_failed".into()))? {
-        return Ok(());
+        return Ok(Some(ShopAccess::with_overrides(user_id, shop_id, Role::Owner, &CapabilityOverrides::new())));
     }
-    if is_shop_member_sqlx(db, shop_id, user_id).await.map_err(|_| AppError::Internal("check_membership_failed".into()))? {
-        return Ok(());
+    let row = sqlx::query_as::<_, (String, Option<String>)>(
+        "SELECT role, permissions FROM shop_staffs WHERE shop_id = ? AND user_id = ? LIMIT 1",
+    )
+    .bind(shop_id)
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(|_| AppError::Internal("check_membership_failed".into()))?;
+    let Some((role, permissions)) = row else {
+        return Ok(None);
+    };
+    let access =
+        ShopAccess::with_overrides(user_id, shop_id, Role::from_staff_role(&role), &parse_overrides(permissions.as_deref()));
+    if !two_factor::staff_compliant(db, shop_id, user_id).await? {
+        return Ok(Some(access.blocked_by_two_factor()));
     }
-    Err(AppError::Unauthorized)
+    Ok(Some(access))
 }
 
-pub async fn ensure_member_or_owner(db: &sea_orm::DatabaseConnection, user_id: i64, shop_id: i64) -> Result<(), AppError> {
-    // 先判断是否店主
-    if crate::repositories::ShopStaffRepository::is_shop_owner(db, shop_id, user_id)
-        .await
-        .map_err(|_| AppError::Internal("check_owner_failed".to_string()))?
-    {
-        return Ok(());
+/// 一次查询加载店铺全部成员（店主与员工）的角色与能力，按 user_id 索引
+///
+/// 与 load_access 规则一致：未满足店铺双因素要求的员工能力为空；用于需要批量判断的场景（如自动分配）
+pub async fn load_shop_access(db: &Database, shop_id: i64) -> Result<HashMap<i64, ShopAccess>, AppError> {
+    let rows = sqlx::query_as::<_, (i64, bool, String, Option<String>, bool)>(
+        "SELECT s.owner_id, 1, 'owner', NULL, 1 FROM shops s WHERE s.id = ? \
+         UNION ALL \
+         SELECT ss.user_id, 0, ss.role, ss.permissions, \
+                (s.require_2fa = 0 OR EXISTS (SELECT 1 FROM user_totp t WHERE t.user_id = ss.user_id AND t.enabled_at IS NOT NULL)) \
+         FROM shop_staffs ss JOIN shops s ON s.id = ss.shop_id \
+         WHERE ss.shop_id = ? AND ss.user_id != s.owner_id",
+    )
+    .bind(shop_id)
+    .bind(shop_id)
+    .fetch_all(db.pool())
+    .await
+    .map_err(|_| AppError::Internal("check_membership_failed".into()))?;
+    Ok(rows
+        .into_iter()
+        .map(|(user_id, is_owner, role, permissions, compliant)| {
+            let access = if is_owner {
+                ShopAccess::with_overrides(user_id, shop_id, Role::Owner, &CapabilityOverrides::new())
+            } else {
+                ShopAccess::with_overrides(user_id, shop_id, Role::from_staff_role(&role), &parse_overrides(permissions.as_deref()))
+            };
+            let access = if compliant { access } else { access.blocked_by_two_factor() };
+            (user_id, access)
+        })
+        .collect())
+}
+
+/// 加载角色与能力，非成员返回 403，未满足店铺双因素要求返回 403 TWO_FACTOR_REQUIRED
+pub async fn require_access(db: &Database, user_id: i64, shop_id: i64) -> Result<ShopAccess, AppError> {
+    match load_access(db, shop_id, user_id).await? {
+        Some(access) if access.two_factor_required => Err(AppError::TwoFactorRequired),
+        Some(access) => Ok(access),
+        None => Err(AppError::Forbidden),
     }
+}
 
-    // 再判断是否员工
-    if crate::repositories::ShopStaffRepository::is_shop_member(db, shop_id, user_id)
-        .await
-        .map_err(|_| AppError::Internal("check_membership_failed".to_string()))?
-    {
-        return Ok(());
+/// 提取器：从路径参数 shop_id 与 Bearer token 解析当前用户的店铺权限（401 未登录，403 非成员）
+#[async_trait]
+impl FromRequestParts<AppState> for ShopAccess {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let AuthUser { user_id } = AuthUser::from_request_parts(parts, state)
+            .await
+            .map_err(|_| AppError::Unauthorized)?;
+        let Path(params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| AppError::BadRequest("invalid_path".into()))?;
+        let shop_id = params
+            .get("shop_id")
+            .and_then(|v| v.parse::<i64>().ok())
+            .ok_or_else(|| AppError::BadRequest("invalid_shop_id".into()))?;
+        require_access(&state.db, user_id, shop_id).await
     }
+}
 
-    Err(AppError::Unauthorized)
+/// 使用 SQLx 的权限检查：是否为店主
+pub async fn is_shop_owner_sqlx(db: &Database, shop_id: i64, user_id: i64) -> anyhow::Result<bool> {
+    let count: i64 = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!: i64" FROM shops s WHERE s.id = ? AND s.owner_id = ?"#,
+        shop_id,
+        user_id
+    )
+    .fetch_one(db.pool())
+    .await?;
+    Ok(count > 0)
 }
@@ -0,0 +1,225 @@
+//! 在线状态（presence）
+//!
+//! 职责：
+//! - 客服 / 客户连接建立与断开时写入 online_status 表（status + last_seen）
+//! - 状态变化时向店铺内客服广播 presence_changed（经 Broadcaster 跨节点扇出）
+//! - 心跳刷新 last_ping_at，供店铺在线列表接口查询
+//! - 每条连接在 presence_connections 中一行（带节点标识）：只有所有节点上都没有该用户的连接时才写入离线；
+//!   各节点定期刷新自己连接的心跳，超过 STALE_AFTER_SECS 未刷新的连接（节点崩溃遗留）被清理并写入离线
+//!
+//! 写库失败只记录日志，不影响 WebSocket 连接本身
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    models::WebSocketMessage,
+    repositories::OnlineStatusRepository,
+    AppState,
+};
+
+pub const USER_TYPE_STAFF: &str = "staff";
+pub const USER_TYPE_CUSTOMER: &str = "customer";
+
+const STATUS_ONLINE: &str = "online";
+const STATUS_OFFLINE: &str = "offline";
+
+/// 各节点刷新自己连接心跳、清理失效连接的间隔（秒）
+pub const KEEPALIVE_INTERVAL_SECS: u64 = 30;
+/// 连接超过该秒数未刷新视为失效（约三个刷新周期）
+pub const STALE_AFTER_SECS: i64 = 90;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PresenceEntry {
+    pub user_id: i64,
+    pub status: String,
+    pub online: bool,
+    pub last_seen: Option<DateTime<Utc>>,
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub customer_code: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShopPresence {
+    pub staff: Vec<PresenceEntry>,
+    pub customers: Vec<PresenceEntry>,
+}
+
+/// 客服进入店铺
+pub async fn staff_online(state: &AppState, user_id: i64, shop_id: i64, connection_id: &str) {
+    set_online(state, USER_TYPE_STAFF, user_id, shop_id, connection_id, None).await;
+}
+
+/// 客服离开店铺：任一节点上仍有该客服在此店铺的其他连接时保持在线
+pub async fn staff_offline_if_idle(state: &AppState, user_id: i64, shop_id: i64, connection_id: &str) {
+    release_connection(state, USER_TYPE_STAFF, user_id, shop_id, connection_id, None).await;
+}
+
+/// 客户连接认证成功
+pub async fn customer_online(state: &AppState, shop_id: i64, customer_id: i64, customer_code: &str, connection_id: &str) {
+    set_online(state, USER_TYPE_CUSTOMER, customer_id, shop_id, connection_id, Some(customer_code)).await;
+}
+
+/// 客户断开：同一访客已重连（本节点或其他节点）时保持在线
+pub async fn customer_offline_if_idle(
+    state: &AppState,
+    shop_id: i64,
+    customer_id: i64,
+    customer_code: &str,
+    connection_id: &str,
+) {
+    release_connection(state, USER_TYPE_CUSTOMER, customer_id, shop_id, connection_id, Some(customer_code)).await;
+}
+
+/// 店铺内在任一节点在线的客服（去重，按 user_id 升序）
+pub async fn online_staff_in_shop(state: &AppState, shop_id: i64) -> anyhow::Result<Vec<i64>> {
+    OnlineStatusRepository::live_staff_in_shop(&state.db_connection, shop_id as i32, STALE_AFTER_SECS).await
+}
+
+/// 启动后台任务：定期刷新本节点连接的心跳，并把失效连接对应的用户写为离线
+pub fn spawn_keepalive(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(std::time::Duration::from_secs(KEEPALIVE_INTERVAL_SECS));
+        loop {
+            ticker.tick().await;
+            let db = &state.db_connection;
+            if let Err(e) = OnlineStatusRepository::touch_node_connections(db, crate::websocket::broadcaster::node_id()).await {
+                tracing::warn!("刷新本节点连接心跳失败: {:?}", e);
+            }
+            match OnlineStatusRepository::sweep_stale(db, STALE_AFTER_SECS).await {
+                Ok(idle) => {
+                    for (user_type, user_id, shop_id) in idle {
+                        set_offline(&state, &user_type, user_id as i64, shop_id as i64, None).await;
+                    }
+                }
+                Err(e) => tracing::warn!("清理失效在线连接失败: {:?}", e),
+            }
+        }
+    });
+}
+
+/// 心跳：刷新 last_ping_at / last_seen，不广播
+pub async fn heartbeat(state: &AppState, user_type: &str, user_id: i64, shop_id: i64) {
+    if let Err(e) = OnlineStatusRepository::touch(&state.db_connection, user_type, user_id as i32, shop_id as i32).await {
+        tracing::warn!("刷新在线心跳失败: {:?}", e);
+    }
+}
+
+/// 店铺在线列表：客服与客户分组返回
+pub async fn shop_presence(state: &AppState, shop_id: i64) -> anyhow::Result<ShopPresence> {
+    let rows = OnlineStatusRepository::find_by_shop(&state.db_connection, shop_id as i32, STALE_AFTER_SECS).await?;
+    let mut presence = ShopPresence { staff: Vec::new(), customers: Vec::new() };
+    for row in rows {
+        // 标记为在线但已没有未失效连接（节点崩溃、尚未被清理）时按离线返回
+        let status = match row.status {
+            Some(status) if status == STATUS_ONLINE && !row.live => STATUS_OFFLINE.to_string(),
+            Some(status) => status,
+            None => STATUS_OFFLINE.to_string(),
+        };
+        let entry = PresenceEntry {
+            user_id: row.user_id as i64,
+            online: status == STATUS_ONLINE,
+            status,
+            last_seen: row.last_seen.map(|t| t.and_utc()),
+            name: if row.user_type == USER_TYPE_STAFF { row.staff_name } else { row.customer_name },
+            customer_code: row.customer_code,
+        };
+        if row.user_type == USER_TYPE_STAFF {
+            presence.staff.push(entry);
+        } else {
+            presence.customers.push(entry);
+        }
+    }
+    Ok(presence)
+}
+
+async fn set_online(
+    state: &AppState,
+    user_type: &str,
+    user_id: i64,
+    shop_id: i64,
+    connection_id: &str,
+    customer_code: Option<&str>,
+) {
+    let node_id = crate::websocket::broadcaster::node_id();
+    if let Err(e) = OnlineStatusRepository::add_connection(
+        &state.db_connection,
+        connection_id,
+        node_id,
+        user_type,
+        user_id as i32,
+        shop_id as i32,
+    )
+    .await
+    {
+        tracing::warn!("写入在线连接失败: {:?}", e);
+    }
+    match OnlineStatusRepository::mark_online(&state.db_connection, user_type, user_id as i32, shop_id as i32, Some(connection_id)).await {
+        Ok(record) => broadcast(state, user_type, user_id, shop_id, STATUS_ONLINE, record.last_seen, customer_code),
+        Err(e) => tracing::warn!("写入在线状态失败: {:?}", e),
+    }
+}
+
+/// 删除该连接的记录；所有节点上都没有该用户的其他连接时写入离线
+async fn release_connection(
+    state: &AppState,
+    user_type: &str,
+    user_id: i64,
+    shop_id: i64,
+    connection_id: &str,
+    customer_code: Option<&str>,
+) {
+    let db = &state.db_connection;
+    if let Err(e) = OnlineStatusRepository::remove_connection(db, connection_id).await {
+        tracing::warn!("删除在线连接失败: {:?}", e);
+    }
+    match OnlineStatusRepository::count_live_connections(db, user_type, user_id as i32, shop_id as i32, STALE_AFTER_SECS).await {
+        Ok(0) => set_offline(state, user_type, user_id, shop_id, customer_code).await,
+        Ok(_) => {}
+        Err(e) => tracing::warn!("统计在线连接失败: {:?}", e),
+    }
+}
+
+async fn set_offline(state: &AppState, user_type: &str, user_id: i64, shop_id: i64, customer_code: Option<&str>) {
+    match OnlineStatusRepository::mark_offline(&state.db_connection, user_type, user_id as i32, shop_id as i32).await {
+        Ok(Some(record)) => broadcast(state, user_type, user_id, shop_id, STATUS_OFFLINE, record.last_seen, customer_code),
+        Ok(None) => {}
+        Err(e) => tracing::warn!("写入离线状态失败: {:?}", e),
+    }
+}
+
+fn broadcast(
+    state: &AppState,
+    user_type: &str,
+    user_id: i64,
+    shop_id: i64,
+    status: &str,
+    last_seen: Option<chrono::NaiveDateTime>,
+    customer_code: Option<&str>,
+) {
+    let notice = WebSocketMessage {
+        message_type: crate::constants::ws_events::PRESENCE_CHANGED.to_string(),
+        content: None,
+        session_id: None,
+        sender_id: None,
+        sender_type: Some("system".to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(json!({
+            "userType": user_type,
+            "userId": user_id,
+            "shopId": shop_id,
+            "status": status,
+            "lastSeen": last_seen.map(|t| t.and_utc()),
+            "customerCode": customer_code,
+        })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: None,
+    };
+    let mut manager = state.connections.lock().unwrap();
+    manager.broadcast_to_staff(shop_id, &notice);
+}
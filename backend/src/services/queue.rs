@@ -0,0 +1,233 @@
+//! 等待队列（未分配会话）
+//!
+//! 职责：
+//! - 店铺等待队列 = 未分配客服、进行中且客户已发过消息的会话（优先级高、来得早的在前）
+//! - 队列变化时向排队客户推送 queue_position（"当前排队第 N 位"），向店铺客服推送 queue_updated
+//! - 客服上线 / 会话关闭腾出容量时按分配策略消化队列；客服也可手动认领队首
+//!
+//! 推送失败只记录日志，不影响触发队列变化的操作本身
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    models::{Session, WebSocketMessage},
+    repositories::{CustomerRepository, SessionRepository},
+    services::{chat::broadcast_session_updated, routing},
+    AppState,
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueEntry {
+    /// 从 1 开始
+    pub position: usize,
+    pub session_id: i64,
+    pub customer_id: i64,
+    pub customer_code: String,
+    pub customer_name: Option<String>,
+    pub priority: i32,
+    pub waiting_since: Option<DateTime<Utc>>,
+}
+
+/// 店铺当前等待队列
+pub async fn shop_queue(state: &AppState, shop_id: i64) -> anyhow::Result<Vec<QueueEntry>> {
+    let rows = SessionRepository::find_waiting_by_shop(&state.db_connection, shop_id as i32).await?;
+    Ok(rows
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| QueueEntry {
+            position: index + 1,
+            session_id: row.session_id as i64,
+            customer_id: row.customer_id as i64,
+            customer_code: row.customer_code,
+            customer_name: row.customer_name,
+            priority: row.priority,
+            waiting_since: row.created_at.map(|t| t.and_utc()),
+        })
+        .collect())
+}
+
+/// 客服认领队首会话；队列为空时返回 None
+pub async fn claim_next(state: &AppState, shop_id: i64, staff_id: i64) -> anyhow::Result<Option<Session>> {
+    // 与自动分配并发时队首可能已被分走，依次尝试后续会话
+    for entry in shop_queue(state, shop_id).await? {
+        if let Some(session) =
+            SessionRepository::assign_if_unassigned(&state.db_connection, entry.session_id as i32, staff_id as i32).await?
+        {
+            let session: Session = session.into();
+            broadcast_session_updated(state, &session, "claimed", Some(staff_id));
+            notify_customer_assigned(state, shop_id, &entry.customer_code, &session);
+            publish(state, shop_id).await;
+            return Ok(Some(session));
+        }
+    }
+    Ok(None)
+}
+
+/// 客户发消息后调用：会话未分配时尝试自动分配，仍未分配则进入（或留在）队列
+pub async fn on_customer_message(state: &AppState, shop_id: i64, session_id: i64, customer_code: &str) -> Option<Session> {
+    let routed = match routing::route_session(state, session_id).await {
+        Ok(Some(assigned)) => {
+            let assigned: Session = assigned.into();
+            broadcast_session_updated(state, &assigned, "auto_assigned", None);
+            notify_customer_assigned(state, shop_id, customer_code, &assigned);
+            Some(assigned)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            tracing::warn!("会话 {} 自动分配失败: {:?}", session_id, e);
+            None
+        }
+    };
+    publish(state, shop_id).await;
+    routed
+}
+
+/// 仅向指定客户推送其排队位置（客户重连认证后使用）
+pub async fn send_position(state: &AppState, shop_id: i64, session_id: i64, customer_code: &str) {
+    let entries = match shop_queue(state, shop_id).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("读取店铺 {} 等待队列失败: {:?}", shop_id, e);
+            return;
+        }
+    };
+    if let Some(entry) = entries.iter().find(|e| e.session_id == session_id) {
+        let notice = waiting_message(shop_id, entry, entries.len());
+        let mut manager = state.connections.lock().unwrap();
+        manager.send_to_customer(shop_id, customer_code, &notice);
+    }
+}
+
+/// 队列可能发生变化后调用：先按分配策略消化队列，再推送最新排队位置
+pub async fn refresh(state: &AppState, shop_id: i64) {
+    drain(state, shop_id).await;
+    publish(state, shop_id).await;
+}
+
+/// 会话已分配给客服时通知客户（位置清零）
+pub async fn notify_session_assigned(state: &AppState, session: &Session) {
+    match CustomerRepository::find_by_id(&state.db_connection, session.customer_id as i32).await {
+        Ok(Some(customer)) => notify_customer_assigned(state, session.shop_id, &customer.customer_id, session),
+        Ok(None) => {}
+        Err(e) => tracing::warn!("查询会话 {} 的客户失败: {:?}", session.id, e),
+    }
+}
+
+/// 按店铺策略依次分配队列中的会话，直到没有可用客服
+async fn drain(state: &AppState, shop_id: i64) {
+    let entries = match shop_queue(state, shop_id).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("读取店铺 {} 等待队列失败: {:?}", shop_id, e);
+            return;
+        }
+    };
+    for entry in entries {
+        match routing::route_session(state, entry.session_id).await {
+            Ok(Some(assigned)) => {
+                let assigned: Session = assigned.into();
+                broadcast_session_updated(state, &assigned, "auto_assigned", None);
+                notify_customer_assigned(state, shop_id, &entry.customer_code, &assigned);
+            }
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("会话 {} 自动分配失败: {:?}", entry.session_id, e);
+                break;
+            }
+        }
+    }
+}
+
+/// 向排队客户推送各自的位置，并向店铺客服推送队列概况
+async fn publish(state: &AppState, shop_id: i64) {
+    let entries = match shop_queue(state, shop_id).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("读取店铺 {} 等待队列失败: {:?}", shop_id, e);
+            return;
+        }
+    };
+    let length = entries.len();
+
+    let mut manager = state.connections.lock().unwrap();
+    for entry in &entries {
+        let notice = waiting_message(shop_id, entry, length);
+        manager.send_to_customer(shop_id, &entry.customer_code, &notice);
+    }
+
+    let overview = WebSocketMessage {
+        message_type: crate::constants::ws_events::QUEUE_UPDATED.to_string(),
+        content: None,
+        session_id: None,
+        sender_id: None,
+        sender_type: Some("system".to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(json!({
+            "shopId": shop_id,
+            "length": length,
+            "sessionIds": entries.iter().map(|e| e.session_id).collect::<Vec<_>>(),
+        })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: None,
+    };
+    manager.broadcast_to_staff(shop_id, &overview);
+}
+
+fn waiting_message(shop_id: i64, entry: &QueueEntry, length: usize) -> WebSocketMessage {
+    queue_position_message(
+        shop_id,
+        entry.session_id,
+        "waiting",
+        Some(entry.position),
+        length,
+        format!("当前排队第 {} 位，请稍候，客服将尽快为您服务", entry.position),
+    )
+}
+
+fn notify_customer_assigned(state: &AppState, shop_id: i64, customer_code: &str, session: &Session) {
+    let notice = queue_position_message(
+        shop_id,
+        session.id,
+        "assigned",
+        None,
+        0,
+        "客服已接入，正在为您服务".to_string(),
+    );
+    let mut manager = state.connections.lock().unwrap();
+    manager.send_to_customer(shop_id, customer_code, &notice);
+}
+
+fn queue_position_message(
+    shop_id: i64,
+    session_id: i64,
+    status: &str,
+    position: Option<usize>,
+    length: usize,
+    content: String,
+) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type: crate::constants::ws_events::QUEUE_POSITION.to_string(),
+        content: Some(content),
+        session_id: Some(session_id),
+        sender_id: None,
+        sender_type: Some("system".to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(json!({
+            "shopId": shop_id,
+            "sessionId": session_id,
+            "status": status,
+            "position": position,
+            "queueLength": length,
+        })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: None,
+    }
+}
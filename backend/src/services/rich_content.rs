@@ -0,0 +1,439 @@
+//! 富消息（messages.rich_content）
+//!
+//! 职责：
+//! - 定义富消息结构：商品卡片、订单卡片、按钮列表、快捷回复
+//! - 持久化前按 message_type 校验结构与长度限制，生成用于预览 / 搜索的纯文本 content
+//! - 解析客户点击按钮后的 quick_reply，生成回答消息的 rich_content
+//!
+//! 富消息的 message_type 与 rich_content.type 一致；quick_reply 只能由服务端生成
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+pub const PRODUCT_CARD: &str = "product_card";
+pub const ORDER_CARD: &str = "order_card";
+pub const BUTTON_LIST: &str = "button_list";
+pub const QUICK_REPLIES: &str = "quick_replies";
+/// 客户点击按钮 / 快捷回复后生成的回答消息
+pub const QUICK_REPLY: &str = "quick_reply";
+
+pub const RICH_TYPES: &[&str] = &[PRODUCT_CARD, ORDER_CARD, BUTTON_LIST, QUICK_REPLIES, QUICK_REPLY];
+
+const MAX_TEXT_CHARS: usize = 500;
+const MAX_TITLE_CHARS: usize = 200;
+const MAX_LABEL_CHARS: usize = 40;
+const MAX_OPTIONS: usize = 10;
+const MAX_ORDER_ITEMS: usize = 50;
+/// 币种、SKU、订单状态等短字段
+const MAX_SHORT_CHARS: usize = 32;
+const MAX_URL_CHARS: usize = 2048;
+/// 按钮 / 快捷回复回传给业务系统的 payload
+const MAX_PAYLOAD_CHARS: usize = 1000;
+const MAX_QUANTITY: u32 = 100_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RichContent {
+    ProductCard(ProductCard),
+    OrderCard(OrderCard),
+    ButtonList(ButtonList),
+    QuickReplies(QuickReplies),
+    QuickReply(QuickReplyAnswer),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProductCard {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sku: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderCard {
+    pub order_no: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub items: Vec<OrderItem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderItem {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ButtonList {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub buttons: Vec<Button>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Button {
+    pub id: String,
+    pub label: String,
+    #[serde(default)]
+    pub action: ButtonAction,
+    /// action = url 时必填
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonAction {
+    /// 点击后以 quick_reply 回到会话
+    #[default]
+    Reply,
+    /// 客户端直接打开链接
+    Url,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuickReplies {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    pub replies: Vec<QuickReplyOption>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuickReplyOption {
+    pub id: String,
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuickReplyAnswer {
+    /// 被点击的按钮列表 / 快捷回复消息
+    pub message_id: i64,
+    pub option_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+}
+
+/// 校验通过的富消息：规范化后的 JSON 与纯文本摘要
+#[derive(Debug, Clone)]
+pub struct PreparedRichContent {
+    pub value: Value,
+    pub summary: String,
+}
+
+pub fn is_rich_type(message_type: &str) -> bool {
+    RICH_TYPES.contains(&message_type)
+}
+
+/// 持久化前校验：富消息类型必须携带与之一致的 rich_content，普通消息不得携带
+pub fn prepare(message_type: &str, sender_type: &str, raw: Option<&Value>) -> Result<Option<PreparedRichContent>> {
+    if !is_rich_type(message_type) {
+        if raw.is_some_and(|v| !v.is_null()) {
+            bail!("unexpected_rich_content");
+        }
+        return Ok(None);
+    }
+    let Some(raw) = raw.filter(|v| !v.is_null()) else {
+        bail!("missing_rich_content");
+    };
+    let rich: RichContent = match serde_json::from_value(raw.clone()) {
+        Ok(rich) => rich,
+        Err(e) => {
+            tracing::debug!("rich_content 解析失败: {}", e);
+            bail!("invalid_rich_content");
+        }
+    };
+    if rich.type_name() != message_type {
+        bail!("invalid_rich_content");
+    }
+    match (&rich, sender_type) {
+        (RichContent::QuickReply(_), _) => bail!("rich_type_not_allowed"),
+        (RichContent::ButtonList(_) | RichContent::QuickReplies(_), "customer") => bail!("rich_type_not_allowed"),
+        _ => {}
+    }
+    rich.validate()?;
+
+    Ok(Some(PreparedRichContent {
+        summary: rich.summary(),
+        value: serde_json::to_value(&rich)?,
+    }))
+}
+
+/// 客户点击按钮 / 快捷回复：校验选项属于该消息，返回回答消息的 rich_content（summary 为选项文案）
+pub fn answer_quick_reply(card: &crate::entities::messages::Model, option_id: &str) -> Result<PreparedRichContent> {
+    let rich: Option<RichContent> = card
+        .rich_content
+        .clone()
+        .and_then(|value| serde_json::from_value(value).ok());
+    let chosen = match &rich {
+        Some(RichContent::ButtonList(list)) => list
+            .buttons
+            .iter()
+            .find(|b| b.id == option_id && b.action == ButtonAction::Reply)
+            .map(|b| (b.label.clone(), b.payload.clone())),
+        Some(RichContent::QuickReplies(replies)) => replies
+            .replies
+            .iter()
+            .find(|r| r.id == option_id)
+            .map(|r| (r.label.clone(), r.payload.clone())),
+        _ => None,
+    };
+    let Some((label, payload)) = chosen else {
+        bail!("invalid_quick_reply");
+    };
+
+    let answer = RichContent::QuickReply(QuickReplyAnswer {
+        message_id: card.id as i64,
+        option_id: option_id.to_string(),
+        payload,
+    });
+    Ok(PreparedRichContent {
+        value: serde_json::to_value(&answer)?,
+        summary: label,
+    })
+}
+
+impl RichContent {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RichContent::ProductCard(_) => PRODUCT_CARD,
+            RichContent::OrderCard(_) => ORDER_CARD,
+            RichContent::ButtonList(_) => BUTTON_LIST,
+            RichContent::QuickReplies(_) => QUICK_REPLIES,
+            RichContent::QuickReply(_) => QUICK_REPLY,
+        }
+    }
+
+    /// 会话列表预览、全文搜索使用的纯文本
+    pub fn summary(&self) -> String {
+        match self {
+            RichContent::ProductCard(card) => format!("[商品] {}", card.title),
+            RichContent::OrderCard(card) => format!("[订单] {}", card.order_no),
+            RichContent::ButtonList(list) => list.text.clone().unwrap_or_else(|| "[按钮]".to_string()),
+            RichContent::QuickReplies(replies) => replies.text.clone().unwrap_or_else(|| "[快捷回复]".to_string()),
+            RichContent::QuickReply(answer) => answer.option_id.clone(),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            RichContent::ProductCard(card) => {
+                check_text(&card.title, MAX_TITLE_CHARS, true)?;
+                check_optional_text(&card.description, MAX_TEXT_CHARS)?;
+                check_optional_text(&card.currency, MAX_SHORT_CHARS)?;
+                check_optional_text(&card.sku, MAX_LABEL_CHARS)?;
+                check_price(card.price)?;
+                check_url(&card.url)?;
+                check_url(&card.image_url)?;
+            }
+            RichContent::OrderCard(card) => {
+                check_text(&card.order_no, MAX_LABEL_CHARS, true)?;
+                check_optional_text(&card.status, MAX_SHORT_CHARS)?;
+                check_optional_text(&card.currency, MAX_SHORT_CHARS)?;
+                check_price(card.total)?;
+                check_url(&card.url)?;
+                if card.items.len() > MAX_ORDER_ITEMS {
+                    bail!("invalid_rich_content");
+                }
+                for item in &card.items {
+                    check_text(&item.title, MAX_TITLE_CHARS, true)?;
+                    check_price(item.price)?;
+                    if item.quantity.is_some_and(|q| q > MAX_QUANTITY) {
+                        bail!("invalid_rich_content");
+                    }
+                    check_url(&item.image_url)?;
+                }
+            }
+            RichContent::ButtonList(list) => {
+                check_optional_text(&list.text, MAX_TEXT_CHARS)?;
+                check_options(list.buttons.iter().map(|b| (b.id.as_str(), b.label.as_str())))?;
+                for button in &list.buttons {
+                    if button.action == ButtonAction::Url && button.url.is_none() {
+                        bail!("invalid_rich_content");
+                    }
+                    check_url(&button.url)?;
+                    check_optional_text(&button.payload, MAX_PAYLOAD_CHARS)?;
+                }
+            }
+            RichContent::QuickReplies(replies) => {
+                check_optional_text(&replies.text, MAX_TEXT_CHARS)?;
+                check_options(replies.replies.iter().map(|r| (r.id.as_str(), r.label.as_str())))?;
+                for reply in &replies.replies {
+                    check_optional_text(&reply.payload, MAX_PAYLOAD_CHARS)?;
+                }
+            }
+            RichContent::QuickReply(_) => {}
+        }
+        Ok(())
+    }
+}
+
+fn check_text(text: &str, max_chars: usize, required: bool) -> Result<()> {
+    if (required && text.trim().is_empty()) || text.chars().count() > max_chars {
+        bail!("invalid_rich_content");
+    }
+    Ok(())
+}
+
+fn check_optional_text(text: &Option<String>, max_chars: usize) -> Result<()> {
+    match text {
+        Some(text) => check_text(text, max_chars, false),
+        None => Ok(()),
+    }
+}
+
+fn check_price(price: Option<f64>) -> Result<()> {
+    if price.is_some_and(|p| !p.is_finite() || p < 0.0) {
+        bail!("invalid_rich_content");
+    }
+    Ok(())
+}
+
+/// 链接只允许 http(s) 与站内相对路径，避免 javascript: 等协议被客户端直接打开
+fn check_url(url: &Option<String>) -> Result<()> {
+    if let Some(url) = url {
+        check_text(url, MAX_URL_CHARS, true)?;
+        let lower = url.trim().to_ascii_lowercase();
+        let relative = lower.starts_with('/') && !lower.starts_with("//");
+        if !(lower.starts_with("https://") || lower.starts_with("http://") || relative) {
+            bail!("invalid_rich_content");
+        }
+    }
+    Ok(())
+}
+
+/// 按钮 / 快捷回复：1..=MAX_OPTIONS 个，id 唯一且非空，文案不超长
+fn check_options<'a>(options: impl Iterator<Item = (&'a str, &'a str)>) -> Result<()> {
+    let mut ids = Vec::new();
+    for (id, label) in options {
+        if id.trim().is_empty() || id.chars().count() > MAX_LABEL_CHARS || ids.contains(&id) {
+            bail!("invalid_rich_content");
+        }
+        check_text(label, MAX_LABEL_CHARS, true)?;
+        ids.push(id);
+    }
+    if ids.is_empty() || ids.len() > MAX_OPTIONS {
+        bail!("invalid_rich_content");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn url(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn check_url_accepts_http_and_site_relative() {
+        assert!(check_url(&None).is_ok());
+        assert!(check_url(&url("https://shop.example.com/p/1")).is_ok());
+        assert!(check_url(&url("HTTP://shop.example.com")).is_ok());
+        assert!(check_url(&url("/products/1")).is_ok());
+    }
+
+    #[test]
+    fn check_url_rejects_script_and_protocol_relative() {
+        assert!(check_url(&url("javascript:alert(1)")).is_err());
+        assert!(check_url(&url("  JavaScript:alert(1)")).is_err());
+        assert!(check_url(&url("data:text/html,<b>x</b>")).is_err());
+        assert!(check_url(&url("//evil.example.com/x")).is_err());
+        assert!(check_url(&url("")).is_err());
+        assert!(check_url(&url(&format!("https://a.com/{}", "x".repeat(MAX_URL_CHARS)))).is_err());
+    }
+
+    #[test]
+    fn prepare_plain_message() {
+        assert!(prepare("text", "staff", None).unwrap().is_none());
+        assert!(prepare("text", "staff", Some(&Value::Null)).unwrap().is_none());
+        let err = prepare("text", "staff", Some(&json!({"type": "product_card", "title": "a"}))).unwrap_err();
+        assert_eq!(err.to_string(), "unexpected_rich_content");
+    }
+
+    #[test]
+    fn prepare_product_card() {
+        let raw = json!({"type": "product_card", "title": "保温杯", "price": 59.9, "currency": "CNY", "url": "https://shop.example.com/p/1"});
+        let prepared = prepare(PRODUCT_CARD, "staff", Some(&raw)).unwrap().unwrap();
+        assert_eq!(prepared.summary, "[商品] 保温杯");
+        assert_eq!(prepared.value["type"], "product_card");
+        assert_eq!(prepared.value["currency"], "CNY");
+
+        assert_eq!(prepare(PRODUCT_CARD, "staff", None).unwrap_err().to_string(), "missing_rich_content");
+        // message_type 与 rich_content.type 不一致
+        assert_eq!(prepare(ORDER_CARD, "staff", Some(&raw)).unwrap_err().to_string(), "invalid_rich_content");
+    }
+
+    #[test]
+    fn prepare_rejects_oversized_fields() {
+        let cases = [
+            json!({"type": "product_card", "title": "a", "currency": "C".repeat(MAX_SHORT_CHARS + 1)}),
+            json!({"type": "product_card", "title": "a", "sku": "s".repeat(MAX_LABEL_CHARS + 1)}),
+            json!({"type": "product_card", "title": "a", "imageUrl": "javascript:alert(1)"}),
+            json!({"type": "order_card", "orderNo": "A1", "status": "s".repeat(MAX_SHORT_CHARS + 1)}),
+            json!({"type": "order_card", "orderNo": "A1", "items": [{"title": "a", "quantity": MAX_QUANTITY + 1}]}),
+            json!({"type": "button_list", "buttons": [{"id": "1", "label": "a", "payload": "p".repeat(MAX_PAYLOAD_CHARS + 1)}]}),
+            json!({"type": "quick_replies", "replies": [{"id": "1", "label": "a", "payload": "p".repeat(MAX_PAYLOAD_CHARS + 1)}]}),
+        ];
+        for raw in cases {
+            let message_type = raw["type"].as_str().unwrap().to_string();
+            assert!(prepare(&message_type, "staff", Some(&raw)).is_err(), "{raw}");
+        }
+    }
+
+    #[test]
+    fn prepare_checks_sender_and_options() {
+        let buttons = json!({"type": "button_list", "text": "请选择", "buttons": [
+            {"id": "yes", "label": "是"},
+            {"id": "site", "label": "官网", "action": "url", "url": "https://shop.example.com"}
+        ]});
+        let prepared = prepare(BUTTON_LIST, "staff", Some(&buttons)).unwrap().unwrap();
+        assert_eq!(prepared.summary, "请选择");
+        assert_eq!(prepare(BUTTON_LIST, "customer", Some(&buttons)).unwrap_err().to_string(), "rich_type_not_allowed");
+
+        let answer = json!({"type": "quick_reply", "messageId": 1, "optionId": "yes"});
+        assert_eq!(prepare(QUICK_REPLY, "customer", Some(&answer)).unwrap_err().to_string(), "rich_type_not_allowed");
+
+        let duplicate = json!({"type": "quick_replies", "replies": [{"id": "a", "label": "1"}, {"id": "a", "label": "2"}]});
+        assert!(prepare(QUICK_REPLIES, "staff", Some(&duplicate)).is_err());
+        let empty = json!({"type": "quick_replies", "replies": []});
+        assert!(prepare(QUICK_REPLIES, "staff", Some(&empty)).is_err());
+        let url_without_link = json!({"type": "button_list", "buttons": [{"id": "a", "label": "1", "action": "url"}]});
+        assert!(prepare(BUTTON_LIST, "staff", Some(&url_without_link)).is_err());
+    }
+}
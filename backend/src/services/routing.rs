@@ -0,0 +1,275 @@
+//! 会话自动分配（routing）
+//!
+//! 职责：
+//! - 客户发出首条消息时，为未分配的会话挑选客服
+//! - 支持轮询（round_robin）、最少进行中会话（least_active）、优先上次接待客服（sticky）三种策略，按店铺配置
+//! - 只考虑当前在该店铺在线（presence_connections，任一节点）、具备 send_messages 能力且未达到最大并发会话数的客服
+//! - 无可用客服时会话保持未分配，留在店铺的未分配队列中
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    constants::routing_policy,
+    entities::sessions,
+    repositories::{RoutingRepository, SessionRepository},
+    services::{
+        permissions::{self, Capability},
+        presence,
+    },
+    AppState,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingStrategy {
+    RoundRobin,
+    LeastActive,
+    Sticky,
+    Manual,
+}
+
+impl RoutingStrategy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            routing_policy::STRATEGY_ROUND_ROBIN => Some(Self::RoundRobin),
+            routing_policy::STRATEGY_LEAST_ACTIVE => Some(Self::LeastActive),
+            routing_policy::STRATEGY_STICKY => Some(Self::Sticky),
+            routing_policy::STRATEGY_MANUAL => Some(Self::Manual),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::RoundRobin => routing_policy::STRATEGY_ROUND_ROBIN,
+            Self::LeastActive => routing_policy::STRATEGY_LEAST_ACTIVE,
+            Self::Sticky => routing_policy::STRATEGY_STICKY,
+            Self::Manual => routing_policy::STRATEGY_MANUAL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutingSettings {
+    pub strategy: &'static str,
+    pub default_max_concurrent: i32,
+}
+
+/// 候选客服：在线且未满载
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Candidate {
+    user_id: i64,
+    active: i64,
+}
+
+/// 读取店铺分配配置（未配置时使用默认值）
+pub async fn shop_settings(state: &AppState, shop_id: i64) -> Result<RoutingSettings> {
+    let record = RoutingRepository::find_settings(&state.db_connection, shop_id).await?;
+    Ok(match record {
+        Some(record) => RoutingSettings {
+            strategy: RoutingStrategy::parse(&record.strategy)
+                .unwrap_or(RoutingStrategy::LeastActive)
+                .as_str(),
+            default_max_concurrent: record.default_max_concurrent,
+        },
+        None => RoutingSettings {
+            strategy: routing_policy::DEFAULT_STRATEGY,
+            default_max_concurrent: routing_policy::DEFAULT_MAX_CONCURRENT,
+        },
+    })
+}
+
+/// 更新店铺分配策略与客服默认最大并发会话数
+pub async fn update_shop_settings(
+    state: &AppState,
+    shop_id: i64,
+    strategy: &str,
+    default_max_concurrent: i32,
+) -> Result<RoutingSettings> {
+    let Some(strategy) = RoutingStrategy::parse(strategy) else {
+        anyhow::bail!("invalid_strategy");
+    };
+    if !(1..=routing_policy::MAX_CONCURRENT_LIMIT).contains(&default_max_concurrent) {
+        anyhow::bail!("invalid_max_concurrent");
+    }
+    let record = RoutingRepository::upsert_settings(
+        &state.db_connection,
+        shop_id,
+        strategy.as_str(),
+        default_max_concurrent,
+    )
+    .await?;
+    Ok(RoutingSettings {
+        strategy: strategy.as_str(),
+        default_max_concurrent: record.default_max_concurrent,
+    })
+}
+
+/// 设置员工最大并发会话数；None 表示跟随店铺默认值
+pub async fn set_staff_max_concurrent(
+    state: &AppState,
+    shop_id: i64,
+    user_id: i64,
+    max_concurrent: Option<i32>,
+) -> Result<()> {
+    if max_concurrent.is_some_and(|max| !(0..=routing_policy::MAX_CONCURRENT_LIMIT).contains(&max)) {
+        anyhow::bail!("invalid_max_concurrent");
+    }
+    let affected = RoutingRepository::set_staff_max_concurrent(&state.db_connection, shop_id, user_id, max_concurrent).await?;
+    if affected == 0 {
+        anyhow::bail!("staff_not_in_shop");
+    }
+    Ok(())
+}
+
+/// 为未分配的会话挑选客服并分配；会话已分配或无可用客服时返回 None
+pub async fn route_session(state: &AppState, session_id: i64) -> Result<Option<sessions::Model>> {
+    let db = &state.db_connection;
+    let Some(session) = SessionRepository::find_by_id(db, session_id as i32).await? else {
+        return Ok(None);
+    };
+    if session.staff_id.is_some() || session.session_status.as_deref() == Some("closed") {
+        return Ok(None);
+    }
+    let shop_id = session.shop_id as i64;
+
+    let record = RoutingRepository::find_settings(db, shop_id).await?;
+    let strategy = record
+        .as_ref()
+        .and_then(|r| RoutingStrategy::parse(&r.strategy))
+        .unwrap_or(RoutingStrategy::LeastActive);
+    if strategy == RoutingStrategy::Manual {
+        return Ok(None);
+    }
+    let default_max = record
+        .as_ref()
+        .map(|r| r.default_max_concurrent)
+        .unwrap_or(routing_policy::DEFAULT_MAX_CONCURRENT);
+    let last_assigned = record.as_ref().and_then(|r| r.last_assigned_user_id).map(|id| id as i64);
+
+    // 以在线状态连接明细为准：客服可能连在其他节点上
+    let mut online = presence::online_staff_in_shop(state, shop_id).await?;
+    if online.is_empty() {
+        return Ok(None);
+    }
+    // 只分配给能回复客户的成员（viewer 等无 send_messages 的不参与）；全部成员的能力一次查询取回
+    let members = permissions::load_shop_access(&state.db, shop_id)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    online.retain(|user_id| members.get(user_id).is_some_and(|a| a.can(Capability::SendMessages)));
+    if online.is_empty() {
+        return Ok(None);
+    }
+
+    let counts = RoutingRepository::active_session_counts(db, shop_id).await?;
+    let limits = RoutingRepository::staff_max_concurrent(db, shop_id).await?;
+    let candidates = available_candidates(&online, &counts, &limits, default_max);
+
+    let chosen = match strategy {
+        RoutingStrategy::RoundRobin => pick_round_robin(&candidates, last_assigned),
+        RoutingStrategy::LeastActive => pick_least_active(&candidates),
+        RoutingStrategy::Sticky => {
+            let last_agent = RoutingRepository::last_agent_for_customer(
+                db,
+                shop_id,
+                session.customer_id as i64,
+                session_id,
+            )
+            .await?;
+            last_agent
+                .filter(|id| candidates.iter().any(|c| c.user_id == *id))
+                .or_else(|| pick_least_active(&candidates))
+        }
+        RoutingStrategy::Manual => None,
+    };
+    let Some(staff_id) = chosen else {
+        tracing::info!("店铺 {} 暂无可分配客服，会话 {} 进入未分配队列", shop_id, session_id);
+        return Ok(None);
+    };
+
+    let assigned = SessionRepository::assign_if_unassigned(db, session_id as i32, staff_id as i32).await?;
+    if assigned.is_some() && strategy == RoutingStrategy::RoundRobin {
+        RoutingRepository::set_last_assigned(db, shop_id, staff_id).await?;
+    }
+    Ok(assigned)
+}
+
+fn available_candidates(
+    online: &[i64],
+    counts: &HashMap<i64, i64>,
+    limits: &HashMap<i64, i32>,
+    default_max: i32,
+) -> Vec<Candidate> {
+    online
+        .iter()
+        .map(|user_id| Candidate {
+            user_id: *user_id,
+            active: counts.get(user_id).copied().unwrap_or(0),
+        })
+        .filter(|c| c.active < limits.get(&c.user_id).copied().unwrap_or(default_max) as i64)
+        .collect()
+}
+
+/// 进行中会话最少者优先，相同则 user_id 小者优先
+fn pick_least_active(candidates: &[Candidate]) -> Option<i64> {
+    candidates
+        .iter()
+        .min_by_key(|c| (c.active, c.user_id))
+        .map(|c| c.user_id)
+}
+
+/// 按 user_id 顺序取上次分配客服之后的下一位，到末尾后回到开头
+fn pick_round_robin(candidates: &[Candidate], last_assigned: Option<i64>) -> Option<i64> {
+    let mut ids: Vec<i64> = candidates.iter().map(|c| c.user_id).collect();
+    ids.sort_unstable();
+    match last_assigned {
+        Some(last) => ids.iter().copied().find(|id| *id > last).or_else(|| ids.first().copied()),
+        None => ids.first().copied(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(items: &[(i64, i64)]) -> Vec<Candidate> {
+        items.iter().map(|&(user_id, active)| Candidate { user_id, active }).collect()
+    }
+
+    #[test]
+    fn round_robin_takes_next_id_and_wraps() {
+        let list = candidates(&[(30, 0), (10, 0), (20, 0)]);
+        assert_eq!(pick_round_robin(&list, None), Some(10));
+        assert_eq!(pick_round_robin(&list, Some(10)), Some(20));
+        assert_eq!(pick_round_robin(&list, Some(20)), Some(30));
+        // 上次分配的是最后一位（或已不在候选中）时回到开头
+        assert_eq!(pick_round_robin(&list, Some(30)), Some(10));
+        assert_eq!(pick_round_robin(&list, Some(99)), Some(10));
+        assert_eq!(pick_round_robin(&list, Some(15)), Some(20));
+        assert_eq!(pick_round_robin(&[], Some(10)), None);
+    }
+
+    #[test]
+    fn least_active_breaks_ties_by_user_id() {
+        assert_eq!(pick_least_active(&candidates(&[(30, 2), (20, 1), (10, 3)])), Some(20));
+        assert_eq!(pick_least_active(&candidates(&[(30, 1), (20, 1), (40, 2)])), Some(20));
+        assert_eq!(pick_least_active(&[]), None);
+    }
+
+    #[test]
+    fn candidates_respect_personal_and_default_limits() {
+        let online = [1, 2, 3, 4];
+        let counts = HashMap::from([(1, 2), (2, 5), (3, 1)]);
+        // 2 单独放宽到 6；3 设为 0 表示不参与自动分配；其余跟随默认 3
+        let limits = HashMap::from([(2, 6), (3, 0)]);
+        assert_eq!(
+            available_candidates(&online, &counts, &limits, 3),
+            candidates(&[(1, 2), (2, 5), (4, 0)])
+        );
+        // 达到默认上限即不再分配
+        let counts = HashMap::from([(1, 3), (4, 3)]);
+        assert_eq!(available_candidates(&online, &counts, &limits, 3), candidates(&[(2, 0)]));
+    }
+}
@@ -9,8 +9,9 @@
 use anyhow::Result;
 use sea_orm::DatabaseConnection;
 
-use crate::repositories::{SessionRepository, ShopStaffRepository, CustomerRepository};
+use crate::repositories::{SessionRepository, CustomerRepository};
 use crate::entities::sessions;
+use crate::services::permissions::ShopAccess;
 
 #[derive(Clone)]
 pub struct SessionService {
@@ -48,173 +49,117 @@ impl SessionService {
     /// 获取店铺的所有活跃会话
     /// 
     /// 业务逻辑：
-    /// 1. 验证访问权限
-    /// 2. 获取活跃会话列表
+    /// 1. 店铺与成员身份来自 ShopAccess（handler 提取器已校验）
+    /// 2. 没有 view_all_sessions 时只返回分配给自己或未分配的会话
     pub async fn get_shop_active_sessions(
         db: &DatabaseConnection,
-        shop_id: i32,
-        requester_user_id: i32,
+        access: &ShopAccess,
     ) -> Result<Vec<sessions::Model>> {
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        SessionRepository::find_active_by_shop(db, shop_id).await
+        let sessions = SessionRepository::find_active_by_shop(db, access.shop_id as i32).await?;
+        Ok(sessions
+            .into_iter()
+            .filter(|s| access.can_view_session(s.staff_id.map(i64::from)))
+            .collect())
     }
     
-    /// 获取客服的活跃会话
+    /// 获取当前客服在该店铺的活跃会话
     /// 
     /// 业务逻辑：
-    /// 1. 验证是否是有效的客服
-    /// 2. 获取该客服的所有活跃会话
+    /// 1. 客服身份与店铺来自 ShopAccess
+    /// 2. 只保留该店铺内分配给自己的会话
     pub async fn get_staff_active_sessions(
         db: &DatabaseConnection,
-        staff_id: i32,
-        shop_id: Option<i32>,
+        access: &ShopAccess,
     ) -> Result<Vec<sessions::Model>> {
-        // 如果指定了店铺，验证权限
-        if let Some(sid) = shop_id {
-            if !ShopStaffRepository::is_shop_member(db, sid as i64, staff_id as i64).await? {
-                anyhow::bail!("permission_denied");
-            }
-        }
-        
-        SessionRepository::find_by_staff(db, staff_id).await
+        let sessions = SessionRepository::find_by_staff(db, access.user_id as i32).await?;
+        Ok(sessions
+            .into_iter()
+            .filter(|s| s.shop_id as i64 == access.shop_id)
+            .collect())
     }
     
     /// 分配客服到会话
     /// 
     /// 业务逻辑：
-    /// 1. 验证会话存在
-    /// 2. 验证客服有权限
+    /// 1. 验证会话存在且未关闭
+    /// 2. 已分配给其他客服时拒绝（应使用转接）
     /// 3. 分配客服
+    ///
+    /// 操作者与目标客服的店铺权限由 handler 层使用 SQLx 校验
     pub async fn assign_staff_to_session(
-        db: &DatabaseConnection,
+        &self,
         session_id: i32,
         staff_id: i32,
-        operator_user_id: i32,
-    ) -> Result<()> {
-        // 1. 获取会话
-        let session = SessionRepository::find_by_id(db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-        
-        // 2. 验证操作者权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, operator_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
+    ) -> Result<sessions::Model> {
+        let session = Self::find_open_session(&self.db, session_id).await?;
         
-        // 3. 验证目标客服权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, staff_id as i64).await? {
-            anyhow::bail!("staff_not_in_shop");
+        if session.staff_id.is_some_and(|current| current != staff_id) {
+            anyhow::bail!("session_already_assigned");
         }
         
-        // 4. 分配客服
-        SessionRepository::assign_staff(db, session_id, staff_id).await
+        SessionRepository::assign_staff(&self.db, session_id, staff_id).await
     }
     
-    /// 自动分配客服
+    /// 关闭会话
     /// 
     /// 业务逻辑：
-    /// 1. 获取店铺的所有客服
-    /// 2. 统计每个客服的当前会话数
-    /// 3. 选择会话数最少的客服
-    pub async fn auto_assign_staff(
-        db: &DatabaseConnection,
-        session_id: i32,
-    ) -> Result<()> {
-        // 获取会话
-        let session = SessionRepository::find_by_id(db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-        
-        // 获取店铺的所有成员
-        let staff_list = ShopStaffRepository::list_shop_staff(db, session.shop_id as i64).await?;
-        
-        if staff_list.is_empty() {
-            anyhow::bail!("no_staff_available");
-        }
-        
-        // 简单策略：选择第一个可用的客服
-        // TODO: 实现更智能的分配策略（基于当前会话数、在线状态等）
-        let (first_staff, _role) = &staff_list[0];
-        
-        SessionRepository::assign_staff(db, session_id, first_staff.id).await
+    /// 1. 验证会话存在且未关闭
+    /// 2. 关闭会话并记录关闭时间
+    pub async fn close_session(&self, session_id: i32) -> Result<sessions::Model> {
+        Self::find_open_session(&self.db, session_id).await?;
+        SessionRepository::close(&self.db, session_id).await
     }
     
-    /// 关闭会话
+    /// 重新打开会话
     /// 
     /// 业务逻辑：
-    /// 1. 验证会话存在
-    /// 2. 验证权限
-    /// 3. 关闭会话
-    pub async fn close_session(
-        db: &DatabaseConnection,
-        session_id: i32,
-        operator_user_id: i32,
-    ) -> Result<()> {
-        // 1. 获取会话
-        let session = SessionRepository::find_by_id(db, session_id)
+    /// 1. 验证会话存在且已关闭
+    /// 2. 客户已有其他进行中的会话时拒绝，避免同一客户出现两个活跃会话
+    /// 3. 恢复为 active 并清空关闭时间
+    pub async fn reopen_session(&self, session_id: i32) -> Result<sessions::Model> {
+        let session = SessionRepository::find_by_id(&self.db, session_id)
             .await?
             .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
         
-        // 2. 验证权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, operator_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
+        if session.session_status.as_deref() != Some("closed") {
+            anyhow::bail!("session_not_closed");
+        }
+        
+        if SessionRepository::find_by_shop_and_customer(&self.db, session.shop_id, session.customer_id)
+            .await?
+            .is_some()
+        {
+            anyhow::bail!("customer_has_active_session");
         }
         
-        // 3. 关闭会话
-        SessionRepository::close(db, session_id).await
+        SessionRepository::reopen(&self.db, session_id).await
     }
     
     /// 设置会话优先级
     /// 
     /// 业务逻辑：
-    /// 1. 验证会话存在
-    /// 2. 验证权限
+    /// 1. 验证优先级范围（0-10）
+    /// 2. 验证会话存在
     /// 3. 设置优先级
     pub async fn set_session_priority(
-        db: &DatabaseConnection,
+        &self,
         session_id: i32,
         priority: i32,
-        operator_user_id: i32,
-    ) -> Result<()> {
-        // 1. 获取会话
-        let session = SessionRepository::find_by_id(db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
-        
-        // 2. 验证权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, operator_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        // 3. 验证优先级范围
-        if priority < 0 || priority > 10 {
+    ) -> Result<sessions::Model> {
+        if !(0..=10).contains(&priority) {
             anyhow::bail!("invalid_priority");
         }
         
-        // 4. 设置优先级
-        SessionRepository::set_priority(db, session_id, priority).await
+        SessionRepository::find_by_id(&self.db, session_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
+        
+        SessionRepository::set_priority(&self.db, session_id, priority).await
     }
     
-    /// 获取未分配的会话
-    /// 
-    /// 业务逻辑：
-    /// 1. 验证访问权限
-    /// 2. 获取未分配的会话列表
-    pub async fn get_unassigned_sessions(
-        db: &DatabaseConnection,
-        shop_id: i32,
-        requester_user_id: i32,
-    ) -> Result<Vec<sessions::Model>> {
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, shop_id as i64, requester_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        SessionRepository::find_unassigned_by_shop(db, shop_id).await
+    /// 获取未分配的会话（按优先级从高到低、创建时间从早到晚）
+    pub async fn get_unassigned_sessions(&self, shop_id: i32) -> Result<Vec<sessions::Model>> {
+        SessionRepository::find_unassigned_by_shop(&self.db, shop_id).await
     }
     
     /// 更新会话的最后消息时间
@@ -228,16 +173,28 @@ impl SessionService {
     /// 获取会话详情
     pub async fn get_session_detail(
         db: &DatabaseConnection,
+        access: &ShopAccess,
+        session_id: i32,
+    ) -> Result<sessions::Model> {
+        Self::find_visible_session(db, access, session_id).await
+    }
+    
+    /// 查找当前成员可访问的会话
+    /// 
+    /// 业务逻辑：
+    /// 1. 会话不存在或不属于该店铺时返回 session_not_found，不暴露其他店铺的会话
+    /// 2. 没有 view_all_sessions 时只能访问分配给自己或未分配的会话
+    pub async fn find_visible_session(
+        db: &DatabaseConnection,
+        access: &ShopAccess,
         session_id: i32,
-        requester_user_id: i32,
     ) -> Result<sessions::Model> {
-        // 获取会话
         let session = SessionRepository::find_by_id(db, session_id)
             .await?
+            .filter(|s| s.shop_id as i64 == access.shop_id)
             .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
         
-        // 验证权限
-        if !ShopStaffRepository::is_shop_member(db, session.shop_id as i64, requester_user_id as i64).await? {
+        if !access.can_view_session(session.staff_id.map(i64::from)) {
             anyhow::bail!("permission_denied");
         }
         
@@ -247,55 +204,76 @@ impl SessionService {
     /// 转移会话到其他客服
     /// 
     /// 业务逻辑：
-    /// 1. 验证权限
-    /// 2. 验证目标客服权限
+    /// 1. 验证会话存在且未关闭
+    /// 2. 目标客服与当前客服相同时拒绝
     /// 3. 重新分配
+    ///
+    /// 操作者与目标客服的店铺权限由 handler 层使用 SQLx 校验
     pub async fn transfer_session(
         &self,
         session_id: i32,
         new_staff_id: i32,
-        operator_user_id: i32,
-    ) -> Result<()> {
-        // 获取会话
-        let session = SessionRepository::find_by_id(&self.db, session_id)
-            .await?
-            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
+    ) -> Result<sessions::Model> {
+        let session = Self::find_open_session(&self.db, session_id).await?;
         
-        // 验证操作者权限
-        if !ShopStaffRepository::is_shop_member(&self.db, session.shop_id as i64, operator_user_id as i64).await? {
-            anyhow::bail!("permission_denied");
+        if session.staff_id == Some(new_staff_id) {
+            anyhow::bail!("session_already_assigned_to_target");
         }
         
-        // 验证新客服权限
-        if !ShopStaffRepository::is_shop_member(&self.db, session.shop_id as i64, new_staff_id as i64).await? {
-            anyhow::bail!("target_staff_not_in_shop");
+        SessionRepository::assign_staff(&self.db, session_id, new_staff_id).await
+    }
+
+    /// 查找未关闭的会话
+    async fn find_open_session(db: &DatabaseConnection, session_id: i32) -> Result<sessions::Model> {
+        let session = SessionRepository::find_by_id(db, session_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("session_not_found"))?;
+        
+        if session.session_status.as_deref() == Some("closed") {
+            anyhow::bail!("session_closed");
         }
         
-        // 重新分配
-        SessionRepository::assign_staff(&self.db, session_id, new_staff_id).await
+        Ok(session)
     }
 
     /// Handler 需要的方法：重置客户未读计数
     pub async fn reset_unread_count(
         &self,
-    _user_id: i64,
+        user_id: i64,
         shop_id: i32,
         customer_id: i32,
     ) -> Result<()> {
         // 权限已由 handler 层使用 SQLx 校验，这里不再重复检查，避免触发不兼容的 Sea-ORM 查询
-        // 重置未读计数 (通过 SessionRepository)
-        SessionRepository::reset_customer_unread_count(&self.db, shop_id, customer_id).await
+        // 重置未读计数 (通过 SessionRepository)，并推进操作者的个人已读游标
+        SessionRepository::reset_customer_unread_count(&self.db, shop_id, customer_id).await?;
+        crate::repositories::UnreadCountRepository::advance_staff_cursors_to_latest(
+            &self.db,
+            shop_id as i64,
+            Some(customer_id as i64),
+            user_id,
+        ).await
     }
 
     /// Handler 需要的方法：重置店铺所有未读计数
     pub async fn reset_all_unread_in_shop(
         &self,
-    _user_id: i64,
+        user_id: i64,
         shop_id: i32,
     ) -> Result<()> {
         // 权限已由 handler 层使用 SQLx 校验，这里不再重复检查
-        // 重置店铺所有未读计数
-        SessionRepository::reset_all_unread_in_shop(&self.db, shop_id).await
+        // 重置店铺所有未读计数，并推进操作者的个人已读游标
+        SessionRepository::reset_all_unread_in_shop(&self.db, shop_id).await?;
+        crate::repositories::UnreadCountRepository::advance_staff_cursors_to_latest(
+            &self.db,
+            shop_id as i64,
+            None,
+            user_id,
+        ).await
+    }
+
+    /// 客服个人未读：返回 (customer_id, unread) 列表
+    pub async fn staff_unread_by_shop(&self, user_id: i64, shop_id: i64) -> Result<Vec<(i64, i64)>> {
+        crate::repositories::UnreadCountRepository::staff_unread_by_shop(&self.db, shop_id, user_id).await
     }
 
     /// Chat Service 需要的方法：根据店铺和客户查找会话
@@ -11,6 +11,7 @@ use sea_orm::DatabaseConnection;
 
 use crate::repositories::{ShopRepository, ShopStaffRepository, UserRepository};
 use crate::entities::{shops, users};
+use crate::services::permissions::{Capability, Role, ShopAccess};
 
 #[derive(Clone)]
 pub struct ShopService {
@@ -89,26 +90,29 @@ impl ShopService {
         ShopRepository::find_accessible_by_user(db, user_id).await
     }
     
-    /// 检查用户是否有权限访问店铺
-    /// 
-    /// 业务逻辑：
-    /// 1. 检查是否是店主
-    /// 2. 检查是否是员工
-    pub async fn can_access_shop(
-        db: &DatabaseConnection,
-        user_id: i32,
-        shop_id: i32,
-    ) -> Result<bool> {
-        ShopStaffRepository::is_shop_member(db, shop_id as i64, user_id as i64).await
+    /// 店主专属操作（店铺资料、删除、API Key）
+    fn ensure_owner(access: &ShopAccess) -> Result<()> {
+        if !access.is_owner() {
+            anyhow::bail!("permission_denied");
+        }
+        Ok(())
     }
     
-    /// 检查用户是否是店主
-    pub async fn is_shop_owner(
-        db: &DatabaseConnection,
-        user_id: i32,
-        shop_id: i32,
-    ) -> Result<bool> {
-        ShopStaffRepository::is_shop_owner(db, shop_id as i64, user_id as i64).await
+    /// 员工管理：需要 manage_staff；涉及 admin（目标现为或将成为 admin）时仅店主可操作
+    fn ensure_can_manage_staff(access: &ShopAccess, touches_admin: bool) -> Result<()> {
+        if !access.can(Capability::ManageStaff) || (touches_admin && !access.is_owner()) {
+            anyhow::bail!("permission_denied");
+        }
+        Ok(())
+    }
+    
+    /// 目标员工的当前角色；不是在职员工时返回 None
+    async fn staff_role(db: &DatabaseConnection, shop_id: i32, user_id: i32) -> Result<Option<Role>> {
+        Ok(ShopStaffRepository::find_by_shop(db, shop_id)
+            .await?
+            .into_iter()
+            .find(|s| s.user_id == user_id)
+            .map(|s| Role::from_staff_role(&s.role)))
     }
     
     /// 更新店铺信息
@@ -118,8 +122,7 @@ impl ShopService {
     /// 2. 更新店铺信息
     pub async fn update_shop(
         db: &DatabaseConnection,
-        shop_id: i32,
-        operator_user_id: i32,
+        access: &ShopAccess,
         name: Option<String>,
         description: Option<String>,
         logo_url: Option<String>,
@@ -128,14 +131,12 @@ impl ShopService {
         contact_phone: Option<String>,
     ) -> Result<shops::Model> {
         // 1. 验证权限（只有店主可以更新）
-        if !Self::is_shop_owner(db, operator_user_id, shop_id).await? {
-            anyhow::bail!("permission_denied");
-        }
+        Self::ensure_owner(access)?;
         
         // 2. 更新店铺
         ShopRepository::update(
             db,
-            shop_id,
+            access.shop_id as i32,
             name,
             description,
             logo_url,
@@ -152,16 +153,13 @@ impl ShopService {
     /// 2. 软删除店铺
     pub async fn delete_shop(
         db: &DatabaseConnection,
-        shop_id: i32,
-        operator_user_id: i32,
+        access: &ShopAccess,
     ) -> Result<()> {
         // 1. 验证权限
-        if !Self::is_shop_owner(db, operator_user_id, shop_id).await? {
-            anyhow::bail!("permission_denied");
-        }
+        Self::ensure_owner(access)?;
         
         // 2. 软删除
-        ShopRepository::soft_delete(db, shop_id).await
+        ShopRepository::soft_delete(db, access.shop_id as i32).await
     }
     
     /// 重新生成 API Key
@@ -171,38 +169,33 @@ impl ShopService {
     /// 2. 重新生成 API Key
     pub async fn regenerate_api_key(
         db: &DatabaseConnection,
-        shop_id: i32,
-        operator_user_id: i32,
+        access: &ShopAccess,
     ) -> Result<String> {
         // 1. 验证权限
-        if !Self::is_shop_owner(db, operator_user_id, shop_id).await? {
-            anyhow::bail!("permission_denied");
-        }
+        Self::ensure_owner(access)?;
         
         // 2. 重新生成
-        ShopRepository::regenerate_api_key(db, shop_id).await
+        ShopRepository::regenerate_api_key(db, access.shop_id as i32).await
     }
     
     /// 添加员工到店铺
     /// 
     /// 业务逻辑：
-    /// 1. 验证操作者是否是店主
+    /// 1. 验证操作者具备 manage_staff（添加 admin 仅限店主）
     /// 2. 根据用户名查找用户
     /// 3. 添加员工
     pub async fn add_staff(
         db: &DatabaseConnection,
-        shop_id: i32,
-        operator_user_id: i32,
+        access: &ShopAccess,
         username: &str,
         role: Option<&str>,
     ) -> Result<()> {
         // 1. 验证权限
-        if !Self::is_shop_owner(db, operator_user_id, shop_id).await? {
-            anyhow::bail!("permission_denied");
-        }
+        let touches_admin = role.is_some_and(|r| Role::from_staff_role(r) == Role::Admin);
+        Self::ensure_can_manage_staff(access, touches_admin)?;
         
         // 2. 添加员工
-        ShopStaffRepository::add_staff_by_username(db, shop_id, username, role).await?;
+        ShopStaffRepository::add_staff_by_username(db, access.shop_id as i32, username, role).await?;
         
         Ok(())
     }
@@ -210,66 +203,61 @@ impl ShopService {
     /// 移除员工
     /// 
     /// 业务逻辑：
-    /// 1. 验证操作者是否是店主
-    /// 2. 验证不能移除店主自己
+    /// 1. 验证不能移除店主自己
+    /// 2. 验证操作者具备 manage_staff（移除 admin 仅限店主）
     /// 3. 移除员工
     pub async fn remove_staff(
         db: &DatabaseConnection,
-        shop_id: i32,
-        operator_user_id: i32,
+        access: &ShopAccess,
         target_user_id: i32,
     ) -> Result<()> {
-        // 1. 验证权限
-        if !Self::is_shop_owner(db, operator_user_id, shop_id).await? {
-            anyhow::bail!("permission_denied");
-        }
+        let shop_id = access.shop_id as i32;
         
-        // 2. 验证不能移除店主
-        if ShopStaffRepository::is_shop_owner(db, shop_id as i64, target_user_id as i64).await? {
+        // 1. 验证不能移除店主
+        if ShopRepository::is_owner(db, shop_id, target_user_id).await? {
             anyhow::bail!("cannot_remove_owner");
         }
         
+        // 2. 验证权限
+        let current = Self::staff_role(db, shop_id, target_user_id).await?;
+        Self::ensure_can_manage_staff(access, current == Some(Role::Admin))?;
+        
         // 3. 移除员工
         ShopStaffRepository::remove_staff(db, shop_id, target_user_id).await
     }
     
-    /// 列出店铺的所有成员（包括店主和员工）
+    /// 列出店铺的所有成员（包括店主和员工）；成员身份来自 ShopAccess
     pub async fn list_shop_members(
         db: &DatabaseConnection,
-        shop_id: i32,
-        requester_user_id: i32,
+        access: &ShopAccess,
     ) -> Result<Vec<(users::Model, String)>> {
-        // 验证访问权限
-        if !Self::can_access_shop(db, requester_user_id, shop_id).await? {
-            anyhow::bail!("permission_denied");
-        }
-        
-        ShopStaffRepository::list_shop_staff(db, shop_id as i64).await
+        ShopStaffRepository::list_shop_staff(db, access.shop_id).await
     }
     
     /// 更新员工角色
     /// 
     /// 业务逻辑：
-    /// 1. 验证操作者是否是店主
-    /// 2. 验证不能修改店主角色
+    /// 1. 验证不能修改店主角色
+    /// 2. 验证操作者具备 manage_staff（目标现为或将成为 admin 时仅限店主）
     /// 3. 更新角色
     pub async fn update_staff_role(
         db: &DatabaseConnection,
-        shop_id: i32,
-        operator_user_id: i32,
+        access: &ShopAccess,
         target_user_id: i32,
         new_role: String,
     ) -> Result<()> {
-        // 1. 验证权限
-        if !Self::is_shop_owner(db, operator_user_id, shop_id).await? {
-            anyhow::bail!("permission_denied");
-        }
+        let shop_id = access.shop_id as i32;
         
-        // 2. 验证不能修改店主
-        if ShopStaffRepository::is_shop_owner(db, shop_id as i64, target_user_id as i64).await? {
+        // 1. 验证不能修改店主
+        if ShopRepository::is_owner(db, shop_id, target_user_id).await? {
             anyhow::bail!("cannot_modify_owner");
         }
         
+        // 2. 验证权限
+        let current = Self::staff_role(db, shop_id, target_user_id).await?;
+        let touches_admin = current == Some(Role::Admin) || Role::from_staff_role(&new_role) == Role::Admin;
+        Self::ensure_can_manage_staff(access, touches_admin)?;
+        
         // 3. 更新角色
         ShopStaffRepository::update_role(db, shop_id, target_user_id, new_role).await
     }
@@ -302,3 +290,31 @@ impl ShopService {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::permissions::CapabilityOverrides;
+
+    fn access(role: Role, overrides: &[(Capability, bool)]) -> ShopAccess {
+        ShopAccess::with_overrides(2, 1, role, &overrides.iter().copied().collect::<CapabilityOverrides>())
+    }
+
+    #[test]
+    fn staff_management_follows_capabilities() {
+        // viewer、被收回 manage_staff 的 admin 都不能管理员工
+        assert!(ShopService::ensure_can_manage_staff(&access(Role::Viewer, &[]), false).is_err());
+        assert!(ShopService::ensure_can_manage_staff(&access(Role::Admin, &[(Capability::ManageStaff, false)]), false).is_err());
+        // 获授 manage_staff 的 agent 可以管理普通员工，但不能涉及 admin
+        let manager = access(Role::Agent, &[(Capability::ManageStaff, true)]);
+        assert!(ShopService::ensure_can_manage_staff(&manager, false).is_ok());
+        assert!(ShopService::ensure_can_manage_staff(&manager, true).is_err());
+        assert!(ShopService::ensure_can_manage_staff(&access(Role::Owner, &[]), true).is_ok());
+    }
+
+    #[test]
+    fn shop_settings_are_owner_only() {
+        assert!(ShopService::ensure_owner(&access(Role::Owner, &[])).is_ok());
+        assert!(ShopService::ensure_owner(&access(Role::Admin, &[])).is_err());
+    }
+}
@@ -11,3 +11,20 @@ pub async fn resolve_shop_id(db: &sea_orm::DatabaseConnection, shop_ref: &str) -
         None => Ok(None),
     }
 }
+
+/// 解析店铺引用（数值ID或API Key）并返回 (shop_id, api_key)，供访客令牌校验使用。
+/// 使用 SQLx 运行时查询，避免 Sea-ORM 列映射问题。
+pub async fn resolve_shop_with_key(db: &crate::database::Database, shop_ref: &str) -> Result<Option<(i64, String)>> {
+    let row = if let Ok(id) = shop_ref.parse::<i64>() {
+        sqlx::query_as::<_, (i64, String)>("SELECT id, api_key FROM shops WHERE id = ? LIMIT 1")
+            .bind(id)
+            .fetch_optional(db.pool())
+            .await?
+    } else {
+        sqlx::query_as::<_, (i64, String)>("SELECT id, api_key FROM shops WHERE api_key = ? LIMIT 1")
+            .bind(shop_ref)
+            .fetch_optional(db.pool())
+            .await?
+    };
+    Ok(row)
+}
@@ -1,13 +1,18 @@
 // Purpose: 店铺员工管理服务层
-// Input: shop_id, requester(user_id), payload(username)
-// Output: 列表 (id/username/email/phone/avatar_url/role) 或 Ok(())
+// Input: shop_id, 操作者 ShopAccess, payload(username / role / permissions)
+// Output: 列表 (id/username/email/phone/avatar_url/role/capabilities) 或 Ok(())
 // Errors: 权限不足/用户不存在/内部错误
+// Note: shop_staffs 实际表结构与 Sea-ORM 实体不一致（无 is_active / joined_at 等列），这里统一使用 SQLx 运行时查询
 
 use crate::{
     database::Database,
     error::AppError,
+    services::{
+        auth_session,
+        permissions::{parse_overrides, Capability, CapabilityOverrides, Role, ShopAccess},
+    },
 };
-use tracing::error;
+use tracing::{error, info};
 
 #[derive(serde::Serialize, Clone, Debug)]
 pub struct StaffItem {
@@ -17,84 +22,278 @@ pub struct StaffItem {
     pub phone: Option<String>,
     pub avatar_url: Option<String>,
     pub role: String,
+    /// 生效的能力（角色默认 + 按人覆盖）
+    pub capabilities: Vec<Capability>,
+    /// 按人覆盖（true 授予 / false 收回）
+    pub permissions: CapabilityOverrides,
 }
 
-pub async fn list_staff(db: &sea_orm::DatabaseConnection, requester_id: i64, shop_id: i64) -> Result<Vec<StaffItem>, AppError> {
-    let is_member = crate::repositories::ShopStaffRepository::is_shop_member(db, shop_id, requester_id)
-        .await
-        .map_err(|_| AppError::Internal("check_membership_failed".to_string()))?;
-    if !is_member {
-        return Err(AppError::Unauthorized);
+type StaffRow = (i64, String, Option<String>, Option<String>, Option<String>, String, Option<String>);
+
+const STAFF_COLUMNS: &str =
+    "u.id, u.username, u.email, u.phone, u.avatar_url, ss.role, ss.permissions FROM shop_staffs ss JOIN users u ON u.id = ss.user_id";
+
+fn staff_item(shop_id: i64, row: StaffRow) -> StaffItem {
+    let (id, username, email, phone, avatar_url, role, permissions) = row;
+    let role = Role::from_staff_role(&role);
+    let permissions = parse_overrides(permissions.as_deref());
+    let access = ShopAccess::with_overrides(id, shop_id, role, &permissions);
+    StaffItem {
+        id,
+        username,
+        email,
+        phone,
+        avatar_url,
+        role: role.as_str().to_string(),
+        capabilities: access.capabilities,
+        permissions,
     }
+}
 
-    let items = crate::repositories::ShopStaffRepository::list_shop_staff(db, shop_id)
-        .await
-        .map_err(|_| AppError::Internal("list_staff_failed".to_string()))?
-        .into_iter()
-        .map(|(u, role)| StaffItem {
-            id: u.id as i64,
-            username: u.username,
-            email: u.email,
-            phone: u.phone,
-            avatar_url: u.avatar_url,
-            role,
-        })
-        .collect();
+fn db_error(code: &'static str) -> impl Fn(sqlx::Error) -> AppError {
+    move |e| {
+        error!(target: "staff", "{}: {}", code, e);
+        AppError::Internal(code.to_string())
+    }
+}
 
+/// 店主在前，员工按加入顺序（成员身份由 handler 校验）
+pub async fn list_staff(db: &Database, shop_id: i64) -> Result<Vec<StaffItem>, AppError> {
+    let mut items = Vec::new();
+
+    let owner = sqlx::query_as::<_, (i64, String, Option<String>, Option<String>, Option<String>)>(
+        "SELECT u.id, u.username, u.email, u.phone, u.avatar_url FROM shops s JOIN users u ON u.id = s.owner_id WHERE s.id = ?",
+    )
+    .bind(shop_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(db_error("list_staff_failed"))?;
+    if let Some((id, username, email, phone, avatar_url)) = owner {
+        items.push(StaffItem {
+            id,
+            username,
+            email,
+            phone,
+            avatar_url,
+            role: Role::Owner.as_str().to_string(),
+            capabilities: Capability::ALL.to_vec(),
+            permissions: CapabilityOverrides::new(),
+        });
+    }
+
+    let rows = sqlx::query_as::<_, StaffRow>(&format!("SELECT {STAFF_COLUMNS} WHERE ss.shop_id = ? ORDER BY ss.id"))
+        .bind(shop_id)
+        .fetch_all(db.pool())
+        .await
+        .map_err(db_error("list_staff_failed"))?;
+    items.extend(rows.into_iter().map(|row| staff_item(shop_id, row)));
     Ok(items)
 }
 
-pub async fn add_staff(db: &sea_orm::DatabaseConnection, requester_id: i64, shop_id: i64, username: &str) -> Result<(), AppError> {
-    error!(target: "staff", "add_staff called: requester_id={}, shop_id={}, username={}", requester_id, shop_id, username);
-    
-    let is_owner = crate::repositories::ShopStaffRepository::is_shop_owner(db, shop_id, requester_id)
-        .await
-        .map_err(|_| AppError::Internal("check_owner_failed".to_string()))?;
-    if !is_owner {
-        error!(target: "staff", "add_staff: requester {} is not owner of shop {}", requester_id, shop_id);
-        return Err(AppError::Unauthorized);
-    }
-    if username.trim().is_empty() {
-        error!(target: "staff", "add_staff: username is empty");
+/// 添加员工
+///
+/// 业务逻辑：
+/// 1. 操作者需要 manage_staff（handler 校验）；只有店主可以直接添加 admin
+/// 2. 角色默认 agent
+/// 3. 用户必须存在，且不是店主或已有成员
+pub async fn add_staff(db: &Database, access: &ShopAccess, username: &str, role: Option<&str>) -> Result<StaffItem, AppError> {
+    let username = username.trim();
+    if username.is_empty() {
         return Err(AppError::BadRequest("username_required".to_string()));
     }
+    let role = match role {
+        Some(raw) => parse_assignable_role(raw)?,
+        None => Role::Agent,
+    };
+    if role == Role::Admin && !access.is_owner() {
+        return Err(AppError::Forbidden);
+    }
+
+    let user_id = sqlx::query_scalar::<_, i64>("SELECT id FROM users WHERE username = ?")
+        .bind(username)
+        .fetch_optional(db.pool())
+        .await
+        .map_err(db_error("add_staff_failed"))?
+        .ok_or_else(|| AppError::BadRequest("user_not_found".to_string()))?;
+    if super::permissions::load_access(db, access.shop_id, user_id).await?.is_some() {
+        return Err(AppError::BadRequest("already_member".to_string()));
+    }
 
-    crate::repositories::ShopStaffRepository::add_shop_staff_by_username(db, shop_id, username.trim(), Some("staff"))
+    sqlx::query("INSERT INTO shop_staffs (shop_id, user_id, role) VALUES (?, ?, ?)")
+        .bind(access.shop_id)
+        .bind(user_id)
+        .bind(role.as_str())
+        .execute(db.pool())
         .await
         .map_err(|e| {
-            let msg = e.to_string();
-            error!(target: "staff", "add_staff failed: {}", msg);
-            if msg.contains("user_not_found") {
-                AppError::BadRequest("user_not_found".to_string())
-            } else if msg.contains("already_member") {
-                AppError::BadRequest("already_member".to_string())
-            } else if msg.to_lowercase().contains("unique") {
+            error!(target: "staff", "add_staff failed: {}", e);
+            if e.to_string().to_lowercase().contains("unique") {
                 AppError::BadRequest("already_member".to_string())
-            } else if msg.to_lowercase().contains("foreign key") {
-                AppError::BadRequest("invalid_shop_or_user".to_string())
             } else {
-                // 默认作为请求问题处理，避免 500 影响用户体验
                 AppError::BadRequest("add_staff_failed".to_string())
             }
-        })
+        })?;
+    find_staff(db, access.shop_id, user_id).await?.ok_or(AppError::NotFound)
 }
 
-pub async fn remove_staff(db: &sea_orm::DatabaseConnection, requester_id: i64, shop_id: i64, user_id: i64) -> Result<(), AppError> {
-    let is_owner = crate::repositories::ShopStaffRepository::is_shop_owner(db, shop_id, requester_id)
+/// 移除员工：不能移除店主；admin 只能由店主移除
+pub async fn remove_staff(db: &Database, access: &ShopAccess, user_id: i64) -> Result<(), AppError> {
+    let target = load_target(db, access, user_id).await?;
+    if target.role == Role::Admin.as_str() && !access.is_owner() {
+        return Err(AppError::Forbidden);
+    }
+    sqlx::query("DELETE FROM shop_staffs WHERE shop_id = ? AND user_id = ?")
+        .bind(access.shop_id)
+        .bind(user_id)
+        .execute(db.pool())
         .await
-        .map_err(|_| AppError::Internal("check_owner_failed".to_string()))?;
-    if !is_owner {
-        return Err(AppError::Unauthorized);
+        .map_err(db_error("remove_staff_failed"))?;
+    Ok(())
+}
+
+/// 吊销员工的全部登录会话（如员工离职），返回吊销数量
+///
+/// 业务逻辑：
+/// 1. 操作者需要 manage_staff（handler 校验）；不能吊销店主，admin 只能由店主吊销
+/// 2. 目标必须是本店员工，吊销的是该用户在所有店铺通用的登录会话
+/// 3. 断开 WebSocket 连接由 handler 负责
+pub async fn revoke_staff_sessions(db: &Database, access: &ShopAccess, user_id: i64) -> Result<u64, AppError> {
+    let target = load_target(db, access, user_id).await?;
+    if target.role == Role::Admin.as_str() && !access.is_owner() {
+        return Err(AppError::Forbidden);
+    }
+    let revoked = auth_session::revoke_all(db, user_id).await?;
+    info!(target: "staff", "店铺 {} 的 {} 吊销员工 {} 的全部登录会话: {}", access.shop_id, access.user_id, user_id, revoked);
+    Ok(revoked)
+}
+
+/// 调整员工角色与按人能力覆盖
+///
+/// 业务逻辑：
+/// 1. 操作者需要 manage_staff（handler 校验），且不能修改自己
+/// 2. 不能修改店主；涉及 admin（授予或修改现有 admin）只能由店主操作
+/// 3. permissions 为空时保留原有覆盖；非店主不能授予自己没有的能力（保留已有授予除外）
+pub async fn update_staff_role(
+    db: &Database,
+    access: &ShopAccess,
+    user_id: i64,
+    role: &str,
+    permissions: Option<CapabilityOverrides>,
+) -> Result<StaffItem, AppError> {
+    if user_id == access.user_id {
+        return Err(AppError::BadRequest("cannot_modify_self".to_string()));
     }
-    // 不允许删除店主
-    if crate::repositories::ShopStaffRepository::is_shop_owner(db, shop_id, user_id).await.unwrap_or(false) {
-        return Err(AppError::BadRequest("cannot_remove_owner".to_string()));
+    let role = parse_assignable_role(role)?;
+    let target = load_target(db, access, user_id).await?;
+    if (role == Role::Admin || target.role == Role::Admin.as_str()) && !access.is_owner() {
+        return Err(AppError::Forbidden);
     }
-    let affected = crate::repositories::ShopStaffRepository::remove_shop_staff(db, shop_id, user_id)
+
+    let permissions = match permissions {
+        Some(overrides) => {
+            ensure_grantable(access, &overrides, &target.permissions)?;
+            overrides
+        }
+        None => target.permissions,
+    };
+    let serialized = if permissions.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_string(&permissions).map_err(|e| AppError::Internal(e.to_string()))?)
+    };
+    sqlx::query("UPDATE shop_staffs SET role = ?, permissions = ? WHERE shop_id = ? AND user_id = ?")
+        .bind(role.as_str())
+        .bind(serialized)
+        .bind(access.shop_id)
+        .bind(user_id)
+        .execute(db.pool())
+        .await
+        .map_err(db_error("update_staff_role_failed"))?;
+    find_staff(db, access.shop_id, user_id).await?.ok_or(AppError::NotFound)
+}
+
+async fn find_staff(db: &Database, shop_id: i64, user_id: i64) -> Result<Option<StaffItem>, AppError> {
+    let row = sqlx::query_as::<_, StaffRow>(&format!("SELECT {STAFF_COLUMNS} WHERE ss.shop_id = ? AND ss.user_id = ?"))
+        .bind(shop_id)
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await
+        .map_err(db_error("find_staff_failed"))?;
+    Ok(row.map(|row| staff_item(shop_id, row)))
+}
+
+/// 被操作的员工：店主返回 400，非成员返回 404
+async fn load_target(db: &Database, access: &ShopAccess, user_id: i64) -> Result<StaffItem, AppError> {
+    if super::permissions::is_shop_owner_sqlx(db, access.shop_id, user_id)
         .await
-        .map_err(|_| AppError::Internal("remove_staff_failed".to_string()))?;
-    if affected == 0 {
-        return Err(AppError::NotFound);
+        .map_err(|_| AppError::Internal("check_owner_failed".to_string()))?
+    {
+        return Err(AppError::BadRequest("cannot_modify_owner".to_string()));
+    }
+    find_staff(db, access.shop_id, user_id).await?.ok_or(AppError::NotFound)
+}
+
+/// 按人覆盖只能授予操作者自己拥有的能力，避免借同事账号提权；店主不受限，目标已有的授予可以保留
+fn ensure_grantable(access: &ShopAccess, requested: &CapabilityOverrides, existing: &CapabilityOverrides) -> Result<(), AppError> {
+    if access.is_owner() {
+        return Ok(());
+    }
+    let escalates = requested
+        .iter()
+        .any(|(cap, granted)| *granted && !access.can(*cap) && existing.get(cap) != Some(&true));
+    if escalates {
+        return Err(AppError::Forbidden);
     }
     Ok(())
 }
+
+/// 可分配的角色（owner 不可分配）
+fn parse_assignable_role(raw: &str) -> Result<Role, AppError> {
+    match raw.trim().to_lowercase().as_str() {
+        "admin" => Ok(Role::Admin),
+        "agent" | "staff" => Ok(Role::Agent),
+        "viewer" => Ok(Role::Viewer),
+        _ => Err(AppError::BadRequest("invalid_role".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides(entries: &[(Capability, bool)]) -> CapabilityOverrides {
+        entries.iter().copied().collect()
+    }
+
+    #[test]
+    fn manager_cannot_grant_capabilities_they_lack() {
+        // 持有 manage_staff 的 agent：没有 export / delete_messages
+        let manager = ShopAccess::with_overrides(2, 1, Role::Agent, &overrides(&[(Capability::ManageStaff, true)]));
+        let none = CapabilityOverrides::new();
+
+        for cap in [Capability::Export, Capability::DeleteMessages, Capability::ManageSettings] {
+            assert!(matches!(ensure_grantable(&manager, &overrides(&[(cap, true)]), &none), Err(AppError::Forbidden)));
+        }
+        // 自己拥有的能力可以授予，任何能力都可以收回
+        assert!(ensure_grantable(&manager, &overrides(&[(Capability::SendMessages, true)]), &none).is_ok());
+        assert!(ensure_grantable(&manager, &overrides(&[(Capability::Export, false)]), &none).is_ok());
+        // 目标已有的授予（如店主授予的）可以原样保留
+        let existing = overrides(&[(Capability::Export, true)]);
+        assert!(ensure_grantable(&manager, &existing, &existing).is_ok());
+    }
+
+    #[test]
+    fn owner_and_admin_grants() {
+        let owner = ShopAccess::with_overrides(1, 1, Role::Owner, &CapabilityOverrides::new());
+        let all: CapabilityOverrides = Capability::ALL.into_iter().map(|cap| (cap, true)).collect();
+        assert!(ensure_grantable(&owner, &all, &CapabilityOverrides::new()).is_ok());
+
+        // admin 缺少被收回的能力时同样不能授予
+        let admin = ShopAccess::with_overrides(3, 1, Role::Admin, &overrides(&[(Capability::Export, false)]));
+        assert!(ensure_grantable(&admin, &overrides(&[(Capability::DeleteMessages, true)]), &CapabilityOverrides::new()).is_ok());
+        assert!(matches!(
+            ensure_grantable(&admin, &overrides(&[(Capability::Export, true)]), &CapabilityOverrides::new()),
+            Err(AppError::Forbidden)
+        ));
+    }
+}
@@ -0,0 +1,539 @@
+//! 双因素认证（TOTP，RFC 6238）
+//!
+//! 职责：
+//! - 绑定：生成密钥与 otpauth:// 配置链接（扫码或手动输入），首次验证通过后才启用，并生成一次性恢复码
+//! - 登录二次验证：密码通过后签发短期挑战令牌，再凭验证码或恢复码换取登录会话
+//! - 验证码只能使用一次（记录最近使用的时间步），恢复码只保存 SHA-256 且用后作废
+//! - 店铺可要求全部员工启用双因素认证，未启用的员工在该店铺内没有任何能力
+//!
+//! TOTP 校验需要原始密钥，因此 user_totp.secret 以 Base32 明文保存
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use serde::Serialize;
+use sha1::Sha1;
+use tracing::{error, info};
+
+use crate::{
+    auth::jwt_keyring,
+    database::Database,
+    error::AppError,
+    jwt::{decode_token, encode_token, Claims, Keyring},
+    services::auth_session::hash_token,
+};
+
+/// 时间步长（秒）与验证码位数，与主流验证器 App 默认值一致
+const PERIOD_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+/// 允许前后各一个时间步的时钟偏差
+const SKEW_STEPS: i64 = 1;
+/// 密钥长度（字节），RFC 4226 推荐 160 位
+const SECRET_LEN: usize = 20;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// 每个恢复码的随机字节数（80 位，Base32 后 16 个字符）
+const RECOVERY_CODE_LEN: usize = 10;
+/// 密码验证通过后完成二次验证的时限
+pub const CHALLENGE_TTL_SECS: i64 = 300;
+
+/// 员工是否满足店铺的双因素要求：店铺未要求，或该用户已启用（参数依次为 user_id、shop_id）
+const STAFF_COMPLIANT_SQL: &str = "SELECT (s.require_2fa = 0 OR EXISTS (SELECT 1 FROM user_totp t WHERE t.user_id = ? AND t.enabled_at IS NOT NULL)) \
+     FROM shops s WHERE s.id = ?";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    /// 已生成密钥但尚未验证启用
+    pub pending: bool,
+    pub recovery_codes_remaining: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Enrollment {
+    /// Base32 密钥，供无法扫码时手动输入
+    pub secret: String,
+    /// otpauth:// 配置链接，可渲染为二维码，移动端也可直接打开
+    pub otpauth_uri: String,
+}
+
+fn db_error(code: &'static str) -> impl Fn(sqlx::Error) -> AppError {
+    move |e| {
+        error!(target: "two_factor", "{}: {}", code, e);
+        AppError::Internal(code.to_string())
+    }
+}
+
+// ========== TOTP ==========
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// RFC 4648 Base32（无填充）
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in encoded.chars().filter(|c| *c != '=' && !c.is_whitespace()) {
+        let value = BASE32_ALPHABET.iter().position(|&a| a as char == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// RFC 4226 HOTP
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC 接受任意长度的密钥");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([digest[offset] & 0x7f, digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// 在允许的时钟偏差内查找与验证码匹配、且晚于 last_used_step 的时间步（now 为 Unix 秒）
+fn matching_step(secret: &[u8], code: u32, last_used_step: i64, now: i64) -> Option<i64> {
+    let current = now / PERIOD_SECS;
+    (current - SKEW_STEPS..=current + SKEW_STEPS)
+        .filter(|step| *step > last_used_step)
+        .find(|step| hotp(secret, *step as u64) == code)
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn new_secret() -> String {
+    base32_encode(&random_bytes::<SECRET_LEN>())
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// 验证器 App 中显示的签发方，可通过 TOTP_ISSUER 覆盖
+fn issuer() -> String {
+    std::env::var("TOTP_ISSUER")
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "客服系统".to_string())
+}
+
+fn otpauth_uri(username: &str, secret: &str) -> String {
+    let issuer = percent_encode(&issuer());
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={PERIOD_SECS}",
+        account = percent_encode(username),
+    )
+}
+
+// ========== 恢复码 ==========
+
+/// 去掉空白与连字符、统一小写后再比较
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// 生成一个恢复码（xxxx-xxxx-xxxx-xxxx，小写 Base32）
+fn new_recovery_code() -> String {
+    let raw = base32_encode(&random_bytes::<RECOVERY_CODE_LEN>()).to_lowercase();
+    raw.as_bytes()
+        .chunks(4)
+        .map(|chunk| std::str::from_utf8(chunk).expect("Base32 为 ASCII"))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// 生成新的恢复码，替换该用户全部旧恢复码，返回明文（只展示一次）
+async fn replace_recovery_codes(db: &Database, user_id: i64) -> Result<Vec<String>, AppError> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| new_recovery_code()).collect();
+
+    let mut tx = db.pool().begin().await.map_err(db_error("recovery_codes_failed"))?;
+    sqlx::query("DELETE FROM user_recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error("recovery_codes_failed"))?;
+    for code in &codes {
+        sqlx::query("INSERT INTO user_recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(hash_token(&normalize_code(code)))
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error("recovery_codes_failed"))?;
+    }
+    tx.commit().await.map_err(db_error("recovery_codes_failed"))?;
+    Ok(codes)
+}
+
+// ========== 绑定 / 解绑 ==========
+
+pub async fn status(db: &Database, user_id: i64) -> Result<TwoFactorStatus, AppError> {
+    // None：未绑定；Some(false)：已生成密钥未启用；Some(true)：已启用
+    let enabled = sqlx::query_scalar::<_, bool>("SELECT enabled_at IS NOT NULL FROM user_totp WHERE user_id = ?")
+        .bind(user_id)
+        .fetch_optional(db.pool())
+        .await
+        .map_err(db_error("two_factor_lookup_failed"))?;
+    let recovery_codes_remaining = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM user_recovery_codes WHERE user_id = ? AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .fetch_one(db.pool())
+    .await
+    .map_err(db_error("two_factor_lookup_failed"))?;
+    Ok(TwoFactorStatus {
+        enabled: enabled == Some(true),
+        pending: enabled == Some(false),
+        recovery_codes_remaining,
+    })
+}
+
+pub async fn is_enabled(db: &Database, user_id: i64) -> Result<bool, AppError> {
+    Ok(status(db, user_id).await?.enabled)
+}
+
+/// 开始绑定
+///
+/// 业务逻辑：
+/// 1. 已启用时返回 two_factor_already_enabled（需先停用）
+/// 2. 生成新密钥覆盖未完成的绑定，启用前不影响登录
+pub async fn begin_enrollment(db: &Database, user_id: i64, username: &str) -> Result<Enrollment, AppError> {
+    if is_enabled(db, user_id).await? {
+        return Err(AppError::BadRequest("two_factor_already_enabled".to_string()));
+    }
+    let secret = new_secret();
+    sqlx::query(
+        "INSERT INTO user_totp (user_id, secret, enabled_at, last_used_step) VALUES (?, ?, NULL, 0) \
+         ON CONFLICT(user_id) DO UPDATE SET secret = excluded.secret, enabled_at = NULL, last_used_step = 0, created_at = CURRENT_TIMESTAMP",
+    )
+    .bind(user_id)
+    .bind(&secret)
+    .execute(db.pool())
+    .await
+    .map_err(db_error("two_factor_enroll_failed"))?;
+    Ok(Enrollment { otpauth_uri: otpauth_uri(username, &secret), secret })
+}
+
+/// 用验证器 App 生成的验证码确认绑定，成功后启用并返回恢复码
+pub async fn confirm_enrollment(db: &Database, user_id: i64, code: &str) -> Result<Vec<String>, AppError> {
+    let row = sqlx::query_as::<_, (String, bool, i64)>(
+        "SELECT secret, enabled_at IS NOT NULL, last_used_step FROM user_totp WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(db_error("two_factor_lookup_failed"))?;
+    let Some((secret, enabled, last_used_step)) = row else {
+        return Err(AppError::BadRequest("two_factor_not_enrolled".to_string()));
+    };
+    if enabled {
+        return Err(AppError::BadRequest("two_factor_already_enabled".to_string()));
+    }
+    let step = verify_totp_code(&secret, code, last_used_step)?;
+
+    let enabled = sqlx::query(
+        "UPDATE user_totp SET enabled_at = CURRENT_TIMESTAMP, last_used_step = ? WHERE user_id = ? AND enabled_at IS NULL AND secret = ?",
+    )
+    .bind(step)
+    .bind(user_id)
+    .bind(&secret)
+    .execute(db.pool())
+    .await
+    .map_err(db_error("two_factor_enable_failed"))?;
+    if enabled.rows_affected() == 0 {
+        return Err(AppError::BadRequest("two_factor_not_enrolled".to_string()));
+    }
+    info!(target: "two_factor", "用户 {} 启用双因素认证", user_id);
+    replace_recovery_codes(db, user_id).await
+}
+
+/// 停用：删除密钥与恢复码（调用方负责校验密码与验证码）
+pub async fn disable(db: &Database, user_id: i64) -> Result<(), AppError> {
+    let mut tx = db.pool().begin().await.map_err(db_error("two_factor_disable_failed"))?;
+    for sql in ["DELETE FROM user_totp WHERE user_id = ?", "DELETE FROM user_recovery_codes WHERE user_id = ?"] {
+        sqlx::query(sql)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error("two_factor_disable_failed"))?;
+    }
+    tx.commit().await.map_err(db_error("two_factor_disable_failed"))?;
+    info!(target: "two_factor", "用户 {} 停用双因素认证", user_id);
+    Ok(())
+}
+
+/// 重新生成恢复码（旧恢复码全部作废），要求已启用
+pub async fn regenerate_recovery_codes(db: &Database, user_id: i64) -> Result<Vec<String>, AppError> {
+    if !is_enabled(db, user_id).await? {
+        return Err(AppError::BadRequest("two_factor_not_enabled".to_string()));
+    }
+    replace_recovery_codes(db, user_id).await
+}
+
+// ========== 验证 ==========
+
+/// 校验 6 位验证码格式与取值，返回匹配的时间步
+fn verify_totp_code(secret: &str, code: &str, last_used_step: i64) -> Result<i64, AppError> {
+    let invalid = || AppError::BadRequest("invalid_two_factor_code".to_string());
+    let code = normalize_code(code);
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let secret = base32_decode(secret).ok_or_else(|| AppError::Internal("invalid_totp_secret".to_string()))?;
+    matching_step(&secret, code.parse().map_err(|_| invalid())?, last_used_step, Utc::now().timestamp()).ok_or_else(invalid)
+}
+
+/// 校验已启用用户的验证码或恢复码
+///
+/// 业务逻辑：
+/// 1. 6 位数字按 TOTP 校验，成功后记录时间步，同一验证码不能再次使用
+/// 2. 其他输入按恢复码校验，成功后该恢复码作废
+/// 3. 未启用双因素认证返回 two_factor_not_enabled，校验失败返回 invalid_two_factor_code
+pub async fn verify(db: &Database, user_id: i64, code: &str) -> Result<(), AppError> {
+    let invalid = || AppError::BadRequest("invalid_two_factor_code".to_string());
+    let row = sqlx::query_as::<_, (String, i64)>(
+        "SELECT secret, last_used_step FROM user_totp WHERE user_id = ? AND enabled_at IS NOT NULL",
+    )
+    .bind(user_id)
+    .fetch_optional(db.pool())
+    .await
+    .map_err(db_error("two_factor_lookup_failed"))?;
+    let Some((secret, last_used_step)) = row else {
+        return Err(AppError::BadRequest("two_factor_not_enabled".to_string()));
+    };
+
+    let normalized = normalize_code(code);
+    if normalized.len() == DIGITS as usize && normalized.bytes().all(|b| b.is_ascii_digit()) {
+        let step = verify_totp_code(&secret, &normalized, last_used_step)?;
+        // 条件更新：并发提交同一验证码时只有一个成功
+        let updated = sqlx::query("UPDATE user_totp SET last_used_step = ? WHERE user_id = ? AND last_used_step < ?")
+            .bind(step)
+            .bind(user_id)
+            .bind(step)
+            .execute(db.pool())
+            .await
+            .map_err(db_error("two_factor_verify_failed"))?;
+        return if updated.rows_affected() == 1 { Ok(()) } else { Err(invalid()) };
+    }
+
+    let used = sqlx::query(
+        "UPDATE user_recovery_codes SET used_at = CURRENT_TIMESTAMP WHERE user_id = ? AND code_hash = ? AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(hash_token(&normalized))
+    .execute(db.pool())
+    .await
+    .map_err(db_error("two_factor_verify_failed"))?;
+    if used.rows_affected() == 0 {
+        return Err(invalid());
+    }
+    info!(target: "two_factor", "用户 {} 使用恢复码完成验证", user_id);
+    Ok(())
+}
+
+// ========== 登录挑战 ==========
+
+fn challenge_keyring() -> Keyring {
+    jwt_keyring().derive(|base| {
+        let mut secret = base.to_vec();
+        secret.extend_from_slice(b":2fa-challenge");
+        secret
+    })
+}
+
+/// 密码验证通过后签发的挑战令牌（独立派生密钥，不能当作 access token 使用）
+pub fn issue_challenge(user_id: i64) -> Result<String, AppError> {
+    let claims = Claims {
+        sub: user_id.to_string(),
+        exp: (Utc::now().timestamp() + CHALLENGE_TTL_SECS) as usize,
+        jti: None,
+    };
+    encode_token(&claims, &challenge_keyring()).map_err(|e| {
+        error!(target: "two_factor", "挑战令牌生成失败: {}", e);
+        AppError::Internal("token_issue_failed".to_string())
+    })
+}
+
+/// 校验挑战令牌，返回用户 ID；无效或过期返回 401
+pub fn verify_challenge(token: &str) -> Result<i64, AppError> {
+    decode_token(token.trim(), &challenge_keyring())
+        .ok()
+        .and_then(|claims| claims.sub.parse().ok())
+        .ok_or(AppError::Unauthorized)
+}
+
+// ========== 店铺要求 ==========
+
+/// 员工是否满足店铺的双因素要求（店主由调用方排除）
+pub async fn staff_compliant(db: &Database, shop_id: i64, user_id: i64) -> Result<bool, AppError> {
+    let compliant = sqlx::query_scalar::<_, bool>(STAFF_COMPLIANT_SQL)
+        .bind(user_id)
+        .bind(shop_id)
+        .fetch_optional(db.pool())
+        .await
+        .map_err(db_error("two_factor_lookup_failed"))?;
+    Ok(compliant.unwrap_or(true))
+}
+
+pub async fn shop_requires(db: &Database, shop_id: i64) -> Result<bool, AppError> {
+    let required = sqlx::query_scalar::<_, bool>("SELECT require_2fa FROM shops WHERE id = ?")
+        .bind(shop_id)
+        .fetch_optional(db.pool())
+        .await
+        .map_err(db_error("two_factor_lookup_failed"))?;
+    required.ok_or(AppError::NotFound)
+}
+
+/// 设置店铺是否要求员工启用双因素认证
+///
+/// 业务逻辑：
+/// 1. 仅店主可操作（handler 校验）
+/// 2. 开启前店主自己必须已启用，避免店主账号成为薄弱环节
+pub async fn set_shop_requirement(db: &Database, shop_id: i64, owner_id: i64, required: bool) -> Result<(), AppError> {
+    if required && !is_enabled(db, owner_id).await? {
+        return Err(AppError::BadRequest("owner_two_factor_not_enabled".to_string()));
+    }
+    sqlx::query("UPDATE shops SET require_2fa = ? WHERE id = ?")
+        .bind(required)
+        .bind(shop_id)
+        .execute(db.pool())
+        .await
+        .map_err(db_error("update_shop_security_failed"))?;
+    info!(target: "two_factor", "店铺 {} 双因素认证要求: {}", shop_id, required);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// RFC 4226 / RFC 6238 测试密钥（SHA-1）
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn base32_matches_rfc4648() {
+        for (plain, encoded) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(base32_encode(plain.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).unwrap(), plain.as_bytes());
+        }
+        // 兼容填充、空格与小写
+        assert_eq!(base32_decode("mzxw 6ytb oi======").unwrap(), b"foobar");
+        assert!(base32_decode("MZXW1").is_none());
+        assert_eq!(base32_decode(&base32_encode(RFC_SECRET)).unwrap(), RFC_SECRET);
+    }
+
+    #[test]
+    fn hotp_matches_rfc4226_appendix_d() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64), code, "counter {counter}");
+        }
+    }
+
+    #[test]
+    fn totp_matches_rfc6238_appendix_b() {
+        // 附录 B 为 8 位验证码，取后 6 位即本系统的 6 位验证码
+        for (time, code) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ] {
+            assert_eq!(matching_step(RFC_SECRET, code % 1_000_000, 0, time), Some(time / PERIOD_SECS), "T={time}");
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_clock_skew() {
+        let now = 1_234_567_890;
+        let current = now / PERIOD_SECS;
+        for step in [current - 1, current, current + 1] {
+            assert_eq!(matching_step(RFC_SECRET, hotp(RFC_SECRET, step as u64), 0, now), Some(step));
+        }
+        for step in [current - 2, current + 2] {
+            assert_eq!(matching_step(RFC_SECRET, hotp(RFC_SECRET, step as u64), 0, now), None);
+        }
+    }
+
+    #[test]
+    fn rejects_replayed_steps() {
+        let now = 1_234_567_890;
+        let current = now / PERIOD_SECS;
+        let code = hotp(RFC_SECRET, current as u64);
+        assert_eq!(matching_step(RFC_SECRET, code, current - 1, now), Some(current));
+        // 该时间步已使用过，同一验证码（以及更早的验证码）不能再次通过
+        assert_eq!(matching_step(RFC_SECRET, code, current, now), None);
+        assert_eq!(matching_step(RFC_SECRET, hotp(RFC_SECRET, (current - 1) as u64), current, now), None);
+        assert_eq!(
+            matching_step(RFC_SECRET, hotp(RFC_SECRET, (current + 1) as u64), current, now),
+            Some(current + 1)
+        );
+    }
+
+    #[test]
+    fn verify_code_checks_format() {
+        let secret = base32_encode(RFC_SECRET);
+        assert!(verify_totp_code(&secret, "12345", 0).is_err());
+        assert!(verify_totp_code(&secret, "12a456", 0).is_err());
+        let now = Utc::now().timestamp();
+        let code = format!("{:06}", hotp(RFC_SECRET, (now / PERIOD_SECS) as u64));
+        let spaced = format!("{} {}", &code[..3], &code[3..]);
+        assert!(verify_totp_code(&secret, &spaced, 0).is_ok());
+    }
+
+    #[test]
+    fn generated_secrets_and_recovery_codes_are_random() {
+        let secret = new_secret();
+        assert_eq!(base32_decode(&secret).unwrap().len(), SECRET_LEN);
+        assert_ne!(secret, new_secret());
+
+        let code = new_recovery_code();
+        assert_eq!(code.len(), 19);
+        assert_eq!(code.matches('-').count(), 3);
+        let normalized = normalize_code(&code.to_uppercase());
+        assert_eq!(base32_decode(&normalized).unwrap().len(), RECOVERY_CODE_LEN);
+        assert_ne!(code, new_recovery_code());
+    }
+}
@@ -0,0 +1,46 @@
+//! 访客身份令牌（店铺维度 HMAC）
+//!
+//! 职责：
+//! - 首次 AUTH 时为访客签发令牌，之后 WebSocket 连接与文件上传都需携带
+//! - 签名密钥 = 服务端 JWT 密钥 + 店铺 api_key，单独泄露任意一方都无法伪造；随 JWT 密钥环一起轮换
+//! - sub 绑定 `shop_id:customer_code`，令牌不能跨店铺或跨访客复用
+
+use crate::auth::jwt_keyring;
+use crate::jwt::{decode_token, encode_token, Claims, JwtError, Keyring};
+
+/// 访客令牌有效期：30 天
+pub const VISITOR_TOKEN_TTL_SECS: i64 = 30 * 24 * 3600;
+
+fn visitor_keyring(api_key: &str) -> Keyring {
+    jwt_keyring().derive(|base| {
+        let mut secret = base.to_vec();
+        secret.push(b':');
+        secret.extend_from_slice(api_key.as_bytes());
+        secret
+    })
+}
+
+fn visitor_subject(shop_id: i64, customer_code: &str) -> String {
+    format!("{}:{}", shop_id, customer_code)
+}
+
+/// 为指定店铺下的访客签发令牌
+pub fn issue_visitor_token(shop_id: i64, api_key: &str, customer_code: &str) -> Result<String, JwtError> {
+    let claims = Claims {
+        sub: visitor_subject(shop_id, customer_code),
+        exp: (chrono::Utc::now().timestamp() + VISITOR_TOKEN_TTL_SECS) as usize,
+        jti: None,
+    };
+    encode_token(&claims, &visitor_keyring(api_key))
+}
+
+/// 校验令牌签名、有效期以及是否属于该店铺下的该访客
+pub fn verify_visitor_token(token: &str, shop_id: i64, api_key: &str, customer_code: &str) -> bool {
+    match decode_token(token, &visitor_keyring(api_key)) {
+        Ok(claims) => claims.sub == visitor_subject(shop_id, customer_code),
+        Err(e) => {
+            tracing::warn!("访客令牌校验失败: {:?}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,472 @@
+// Purpose: 跨节点 WebSocket 消息扇出（Broadcaster 抽象 + 本地/ SQLite outbox 两种实现）
+// Input: 本节点已完成本地投递的目标（BroadcastTarget）与事件（消息文本或关闭连接）
+// Output: LocalBroadcaster 不做任何事；SqliteOutboxBroadcaster 写入共享 ws_outbox 表，
+//         其他节点轮询该表并投递给自己持有的连接
+// Errors: 写入/轮询失败仅记录日志，不影响本地投递
+use std::{
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use axum::extract::ws::Message;
+use sqlx::SqlitePool;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use super::manager::ConnectionManager;
+
+/// 单节点轮询时每批读取的最大行数
+const POLL_BATCH_SIZE: i64 = 500;
+
+const OUTBOX_SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS ws_outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    origin_node TEXT NOT NULL,
+    target_kind TEXT NOT NULL,
+    shop_id INTEGER,
+    customer_code TEXT,
+    user_id INTEGER,
+    payload TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+)"#;
+
+/// 本进程的节点标识：WS_NODE_ID，未设置时随机生成（outbox 与在线状态连接明细共用）
+pub fn node_id() -> &'static str {
+    static NODE_ID: OnceLock<String> = OnceLock::new();
+    NODE_ID.get_or_init(|| std::env::var("WS_NODE_ID").unwrap_or_else(|_| Uuid::new_v4().to_string()))
+}
+
+/// 消息投递目标，与 ConnectionManager 的三种发送方式一一对应
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastTarget {
+    Customer { shop_id: i64, customer_code: String },
+    ShopStaff { shop_id: i64 },
+    /// 店铺内能查看该会话的客服；staff_id 为会话当前分配的客服
+    SessionStaff { shop_id: i64, staff_id: Option<i64> },
+    StaffUser { user_id: i64 },
+    /// 客服在某个店铺内的连接（被移出店铺、角色变更）
+    ShopStaffUser { shop_id: i64, user_id: i64 },
+    /// 客服以某个登录会话（jti）建立的连接
+    StaffSession { user_id: i64, jti: String },
+}
+
+/// 跨节点转发的事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BroadcastEvent {
+    /// 序列化后的 WebSocketMessage
+    Text(String),
+    /// 关闭目标连接（拉黑客户、吊销客服登录会话、员工被移出店铺或角色变更）
+    Close,
+}
+
+/// outbox 中关闭事件的 target_kind 前缀，payload 留空
+const CLOSE_KIND_PREFIX: &str = "close:";
+
+impl BroadcastEvent {
+    fn into_message(self) -> Message {
+        match self {
+            BroadcastEvent::Text(payload) => Message::Text(payload),
+            BroadcastEvent::Close => Message::Close(None),
+        }
+    }
+}
+
+impl BroadcastTarget {
+    fn kind(&self) -> &'static str {
+        match self {
+            BroadcastTarget::Customer { .. } => "customer",
+            BroadcastTarget::ShopStaff { .. } => "shop_staff",
+            BroadcastTarget::SessionStaff { .. } => "session_staff",
+            BroadcastTarget::StaffUser { .. } => "staff_user",
+            BroadcastTarget::ShopStaffUser { .. } => "shop_staff_user",
+            BroadcastTarget::StaffSession { .. } => "staff_session",
+        }
+    }
+
+    /// outbox 行的 target_kind：关闭事件加 close: 前缀
+    fn outbox_kind(&self, event: &BroadcastEvent) -> String {
+        match event {
+            BroadcastEvent::Text(_) => self.kind().to_string(),
+            BroadcastEvent::Close => format!("{CLOSE_KIND_PREFIX}{}", self.kind()),
+        }
+    }
+
+    fn from_row(kind: &str, shop_id: Option<i64>, customer_code: Option<String>, user_id: Option<i64>) -> Option<Self> {
+        match kind {
+            "customer" => Some(BroadcastTarget::Customer {
+                shop_id: shop_id?,
+                customer_code: customer_code?,
+            }),
+            "shop_staff" => Some(BroadcastTarget::ShopStaff { shop_id: shop_id? }),
+            // 会话分配的客服存放在 user_id 列
+            "session_staff" => Some(BroadcastTarget::SessionStaff { shop_id: shop_id?, staff_id: user_id }),
+            "staff_user" => Some(BroadcastTarget::StaffUser { user_id: user_id? }),
+            "shop_staff_user" => Some(BroadcastTarget::ShopStaffUser {
+                shop_id: shop_id?,
+                user_id: user_id?,
+            }),
+            // 登录会话的 jti 存放在 customer_code 列
+            "staff_session" => Some(BroadcastTarget::StaffSession {
+                user_id: user_id?,
+                jti: customer_code?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// outbox 行还原为投递目标与事件
+fn decode_row(
+    kind: &str,
+    shop_id: Option<i64>,
+    customer_code: Option<String>,
+    user_id: Option<i64>,
+    payload: String,
+) -> Option<(BroadcastTarget, BroadcastEvent)> {
+    match kind.strip_prefix(CLOSE_KIND_PREFIX) {
+        Some(kind) => Some((BroadcastTarget::from_row(kind, shop_id, customer_code, user_id)?, BroadcastEvent::Close)),
+        None => Some((BroadcastTarget::from_row(kind, shop_id, customer_code, user_id)?, BroadcastEvent::Text(payload))),
+    }
+}
+
+/// 跨节点广播器：ConnectionManager 完成本地投递后调用 publish，把事件交给其他节点
+pub trait Broadcaster: Send + Sync + std::fmt::Debug {
+    fn publish(&self, target: &BroadcastTarget, event: &BroadcastEvent);
+}
+
+/// 默认实现：单节点部署，本地投递即全部
+#[derive(Debug, Default)]
+pub struct LocalBroadcaster;
+
+impl Broadcaster for LocalBroadcaster {
+    fn publish(&self, _target: &BroadcastTarget, _event: &BroadcastEvent) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxConfig {
+    /// 节点标识，用于跳过自己写入的消息
+    pub node_id: String,
+    pub poll_interval: Duration,
+    /// outbox 行保留时长（秒），过期后由各节点清理
+    pub retention_secs: i64,
+}
+
+impl OutboxConfig {
+    /// 从环境变量读取：WS_NODE_ID（默认随机）、WS_OUTBOX_POLL_MS（默认 200）
+    pub fn from_env() -> Self {
+        let node_id = node_id().to_string();
+        let poll_ms = std::env::var("WS_OUTBOX_POLL_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(200);
+        Self {
+            node_id,
+            poll_interval: Duration::from_millis(poll_ms),
+            retention_secs: 300,
+        }
+    }
+}
+
+/// 基于共享 SQLite outbox 表的多节点广播器
+#[derive(Debug)]
+pub struct SqliteOutboxBroadcaster {
+    node_id: String,
+    writer: mpsc::UnboundedSender<(BroadcastTarget, BroadcastEvent)>,
+}
+
+impl SqliteOutboxBroadcaster {
+    /// 建表、启动写入与轮询任务，并把自身安装到 ConnectionManager
+    pub async fn start(
+        pool: SqlitePool,
+        manager: Arc<Mutex<ConnectionManager>>,
+        config: OutboxConfig,
+    ) -> anyhow::Result<Arc<Self>> {
+        sqlx::query(OUTBOX_SCHEMA).execute(&pool).await?;
+
+        // 只投递启动之后产生的消息
+        let mut last_id: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM ws_outbox")
+            .fetch_one(&pool)
+            .await?;
+
+        let (writer, mut queue) = mpsc::unbounded_channel::<(BroadcastTarget, BroadcastEvent)>();
+
+        // 写入任务：保持发布顺序，串行写入
+        let write_pool = pool.clone();
+        let origin = config.node_id.clone();
+        tokio::spawn(async move {
+            while let Some((target, event)) = queue.recv().await {
+                let (shop_id, customer_code, user_id) = match &target {
+                    BroadcastTarget::Customer { shop_id, customer_code } => (Some(*shop_id), Some(customer_code.clone()), None),
+                    BroadcastTarget::ShopStaff { shop_id } => (Some(*shop_id), None, None),
+                    BroadcastTarget::SessionStaff { shop_id, staff_id } => (Some(*shop_id), None, *staff_id),
+                    BroadcastTarget::StaffUser { user_id } => (None, None, Some(*user_id)),
+                    BroadcastTarget::ShopStaffUser { shop_id, user_id } => (Some(*shop_id), None, Some(*user_id)),
+                    BroadcastTarget::StaffSession { user_id, jti } => (None, Some(jti.clone()), Some(*user_id)),
+                };
+                if let Err(e) = sqlx::query(
+                    "INSERT INTO ws_outbox (origin_node, target_kind, shop_id, customer_code, user_id, payload) VALUES (?, ?, ?, ?, ?, ?)",
+                )
+                .bind(&origin)
+                .bind(target.outbox_kind(&event))
+                .bind(shop_id)
+                .bind(customer_code)
+                .bind(user_id)
+                .bind(match event {
+                    BroadcastEvent::Text(payload) => payload,
+                    BroadcastEvent::Close => String::new(),
+                })
+                .execute(&write_pool)
+                .await
+                {
+                    tracing::warn!("写入 ws_outbox 失败: {:?}", e);
+                }
+            }
+        });
+
+        // 轮询任务：读取其他节点写入的消息并在本地投递
+        let node_id = config.node_id.clone();
+        let poll_manager = manager.clone();
+        let poll_interval = config.poll_interval;
+        let retention_secs = config.retention_secs;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(poll_interval);
+            let mut last_cleanup = std::time::Instant::now();
+            loop {
+                ticker.tick().await;
+
+                let rows = sqlx::query_as::<_, (i64, String, String, Option<i64>, Option<String>, Option<i64>, String)>(
+                    "SELECT id, origin_node, target_kind, shop_id, customer_code, user_id, payload FROM ws_outbox WHERE id > ? ORDER BY id LIMIT ?",
+                )
+                .bind(last_id)
+                .bind(POLL_BATCH_SIZE)
+                .fetch_all(&pool)
+                .await;
+
+                match rows {
+                    Ok(rows) => {
+                        for (id, origin_node, kind, shop_id, customer_code, user_id, payload) in rows {
+                            last_id = id;
+                            if origin_node == node_id {
+                                continue;
+                            }
+                            if let Some((target, event)) = decode_row(&kind, shop_id, customer_code, user_id, payload) {
+                                let manager = poll_manager.lock().unwrap();
+                                manager.deliver_local(&target, event.into_message());
+                            }
+                        }
+                    }
+                    Err(e) => tracing::warn!("轮询 ws_outbox 失败: {:?}", e),
+                }
+
+                if last_cleanup.elapsed().as_secs() as i64 >= retention_secs {
+                    last_cleanup = std::time::Instant::now();
+                    let cutoff = format!("-{} seconds", retention_secs);
+                    if let Err(e) = sqlx::query("DELETE FROM ws_outbox WHERE created_at < datetime('now', ?)")
+                        .bind(cutoff)
+                        .execute(&pool)
+                        .await
+                    {
+                        tracing::warn!("清理 ws_outbox 失败: {:?}", e);
+                    }
+                }
+            }
+        });
+
+        let broadcaster = Arc::new(Self {
+            node_id: config.node_id,
+            writer,
+        });
+        manager.lock().unwrap().set_broadcaster(broadcaster.clone());
+        tracing::info!("🌐 已启用 SQLite outbox 跨节点广播，节点: {}", broadcaster.node_id);
+        Ok(broadcaster)
+    }
+}
+
+impl Broadcaster for SqliteOutboxBroadcaster {
+    fn publish(&self, target: &BroadcastTarget, event: &BroadcastEvent) {
+        let _ = self.writer.send((target.clone(), event.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::WebSocketMessage;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use std::str::FromStr;
+
+    async fn node(path: &str, node_id: &str) -> Arc<Mutex<ConnectionManager>> {
+        let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path))
+            .unwrap()
+            .create_if_missing(true);
+        let pool = SqlitePoolOptions::new().max_connections(2).connect_with(options).await.unwrap();
+        let manager = Arc::new(Mutex::new(ConnectionManager::new()));
+        let config = OutboxConfig {
+            node_id: node_id.to_string(),
+            poll_interval: Duration::from_millis(20),
+            retention_secs: 300,
+        };
+        SqliteOutboxBroadcaster::start(pool, manager.clone(), config).await.unwrap();
+        manager
+    }
+
+    fn text_message(content: &str) -> WebSocketMessage {
+        WebSocketMessage {
+            message_type: crate::constants::ws_events::NEW_MESSAGE.to_string(),
+            content: Some(content.to_string()),
+            session_id: Some(1),
+            sender_id: None,
+            sender_type: Some("staff".to_string()),
+            timestamp: None,
+            metadata: None,
+            file_url: None,
+            file_name: None,
+            file_size: None,
+            media_duration: None,
+            message_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_fan_out_between_two_nodes() {
+        let path = std::env::temp_dir().join(format!("ws_outbox_{}.db", Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        let node_a = node(&path, "node-a").await;
+        let node_b = node(&path, "node-b").await;
+
+        // 客户连在 A，客服连在 B
+        let (customer_tx, mut customer_rx) = mpsc::unbounded_channel();
+        node_a.lock().unwrap().add_customer_connection(7, "visitor-1", customer_tx);
+        let (staff_tx, mut staff_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(42, 7, true, None, staff_tx);
+
+        node_b.lock().unwrap().send_to_customer(7, "visitor-1", &text_message("hello from staff"));
+        node_a.lock().unwrap().broadcast_to_staff(7, &text_message("hello from customer"));
+
+        let to_customer = tokio::time::timeout(Duration::from_secs(5), customer_rx.recv())
+            .await
+            .expect("customer on node A should receive staff reply")
+            .unwrap();
+        let to_staff = tokio::time::timeout(Duration::from_secs(5), staff_rx.recv())
+            .await
+            .expect("staff on node B should receive customer message")
+            .unwrap();
+
+        assert!(matches!(to_customer, Message::Text(ref t) if t.contains("hello from staff")));
+        assert!(matches!(to_staff, Message::Text(ref t) if t.contains("hello from customer")));
+
+        // 自己写入的消息不会被本节点重复投递
+        assert!(staff_rx.try_recv().is_err());
+        assert!(customer_rx.try_recv().is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn session_events_only_reach_staff_who_can_view_the_session() {
+        let path = std::env::temp_dir().join(format!("ws_outbox_{}.db", Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        let node_a = node(&path, "node-a").await;
+        let node_b = node(&path, "node-b").await;
+
+        // B 上：42 可查看全部会话，43、44 只能查看自己的会话
+        let (all_tx, mut all_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(42, 7, true, None, all_tx);
+        let (own_tx, mut own_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(43, 7, false, None, own_tx);
+        let (other_tx, mut other_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(44, 7, false, None, other_tx);
+
+        node_a.lock().unwrap().broadcast_to_session_staff(7, Some(43), &text_message("assigned to 43"));
+        node_a.lock().unwrap().broadcast_to_session_staff(7, None, &text_message("unassigned"));
+
+        for rx in [&mut all_rx, &mut own_rx] {
+            for expected in ["assigned to 43", "unassigned"] {
+                let got = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap();
+                assert!(matches!(got, Message::Text(ref t) if t.contains(expected)));
+            }
+        }
+        let got = tokio::time::timeout(Duration::from_secs(5), other_rx.recv()).await.unwrap().unwrap();
+        assert!(matches!(got, Message::Text(ref t) if t.contains("unassigned")));
+        assert!(other_rx.try_recv().is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn close_reaches_customer_on_other_node() {
+        let path = std::env::temp_dir().join(format!("ws_outbox_{}.db", Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        let node_a = node(&path, "node-a").await;
+        let node_b = node(&path, "node-b").await;
+
+        let (customer_tx, mut customer_rx) = mpsc::unbounded_channel();
+        node_a.lock().unwrap().add_customer_connection(7, "visitor-1", customer_tx);
+        let (other_tx, mut other_rx) = mpsc::unbounded_channel();
+        node_a.lock().unwrap().add_customer_connection(7, "visitor-2", other_tx);
+
+        // 在 B 上拉黑 A 上的客户
+        node_b.lock().unwrap().close_customer(7, "visitor-1");
+
+        let closed = tokio::time::timeout(Duration::from_secs(5), customer_rx.recv())
+            .await
+            .expect("customer on node A should be closed")
+            .unwrap();
+        assert!(matches!(closed, Message::Close(None)));
+        assert!(other_rx.try_recv().is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn staff_close_reaches_other_node() {
+        let path = std::env::temp_dir().join(format!("ws_outbox_{}.db", Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        let node_a = node(&path, "node-a").await;
+        let node_b = node(&path, "node-b").await;
+
+        // 42 在 B 上以两个登录会话各开一个连接，另一客服 43 不受影响
+        let (first_tx, mut first_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(42, 7, true, Some("jti-1"), first_tx);
+        let (second_tx, mut second_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(42, 8, true, Some("jti-2"), second_tx);
+        let (other_tx, mut other_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(43, 7, true, Some("jti-3"), other_tx);
+
+        // 在 A 上注销 jti-1：只关闭以该会话建立的连接
+        node_a.lock().unwrap().close_staff_session(42, "jti-1");
+        let closed = tokio::time::timeout(Duration::from_secs(5), first_rx.recv()).await.unwrap().unwrap();
+        assert!(matches!(closed, Message::Close(None)));
+        assert!(second_rx.try_recv().is_err());
+
+        // 在 A 上吊销 42 的全部会话
+        node_a.lock().unwrap().close_staff_user(42);
+        let closed = tokio::time::timeout(Duration::from_secs(5), second_rx.recv()).await.unwrap().unwrap();
+        assert!(matches!(closed, Message::Close(None)));
+        assert!(other_rx.try_recv().is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn shop_staff_close_only_affects_that_shop() {
+        let path = std::env::temp_dir().join(format!("ws_outbox_{}.db", Uuid::new_v4()));
+        let path = path.to_string_lossy().to_string();
+        let node_a = node(&path, "node-a").await;
+        let node_b = node(&path, "node-b").await;
+
+        // 42 在 B 上同时连着店铺 7 和 8
+        let (shop7_tx, mut shop7_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(42, 7, true, None, shop7_tx);
+        let (shop8_tx, mut shop8_rx) = mpsc::unbounded_channel();
+        node_b.lock().unwrap().add_staff_connection(42, 8, true, None, shop8_tx);
+
+        // 在 A 上把 42 移出店铺 7
+        node_a.lock().unwrap().close_shop_staff_user(7, 42);
+        let closed = tokio::time::timeout(Duration::from_secs(5), shop7_rx.recv()).await.unwrap().unwrap();
+        assert!(matches!(closed, Message::Close(None)));
+        assert!(shop8_rx.try_recv().is_err());
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
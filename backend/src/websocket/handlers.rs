@@ -1,7 +1,7 @@
 // Purpose: WebSocket 消息处理（客户/客服）与解析辅助函数
-// Input: WebSocketIncomingMessage、上下文 CustomerWsCtx（包含状态、发送通道、用户与会话缓存）
+// Input: WebSocketIncomingMessage、上下文 CustomerWsCtx / StaffWsCtx（包含状态、发送通道、用户与会话缓存、认证状态）
 // Output: 通过 UnboundedSender<Message> 发送序列化后的 WebSocketMessage 给对应连接；广播到 ConnectionManager
-// Errors: 解析失败（payload 无效/字段缺失）、数据库查询失败、持久化失败
+// Errors: 解析失败（payload 无效/字段缺失）、认证/店铺权限校验失败、数据库查询失败、持久化失败
 use anyhow::Result;
 use axum::extract::ws::Message;
 use chrono::Utc;
@@ -10,7 +10,13 @@ use tokio::sync::mpsc;
 
 use crate::{
     models::{Customer, Session, WebSocketIncomingMessage, WebSocketMessage},
-    services::chat::{ChatService, MessagePayload},
+    entities::sessions,
+    repositories::{MessageRepository, SessionRepository},
+    services::chat::{broadcast_message_change, ChatService, MessagePayload},
+    services::message_service::MessageAuthor,
+    services::auth_session,
+    services::permissions::{self, Capability, ShopAccess},
+    services::{canned_response, customer_service::BlockInfo, presence, queue},
     AppState,
 };
 
@@ -19,9 +25,25 @@ pub struct CustomerWsCtx<'a> {
     pub chat: &'a ChatService<'a>,
     pub shop_id: i64,
     pub customer_code: &'a str,
+    pub api_key: &'a str,
     pub outbound: &'a mpsc::UnboundedSender<Message>,
     pub customer: &'a mut Option<Customer>,
     pub session: &'a mut Option<Session>,
+    pub verified: &'a mut bool,
+    /// 本连接在在线状态连接明细中的标识（认证前连接尚未注册到 ConnectionManager）
+    pub presence_id: &'a str,
+}
+
+pub struct StaffWsCtx<'a> {
+    pub state: &'a AppState,
+    pub chat: &'a ChatService<'a>,
+    pub user_id: i64,
+    pub outbound: &'a mpsc::UnboundedSender<Message>,
+    pub connection_id: &'a mut Option<String>,
+    pub active_shop: &'a mut Option<i64>,
+    pub authenticated: &'a mut bool,
+    /// 本连接所用登录会话的 jti（握手或首帧 auth 时确定），心跳时据此重新校验
+    pub auth_jti: &'a mut Option<String>,
 }
 
 pub async fn handle_customer_ws_message(
@@ -43,6 +65,26 @@ pub async fn handle_customer_ws_message(
     
     let meta_ref = incoming.metadata.as_ref();
 
+    // 握手阶段未携带访客令牌：首帧必须是 auth，且只允许新访客（由服务端签发令牌）
+    if !*ctx.verified {
+        if incoming.message_type != crate::constants::ws_incoming::AUTH {
+            send_auth_failed(ctx.outbound, "unauthenticated");
+            anyhow::bail!("customer_unauthenticated");
+        }
+        if ctx.chat.customer_exists(ctx.shop_id, ctx.customer_code).await? {
+            send_auth_failed(ctx.outbound, "visitor_token_required");
+            anyhow::bail!("customer_visitor_token_required");
+        }
+    }
+
+    // 被拉黑的客户（含连接期间被拉黑）除心跳外的任何操作都会被拒绝并断开
+    if incoming.message_type != crate::constants::ws_incoming::PING {
+        if let Some(block) = ctx.state.customer_service.active_block(ctx.shop_id as i32, ctx.customer_code).await? {
+            send_blocked(ctx.outbound, &block);
+            return Err(CustomerBlocked.into());
+        }
+    }
+
     match incoming.message_type.as_str() {
         crate::constants::ws_incoming::PING => {
             // 简单心跳响应：仅回发 pong 给客户连接
@@ -58,19 +100,26 @@ pub async fn handle_customer_ws_message(
                 file_name: None,
                 file_size: None,
                 media_duration: None,
+                message_id: None,
             };
             if let Ok(payload) = serde_json::to_string(&pong) {
                 let _ = ctx.outbound.send(Message::Text(payload));
             }
+            if let Some(customer) = ctx.customer.as_ref() {
+                presence::heartbeat(ctx.state, presence::USER_TYPE_CUSTOMER, customer.id, ctx.shop_id).await;
+            }
         }
         crate::constants::ws_incoming::AUTH => {
             let (name, email, avatar, ip, user_agent) = extract_customer_profile(meta_ref);
+            let (tags, attributes) = extract_customer_traits(meta_ref);
             let profile = crate::services::chat::CustomerProfile {
                 name: name.as_deref(),
                 email: email.as_deref(),
                 avatar: avatar.as_deref(),
                 ip: ip.as_deref(),
                 user_agent: user_agent.as_deref(),
+                tags: tags.as_deref(),
+                attributes,
             };
 
             let (cust, sess) = ctx
@@ -80,6 +129,14 @@ pub async fn handle_customer_ws_message(
 
             *ctx.customer = Some(cust.clone());
             *ctx.session = Some(sess.clone());
+            *ctx.verified = true;
+
+            // 每次认证都刷新访客令牌，客户端需保存并在重连/上传时携带
+            let visitor_token = crate::services::visitor_token::issue_visitor_token(
+                ctx.shop_id,
+                ctx.api_key,
+                ctx.customer_code,
+            )?;
 
             let auth_success = WebSocketMessage {
                 message_type: crate::constants::ws_events::AUTH_SUCCESS.to_string(),
@@ -92,22 +149,41 @@ pub async fn handle_customer_ws_message(
                     "sessionId": sess.id,
                     "customerId": cust.id,
                     "customerCode": cust.customer_id,
-                    "shopId": ctx.shop_id
+                    "shopId": ctx.shop_id,
+                    "visitorToken": visitor_token
                 })),
                 file_url: None,
                 file_name: None,
                 file_size: None,
                 media_duration: None,
+                message_id: None,
             };
 
             if let Ok(payload) = serde_json::to_string(&auth_success) {
                 let _ = ctx.outbound.send(Message::Text(payload));
             }
 
-            let mut manager = ctx.state.connections.lock().unwrap();
-            manager.broadcast_to_staff(ctx.shop_id, &auth_success);
+            // 广播给客服时不携带访客令牌
+            let mut staff_notice = auth_success;
+            if let Some(Value::Object(map)) = staff_notice.metadata.as_mut() {
+                map.remove("visitorToken");
+            }
+            {
+                let mut manager = ctx.state.connections.lock().unwrap();
+                manager.broadcast_to_session_staff(ctx.shop_id, sess.staff_id, &staff_notice);
+            }
+            presence::customer_online(ctx.state, ctx.shop_id, cust.id, &cust.customer_id, ctx.presence_id).await;
+            if sess.staff_id.is_none() {
+                queue::send_position(ctx.state, ctx.shop_id, sess.id, &cust.customer_id).await;
+            }
+
+            // 断线重连：按 lastMessageId 补发错过的消息
+            if let Some(last_message_id) = extract_last_message_id(meta_ref) {
+                let missed = ctx.chat.replay_for_session(sess.id, last_message_id).await?;
+                send_replay(ctx.outbound, &missed);
+            }
         }
-        crate::constants::ws_incoming::SEND_MESSAGE => {
+        crate::constants::ws_incoming::SEND_MESSAGE | crate::constants::ws_incoming::QUICK_REPLY => {
             eprintln!("📨 [Customer WS] 处理发送消息请求");
             eprintln!("🔍 [Customer WS] incoming.content = {:?}", incoming.content);
             eprintln!("🔍 [Customer WS] incoming.metadata = {:?}", incoming.metadata);
@@ -123,7 +199,25 @@ pub async fn handle_customer_ws_message(
 
             eprintln!("✅ [Customer WS] 客户上下文: customer_id={}, session_id={}", cust.id, sess.id);
 
-            let message_type = extract_message_kind(meta_ref);
+            // 点击按钮 / 快捷回复：作为引用被点击消息的 quick_reply 消息进入会话
+            let (message_type, rich_content, reply_to) =
+                if incoming.message_type == crate::constants::ws_incoming::QUICK_REPLY {
+                    let Some(card_id) = extract_receipt_message_id(meta_ref) else {
+                        tracing::warn!("Customer quick_reply missing messageId");
+                        return Ok(());
+                    };
+                    let option_id = meta_ref
+                        .and_then(|m| m.get("optionId"))
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default();
+                    (
+                        crate::services::rich_content::QUICK_REPLY.to_string(),
+                        Some(json!({ "type": crate::services::rich_content::QUICK_REPLY, "messageId": card_id, "optionId": option_id })),
+                        Some(card_id),
+                    )
+                } else {
+                    (extract_message_kind(meta_ref), incoming.rich_content.clone(), incoming.reply_to)
+                };
             let mut metadata = incoming
                 .metadata
                 .clone()
@@ -150,6 +244,8 @@ pub async fn handle_customer_ws_message(
                 file_size: incoming.file_size,
                 media_duration: incoming.media_duration,
                 metadata: Some(metadata),
+                reply_to,
+                rich_content,
             };
 
             eprintln!("💾 [Customer WS] 准备持久化消息: content={:?}", 
@@ -162,14 +258,59 @@ pub async fn handle_customer_ws_message(
 
             eprintln!("✅ [Customer WS] 消息已保存到数据库: message_id={}", persisted.message.id);
 
+            if let Some(routed) = persisted.routed_session.clone() {
+                *ctx.session = Some(routed);
+            }
+
             if let Ok(payload) = serde_json::to_string(&persisted.ws_message) {
                 let _ = ctx.outbound.send(Message::Text(payload.clone()));
                 eprintln!("📤 [Customer WS] 消息已回显给客户");
             }
 
+            let staff_id = current_session_staff(ctx.state, sess.id).await?;
+            let mut manager = ctx.state.connections.lock().unwrap();
+            manager.broadcast_to_session_staff(ctx.shop_id, staff_id, &persisted.ws_message);
+            eprintln!("📡 [Customer WS] 消息已广播给店铺 {} 中可查看该会话的客服", ctx.shop_id);
+        }
+        crate::constants::ws_incoming::READ | crate::constants::ws_incoming::DELIVERED => {
+            let Some(sess) = ctx.session.clone() else {
+                tracing::warn!("Customer receipt before auth");
+                return Ok(());
+            };
+            let Some(up_to) = extract_receipt_message_id(meta_ref) else {
+                tracing::warn!("Customer receipt missing messageId");
+                return Ok(());
+            };
+            let read = incoming.message_type == crate::constants::ws_incoming::READ;
+            if ctx.chat.apply_receipt(sess.id, "customer", read, up_to).await? == 0 {
+                return Ok(());
+            }
+
+            let receipt = build_receipt(
+                read,
+                sess.id,
+                "customer",
+                ctx.customer.as_ref().map(|c| c.id),
+                up_to,
+                json!({ "shopId": ctx.shop_id, "customerCode": ctx.customer_code }),
+            );
+            let staff_id = current_session_staff(ctx.state, sess.id).await?;
             let mut manager = ctx.state.connections.lock().unwrap();
-            manager.broadcast_to_staff(ctx.shop_id, &persisted.ws_message);
-            eprintln!("📡 [Customer WS] 消息已广播给店铺 {} 的所有客服", ctx.shop_id);
+            manager.broadcast_to_session_staff(ctx.shop_id, staff_id, &receipt);
+        }
+        crate::constants::ws_incoming::EDIT_MESSAGE | crate::constants::ws_incoming::RECALL_MESSAGE => {
+            let Some(cust) = ctx.customer.clone() else {
+                tracing::warn!("Customer {} before auth", incoming.message_type);
+                return Ok(());
+            };
+            let Some(message_id) = extract_receipt_message_id(meta_ref) else {
+                send_action_failed(ctx.outbound, &incoming.message_type, None, "missing_message_id");
+                return Ok(());
+            };
+            let author = MessageAuthor::Customer(cust.id);
+            if let Err(e) = apply_message_change(ctx.state, author, &incoming, message_id).await {
+                send_action_failed(ctx.outbound, &incoming.message_type, Some(message_id), &e.to_string());
+            }
         }
         crate::constants::ws_incoming::TYPING => {
             if let Some(sess) = ctx.session.as_ref() {
@@ -198,10 +339,12 @@ pub async fn handle_customer_ws_message(
                     file_name: None,
                     file_size: None,
                     media_duration: None,
+                    message_id: None,
                 };
 
+                let staff_id = current_session_staff(ctx.state, sess.id).await?;
                 let mut manager = ctx.state.connections.lock().unwrap();
-                manager.broadcast_to_staff(ctx.shop_id, &typing_message);
+                manager.broadcast_to_session_staff(ctx.shop_id, staff_id, &typing_message);
             }
         }
         other => {
@@ -213,15 +356,34 @@ pub async fn handle_customer_ws_message(
 }
 
 pub async fn handle_staff_ws_message(
-    state: &AppState,
-    chat_service: &ChatService<'_>,
-    user_id: i64,
-    outbound: &mpsc::UnboundedSender<Message>,
-    connection_id: &mut Option<String>,
-    active_shop: &mut Option<i64>,
+    ctx: &mut StaffWsCtx<'_>,
     incoming: WebSocketIncomingMessage,
 ) -> Result<()> {
     let meta_ref = incoming.metadata.as_ref();
+    let state = ctx.state;
+    let chat_service = ctx.chat;
+    let user_id = ctx.user_id;
+    let outbound = ctx.outbound;
+
+    // 握手阶段未携带 token 时，首帧必须是带 metadata.token 的 auth
+    if !*ctx.authenticated {
+        if incoming.message_type != crate::constants::ws_incoming::AUTH {
+            send_auth_failed(outbound, "unauthenticated");
+            anyhow::bail!("staff_unauthenticated");
+        }
+        let session = match extract_token(meta_ref) {
+            Some(token) => crate::auth::authenticate_token(&state.db, &token).await.ok(),
+            None => None,
+        };
+        match session {
+            Some(session) if session.user_id == user_id => *ctx.auth_jti = Some(session.jti),
+            _ => {
+                send_auth_failed(outbound, "invalid_token");
+                anyhow::bail!("staff_invalid_token");
+            }
+        }
+        *ctx.authenticated = true;
+    }
 
     match incoming.message_type.as_str() {
         crate::constants::ws_incoming::PING => {
@@ -237,10 +399,24 @@ pub async fn handle_staff_ws_message(
                 file_name: None,
                 file_size: None,
                 media_duration: None,
+                message_id: None,
             };
             if let Ok(payload) = serde_json::to_string(&pong) {
                 let _ = outbound.send(Message::Text(payload));
             }
+            if let Some(shop_id) = *ctx.active_shop {
+                presence::heartbeat(state, presence::USER_TYPE_STAFF, user_id, shop_id).await;
+            }
+            // 每次心跳重新校验登录会话：已注销 / 吊销（含在其他节点上操作且关闭事件丢失时）则断开
+            if let Some(jti) = ctx.auth_jti.as_deref() {
+                let active = auth_session::is_active(&state.db, user_id, jti).await.unwrap_or(true);
+                if !active {
+                    tracing::warn!("Staff {} auth session revoked, closing WebSocket", user_id);
+                    send_auth_failed(outbound, "session_revoked");
+                    *ctx.authenticated = false;
+                    return Ok(());
+                }
+            }
         }
         crate::constants::ws_incoming::AUTH => {
             let Some(shop_id) = extract_shop_id(meta_ref) else {
@@ -248,13 +424,59 @@ pub async fn handle_staff_ws_message(
                 return Ok(());
             };
 
-            if connection_id.is_none() {
+            // 必须是店主或店铺员工才能订阅该店铺的实时消息
+            let Some(access) = permissions::load_access(&state.db, shop_id, user_id)
+                .await
+                .map_err(|e| anyhow::anyhow!("{}", e))?
+            else {
+                tracing::warn!("Staff {} is not a member of shop {}", user_id, shop_id);
+                send_auth_failed(outbound, "forbidden");
+                return Ok(());
+            };
+            if access.two_factor_required {
+                tracing::warn!("Staff {} has not enabled two-factor required by shop {}", user_id, shop_id);
+                send_auth_failed(outbound, "two_factor_required");
+                return Ok(());
+            }
+            let view_all_sessions = access.can(Capability::ViewAllSessions);
+
+            let mut left_shop = None;
+            let mut joined = None;
+            {
                 let mut manager = state.connections.lock().unwrap();
-                let id = manager.add_staff_connection(user_id, shop_id, outbound.clone());
-                *connection_id = Some(id);
+                // 切换店铺时重新注册连接
+                if *ctx.active_shop != Some(shop_id) {
+                    if let Some(old_id) = ctx.connection_id.take() {
+                        manager.remove_connection(&old_id);
+                        left_shop = ctx.active_shop.map(|old_shop| (old_shop, old_id));
+                    }
+                }
+                match ctx.connection_id.as_deref() {
+                    Some(id) => manager.set_view_all_sessions(id, view_all_sessions),
+                    None => {
+                        let id = manager.add_staff_connection(
+                            user_id,
+                            shop_id,
+                            view_all_sessions,
+                            ctx.auth_jti.as_deref(),
+                            outbound.clone(),
+                        );
+                        *ctx.connection_id = Some(id.clone());
+                        joined = Some(id);
+                    }
+                }
             }
 
-            *active_shop = Some(shop_id);
+            *ctx.active_shop = Some(shop_id);
+
+            if let Some((old_shop, old_id)) = left_shop {
+                presence::staff_offline_if_idle(state, user_id, old_shop, &old_id).await;
+            }
+            if let Some(connection_id) = joined {
+                presence::staff_online(state, user_id, shop_id, &connection_id).await;
+                // 新的在线客服可能有空闲容量，尝试消化等待队列
+                queue::refresh(state, shop_id).await;
+            }
 
             let auth_success = WebSocketMessage {
                 message_type: crate::constants::ws_events::AUTH_SUCCESS.to_string(),
@@ -271,21 +493,47 @@ pub async fn handle_staff_ws_message(
                 file_name: None,
                 file_size: None,
                 media_duration: None,
+                message_id: None,
             };
 
             if let Ok(payload) = serde_json::to_string(&auth_success) {
                 let _ = outbound.send(Message::Text(payload));
             }
+
+            // 断线重连：按 lastMessageId 补发店铺内错过的消息
+            if let Some(last_message_id) = extract_last_message_id(meta_ref) {
+                let missed = chat_service.replay_for_shop(&access, last_message_id).await?;
+                send_replay(outbound, &missed);
+            }
         }
-        crate::constants::ws_incoming::SEND_MESSAGE => {
+        crate::constants::ws_incoming::SEND_MESSAGE | crate::constants::ws_incoming::SEND_TEMPLATE => {
             let Some(session_id) = incoming.session_id else {
                 tracing::warn!("Staff send_message missing session_id");
                 return Ok(());
             };
 
             let (session, customer) = chat_service.resolve_session(session_id).await?;
+            if !staff_can_send(ctx, &session).await? {
+                send_action_failed(outbound, &incoming.message_type, None, "forbidden");
+                return Ok(());
+            }
 
-            let message_type = extract_message_kind(meta_ref);
+            // 快捷回复模板：按会话展开变量后作为普通文本消息发送
+            let (message_type, content) = if incoming.message_type == crate::constants::ws_incoming::SEND_TEMPLATE {
+                let Some(template_id) = meta_ref.and_then(|m| m.get("templateId")).and_then(|v| v.as_i64()) else {
+                    send_action_failed(outbound, &incoming.message_type, None, "missing_template_id");
+                    return Ok(());
+                };
+                match canned_response::render(state, template_id, session.shop_id as i64, session_id, user_id).await {
+                    Ok(content) => ("text".to_string(), Some(content)),
+                    Err(e) => {
+                        send_action_failed(outbound, &incoming.message_type, None, &e.to_string());
+                        return Ok(());
+                    }
+                }
+            } else {
+                (extract_message_kind(meta_ref), incoming.content.clone())
+            };
             let mut metadata = incoming
                 .metadata
                 .clone()
@@ -306,13 +554,15 @@ pub async fn handle_staff_ws_message(
             }.or(incoming.file_url.clone());
 
             let payload = MessagePayload {
-                content: incoming.content.clone(),
+                content,
                 message_type,
                 file_url,
                 file_name: incoming.file_name.clone(),
                 file_size: incoming.file_size,
                 media_duration: incoming.media_duration,
                 metadata: Some(metadata),
+                reply_to: incoming.reply_to,
+                rich_content: incoming.rich_content.clone(),
             };
 
             let persisted = chat_service
@@ -325,11 +575,97 @@ pub async fn handle_staff_ws_message(
                 &customer.customer_id,
                 &persisted.ws_message,
             );
-            manager.broadcast_to_staff(session.shop_id as i64, &persisted.ws_message);
+            manager.broadcast_to_session_staff(session.shop_id as i64, session.staff_id.map(i64::from), &persisted.ws_message);
+        }
+        crate::constants::ws_incoming::ADD_NOTE => {
+            let Some(session_id) = incoming.session_id else {
+                tracing::warn!("Staff add_note missing session_id");
+                return Ok(());
+            };
+
+            let (session, customer) = chat_service.resolve_session(session_id).await?;
+            if !staff_can_send(ctx, &session).await? {
+                send_action_failed(outbound, &incoming.message_type, None, "forbidden");
+                return Ok(());
+            }
+
+            let content = incoming.content.clone().unwrap_or_default();
+            let persisted = match chat_service
+                .persist_note(&session.clone().into(), user_id, content, incoming.reply_to, &customer.into())
+                .await
+            {
+                Ok(persisted) => persisted,
+                Err(e) => {
+                    send_action_failed(outbound, &incoming.message_type, None, &e.to_string());
+                    return Ok(());
+                }
+            };
+
+            // 内部备注只推送给店铺客服
+            let mut manager = state.connections.lock().unwrap();
+            manager.broadcast_to_session_staff(session.shop_id as i64, session.staff_id.map(i64::from), &persisted.ws_message);
+        }
+        crate::constants::ws_incoming::READ | crate::constants::ws_incoming::DELIVERED => {
+            let Some(session_id) = incoming.session_id else {
+                tracing::warn!("Staff receipt missing session_id");
+                return Ok(());
+            };
+            let Some(up_to) = extract_receipt_message_id(meta_ref) else {
+                tracing::warn!("Staff receipt missing messageId");
+                return Ok(());
+            };
+            let (session, customer) = chat_service.resolve_session(session_id).await?;
+            ensure_staff_session_access(ctx, &session).await?;
+
+            let read = incoming.message_type == crate::constants::ws_incoming::READ;
+            let updated = chat_service.apply_receipt(session_id, "staff", read, up_to).await?;
+            if read {
+                // 同步店铺未读数与该客服的个人已读游标（即使消息已被其他客服读过）
+                chat_service
+                    .record_staff_read(session.shop_id as i64, customer.id as i64, user_id, up_to)
+                    .await?;
+            }
+            if updated == 0 {
+                return Ok(());
+            }
+
+            let receipt = build_receipt(
+                read,
+                session_id,
+                "staff",
+                Some(user_id),
+                up_to,
+                json!({ "shopId": session.shop_id, "customerCode": customer.customer_id }),
+            );
+            let mut manager = state.connections.lock().unwrap();
+            manager.send_to_customer(session.shop_id as i64, &customer.customer_id, &receipt);
+            manager.broadcast_to_session_staff(session.shop_id as i64, session.staff_id.map(i64::from), &receipt);
+        }
+        crate::constants::ws_incoming::EDIT_MESSAGE | crate::constants::ws_incoming::RECALL_MESSAGE => {
+            let Some(message_id) = extract_receipt_message_id(meta_ref) else {
+                send_action_failed(outbound, &incoming.message_type, None, "missing_message_id");
+                return Ok(());
+            };
+            let Some(message) = MessageRepository::find_by_id(&state.db_connection, message_id as i32).await? else {
+                send_action_failed(outbound, &incoming.message_type, Some(message_id), "message_not_found");
+                return Ok(());
+            };
+            let (session, _) = chat_service.resolve_session(message.session_id as i64).await?;
+            // 编辑 / 撤回与发送消息同样需要 send_messages 能力
+            if !staff_can_send(ctx, &session).await? {
+                send_action_failed(outbound, &incoming.message_type, Some(message_id), "forbidden");
+                return Ok(());
+            }
+
+            let author = MessageAuthor::Staff(user_id);
+            if let Err(e) = apply_message_change(state, author, &incoming, message_id).await {
+                send_action_failed(outbound, &incoming.message_type, Some(message_id), &e.to_string());
+            }
         }
         crate::constants::ws_incoming::TYPING => {
             if let Some(session_id) = incoming.session_id {
                 let (session, customer) = chat_service.resolve_session(session_id).await?;
+                ensure_staff_session_access(ctx, &session).await?;
 
                 let mut metadata = incoming
                     .metadata
@@ -355,11 +691,12 @@ pub async fn handle_staff_ws_message(
                     file_name: None,
                     file_size: None,
                     media_duration: None,
+                    message_id: None,
                 };
 
                 let mut manager = state.connections.lock().unwrap();
                 manager.send_to_customer(session.shop_id as i64, &customer.customer_id, &typing_message);
-                manager.broadcast_to_staff(session.shop_id as i64, &typing_message);
+                manager.broadcast_to_session_staff(session.shop_id as i64, session.staff_id.map(i64::from), &typing_message);
             }
         }
         other => {
@@ -370,6 +707,206 @@ pub async fn handle_staff_ws_message(
     Ok(())
 }
 
+/// 回复客户 / 写备注 / 编辑撤回需要 send_messages 能力，且会话对该客服可见；非店铺成员视为越权
+async fn staff_can_send(ctx: &StaffWsCtx<'_>, session: &sessions::Model) -> Result<bool> {
+    let access = load_staff_access(ctx, session.shop_id as i64).await?;
+    Ok(access.can_view_session(session.staff_id.map(i64::from)) && access.can(Capability::SendMessages))
+}
+
+/// 会话访问校验：店铺成员，且拥有 view_all_sessions 或会话分配给自己 / 尚未分配
+async fn ensure_staff_session_access(ctx: &StaffWsCtx<'_>, session: &sessions::Model) -> Result<()> {
+    let access = load_staff_access(ctx, session.shop_id as i64).await?;
+    if !access.can_view_session(session.staff_id.map(i64::from)) {
+        anyhow::bail!("staff_forbidden_session");
+    }
+    Ok(())
+}
+
+async fn load_staff_access(ctx: &StaffWsCtx<'_>, shop_id: i64) -> Result<ShopAccess> {
+    match permissions::load_access(&ctx.state.db, shop_id, ctx.user_id)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}", e))?
+    {
+        Some(access) => Ok(access),
+        None => anyhow::bail!("staff_forbidden_shop"),
+    }
+}
+
+/// 会话当前分配的客服：连接上缓存的会话可能已被认领 / 转接，推送给客服前重新读取
+async fn current_session_staff(state: &AppState, session_id: i64) -> Result<Option<i64>> {
+    let session = SessionRepository::find_by_id(&state.db_connection, session_id as i32).await?;
+    Ok(session.and_then(|s| s.staff_id).map(i64::from))
+}
+
+/// 构建 message_read / message_delivered 回执事件，metadata.messageId 为回执覆盖到的最大消息 id
+fn build_receipt(
+    read: bool,
+    session_id: i64,
+    reader_type: &str,
+    reader_id: Option<i64>,
+    up_to_message_id: i64,
+    extra: Value,
+) -> WebSocketMessage {
+    let event = if read {
+        crate::constants::ws_events::MESSAGE_READ
+    } else {
+        crate::constants::ws_events::MESSAGE_DELIVERED
+    };
+    let mut metadata = match extra {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    metadata.insert("messageId".to_string(), json!(up_to_message_id));
+    metadata.insert("readerType".to_string(), json!(reader_type));
+
+    WebSocketMessage {
+        message_type: event.to_string(),
+        content: None,
+        session_id: Some(session_id),
+        sender_id: reader_id,
+        sender_type: Some(reader_type.to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(Value::Object(metadata)),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: None,
+    }
+}
+
+/// 按 id 顺序逐条发送补发消息
+fn send_replay(outbound: &mpsc::UnboundedSender<Message>, messages: &[WebSocketMessage]) {
+    for message in messages {
+        if let Ok(payload) = serde_json::to_string(message) {
+            let _ = outbound.send(Message::Text(payload));
+        }
+    }
+}
+
+/// 编辑 / 撤回消息，成功后推送 message_edited / message_recalled 给客户与店铺客服
+async fn apply_message_change(
+    state: &AppState,
+    author: MessageAuthor,
+    incoming: &WebSocketIncomingMessage,
+    message_id: i64,
+) -> Result<()> {
+    let (message, event) = if incoming.message_type == crate::constants::ws_incoming::EDIT_MESSAGE {
+        let content = incoming.content.as_deref().unwrap_or_default();
+        let message = state.message_service.edit_message(author, message_id, content).await?;
+        (message, crate::constants::ws_events::MESSAGE_EDITED)
+    } else {
+        let message = state.message_service.recall_message(author, message_id).await?;
+        (message, crate::constants::ws_events::MESSAGE_RECALLED)
+    };
+    broadcast_message_change(state, event, &message, (author.sender_type(), author.id())).await;
+    Ok(())
+}
+
+/// 编辑 / 撤回失败时仅通知发起方（system 事件，metadata.error 为错误码）
+fn send_action_failed(
+    outbound: &mpsc::UnboundedSender<Message>,
+    action: &str,
+    message_id: Option<i64>,
+    reason: &str,
+) {
+    let failed = WebSocketMessage {
+        message_type: crate::constants::ws_events::SYSTEM.to_string(),
+        content: Some(reason.to_string()),
+        session_id: None,
+        sender_id: None,
+        sender_type: Some("system".to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(json!({ "action": action, "messageId": message_id, "error": reason })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id,
+    };
+    if let Ok(payload) = serde_json::to_string(&failed) {
+        let _ = outbound.send(Message::Text(payload));
+    }
+}
+
+fn send_auth_failed(outbound: &mpsc::UnboundedSender<Message>, reason: &str) {
+    let failed = WebSocketMessage {
+        message_type: crate::constants::ws_events::AUTH_FAILED.to_string(),
+        content: Some(reason.to_string()),
+        session_id: None,
+        sender_id: None,
+        sender_type: Some("system".to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(json!({ "reason": reason })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: None,
+    };
+    if let Ok(payload) = serde_json::to_string(&failed) {
+        let _ = outbound.send(Message::Text(payload));
+    }
+}
+
+/// 入站帧触发限流：告知客户端该帧已被丢弃及建议的重试间隔
+pub fn send_rate_limited(outbound: &mpsc::UnboundedSender<Message>, message_type: &str, retry_after_secs: u64) {
+    let limited = WebSocketMessage {
+        message_type: crate::constants::ws_events::RATE_LIMITED.to_string(),
+        content: Some("发送过于频繁，请稍后再试".to_string()),
+        session_id: None,
+        sender_id: None,
+        sender_type: Some("system".to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(json!({
+            "messageType": message_type,
+            "retryAfter": retry_after_secs,
+        })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: None,
+    };
+    if let Ok(payload) = serde_json::to_string(&limited) {
+        let _ = outbound.send(Message::Text(payload));
+    }
+}
+
+/// 客户已被拉黑：调用方据此断开连接（用 downcast_ref 判断，不依赖错误文案）
+#[derive(Debug, thiserror::Error)]
+#[error("customer_blocked")]
+pub struct CustomerBlocked;
+
+/// 客户被拉黑的系统事件
+pub fn blocked_event(block: &BlockInfo) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type: crate::constants::ws_events::BLOCKED.to_string(),
+        content: block.reason.clone(),
+        session_id: None,
+        sender_id: None,
+        sender_type: Some("system".to_string()),
+        timestamp: Some(Utc::now()),
+        metadata: Some(json!({
+            "reason": block.reason,
+            "blockedUntil": block.blocked_until,
+        })),
+        file_url: None,
+        file_name: None,
+        file_size: None,
+        media_duration: None,
+        message_id: None,
+    }
+}
+
+/// 发送拉黑事件并关闭连接
+fn send_blocked(outbound: &mpsc::UnboundedSender<Message>, block: &BlockInfo) {
+    if let Ok(payload) = serde_json::to_string(&blocked_event(block)) {
+        let _ = outbound.send(Message::Text(payload));
+    }
+    let _ = outbound.send(Message::Close(None));
+}
+
 async fn ensure_customer_context(
     chat_service: &ChatService<'_>,
     shop_id: i64,
@@ -423,6 +960,18 @@ fn extract_customer_profile(metadata: Option<&Value>) -> CustomerProfileTuple {
     (name, email, avatar, ip, agent)
 }
 
+/// AUTH metadata 中的 customerTags（字符串数组）与 customerAttributes（对象）
+fn extract_customer_traits(metadata: Option<&Value>) -> (Option<Vec<String>>, Option<&Map<String, Value>>) {
+    let tags = metadata
+        .and_then(|value| value.get("customerTags"))
+        .and_then(|v| v.as_array())
+        .map(|items| items.iter().filter_map(|v| v.as_str()).map(|s| s.to_string()).collect());
+    let attributes = metadata
+        .and_then(|value| value.get("customerAttributes"))
+        .and_then(|v| v.as_object());
+    (tags, attributes)
+}
+
 fn extract_message_kind(metadata: Option<&Value>) -> String {
     metadata
         .and_then(|value| value.get("messageType"))
@@ -443,3 +992,18 @@ fn value_to_i64(value: &Value) -> Option<i64> {
 fn extract_shop_id(metadata: Option<&Value>) -> Option<i64> {
     metadata.and_then(|value| value.get("shopId")).and_then(value_to_i64)
 }
+
+fn extract_token(metadata: Option<&Value>) -> Option<String> {
+    metadata
+        .and_then(|value| value.get("token"))
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+}
+
+fn extract_last_message_id(metadata: Option<&Value>) -> Option<i64> {
+    metadata.and_then(|value| value.get("lastMessageId")).and_then(value_to_i64)
+}
+
+fn extract_receipt_message_id(metadata: Option<&Value>) -> Option<i64> {
+    metadata.and_then(|value| value.get("messageId")).and_then(value_to_i64)
+}
@@ -2,11 +2,14 @@
 // Input: 连接上下文（user_id/shop_id/customer_id）与要发送的 WebSocketMessage
 // Output: 通过保存的 UnboundedSender<Message> 向目标连接发送消息
 // Errors: 发送失败时静默丢弃（不 panic），外部应根据业务需要进行重试或清理
+// Note: 本地投递后通过 Broadcaster 转发给其他节点（默认 LocalBroadcaster 不转发）
 use axum::extract::ws::Message;
 use std::collections::HashMap;
+use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedSender;
 use uuid::Uuid;
 
+use super::broadcaster::{BroadcastEvent, BroadcastTarget, Broadcaster, LocalBroadcaster};
 use crate::models::WebSocketMessage;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -22,6 +25,10 @@ pub struct ConnectionHandle {
     pub user_id: Option<i64>,
     pub shop_id: Option<i64>,
     pub customer_id: Option<String>,
+    /// 客服连接：是否拥有 view_all_sessions（否则只接收分配给自己或未分配会话的推送）
+    pub view_all_sessions: bool,
+    /// 客服连接：建立连接所用的登录会话 jti，注销该会话时断开
+    pub auth_jti: Option<String>,
 }
 
 #[derive(Debug)]
@@ -30,6 +37,7 @@ pub struct ConnectionManager {
     staff_connections: HashMap<i64, Vec<String>>,          // staff_user_id -> connection_ids
     shop_staff_connections: HashMap<i64, Vec<String>>,     // shop_id -> connection_ids
     customer_connections: HashMap<(i64, String), String>,  // (shop_id, customer_code) -> connection_id
+    broadcaster: Arc<dyn Broadcaster>,
 }
 
 impl Default for ConnectionManager {
@@ -45,13 +53,21 @@ impl ConnectionManager {
             staff_connections: HashMap::new(),
             shop_staff_connections: HashMap::new(),
             customer_connections: HashMap::new(),
+            broadcaster: Arc::new(LocalBroadcaster),
         }
     }
 
+    /// 替换跨节点广播器（多节点部署时安装 SqliteOutboxBroadcaster）
+    pub fn set_broadcaster(&mut self, broadcaster: Arc<dyn Broadcaster>) {
+        self.broadcaster = broadcaster;
+    }
+
     pub fn add_staff_connection(
         &mut self,
         user_id: i64,
         shop_id: i64,
+        view_all_sessions: bool,
+        auth_jti: Option<&str>,
         sender: UnboundedSender<Message>,
     ) -> String {
         let connection_id = Uuid::new_v4().to_string();
@@ -62,6 +78,8 @@ impl ConnectionManager {
             user_id: Some(user_id),
             shop_id: Some(shop_id),
             customer_id: None,
+            view_all_sessions,
+            auth_jti: auth_jti.map(str::to_string),
         };
 
         self.staff_connections
@@ -91,6 +109,8 @@ impl ConnectionManager {
             user_id: None,
             shop_id: Some(shop_id),
             customer_id: Some(customer_id.to_string()),
+            view_all_sessions: false,
+            auth_jti: None,
         };
 
         self.customer_connections
@@ -100,6 +120,13 @@ impl ConnectionManager {
         connection_id
     }
 
+    /// 重新认证同一店铺时刷新连接的会话可见范围
+    pub fn set_view_all_sessions(&mut self, connection_id: &str, view_all_sessions: bool) {
+        if let Some(handle) = self.connections.get_mut(connection_id) {
+            handle.view_all_sessions = view_all_sessions;
+        }
+    }
+
     pub fn remove_connection(&mut self, connection_id: &str) {
         if let Some(handle) = self.connections.remove(connection_id) {
             match handle.user_type {
@@ -124,7 +151,11 @@ impl ConnectionManager {
                 ConnectionUserType::Customer => {
                     if let (Some(shop_id), Some(customer_id)) = (handle.shop_id, handle.customer_id)
                     {
-                        self.customer_connections.remove(&(shop_id, customer_id));
+                        // 同一访客重连后映射已指向新连接，旧连接断开时不能误删
+                        let key = (shop_id, customer_id);
+                        if self.customer_connections.get(&key).map(|id| id == connection_id).unwrap_or(false) {
+                            self.customer_connections.remove(&key);
+                        }
                     }
                 }
             }
@@ -149,42 +180,130 @@ impl ConnectionManager {
         customer_id: &str,
         message: &WebSocketMessage,
     ) {
-        let conn_key = (shop_id, customer_id.to_string());
-        if let Some(connection_id) = self.customer_connections.get(&conn_key) {
-            if let Some(handle) = self.connections.get(connection_id) {
-                if let Ok(msg) = serialize_message(message) {
-                    let _ = handle.sender.send(msg);
-                }
-            }
-        }
+        let target = BroadcastTarget::Customer {
+            shop_id,
+            customer_code: customer_id.to_string(),
+        };
+        self.dispatch(target, message);
+    }
+
+    /// 关闭该客户在所有节点上的连接（如被拉黑）
+    pub fn close_customer(&self, shop_id: i64, customer_id: &str) {
+        let target = BroadcastTarget::Customer {
+            shop_id,
+            customer_code: customer_id.to_string(),
+        };
+        self.close_target(&target);
     }
 
+    /// 关闭该客服在所有节点上的全部连接（如吊销全部登录会话、重置密码）
+    pub fn close_staff_user(&self, user_id: i64) {
+        self.close_target(&BroadcastTarget::StaffUser { user_id });
+    }
+
+    /// 关闭该客服在所有节点上连接到某个店铺的连接（如被移出店铺、角色变更后需重新认证）
+    pub fn close_shop_staff_user(&self, shop_id: i64, user_id: i64) {
+        self.close_target(&BroadcastTarget::ShopStaffUser { shop_id, user_id });
+    }
+
+    /// 关闭该客服在所有节点上以某个登录会话建立的连接（如注销）
+    pub fn close_staff_session(&self, user_id: i64, jti: &str) {
+        self.close_target(&BroadcastTarget::StaffSession {
+            user_id,
+            jti: jti.to_string(),
+        });
+    }
+
+    /// 店铺级通知（排队概况、在线状态、会话状态变更），推送给店铺全部客服
     pub fn broadcast_to_staff(&mut self, shop_id: i64, message: &WebSocketMessage) {
-        if let Some(connection_ids) = self.shop_staff_connections.get(&shop_id) {
-            if let Ok(msg) = serialize_message(message) {
-                for connection_id in connection_ids {
+        self.dispatch(BroadcastTarget::ShopStaff { shop_id }, message);
+    }
+
+    /// 会话内事件（消息、编辑 / 撤回、回执、输入中），只推送给能查看该会话的客服
+    ///
+    /// staff_id 为会话当前分配的客服；可见性规则与 ShopAccess::can_view_session 一致
+    pub fn broadcast_to_session_staff(&mut self, shop_id: i64, staff_id: Option<i64>, message: &WebSocketMessage) {
+        self.dispatch(BroadcastTarget::SessionStaff { shop_id, staff_id }, message);
+    }
+
+    pub fn send_to_staff_user(&mut self, user_id: i64, message: &WebSocketMessage) {
+        self.dispatch(BroadcastTarget::StaffUser { user_id }, message);
+    }
+
+    /// 本地投递并交给 Broadcaster 转发到其他节点
+    fn dispatch(&self, target: BroadcastTarget, message: &WebSocketMessage) {
+        if let Ok(payload) = serde_json::to_string(message) {
+            self.deliver_local(&target, Message::Text(payload.clone()));
+            self.broadcaster.publish(&target, &BroadcastEvent::Text(payload));
+        }
+    }
+
+    /// 关闭本节点上的目标连接，并通知其他节点关闭各自持有的连接
+    fn close_target(&self, target: &BroadcastTarget) {
+        self.deliver_local(target, Message::Close(None));
+        self.broadcaster.publish(target, &BroadcastEvent::Close);
+    }
+
+    /// 仅投递给本节点持有的连接（其他节点转发来的消息走这里，不会再次发布）
+    pub fn deliver_local(&self, target: &BroadcastTarget, msg: Message) {
+        match target {
+            BroadcastTarget::Customer { shop_id, customer_code } => {
+                let conn_key = (*shop_id, customer_code.clone());
+                if let Some(connection_id) = self.customer_connections.get(&conn_key) {
                     if let Some(handle) = self.connections.get(connection_id) {
+                        let _ = handle.sender.send(msg);
+                    }
+                }
+            }
+            BroadcastTarget::ShopStaff { shop_id } => {
+                if let Some(connection_ids) = self.shop_staff_connections.get(shop_id) {
+                    self.send_to_all(connection_ids, &msg);
+                }
+            }
+            BroadcastTarget::SessionStaff { shop_id, staff_id } => {
+                let Some(connection_ids) = self.shop_staff_connections.get(shop_id) else {
+                    return;
+                };
+                for handle in connection_ids.iter().filter_map(|id| self.connections.get(id)) {
+                    let visible = handle.view_all_sessions || staff_id.is_none_or(|id| handle.user_id == Some(id));
+                    if visible {
                         let _ = handle.sender.send(msg.clone());
                     }
                 }
             }
-        }
-    }
-
-    pub fn send_to_staff_user(&mut self, user_id: i64, message: &WebSocketMessage) {
-        if let Some(connection_ids) = self.staff_connections.get(&user_id) {
-            if let Ok(msg) = serialize_message(message) {
-                for connection_id in connection_ids {
-                    if let Some(handle) = self.connections.get(connection_id) {
+            BroadcastTarget::StaffUser { user_id } => {
+                if let Some(connection_ids) = self.staff_connections.get(user_id) {
+                    self.send_to_all(connection_ids, &msg);
+                }
+            }
+            BroadcastTarget::ShopStaffUser { shop_id, user_id } => {
+                let Some(connection_ids) = self.staff_connections.get(user_id) else {
+                    return;
+                };
+                for handle in connection_ids.iter().filter_map(|id| self.connections.get(id)) {
+                    if handle.shop_id == Some(*shop_id) {
+                        let _ = handle.sender.send(msg.clone());
+                    }
+                }
+            }
+            BroadcastTarget::StaffSession { user_id, jti } => {
+                let Some(connection_ids) = self.staff_connections.get(user_id) else {
+                    return;
+                };
+                for handle in connection_ids.iter().filter_map(|id| self.connections.get(id)) {
+                    if handle.auth_jti.as_deref() == Some(jti.as_str()) {
                         let _ = handle.sender.send(msg.clone());
                     }
                 }
             }
         }
     }
-}
 
-fn serialize_message(message: &WebSocketMessage) -> Result<Message, serde_json::Error> {
-    let payload = serde_json::to_string(message)?;
-    Ok(Message::Text(payload))
+    fn send_to_all(&self, connection_ids: &[String], msg: &Message) {
+        for connection_id in connection_ids {
+            if let Some(handle) = self.connections.get(connection_id) {
+                let _ = handle.sender.send(msg.clone());
+            }
+        }
+    }
 }
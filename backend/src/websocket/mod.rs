@@ -1,8 +1,13 @@
 // Purpose: WebSocket 模块入口与公共导出
-// Exports: ConnectionManager, handle_customer_ws_message, handle_staff_ws_message, CustomerWsCtx
+// Exports: ConnectionManager, Broadcaster（跨节点扇出）, handle_customer_ws_message, handle_staff_ws_message, CustomerWsCtx, StaffWsCtx, CustomerBlocked, blocked_event, send_rate_limited
 
 pub mod manager;
 pub mod handlers;
+pub mod broadcaster;
 
 pub use manager::ConnectionManager;
-pub use handlers::{handle_customer_ws_message, handle_staff_ws_message, CustomerWsCtx};
+pub use broadcaster::{OutboxConfig, SqliteOutboxBroadcaster};
+pub use handlers::{
+    blocked_event, handle_customer_ws_message, handle_staff_ws_message, send_rate_limited, CustomerBlocked, CustomerWsCtx,
+    StaffWsCtx,
+};
@@ -54,6 +54,12 @@ const StaffList: React.FC<StaffListProps> = ({ data, loading, onSelect, onRemove
     switch (role) {
       case 'owner':
         return '店主';
+      case 'admin':
+        return '管理员';
+      case 'agent':
+        return '客服';
+      case 'viewer':
+        return '只读';
       case 'staff':
         return '员工';
       default:
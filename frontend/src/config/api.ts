@@ -51,6 +51,7 @@ api.interceptors.request.use((config) => {
   // 判断是否是不需要认证的公开端点（登录、注册等）
   const isPublicEndpoint = config.url?.includes('/auth/login') || 
                            config.url?.includes('/auth/register') ||
+                           config.url?.includes('/auth/refresh') ||
                            config.url?.includes('/health');
   
   // 若调用方已显式设置，则不覆盖
@@ -95,10 +96,31 @@ api.interceptors.request.use((config) => {
   return config;
 });
 
+// access token 过期时用 refresh token 换新令牌（由 authStore 注册，避免循环依赖）
+let tokenRefresher: (() => Promise<string | null>) | null = null;
+// 并发的 401 共用同一次刷新
+let refreshing: Promise<string | null> | null = null;
+
+export function setTokenRefresher(fn: () => Promise<string | null>) {
+  tokenRefresher = fn;
+}
+
 // 全局响应处理（这里简单打印，可扩展）
 api.interceptors.response.use(
   (resp) => resp,
-  (err) => {
+  async (err) => {
+    // 401：先尝试刷新令牌并重试一次原请求
+    const config = err?.config;
+    const isAuthEndpoint = config?.url?.includes('/auth/login') || config?.url?.includes('/auth/refresh');
+    if (err?.response?.status === 401 && config && !config._retried && !isAuthEndpoint && tokenRefresher) {
+      refreshing ||= tokenRefresher().finally(() => { refreshing = null; });
+      const token = await refreshing;
+      if (token) {
+        config._retried = true;
+        config.headers = { ...(config.headers || {}), Authorization: `Bearer ${token}` };
+        return api(config);
+      }
+    }
     // 统一处理 401：清理本地登录状态并跳回登录
     if (err?.response?.status === 401 && typeof window !== 'undefined') {
       // 检查是否是上传相关的请求，给予更友好的提示
@@ -120,6 +142,7 @@ api.interceptors.response.use(
               isAuthenticated: false,
               user: null,
               token: null,
+              refreshToken: null,
             };
             window.localStorage.setItem('auth-storage', JSON.stringify(parsed));
           }
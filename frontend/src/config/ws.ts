@@ -9,8 +9,11 @@ const isDev = window.location.port === '3000';
 const PROTOCOL = isDev ? 'ws' : 'wss';
 const DEFAULT_PORT = isDev ? '8080' : (process.env.REACT_APP_WS_PORT || '8443');
 
-export function staffSocket(userId: string) {
-  return new WebSocket(`${PROTOCOL}://${HOST}:${DEFAULT_PORT}/ws/staff/${userId}`);
+// 客服连接需携带登录 token，服务端在握手阶段校验
+export function staffSocket(userId: string, token: string) {
+  return new WebSocket(
+    `${PROTOCOL}://${HOST}:${DEFAULT_PORT}/ws/staff/${userId}?token=${encodeURIComponent(token)}`
+  );
 }
 
 export function customerSocket(shopId: string | number, customerId: string | number) {
@@ -1,6 +1,7 @@
 import React, { useState } from 'react';
 import styled from 'styled-components';
 import { useAuthStore } from '../stores/authStore';
+import { requestPasswordReset, resetPassword } from '../services/passwordReset';
 import { Button, Input, Card } from '../styles/globalStyles';
 import { theme } from '../styles/globalStyles';
 
@@ -96,6 +97,23 @@ const ErrorMessage = styled.div`
   margin-top: ${theme.spacing.sm};
 `;
 
+const InfoMessage = styled.div`
+  color: ${theme.colors.success};
+  font-size: ${theme.typography.small};
+  text-align: center;
+`;
+
+const RESET_ERRORS: Record<string, string> = {
+  invalid_reset_token: '重置链接无效或已使用，请重新申请',
+  reset_token_expired: '重置链接已过期，请重新申请',
+  password_too_short: '密码至少 6 位',
+  password_too_long: '密码不能超过 100 位',
+};
+
+/** 邮件中的重置链接形如 /?reset_token=... */
+const readResetToken = () =>
+  typeof window === 'undefined' ? null : new URLSearchParams(window.location.search).get('reset_token');
+
 const LoginPage: React.FC = () => {
   const [activeTab, setActiveTab] = useState<'login' | 'register'>('login');
   const [formData, setFormData] = useState({
@@ -107,8 +125,14 @@ const LoginPage: React.FC = () => {
   });
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState('');
+  const [info, setInfo] = useState('');
+  // 找回密码流程：forgot 申请重置邮件，reset 通过邮件链接设置新密码
+  const [resetToken] = useState(readResetToken);
+  const [recovery, setRecovery] = useState<'forgot' | 'reset' | null>(resetToken ? 'reset' : null);
+  
+  const [twoFactorCode, setTwoFactorCode] = useState('');
   
-  const { login, register } = useAuthStore();
+  const { login, register, twoFactorChallenge, verifyTwoFactor, cancelTwoFactor } = useAuthStore();
 
   const handleInputChange = (e: React.ChangeEvent<HTMLInputElement>) => {
     setFormData({
@@ -118,6 +142,78 @@ const LoginPage: React.FC = () => {
     setError('');
   };
 
+  const leaveRecovery = (message = '') => {
+    if (resetToken) {
+      window.history.replaceState(null, '', window.location.pathname);
+    }
+    setRecovery(null);
+    setActiveTab('login');
+    setError('');
+    setInfo(message);
+  };
+
+  const handleRecoverySubmit = async (e: React.FormEvent) => {
+    e.preventDefault();
+    setError('');
+    setInfo('');
+
+    if (recovery === 'forgot') {
+      if (!formData.username.trim()) {
+        setError('请输入用户名或邮箱');
+        return;
+      }
+    } else {
+      if (!formData.password || formData.password !== formData.confirmPassword) {
+        setError(formData.password ? '两次输入的密码不一致' : '请输入新密码');
+        return;
+      }
+    }
+
+    setLoading(true);
+    try {
+      if (recovery === 'forgot') {
+        await requestPasswordReset(formData.username.trim());
+        setInfo('如果该账号已绑定邮箱，重置链接已发送，请查收邮件');
+      } else if (resetToken) {
+        await resetPassword(resetToken, formData.password);
+        setFormData({ ...formData, password: '', confirmPassword: '' });
+        leaveRecovery('密码已重置，请使用新密码登录');
+      }
+    } catch (err: any) {
+      if (err?.response?.status === 429) {
+        setError('操作过于频繁，请稍后再试');
+      } else {
+        setError(RESET_ERRORS[err?.response?.data?.message] || '请求失败，请稍后重试');
+      }
+    } finally {
+      setLoading(false);
+    }
+  };
+
+  const handleTwoFactorSubmit = async (e: React.FormEvent) => {
+    e.preventDefault();
+    if (!twoFactorCode.trim()) {
+      setError('请输入验证码');
+      return;
+    }
+    setLoading(true);
+    setError('');
+    try {
+      if (!(await verifyTwoFactor(twoFactorCode))) {
+        setError('验证码错误或已使用');
+      }
+    } finally {
+      setTwoFactorCode('');
+      setLoading(false);
+    }
+  };
+
+  const leaveTwoFactor = () => {
+    cancelTwoFactor();
+    setTwoFactorCode('');
+    setError('');
+  };
+
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
     
@@ -139,12 +235,18 @@ const LoginPage: React.FC = () => {
 
     setLoading(true);
     setError('');
+    setInfo('');
 
     try {
       let success = false;
       
       if (activeTab === 'login') {
-        success = await login(formData.username, formData.password);
+        const result = await login(formData.username, formData.password);
+        if (result === 'throttled') {
+          setError('登录失败次数过多，请稍后再试');
+          return;
+        }
+        success = result !== 'failed';
       } else {
         success = await register(
           formData.username,
@@ -173,103 +275,199 @@ const LoginPage: React.FC = () => {
           <LogoSubtitle>多店铺客服管理系统</LogoSubtitle>
         </Logo>
 
-        <TabContainer>
-          <Tab
-            active={activeTab === 'login'}
-            onClick={() => setActiveTab('login')}
-            type="button"
-          >
-            登录
-          </Tab>
-          <Tab
-            active={activeTab === 'register'}
-            onClick={() => setActiveTab('register')}
-            type="button"
-          >
-            注册
-          </Tab>
-        </TabContainer>
-
-        <Form onSubmit={handleSubmit}>
-          <FormGroup>
-            <Label htmlFor="username">用户名</Label>
-            <Input
-              id="username"
-              name="username"
-              type="text"
-              placeholder="请输入用户名"
-              value={formData.username}
-              onChange={handleInputChange}
-              autoComplete="username"
-            />
-          </FormGroup>
-
-          <FormGroup>
-            <Label htmlFor="password">密码</Label>
-            <Input
-              id="password"
-              name="password"
-              type="password"
-              placeholder="请输入密码"
-              value={formData.password}
-              onChange={handleInputChange}
-              autoComplete={activeTab === 'register' ? 'new-password' : 'current-password'}
-            />
-          </FormGroup>
-
-          {activeTab === 'register' && (
-            <>
+        {twoFactorChallenge ? (
+          <Form onSubmit={handleTwoFactorSubmit}>
+            <FormGroup>
+              <Label htmlFor="twoFactorCode">双因素验证码</Label>
+              <Input
+                id="twoFactorCode"
+                name="twoFactorCode"
+                type="text"
+                inputMode="numeric"
+                placeholder="验证器 App 中的 6 位验证码，或恢复码"
+                value={twoFactorCode}
+                onChange={(e) => { setTwoFactorCode(e.target.value); setError(''); }}
+                autoComplete="one-time-code"
+                autoFocus
+              />
+            </FormGroup>
+
+            {error && <ErrorMessage>{error}</ErrorMessage>}
+
+            <Button type="submit" variant="primary" fullWidth disabled={loading}>
+              {loading ? '请稍候...' : '验证'}
+            </Button>
+            <Button type="button" variant="text" fullWidth onClick={leaveTwoFactor}>
+              返回登录
+            </Button>
+          </Form>
+        ) : recovery ? (
+          <Form onSubmit={handleRecoverySubmit}>
+            {recovery === 'forgot' ? (
               <FormGroup>
-                <Label htmlFor="confirmPassword">确认密码</Label>
+                <Label htmlFor="username">用户名或邮箱</Label>
                 <Input
-                  id="confirmPassword"
-                  name="confirmPassword"
-                  type="password"
-                  placeholder="请再次输入密码"
-                  value={formData.confirmPassword}
+                  id="username"
+                  name="username"
+                  type="text"
+                  placeholder="重置链接将发送到账号绑定的邮箱"
+                  value={formData.username}
                   onChange={handleInputChange}
-                  autoComplete="new-password"
+                  autoComplete="username"
                 />
               </FormGroup>
+            ) : (
+              <>
+                <FormGroup>
+                  <Label htmlFor="password">新密码</Label>
+                  <Input
+                    id="password"
+                    name="password"
+                    type="password"
+                    placeholder="请输入新密码"
+                    value={formData.password}
+                    onChange={handleInputChange}
+                    autoComplete="new-password"
+                  />
+                </FormGroup>
+                <FormGroup>
+                  <Label htmlFor="confirmPassword">确认新密码</Label>
+                  <Input
+                    id="confirmPassword"
+                    name="confirmPassword"
+                    type="password"
+                    placeholder="请再次输入新密码"
+                    value={formData.confirmPassword}
+                    onChange={handleInputChange}
+                    autoComplete="new-password"
+                  />
+                </FormGroup>
+              </>
+            )}
+
+            {error && <ErrorMessage>{error}</ErrorMessage>}
+            {info && <InfoMessage>{info}</InfoMessage>}
+
+            <Button type="submit" variant="primary" fullWidth disabled={loading}>
+              {loading ? '请稍候...' : (recovery === 'forgot' ? '发送重置邮件' : '重置密码')}
+            </Button>
+            <Button type="button" variant="text" fullWidth onClick={() => leaveRecovery()}>
+              返回登录
+            </Button>
+          </Form>
+        ) : (
+          <>
+            <TabContainer>
+              <Tab
+                active={activeTab === 'login'}
+                onClick={() => setActiveTab('login')}
+                type="button"
+              >
+                登录
+              </Tab>
+              <Tab
+                active={activeTab === 'register'}
+                onClick={() => setActiveTab('register')}
+                type="button"
+              >
+                注册
+              </Tab>
+            </TabContainer>
+
+            <Form onSubmit={handleSubmit}>
               <FormGroup>
-                <Label htmlFor="email">邮箱（可选）</Label>
+                <Label htmlFor="username">用户名</Label>
                 <Input
-                  id="email"
-                  name="email"
-                  type="email"
-                  placeholder="请输入邮箱"
-                  value={formData.email}
+                  id="username"
+                  name="username"
+                  type="text"
+                  placeholder="请输入用户名"
+                  value={formData.username}
                   onChange={handleInputChange}
-                  autoComplete="email"
+                  autoComplete="username"
                 />
               </FormGroup>
 
               <FormGroup>
-                <Label htmlFor="phone">手机号（可选）</Label>
+                <Label htmlFor="password">密码</Label>
                 <Input
-                  id="phone"
-                  name="phone"
-                  type="tel"
-                  placeholder="请输入手机号"
-                  value={formData.phone}
+                  id="password"
+                  name="password"
+                  type="password"
+                  placeholder="请输入密码"
+                  value={formData.password}
                   onChange={handleInputChange}
-                  autoComplete="tel"
+                  autoComplete={activeTab === 'register' ? 'new-password' : 'current-password'}
                 />
               </FormGroup>
-            </>
-          )}
-
-          {error && <ErrorMessage>{error}</ErrorMessage>}
-
-          <Button
-            type="submit"
-            variant="primary"
-            fullWidth
-            disabled={loading}
-          >
-            {loading ? '请稍候...' : (activeTab === 'login' ? '登录' : '注册')}
-          </Button>
-        </Form>
+
+              {activeTab === 'register' && (
+                <>
+                  <FormGroup>
+                    <Label htmlFor="confirmPassword">确认密码</Label>
+                    <Input
+                      id="confirmPassword"
+                      name="confirmPassword"
+                      type="password"
+                      placeholder="请再次输入密码"
+                      value={formData.confirmPassword}
+                      onChange={handleInputChange}
+                      autoComplete="new-password"
+                    />
+                  </FormGroup>
+                  <FormGroup>
+                    <Label htmlFor="email">邮箱（可选）</Label>
+                    <Input
+                      id="email"
+                      name="email"
+                      type="email"
+                      placeholder="请输入邮箱"
+                      value={formData.email}
+                      onChange={handleInputChange}
+                      autoComplete="email"
+                    />
+                  </FormGroup>
+
+                  <FormGroup>
+                    <Label htmlFor="phone">手机号（可选）</Label>
+                    <Input
+                      id="phone"
+                      name="phone"
+                      type="tel"
+                      placeholder="请输入手机号"
+                      value={formData.phone}
+                      onChange={handleInputChange}
+                      autoComplete="tel"
+                    />
+                  </FormGroup>
+                </>
+              )}
+
+              {error && <ErrorMessage>{error}</ErrorMessage>}
+              {info && <InfoMessage>{info}</InfoMessage>}
+
+              <Button
+                type="submit"
+                variant="primary"
+                fullWidth
+                disabled={loading}
+              >
+                {loading ? '请稍候...' : (activeTab === 'login' ? '登录' : '注册')}
+              </Button>
+
+              {activeTab === 'login' && (
+                <Button
+                  type="button"
+                  variant="text"
+                  fullWidth
+                  onClick={() => { setRecovery('forgot'); setError(''); setInfo(''); }}
+                >
+                  忘记密码？
+                </Button>
+              )}
+            </Form>
+          </>
+        )}
       </LoginCard>
     </LoginContainer>
   );
@@ -3,6 +3,16 @@ import styled from 'styled-components';
 import { FiChevronLeft } from 'react-icons/fi';
 import { api } from '../../../config/api';
 import { useAuthStore } from '../../../stores/authStore';
+import {
+  TwoFactorEnrollment,
+  TwoFactorStatus,
+  beginTwoFactorSetup,
+  disableTwoFactor,
+  enableTwoFactor,
+  getTwoFactorStatus,
+  regenerateRecoveryCodes,
+} from '../../../services/twoFactor';
+import { LoginEvent, LOGIN_RESULT_LABELS, getMyLoginEvents } from '../../../services/loginEvents';
 import toast from 'react-hot-toast';
 
 const Container = styled.div`
@@ -113,11 +123,45 @@ const Hint = styled.div`
   margin-top: -6px;
 `;
 
+const Note = styled.p`
+  color: #666;
+  font-size: 14px;
+  margin: 0 0 8px;
+  word-break: break-all;
+`;
+
+const CodeList = styled.pre`
+  background: #f8f9fa;
+  border-radius: 8px;
+  padding: 12px;
+  font-size: 14px;
+  columns: 2;
+`;
+
+const EventRow = styled.div<{ failed: boolean }>`
+  display: flex;
+  justify-content: space-between;
+  gap: 12px;
+  padding: 8px 0;
+  border-bottom: 1px solid #f0f0f0;
+  font-size: 13px;
+  color: ${p => (p.failed ? '#dc3545' : '#333')};
+
+  &:last-child { border-bottom: none; }
+`;
+
+const TWO_FACTOR_ERRORS: Record<string, string> = {
+  invalid_two_factor_code: '验证码错误或已使用',
+  two_factor_not_enrolled: '请先生成密钥',
+  two_factor_already_enabled: '双因素认证已启用',
+  two_factor_not_enabled: '双因素认证未启用',
+};
+
 interface PersonalInfoModalProps { isOpen: boolean; onClose: () => void; }
 
 const PersonalInfoModal: React.FC<PersonalInfoModalProps> = ({ isOpen, onClose }) => {
   const { user, setUser } = useAuthStore();
-  const [activeTab, setActiveTab] = useState<'profile' | 'password'>('profile');
+  const [activeTab, setActiveTab] = useState<'profile' | 'password' | 'security'>('profile');
   const [saving, setSaving] = useState(false);
   const [form, setForm] = useState({
     username: user?.username || '',
@@ -125,9 +169,69 @@ const PersonalInfoModal: React.FC<PersonalInfoModalProps> = ({ isOpen, onClose }
     phone: user?.phone || '',
   });
   const [pwd, setPwd] = useState({ current_password: '', new_password: '', confirm_password: '' });
+  const [twoFactor, setTwoFactor] = useState<TwoFactorStatus | null>(null);
+  const [enrollment, setEnrollment] = useState<TwoFactorEnrollment | null>(null);
+  const [otp, setOtp] = useState({ code: '', password: '' });
+  const [recoveryCodes, setRecoveryCodes] = useState<string[]>([]);
+  const [loginEvents, setLoginEvents] = useState<LoginEvent[]>([]);
 
   if (!isOpen) return null;
 
+  const openSecurity = async () => {
+    setActiveTab('security');
+    try {
+      const [status, events] = await Promise.all([getTwoFactorStatus(), getMyLoginEvents(10)]);
+      setTwoFactor(status);
+      setLoginEvents(events);
+    } catch {
+      toast.error('获取账号安全信息失败');
+    }
+  };
+
+  // 统一处理双因素相关操作：成功后清空输入并刷新状态
+  const runTwoFactor = async (action: () => Promise<void>) => {
+    setSaving(true);
+    try {
+      await action();
+      setOtp({ code: '', password: '' });
+      setTwoFactor(await getTwoFactorStatus());
+    } catch (err: any) {
+      const status = err?.response?.status;
+      const msg = err?.response?.data?.message;
+      if (status === 401) {
+        toast.error('密码不正确');
+      } else if (status === 429) {
+        toast.error('操作过于频繁，请稍后再试');
+      } else {
+        toast.error(TWO_FACTOR_ERRORS[msg] || '操作失败，请稍后重试');
+      }
+    } finally {
+      setSaving(false);
+    }
+  };
+
+  const startSetup = () => runTwoFactor(async () => {
+    setRecoveryCodes([]);
+    setEnrollment(await beginTwoFactorSetup());
+  });
+
+  const confirmSetup = () => runTwoFactor(async () => {
+    setRecoveryCodes(await enableTwoFactor(otp.code.trim()));
+    setEnrollment(null);
+    toast.success('双因素认证已启用');
+  });
+
+  const turnOff = () => runTwoFactor(async () => {
+    await disableTwoFactor(otp.password, otp.code.trim());
+    setRecoveryCodes([]);
+    toast.success('双因素认证已停用');
+  });
+
+  const renewCodes = () => runTwoFactor(async () => {
+    setRecoveryCodes(await regenerateRecoveryCodes(otp.code.trim()));
+    toast.success('恢复码已重新生成');
+  });
+
   const saveProfile = async () => {
     setSaving(true);
     try {
@@ -196,6 +300,7 @@ const PersonalInfoModal: React.FC<PersonalInfoModalProps> = ({ isOpen, onClose }
         <Tabs>
           <Tab active={activeTab==='profile'} onClick={() => setActiveTab('profile')}>资料</Tab>
           <Tab active={activeTab==='password'} onClick={() => setActiveTab('password')}>密码</Tab>
+          <Tab active={activeTab==='security'} onClick={openSecurity}>两步验证</Tab>
         </Tabs>
 
         {activeTab === 'profile' && (
@@ -238,6 +343,59 @@ const PersonalInfoModal: React.FC<PersonalInfoModalProps> = ({ isOpen, onClose }
             )}
           </Section>
         )}
+
+        {activeTab === 'security' && twoFactor && (
+          <Section>
+            {recoveryCodes.length > 0 && (
+              <>
+                <Note>请妥善保存以下恢复码，每个只能使用一次，关闭后将无法再次查看：</Note>
+                <CodeList>{recoveryCodes.join('\n')}</CodeList>
+              </>
+            )}
+
+            {twoFactor.enabled ? (
+              <>
+                <Note>双因素认证已启用，剩余恢复码 {twoFactor.recovery_codes_remaining} 个。</Note>
+                <Row><Label>验证码</Label><Input value={otp.code} autoComplete="one-time-code" placeholder="验证码或恢复码" onChange={e=>setOtp({...otp, code:e.target.value})} /></Row>
+                <Row><Label>当前密码</Label><Input type="password" value={otp.password} placeholder="停用时需要" onChange={e=>setOtp({...otp, password:e.target.value})} /></Row>
+                <Actions>
+                  <Button disabled={saving || !otp.code} onClick={renewCodes}>重新生成恢复码</Button>
+                  <Button primary disabled={saving || !otp.code || !otp.password} onClick={turnOff}>停用</Button>
+                </Actions>
+              </>
+            ) : enrollment ? (
+              <>
+                <Note>使用验证器 App 打开 <a href={enrollment.otpauth_uri}>此链接</a>，或手动输入密钥：</Note>
+                <CodeList style={{ columns: 1 }}>{enrollment.secret}</CodeList>
+                <Row><Label>验证码</Label><Input value={otp.code} inputMode="numeric" autoComplete="one-time-code" placeholder="App 中显示的 6 位数字" onChange={e=>setOtp({...otp, code:e.target.value})} /></Row>
+                <Actions>
+                  <Button onClick={() => setEnrollment(null)}>取消</Button>
+                  <Button primary disabled={saving || !otp.code} onClick={confirmSetup}>启用</Button>
+                </Actions>
+              </>
+            ) : (
+              <>
+                <Note>启用后，登录时除密码外还需要输入验证器 App 生成的验证码。</Note>
+                <Actions>
+                  <Button primary disabled={saving} onClick={startSetup}>开始设置</Button>
+                </Actions>
+              </>
+            )}
+          </Section>
+        )}
+
+        {activeTab === 'security' && loginEvents.length > 0 && (
+          <Section>
+            <Note>最近登录记录</Note>
+            {loginEvents.map(ev => (
+              <EventRow key={ev.id} failed={!ev.success && ev.result !== 'two_factor_pending'}>
+                <span>{new Date(ev.created_at).toLocaleString()}</span>
+                <span>{ev.ip_address || '-'}</span>
+                <span>{LOGIN_RESULT_LABELS[ev.result] || ev.result}{ev.two_factor && ev.success ? '（两步验证）' : ''}</span>
+              </EventRow>
+            ))}
+          </Section>
+        )}
       </Content>
     </Container>
   );
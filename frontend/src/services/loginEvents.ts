@@ -0,0 +1,30 @@
+import { api } from '../config/api';
+
+export interface LoginEvent {
+  id: number;
+  user_id: number | null;
+  username: string;
+  ip_address: string | null;
+  user_agent: string | null;
+  /** success | invalid_credentials | user_inactive | two_factor_pending | two_factor_failed | locked */
+  result: string;
+  success: boolean;
+  /** 是否经过双因素认证步骤 */
+  two_factor: boolean;
+  created_at: string;
+}
+
+export const LOGIN_RESULT_LABELS: Record<string, string> = {
+  success: '登录成功',
+  invalid_credentials: '密码错误',
+  user_inactive: '账号已停用',
+  two_factor_pending: '等待二次验证',
+  two_factor_failed: '验证码错误',
+  locked: '失败次数过多被拒绝',
+};
+
+/** 当前用户的登录记录，按时间倒序 */
+export async function getMyLoginEvents(limit = 20, offset = 0): Promise<LoginEvent[]> {
+  const res = await api.get('/api/user/login-events', { params: { limit, offset } });
+  return res.data;
+}
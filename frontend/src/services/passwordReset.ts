@@ -0,0 +1,11 @@
+import { api } from '../config/api';
+
+/** 申请找回密码（用户名或邮箱）；账号不存在时服务端同样返回成功 */
+export async function requestPasswordReset(account: string) {
+  await api.post('/api/auth/forgot', { account });
+}
+
+/** 用邮件中的重置令牌设置新密码；成功后该账号所有已登录设备需要重新登录 */
+export async function resetPassword(token: string, newPassword: string) {
+  await api.post('/api/auth/reset', { token, new_password: newPassword });
+}
@@ -7,6 +7,17 @@ export interface StaffItem {
   phone?: string | null;
   avatar_url?: string | null;
   role: string;
+  capabilities?: string[];
+  permissions?: Record<string, boolean>;
+}
+
+export type StaffRole = 'admin' | 'agent' | 'viewer';
+
+export interface MyShopPermissions {
+  user_id: number;
+  shop_id: number;
+  role: 'owner' | StaffRole;
+  capabilities: string[];
 }
 
 export async function listShopStaff(shopId: number) {
@@ -14,10 +25,30 @@ export async function listShopStaff(shopId: number) {
   return res.data;
 }
 
-export async function addShopStaff(shopId: number, username: string) {
-  await api.post(`/api/shops/${shopId}/staff`, { username });
+export async function addShopStaff(shopId: number, username: string, role?: StaffRole) {
+  await api.post(`/api/shops/${shopId}/staff`, { username, role });
+}
+
+export async function updateShopStaffRole(
+  shopId: number,
+  userId: number,
+  role: StaffRole,
+  permissions?: Record<string, boolean>,
+) {
+  const res = await api.put<StaffItem>(`/api/shops/${shopId}/staff/${userId}/role`, { role, permissions });
+  return res.data;
+}
+
+export async function getMyShopPermissions(shopId: number) {
+  const res = await api.get<MyShopPermissions>(`/api/shops/${shopId}/permissions/me`);
+  return res.data;
 }
 
 export async function removeShopStaff(shopId: number, userId: number) {
   await api.delete(`/api/shops/${shopId}/staff/${userId}`);
 }
+
+export async function revokeShopStaffSessions(shopId: number, userId: number) {
+  const res = await api.post<{ revoked: number }>(`/api/shops/${shopId}/staff/${userId}/revoke-sessions`);
+  return res.data.revoked;
+}
@@ -0,0 +1,41 @@
+import { api } from '../config/api';
+
+export interface TwoFactorStatus {
+  enabled: boolean;
+  /** 已生成密钥但尚未验证启用 */
+  pending: boolean;
+  recovery_codes_remaining: number;
+}
+
+export interface TwoFactorEnrollment {
+  /** Base32 密钥，供无法扫码时手动输入 */
+  secret: string;
+  otpauth_uri: string;
+}
+
+export async function getTwoFactorStatus(): Promise<TwoFactorStatus> {
+  const res = await api.get('/api/user/2fa');
+  return res.data;
+}
+
+/** 生成新的密钥；提交验证码确认前不会影响登录 */
+export async function beginTwoFactorSetup(): Promise<TwoFactorEnrollment> {
+  const res = await api.post('/api/user/2fa/setup');
+  return res.data;
+}
+
+/** 确认绑定并启用，返回只显示一次的恢复码 */
+export async function enableTwoFactor(code: string): Promise<string[]> {
+  const res = await api.post('/api/user/2fa/enable', { code });
+  return res.data.recovery_codes;
+}
+
+export async function disableTwoFactor(password: string, code: string) {
+  await api.post('/api/user/2fa/disable', { password, code });
+}
+
+/** 重新生成恢复码，旧恢复码全部作废 */
+export async function regenerateRecoveryCodes(code: string): Promise<string[]> {
+  const res = await api.post('/api/user/2fa/recovery-codes', { code });
+  return res.data.recovery_codes;
+}
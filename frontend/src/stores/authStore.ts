@@ -1,7 +1,7 @@
 import { create } from 'zustand';
 import { persist } from 'zustand/middleware';
 // 使用集中管理的 Axios 实例
-import { api, setAuthToken } from '../config/api';
+import { api, setAuthToken, setTokenRefresher } from '../config/api';
 import toast from 'react-hot-toast';
 
 interface User {
@@ -12,15 +12,26 @@ interface User {
   avatar_url?: string;
 }
 
+/** 登录结果：two_factor 表示密码正确，但还需要提交双因素验证码；throttled 表示失败次数过多被暂时限制 */
+export type LoginResult = 'success' | 'two_factor' | 'throttled' | 'failed';
+
 interface AuthState {
   isAuthenticated: boolean;
   user: User | null;
   token: string | null;
+  refreshToken: string | null;
   hydrated: boolean; // 标识持久化恢复是否完成
+  /** 密码校验通过后下发的双因素挑战令牌（不持久化） */
+  twoFactorChallenge: string | null;
   setHasHydrated: (value: boolean) => void;
   setUser: (patch: Partial<User> | User) => void;
-  login: (username: string, password: string) => Promise<boolean>;
+  login: (username: string, password: string) => Promise<LoginResult>;
+  /** 提交验证器 App 中的验证码或恢复码，完成双因素登录 */
+  verifyTwoFactor: (code: string) => Promise<boolean>;
+  cancelTwoFactor: () => void;
   logout: () => void;
+  /** 用 refresh token 换取新的 access token，失败返回 null */
+  refreshSession: () => Promise<string | null>;
   register: (username: string, password: string, email?: string, phone?: string) => Promise<boolean>;
 }
 
@@ -30,7 +41,9 @@ export const useAuthStore = create<AuthState>()(
       isAuthenticated: false,
       user: null,
       token: null,
+      refreshToken: null,
       hydrated: false,
+      twoFactorChallenge: null,
       setUser: (patch: Partial<User> | User) => set((state: AuthState) => ({
         user: state.user ? { ...state.user, ...patch } as User : (patch as User),
       })),
@@ -43,7 +56,12 @@ export const useAuthStore = create<AuthState>()(
             password,
           });
 
-          const { token, user } = response.data;
+          if (response.data?.two_factor_required) {
+            set({ twoFactorChallenge: response.data.challenge_token });
+            return 'two_factor';
+          }
+
+          const { token, refresh_token, user } = response.data;
           
           // 设置默认的 Authorization header
           setAuthToken(token);
@@ -52,18 +70,65 @@ export const useAuthStore = create<AuthState>()(
             isAuthenticated: true,
             user,
             token,
+            refreshToken: refresh_token ?? null,
           });
 
           toast.success('登录成功');
-          return true;
+          return 'success';
         } catch (error: any) {
+          if (error.response?.status === 429) {
+            // 失败次数过多：服务端按用户名与 IP 逐次延迟并临时锁定
+            const retryAfter = Number(error.response.headers?.['retry-after']) || 0;
+            toast.error(retryAfter > 60
+              ? `登录失败次数过多，请 ${Math.ceil(retryAfter / 60)} 分钟后再试`
+              : `登录过于频繁，请 ${Math.max(retryAfter, 1)} 秒后再试`);
+            return 'throttled';
+          }
           const message = error.response?.data?.message || '登录失败';
           toast.error(message);
+          return 'failed';
+        }
+      },
+
+      verifyTwoFactor: async (code: string) => {
+        const challengeToken = get().twoFactorChallenge;
+        if (!challengeToken) return false;
+        try {
+          const response = await api.post('/api/auth/login/2fa', {
+            challenge_token: challengeToken,
+            code: code.trim(),
+          });
+          const { token, refresh_token, user } = response.data;
+          setAuthToken(token);
+          set({
+            isAuthenticated: true,
+            user,
+            token,
+            refreshToken: refresh_token ?? null,
+            twoFactorChallenge: null,
+          });
+          toast.success('登录成功');
+          return true;
+        } catch (error: any) {
+          // 挑战令牌过期（5 分钟）后需要重新输入密码
+          if (error.response?.status === 401) {
+            set({ twoFactorChallenge: null });
+            toast.error('验证已超时，请重新登录');
+          }
+          if (error.response?.status === 429) {
+            toast.error('验证失败次数过多，请稍后再试');
+          }
           return false;
         }
       },
 
+      cancelTwoFactor: () => set({ twoFactorChallenge: null }),
+
       logout: () => {
+        // 通知服务端注销当前会话（失败不影响本地退出）
+        if (get().token) {
+          api.post('/api/auth/logout').catch(() => {});
+        }
         // 清除 Authorization header
   setAuthToken(undefined);
         
@@ -71,11 +136,26 @@ export const useAuthStore = create<AuthState>()(
           isAuthenticated: false,
           user: null,
           token: null,
+          refreshToken: null,
         });
 
         toast.success('已退出登录');
       },
 
+      refreshSession: async () => {
+        const refreshToken = get().refreshToken;
+        if (!refreshToken) return null;
+        try {
+          const response = await api.post('/api/auth/refresh', { refresh_token: refreshToken });
+          const { token, refresh_token, user } = response.data;
+          setAuthToken(token);
+          set({ token, refreshToken: refresh_token, user });
+          return token as string;
+        } catch {
+          return null;
+        }
+      },
+
       register: async (username: string, password: string, email?: string, phone?: string) => {
         try {
           // 检查API健康状况
@@ -88,7 +168,7 @@ export const useAuthStore = create<AuthState>()(
             phone,
           });
           
-          const { token, user } = response.data;
+          const { token, refresh_token, user } = response.data;
           
           if (!token || !user) {
             toast.error('注册响应格式错误');
@@ -102,6 +182,7 @@ export const useAuthStore = create<AuthState>()(
             isAuthenticated: true,
             user,
             token,
+            refreshToken: refresh_token ?? null,
           });
 
           toast.success('注册成功');
@@ -137,6 +218,7 @@ export const useAuthStore = create<AuthState>()(
         isAuthenticated: state.isAuthenticated,
         user: state.user,
         token: state.token,
+        refreshToken: state.refreshToken,
       }),
       onRehydrateStorage: () => (state: any) => {
         // 恢复完成：同步 Authorization 头
@@ -149,4 +231,6 @@ export const useAuthStore = create<AuthState>()(
       },
     }
   )
-);
\ No newline at end of file
+);
+
+setTokenRefresher(() => useAuthStore.getState().refreshSession());
@@ -23,6 +23,8 @@ interface WSState {
   reconnectAttempts?: number;
   reconnectTimer?: any;
   heartbeatTimer?: any;
+  // 已收到的最大消息 id，重连 auth 时回传以补发断线期间的消息
+  lastMessageId?: number;
   connect: (shopId: number) => void;
   disconnect: () => void;
   addMessageListener: (listener: MessageListener) => void;
@@ -40,8 +42,8 @@ export const useWSStore = create<WSState>((set, get) => ({
   heartbeatTimer: undefined,
 
   connect: (shopId: number) => {
-    const { user } = useAuthStore.getState();
-    if (!user) return;
+    const { user, token } = useAuthStore.getState();
+    if (!user || !token) return;
 
     // 避免重复连接
     const existing = get().socket;
@@ -67,14 +69,16 @@ export const useWSStore = create<WSState>((set, get) => ({
       set({ reconnectTimer: undefined });
     }
 
-    set({ status: 'connecting', activeShopId: shopId });
-    const ws = staffSocket(String(user.id));
+    // 切换店铺时游标失效
+    const lastMessageId = get().activeShopId === shopId ? get().lastMessageId : undefined;
+    set({ status: 'connecting', activeShopId: shopId, lastMessageId });
+    const ws = staffSocket(String(user.id), token);
 
     ws.onopen = () => {
-      // 发送 staff auth，附带 shopId
+      // 发送 staff auth，附带 shopId 与消息游标
       const authMsg = {
         messageType: 'auth',
-        metadata: { shopId },
+        metadata: lastMessageId ? { shopId, lastMessageId } : { shopId },
       };
       ws.send(JSON.stringify(authMsg));
       // 启动心跳：每 25s 发送一次 ping，保持链路活跃，避免中间代理空闲断开
@@ -100,6 +104,9 @@ export const useWSStore = create<WSState>((set, get) => ({
     ws.onmessage = (ev: MessageEvent) => {
       try {
         const data = JSON.parse(ev.data);
+        if (typeof data?.messageId === 'number' && data.messageId > (get().lastMessageId ?? 0)) {
+          set({ lastMessageId: data.messageId });
+        }
         const n = normalizeWSMessage(data);
         console.log('🔄 wsStore接收到消息(规范化):', n);
         
@@ -15,6 +15,32 @@ export interface ChatMessage {
   sessionId?: number;
   fileUrl?: string;
   fileName?: string;
+  replyTo?: ReplyPreview; // 引用回复的消息预览
+  richContent?: RichContent; // 富消息（商品卡片 / 订单卡片 / 按钮列表 / 快捷回复）
+}
+
+export interface RichOption {
+  id: string;
+  label: string;
+  action?: 'reply' | 'url'; // 仅按钮列表；url 按钮由客户端直接打开链接
+  url?: string;
+  payload?: string;
+}
+
+export type RichContent =
+  | { type: 'product_card'; title: string; price?: number; currency?: string; imageUrl?: string; url?: string; description?: string; sku?: string }
+  | { type: 'order_card'; orderNo: string; status?: string; total?: number; currency?: string; url?: string;
+      items: { title: string; quantity?: number; price?: number; imageUrl?: string }[] }
+  | { type: 'button_list'; text?: string; buttons: RichOption[] }
+  | { type: 'quick_replies'; text?: string; replies: RichOption[] }
+  | { type: 'quick_reply'; messageId: number; optionId: string; payload?: string };
+
+export interface ReplyPreview {
+  id: number;
+  senderType?: string;
+  senderId?: number;
+  messageType?: string;
+  content?: string;
 }
 
 export interface WebSocketMessage {
@@ -29,9 +55,29 @@ export interface WebSocketMessage {
   fileUrl?: string;       // 驼峰命名（Rust后端序列化后的实际字段名）
   file_name?: string;     // 保持下划线命名作为备用
   fileName?: string;      // 驼峰命名（Rust后端序列化后的实际字段名）
+  messageId?: number;     // 消息主键（单调递增），用于断线重连补发
+}
+
+export interface QueuePosition {
+  sessionId: number;
+  status: 'waiting' | 'assigned';
+  position: number | null; // 从 1 开始；已接入客服时为 null
+  queueLength: number;
+  content: string;
+}
+
+export interface MessageChange {
+  kind: 'edited' | 'recalled';
+  messageId: number;
+  sessionId?: number;
+  content?: string; // 编辑后的内容；撤回时为空
+  operatorType?: string;
+  changedAt: Date;
 }
 
 export type MessageHandler = (message: ChatMessage) => void;
+export type MessageChangeHandler = (change: MessageChange) => void;
+export type QueueHandler = (queue: QueuePosition) => void;
 export type ConnectionHandler = (config: ServerConfig) => void;
 export type ErrorHandler = (error: Error) => void;
 export type DisconnectHandler = () => void;
@@ -40,6 +86,8 @@ export class WebSocketClient {
   private ws: WebSocket | null = null;
   private shopId: string;
   private customerId: string;
+  private visitorToken: string | null = null; // 服务端首次 auth 时签发的访客令牌
+  private lastMessageId = 0; // 已收到的最大消息 id，重连 auth 时回传以补发错过的消息
   private serverConfig: ServerConfig | null = null;
   private configManager: ConfigManager;
   
@@ -48,6 +96,8 @@ export class WebSocketClient {
   private connectHandlers: ConnectionHandler[] = [];
   private errorHandlers: ErrorHandler[] = [];
   private disconnectHandlers: DisconnectHandler[] = [];
+  private queueHandlers: QueueHandler[] = [];
+  private messageChangeHandlers: MessageChangeHandler[] = [];
 
   /**
    * 协议适配工具函数 - 统一的协议适配策略
@@ -136,6 +186,28 @@ export class WebSocketClient {
     this.shopId = shopId;
     this.configManager = ConfigManager.getInstance();
     this.customerId = customerId || this.generateCustomerId();
+    this.visitorToken = this.loadVisitorToken();
+  }
+
+  private visitorTokenKey(): string {
+    return `qc_visitor_token_${this.shopId}_${this.customerId}`;
+  }
+
+  private loadVisitorToken(): string | null {
+    try {
+      return localStorage.getItem(this.visitorTokenKey());
+    } catch {
+      return null;
+    }
+  }
+
+  private saveVisitorToken(token: string): void {
+    this.visitorToken = token;
+    try {
+      localStorage.setItem(this.visitorTokenKey(), token);
+    } catch {
+      // 隐私模式下 localStorage 不可用，仅保存在内存中
+    }
   }
 
   /**
@@ -199,6 +271,10 @@ export class WebSocketClient {
         this.serverConfig.serverUrl.replace(/^https:/, 'wss:').replace(/^http:/, 'ws:');
       wsUrl = `${wsBase}/ws/customer/${this.shopId}/${this.customerId}`;
     }
+    // 携带访客令牌证明身份；首次连接没有令牌，由服务端在 auth_success 中签发
+    if (this.visitorToken) {
+      wsUrl += `?token=${encodeURIComponent(this.visitorToken)}`;
+    }
 
     console.log(`🔗 连接WebSocket: ${wsUrl}`);
 
@@ -274,7 +350,8 @@ export class WebSocketClient {
         messageType: 'auth',
         metadata: { 
           apiKey: this.shopId, 
-          customerId: this.customerId 
+          customerId: this.customerId,
+          ...(this.lastMessageId > 0 ? { lastMessageId: this.lastMessageId } : {})
         }
       };
       this.ws.send(JSON.stringify(authMessage));
@@ -293,7 +370,56 @@ export class WebSocketClient {
         this.handlePong();
         return;
       }
+
+      // 记录消息游标
+      if (typeof message.messageId === 'number' && message.messageId > this.lastMessageId) {
+        this.lastMessageId = message.messageId;
+      }
+
+      // 收到客服消息后自动回送送达回执
+      if (message.messageType === 'new_message' && message.senderType === 'staff' && typeof message.messageId === 'number') {
+        this.sendReceipt('delivered', message.messageId);
+      }
+
+      // 保存服务端签发的访客令牌，用于重连与文件上传
+      if (message.messageType === 'auth_success' && typeof message.metadata?.visitorToken === 'string') {
+        this.saveVisitorToken(message.metadata.visitorToken);
+      }
       
+      // 排队位置：以系统消息展示（"当前排队第 N 位"），同时通知自定义监听器
+      if (message.messageType === 'queue_position') {
+        this.notifyQueue({
+          sessionId: message.metadata?.sessionId,
+          status: message.metadata?.status,
+          position: message.metadata?.position ?? null,
+          queueLength: message.metadata?.queueLength ?? 0,
+          content: message.content || '',
+        });
+        if (message.content) {
+          this.notifyMessage({
+            content: message.content,
+            messageType: 'system',
+            senderType: 'staff',
+            timestamp: message.timestamp ? new Date(message.timestamp) : new Date(),
+            sessionId: message.sessionId,
+          });
+        }
+        return;
+      }
+
+      // 消息被编辑 / 撤回
+      if (message.messageType === 'message_edited' || message.messageType === 'message_recalled') {
+        this.notifyMessageChange({
+          kind: message.messageType === 'message_edited' ? 'edited' : 'recalled',
+          messageId: message.messageId ?? message.metadata?.messageId,
+          sessionId: message.sessionId,
+          content: message.content,
+          operatorType: message.senderType,
+          changedAt: message.timestamp ? new Date(message.timestamp) : new Date(),
+        });
+        return;
+      }
+
       // 添加调试日志
       console.log('🔍 收到原始WebSocket消息:', {
         messageType: message.messageType,
@@ -312,6 +438,7 @@ export class WebSocketClient {
         const adaptedFileUrl = rawFileUrl ? this.adaptUrlProtocol(rawFileUrl) : undefined;
         
         const chatMessage: ChatMessage = {
+          id: message.messageId,
           content: message.content,
           messageType: (message.metadata?.messageType as ChatMessage['messageType']) || 'text',
           senderType: (message.senderType as ChatMessage['senderType']) || 'staff',
@@ -319,7 +446,9 @@ export class WebSocketClient {
           fileUrl: adaptedFileUrl, // 使用协议适配后的URL
           fileName: message.fileName || message.file_name, // 优先使用驼峰命名，备用下划线命名
           sessionId: message.sessionId,
-          senderId: message.senderId
+          senderId: message.senderId,
+          replyTo: message.metadata?.replyTo,
+          richContent: message.metadata?.richContent
         };
 
         // 添加解析后的消息调试日志
@@ -347,7 +476,7 @@ export class WebSocketClient {
   /**
    * 发送文本消息
    */
-  sendMessage(content: string, messageType: ChatMessage['messageType'] = 'text'): void {
+  sendMessage(content: string, messageType: ChatMessage['messageType'] = 'text', replyTo?: number): void {
     if (!this.ws || this.ws.readyState !== WebSocket.OPEN) {
       console.warn('⚠️ WebSocket未连接，无法发送消息');
       return;
@@ -357,13 +486,61 @@ export class WebSocketClient {
       messageType: 'send_message',
       content,
       senderType: 'customer',
-      metadata: { messageType }
+      metadata: { messageType },
+      ...(replyTo ? { replyTo } : {})
     };
 
     this.ws.send(JSON.stringify(messageData));
     console.log('📤 发送消息:', content);
   }
 
+  /**
+   * 点击按钮列表 / 快捷回复中的选项，回答以 quick_reply 消息进入会话
+   */
+  sendQuickReply(messageId: number, optionId: string): void {
+    if (!this.ws || this.ws.readyState !== WebSocket.OPEN) {
+      console.warn('⚠️ WebSocket未连接，无法发送快捷回复');
+      return;
+    }
+    this.ws.send(JSON.stringify({ messageType: 'quick_reply', metadata: { messageId, optionId } }));
+  }
+
+  /**
+   * 编辑自己发送的文本消息（服务端限制发送后的可编辑时长）
+   */
+  editMessage(messageId: number, content: string): void {
+    if (!this.ws || this.ws.readyState !== WebSocket.OPEN) {
+      console.warn('⚠️ WebSocket未连接，无法编辑消息');
+      return;
+    }
+    this.ws.send(JSON.stringify({ messageType: 'edit_message', content, metadata: { messageId } }));
+  }
+
+  /**
+   * 撤回自己发送的消息（服务端限制发送后的可撤回时长）
+   */
+  recallMessage(messageId: number): void {
+    if (!this.ws || this.ws.readyState !== WebSocket.OPEN) {
+      console.warn('⚠️ WebSocket未连接，无法撤回消息');
+      return;
+    }
+    this.ws.send(JSON.stringify({ messageType: 'recall_message', metadata: { messageId } }));
+  }
+
+  /**
+   * 发送已读回执：messageId 及之前的客服消息标记为已读
+   */
+  markRead(messageId: number): void {
+    this.sendReceipt('read', messageId);
+  }
+
+  private sendReceipt(kind: 'read' | 'delivered', messageId: number): void {
+    if (!this.ws || this.ws.readyState !== WebSocket.OPEN) {
+      return;
+    }
+    this.ws.send(JSON.stringify({ messageType: kind, metadata: { messageId } }));
+  }
+
   /**
    * 发送文件消息（图片、文件、语音等）
    */
@@ -401,6 +578,9 @@ export class WebSocketClient {
     formData.append('shopId', this.shopId);
     formData.append('messageType', messageType);
     formData.append('customerCode', this.customerId);
+    if (this.visitorToken) {
+      formData.append('visitorToken', this.visitorToken);
+    }
 
     // 构建上传URL
     const uploadUrl = this.serverConfig.endpoints?.upload || 
@@ -511,6 +691,14 @@ export class WebSocketClient {
     this.messageHandlers.push(handler);
   }
 
+  onQueuePosition(handler: QueueHandler): void {
+    this.queueHandlers.push(handler);
+  }
+
+  onMessageChange(handler: MessageChangeHandler): void {
+    this.messageChangeHandlers.push(handler);
+  }
+
   onConnect(handler: ConnectionHandler): void {
     this.connectHandlers.push(handler);
   }
@@ -555,6 +743,26 @@ export class WebSocketClient {
     });
   }
 
+  private notifyMessageChange(change: MessageChange): void {
+    this.messageChangeHandlers.forEach(handler => {
+      try {
+        handler(change);
+      } catch (error) {
+        console.error('消息变更处理器错误:', error);
+      }
+    });
+  }
+
+  private notifyQueue(queue: QueuePosition): void {
+    this.queueHandlers.forEach(handler => {
+      try {
+        handler(queue);
+      } catch (error) {
+        console.error('排队处理器错误:', error);
+      }
+    });
+  }
+
   private notifyConnect(config: ServerConfig): void {
     this.connectHandlers.forEach(handler => {
       try {
@@ -38,6 +38,8 @@ export interface SDKConfig {
   customerName?: string;
   customerEmail?: string;
   customerAvatar?: string;
+  customerTags?: string[]; // 客户标签（如 VIP），仅追加，不会移除客服设置的标签
+  customerAttributes?: Record<string, string | number | boolean | null>; // 自定义属性，null 表示删除
   reconnectInterval?: number;
   maxReconnectAttempts?: number;
   autoDetectServer?: boolean; // 是否启用自动服务器检测
@@ -89,7 +91,8 @@ export type EventType =
   | 'error'
   | 'reconnecting'
   | 'staffOnline'
-  | 'staffOffline';
+  | 'staffOffline'
+  | 'blocked';
 
 // 事件监听器
 export type EventListener = (data?: any) => void;
@@ -113,6 +116,7 @@ export class CustomerServiceSDK {
   private reconnectTimer: NodeJS.Timeout | null = null;
   private isConnecting = false;
   private sessionId: number | null = null;
+  private isBlocked = false; // 被拉黑后服务器关闭连接，不再自动重连
   private serverConfig: ServerConfig | null = null;
   private autoUpdater?: SDKAutoUpdater;
   private readonly version = '2.1.0'; // SDK版本号
@@ -128,7 +132,7 @@ export class CustomerServiceSDK {
     };
 
     // 初始化事件监听器映射
-    ['connected', 'disconnected', 'message', 'typing', 'error', 'reconnecting', 'staffOnline', 'staffOffline'].forEach(eventType => {
+    ['connected', 'disconnected', 'message', 'typing', 'error', 'reconnecting', 'staffOnline', 'staffOffline', 'blocked'].forEach(eventType => {
       this.eventListeners.set(eventType as EventType, []);
     });
 
@@ -475,6 +479,8 @@ export class CustomerServiceSDK {
         customerName: this.config.customerName,
         customerEmail: this.config.customerEmail,
         customerAvatar: this.config.customerAvatar,
+        customerTags: this.config.customerTags,
+        customerAttributes: this.config.customerAttributes,
       }
     };
 
@@ -528,6 +534,22 @@ export class CustomerServiceSDK {
           });
           break;
           
+        case 'blocked':
+          this.isBlocked = true;
+          this.emit('blocked', {
+            reason: message.metadata?.reason,
+            blockedUntil: message.metadata?.blockedUntil ? new Date(message.metadata.blockedUntil) : null,
+          });
+          break;
+
+        case 'rate_limited':
+          this.emit('error', {
+            type: 'rate_limited',
+            messageType: message.metadata?.messageType,
+            retryAfter: message.metadata?.retryAfter,
+          });
+          break;
+
         case 'staff_status':
           if (message.metadata?.isOnline) {
             this.emit('staffOnline', message.metadata);
@@ -549,7 +571,7 @@ export class CustomerServiceSDK {
     this.emit('disconnected');
     
     // 尝试重连
-    if (this.reconnectAttempts < this.config.maxReconnectAttempts!) {
+    if (!this.isBlocked && this.reconnectAttempts < this.config.maxReconnectAttempts!) {
       this.emit('reconnecting', { attempt: this.reconnectAttempts + 1 });
       this.reconnectTimer = setTimeout(() => {
         this.reconnectAttempts++;